@@ -0,0 +1,120 @@
+use indexmap::IndexMap;
+
+use super::Invocation;
+
+// how the invocations to execute are selected among the responses of an ensemble
+#[derive(Debug, Default, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum Strategy {
+    // execute the invocations returned by most responses
+    #[default]
+    Majority,
+    // execute the invocations only if all responses agree
+    Unanimous,
+}
+
+// the result of a vote
+#[derive(Debug, PartialEq)]
+pub enum Selection {
+    // index of the response whose invocations have been selected
+    Selected(usize),
+    // none of the responses contained invocations
+    Empty,
+    // the responses did not reach consensus
+    NoConsensus,
+}
+
+// a comparable representation of a set of invocations, attributes are serialized in order
+fn vote_key(invocations: &[Invocation]) -> String {
+    invocations
+        .iter()
+        .map(|inv| inv.to_string())
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+// select the winning set of invocations, on ties the first response wins
+pub fn select(candidates: &[Vec<Invocation>], strategy: Strategy) -> Selection {
+    // vote key -> (index of the first response, number of votes)
+    let mut votes: IndexMap<String, (usize, usize)> = IndexMap::new();
+    let mut empty = 0;
+
+    for (idx, invocations) in candidates.iter().enumerate() {
+        if invocations.is_empty() {
+            empty += 1;
+        } else {
+            votes.entry(vote_key(invocations)).or_insert((idx, 0)).1 += 1;
+        }
+    }
+
+    if votes.is_empty() {
+        return Selection::Empty;
+    }
+
+    match strategy {
+        Strategy::Majority => {
+            let (mut best, mut best_count) = (0, 0);
+            for (idx, count) in votes.values() {
+                if *count > best_count {
+                    (best, best_count) = (*idx, *count);
+                }
+            }
+            Selection::Selected(best)
+        }
+        Strategy::Unanimous => {
+            if votes.len() == 1 && empty == 0 {
+                Selection::Selected(votes.values().next().unwrap().0)
+            } else {
+                Selection::NoConsensus
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn inv(action: &str, key: &str) -> Invocation {
+        let mut attributes = HashMap::new();
+        attributes.insert("key".to_string(), key.to_string());
+        Invocation::new(action.to_string(), Some(attributes), None)
+    }
+
+    #[test]
+    fn test_majority() {
+        let candidates = vec![
+            vec![inv("delete-memory", "a")],
+            vec![inv("delete-memory", "b")],
+            vec![],
+            vec![inv("delete-memory", "b")],
+        ];
+
+        assert_eq!(
+            select(&candidates, Strategy::Majority),
+            Selection::Selected(1)
+        );
+        assert_eq!(
+            select(&candidates, Strategy::Unanimous),
+            Selection::NoConsensus
+        );
+    }
+
+    #[test]
+    fn test_unanimous() {
+        let candidates = vec![
+            vec![inv("delete-memory", "a")],
+            vec![inv("delete-memory", "a")],
+        ];
+
+        assert_eq!(
+            select(&candidates, Strategy::Unanimous),
+            Selection::Selected(0)
+        );
+        assert_eq!(
+            select(&[vec![], vec![]], Strategy::Majority),
+            Selection::Empty
+        );
+    }
+}
@@ -1,8 +1,10 @@
 use serde::{Deserialize, Serialize};
 
 mod channel;
+mod observer;
 
 pub(crate) use channel::*;
+pub use observer::*;
 
 use super::{
     generator::Options,
@@ -11,8 +13,15 @@ use super::{
 };
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "event", content = "data", rename_all = "snake_case")]
 pub(crate) enum Event {
+    StepStarted {
+        step: usize,
+        max_steps: usize,
+    },
     MetricsUpdate(Metrics),
+    // part of the response being streamed
+    ResponseChunk(String),
     StorageUpdate {
         storage_name: String,
         storage_type: StorageType,
@@ -27,6 +36,9 @@ pub(crate) enum Event {
         invocation: Invocation,
         error: Option<String>,
     },
+    ActionStarted {
+        invocation: Invocation,
+    },
     ActionTimeout {
         invocation: Invocation,
         elapsed: std::time::Duration,
@@ -35,10 +47,13 @@ pub(crate) enum Event {
         invocation: Invocation,
         error: Option<String>,
         result: Option<String>,
+        data: Option<serde_json::Value>,
         elapsed: std::time::Duration,
     },
     TaskComplete {
         impossible: bool,
         reason: Option<String>,
     },
+    // a fatal error stopped the agent
+    Error(String),
 }
@@ -0,0 +1,25 @@
+use crate::agent::Invocation;
+
+// callbacks for programmatic consumers of the agent, all of them do nothing by default
+pub trait Observer: Send + Sync {
+    fn on_step_start(&self, _step: usize, _max_steps: usize) {}
+
+    // the raw response of the model, before parsing
+    fn on_model_response(&self, _raw: &str) {}
+
+    // a valid invocation is about to be executed
+    fn on_invocation(&self, _invocation: &Invocation) {}
+
+    fn on_action_result(
+        &self,
+        _invocation: &Invocation,
+        _result: Option<&str>,
+        _error: Option<&str>,
+    ) {
+    }
+
+    // the typed data of an action output, if the action returned any
+    fn on_action_data(&self, _invocation: &Invocation, _data: &serde_json::Value) {}
+
+    fn on_complete(&self, _impossible: bool, _reason: Option<&str>) {}
+}
@@ -0,0 +1,187 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use serde_json::{json, Value};
+
+use super::{tool_call_to_invocation, ChatResponse, Client, Message, Options, TokenUsage};
+use crate::agent::rag;
+
+const API_URL: &str = "https://api.anthropic.com/v1/messages";
+const API_VERSION: &str = "2023-06-01";
+// used unless ANTHROPIC_MAX_TOKENS is set
+const DEFAULT_MAX_TOKENS: u32 = 4096;
+
+pub struct AnthropicClient {
+    model: String,
+    api_key: String,
+    max_tokens: u32,
+    client: reqwest::Client,
+}
+
+// the messages api wants alternating user and assistant turns starting with the user one,
+// so consecutive messages with the same role are merged
+fn to_messages(options: &Options) -> Vec<Value> {
+    let mut messages: Vec<(&str, String)> = vec![("user", options.prompt.trim().to_string())];
+
+    for m in &options.history {
+        let (role, data) = match m {
+            Message::Agent(data, _) => ("assistant", data.trim()),
+            Message::Feedback(data, _) => ("user", data.trim()),
+        };
+
+        match messages.last_mut() {
+            Some((last_role, content)) if *last_role == role => {
+                content.push_str("\n\n");
+                content.push_str(data);
+            }
+            _ => messages.push((role, data.to_string())),
+        }
+    }
+
+    messages
+        .into_iter()
+        .map(|(role, content)| json!({"role": role, "content": content}))
+        .collect()
+}
+
+#[async_trait]
+impl Client for AnthropicClient {
+    fn new(_: &str, _: u16, model_name: &str, _: u32) -> anyhow::Result<Self>
+    where
+        Self: Sized,
+    {
+        let api_key = std::env::var("ANTHROPIC_API_KEY")
+            .map_err(|_| anyhow!("Missing ANTHROPIC_API_KEY".to_string()))?;
+        let max_tokens = if let Ok(value) = std::env::var("ANTHROPIC_MAX_TOKENS") {
+            value
+                .parse()
+                .map_err(|e| anyhow!("invalid ANTHROPIC_MAX_TOKENS '{}': {}", value, e))?
+        } else {
+            DEFAULT_MAX_TOKENS
+        };
+
+        Ok(Self {
+            model: model_name.to_string(),
+            api_key,
+            max_tokens,
+            client: reqwest::Client::new(),
+        })
+    }
+
+    fn supports_tools(&self) -> bool {
+        true
+    }
+
+    async fn chat(&self, options: &Options) -> anyhow::Result<ChatResponse> {
+        let mut body = json!({
+            "model": &self.model,
+            "max_tokens": self.max_tokens,
+            "system": options.system_prompt.trim(),
+            "messages": to_messages(options),
+        });
+        if let Some(temperature) = options.generation.temperature {
+            body["temperature"] = json!(temperature);
+        }
+        if let Some(top_k) = options.generation.top_k {
+            body["top_k"] = json!(top_k);
+        }
+        if let Some(top_p) = options.generation.top_p {
+            body["top_p"] = json!(top_p);
+        }
+        if !options.tools.is_empty() {
+            body["tools"] = options
+                .tools
+                .iter()
+                .map(|tool| {
+                    json!({
+                        "name": tool.name,
+                        "description": tool.description,
+                        "input_schema": tool.parameters,
+                    })
+                })
+                .collect();
+        }
+
+        let resp = self
+            .client
+            .post(API_URL)
+            .header("x-api-key", &self.api_key)
+            .header("anthropic-version", API_VERSION)
+            .json(&body)
+            .send()
+            .await?;
+
+        let status = resp.status();
+        let text = resp.text().await?;
+        if !status.is_success() {
+            // try to report the error message only
+            let error = serde_json::from_str::<Value>(&text)
+                .ok()
+                .and_then(|v| v["error"]["message"].as_str().map(|s| s.to_string()))
+                .unwrap_or(text);
+
+            return Err(anyhow!("{}: {}", status, error));
+        }
+
+        let resp: Value = serde_json::from_str(&text)?;
+        let mut content = vec![];
+        let mut invocations = vec![];
+        for block in resp["content"].as_array().unwrap_or(&vec![]) {
+            match block["type"].as_str() {
+                Some("text") => content.push(block["text"].as_str().unwrap_or("").to_string()),
+                Some("tool_use") => {
+                    let name = block["name"].as_str().unwrap_or("");
+                    match tool_call_to_invocation(name, &block["input"].to_string()) {
+                        Ok(invocation) => invocations.push(invocation),
+                        Err(err) => log::error!("can't parse tool call {:?}: {}", block, err),
+                    }
+                }
+                _ => log::debug!("ignoring content block {:?}", block),
+            }
+        }
+
+        let usage = &resp["usage"];
+        let usage = usage["input_tokens"]
+            .as_u64()
+            .zip(usage["output_tokens"].as_u64())
+            .map(|(prompt, completion)| TokenUsage::new(prompt, completion));
+
+        Ok(ChatResponse {
+            content: content.join("\n"),
+            invocations,
+            usage,
+        })
+    }
+}
+
+#[async_trait]
+impl rag::Embedder for AnthropicClient {
+    async fn embed(&self, _: &str) -> Result<rag::Embeddings> {
+        Err(anyhow!("the anthropic generator does not support embeddings, use a different embedder for RAG with --embedder"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_messages() {
+        let options = Options::new(
+            "system".to_string(),
+            "prompt".to_string(),
+            vec![
+                Message::Feedback("first".to_string(), None),
+                Message::Agent("action".to_string(), None),
+                Message::Feedback("result".to_string(), None),
+            ],
+        );
+
+        let messages = to_messages(&options);
+
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[0]["role"], "user");
+        assert_eq!(messages[0]["content"], "prompt\n\nfirst");
+        assert_eq!(messages[1]["role"], "assistant");
+        assert_eq!(messages[2]["content"], "result");
+    }
+}
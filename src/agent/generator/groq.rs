@@ -4,9 +4,9 @@ use groq_api_rs::completion::{client::Groq, request::builder, response::ErrorRes
 use lazy_static::lazy_static;
 use regex::Regex;
 
-use crate::agent::generator::Message;
+use crate::agent::{generator::Message, rag};
 
-use super::{Client, Options};
+use super::{ChatResponse, Client, Options, TokenUsage};
 
 lazy_static! {
     static ref RETRY_TIME_PARSER: Regex =
@@ -32,7 +32,7 @@ impl Client for GroqClient {
         Ok(Self { model, api_key })
     }
 
-    async fn chat(&self, options: &Options) -> Result<String> {
+    async fn chat(&self, options: &Options) -> Result<ChatResponse> {
         let mut chat_history = vec![
             groq_api_rs::completion::message::Message::SystemMessage {
                 role: Some("system".to_string()),
@@ -91,7 +91,16 @@ impl Client for GroqClient {
             });
         }
 
-        let request = builder::RequestBuilder::new(self.model.clone()).with_stream(false);
+        let mut request = builder::RequestBuilder::new(self.model.clone()).with_stream(false);
+        if let Some(temperature) = options.generation.temperature {
+            request = request.with_temperature(temperature);
+        }
+        if let Some(top_p) = options.generation.top_p {
+            request = request.with_top_p(top_p);
+        }
+        if let Some(seed) = options.generation.seed {
+            request = request.with_seed(seed);
+        }
 
         let client = Groq::new(&self.api_key);
         let client = client.add_messages(chat_history);
@@ -112,22 +121,29 @@ impl Client for GroqClient {
             return Err(error);
         }
 
-        let choice = match resp.unwrap() {
-            groq_api_rs::completion::client::CompletionOption::NonStream(resp) => {
-                resp.choices.first().unwrap().to_owned()
-            }
+        let (choice, usage) = match resp.unwrap() {
+            groq_api_rs::completion::client::CompletionOption::NonStream(resp) => (
+                resp.choices.first().unwrap().to_owned(),
+                TokenUsage::new(
+                    resp.usage.prompt_tokens as u64,
+                    resp.usage.completion_tokens as u64,
+                ),
+            ),
             groq_api_rs::completion::client::CompletionOption::Stream(_) => {
                 return Err(anyhow!("Groq streaming is not supported yet, if this happens please open an issue on GitHub"));
             }
         };
 
-        Ok(choice.message.content.to_string())
+        Ok(ChatResponse {
+            usage: Some(usage),
+            ..choice.message.content.to_string().into()
+        })
     }
 }
 
 #[async_trait]
-impl mini_rag::Embedder for GroqClient {
-    async fn embed(&self, _text: &str) -> Result<mini_rag::Embeddings> {
+impl rag::Embedder for GroqClient {
+    async fn embed(&self, _text: &str) -> Result<rag::Embeddings> {
         // TODO: extend the rust client to do this
         todo!("groq embeddings generation not yet implemented")
     }
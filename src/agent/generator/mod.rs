@@ -7,16 +7,19 @@ use lazy_static::lazy_static;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 
-use super::Invocation;
+use super::{namespaces::Action, rag, Invocation};
 
+#[cfg(feature = "anthropic")]
+mod anthropic;
 #[cfg(feature = "fireworks")]
 mod fireworks;
 #[cfg(feature = "groq")]
 mod groq;
 #[cfg(feature = "ollama")]
 mod ollama;
-#[cfg(feature = "openai")]
+#[cfg(any(feature = "openai", feature = "fireworks"))]
 mod openai;
+pub mod retry;
 
 lazy_static! {
     static ref RETRY_TIME_PARSER: Regex =
@@ -24,11 +27,26 @@ lazy_static! {
     static ref CONN_RESET_PARSER: Regex = Regex::new(r"(?m)^.+onnection reset by peer.*").unwrap();
 }
 
+// sampling parameters, the ones not set are left to the backend or model defaults
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct GenerationConfig {
+    pub temperature: Option<f32>,
+    pub top_k: Option<u32>,
+    pub top_p: Option<f32>,
+    pub repeat_penalty: Option<f32>,
+    pub seed: Option<i32>,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Options {
     pub system_prompt: String,
     pub prompt: String,
     pub history: Vec<Message>,
+    pub generation: GenerationConfig,
+    // base64 encoded images for multimodal models
+    pub images: Vec<String>,
+    // actions advertised as structured tools, only set for backends supporting them
+    pub tools: Vec<Tool>,
 }
 
 impl Options {
@@ -37,6 +55,59 @@ impl Options {
             system_prompt,
             prompt,
             history,
+            generation: GenerationConfig::default(),
+            images: vec![],
+            tools: vec![],
+        }
+    }
+}
+
+// an action as a function the model can call
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Tool {
+    pub name: String,
+    pub description: String,
+    // json schema of the arguments
+    pub parameters: serde_json::Value,
+}
+
+impl Tool {
+    // attributes are string arguments and the payload, if any, is the "payload" one as for
+    // tool_call_to_invocation
+    #[allow(clippy::borrowed_box)]
+    pub fn from_action(action: &Box<dyn Action>) -> Self {
+        let mut properties = serde_json::Map::new();
+        let mut required = action.required_attributes();
+
+        let attributes = action.attributes().unwrap_or_default();
+        let optional = action.optional_attributes().unwrap_or_default();
+        for (name, example) in attributes.iter().chain(optional.iter()) {
+            properties.insert(
+                name.to_string(),
+                serde_json::json!({"type": "string", "description": format!("for example: {}", example)}),
+            );
+        }
+
+        if let Some(example) = action.example_payload() {
+            properties.insert(
+                "payload".to_string(),
+                serde_json::json!({"type": "string", "description": format!("for example: {}", example)}),
+            );
+            required.push("payload".to_string());
+        }
+
+        Self {
+            name: action.name().to_string(),
+            description: action
+                .description()
+                .trim()
+                .trim_end_matches(':')
+                .to_string(),
+            parameters: serde_json::json!({
+                "type": "object",
+                "properties": properties,
+                "required": required,
+            }),
         }
     }
 }
@@ -47,6 +118,14 @@ pub enum Message {
     Feedback(String, Option<Invocation>),
 }
 
+impl Message {
+    pub fn content(&self) -> &str {
+        match self {
+            Message::Agent(data, _) | Message::Feedback(data, _) => data,
+        }
+    }
+}
+
 impl Display for Message {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
@@ -60,13 +139,158 @@ impl Display for Message {
     }
 }
 
+// tokens consumed by one or more generations
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct TokenUsage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+}
+
+impl TokenUsage {
+    pub fn new(prompt_tokens: u64, completion_tokens: u64) -> Self {
+        Self {
+            prompt_tokens,
+            completion_tokens,
+        }
+    }
+
+    pub fn add(&mut self, other: &TokenUsage) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+    }
+
+    pub fn total(&self) -> u64 {
+        self.prompt_tokens + self.completion_tokens
+    }
+
+    pub fn cost(&self, prices: &TokenPrices) -> f64 {
+        (self.prompt_tokens as f64 * prices.prompt
+            + self.completion_tokens as f64 * prices.completion)
+            / 1000.0
+    }
+}
+
+// price per 1000 tokens
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TokenPrices {
+    pub prompt: f64,
+    pub completion: f64,
+}
+
+// tokens consumed by the run and their estimated cost, if prices are configured
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct UsageSummary {
+    pub tokens: TokenUsage,
+    pub cost: Option<f64>,
+}
+
+impl Display for UsageSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "tokens: {} prompt + {} completion = {}",
+            self.tokens.prompt_tokens,
+            self.tokens.completion_tokens,
+            self.tokens.total()
+        )?;
+        if let Some(cost) = self.cost {
+            write!(f, " (estimated cost {:.4})", cost)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ChatResponse {
+    pub content: String,
+    // invocations returned as structured tool calls, for backends supporting them
+    pub invocations: Vec<Invocation>,
+    // tokens consumed by the generation, for backends reporting them
+    pub usage: Option<TokenUsage>,
+}
+
+impl From<String> for ChatResponse {
+    fn from(content: String) -> Self {
+        Self {
+            content,
+            invocations: vec![],
+            usage: None,
+        }
+    }
+}
+
+// map a structured tool call to an invocation, the "payload" argument is used as payload
+// and all the other arguments as attributes
+#[cfg(any(feature = "openai", feature = "fireworks", feature = "anthropic"))]
+pub(crate) fn tool_call_to_invocation(name: &str, arguments: &str) -> Result<Invocation> {
+    if name.is_empty() {
+        return Err(anyhow!("tool call without a function name"));
+    }
+
+    let arguments: serde_json::Value = if arguments.trim().is_empty() {
+        serde_json::Value::Object(Default::default())
+    } else {
+        serde_json::from_str(arguments)?
+    };
+    let arguments = arguments
+        .as_object()
+        .ok_or_else(|| anyhow!("tool call arguments are not an object"))?;
+
+    let mut attributes = std::collections::HashMap::new();
+    let mut payload = None;
+    for (key, value) in arguments {
+        let value = match value {
+            serde_json::Value::String(s) => s.to_string(),
+            _ => value.to_string(),
+        };
+        if key == "payload" {
+            payload = Some(value);
+        } else {
+            attributes.insert(key.to_string(), value);
+        }
+    }
+
+    Ok(Invocation::new(
+        name.to_string(),
+        if attributes.is_empty() {
+            None
+        } else {
+            Some(attributes)
+        },
+        payload,
+    ))
+}
+
 #[async_trait]
-pub trait Client: mini_rag::Embedder + Send + Sync {
+pub trait Client: rag::Embedder + Send + Sync {
     fn new(url: &str, port: u16, model_name: &str, context_window: u32) -> Result<Self>
     where
         Self: Sized;
 
-    async fn chat(&self, options: &Options) -> Result<String>;
+    async fn chat(&self, options: &Options) -> Result<ChatResponse>;
+
+    // true if the backend accepts Options::tools and returns the calls as invocations
+    fn supports_tools(&self) -> bool {
+        false
+    }
+
+    // ask the backend for the context length of the model and use it from now on, returns
+    // None if the backend can't tell
+    async fn detect_context_window(&mut self) -> Result<Option<u32>> {
+        Ok(None)
+    }
+
+    // same as chat but on_chunk is called with the text as it is generated, by default the
+    // whole response is passed at once for backends not supporting streaming
+    async fn chat_stream(
+        &self,
+        options: &Options,
+        on_chunk: &(dyn for<'c> Fn(&'c str) + Send + Sync),
+    ) -> Result<ChatResponse> {
+        let response = self.chat(options).await?;
+        on_chunk(&response.content);
+        Ok(response)
+    }
 
     async fn check_rate_limit(&self, error: &str) -> bool {
         // if rate limit exceeded, parse the retry time and retry
@@ -145,6 +369,13 @@ macro_rules! factory_body {
                 $model_name,
                 $context_window,
             )?)),
+            #[cfg(feature = "anthropic")]
+            "anthropic" => Ok(Box::new(anthropic::AnthropicClient::new(
+                $url,
+                $port,
+                $model_name,
+                $context_window,
+            )?)),
             #[cfg(feature = "groq")]
             "groq" => Ok(Box::new(groq::GroqClient::new(
                 $url,
@@ -173,6 +404,54 @@ pub fn factory_embedder(
     port: u16,
     model_name: &str,
     context_window: u32,
-) -> Result<Box<dyn mini_rag::Embedder>> {
+) -> Result<Box<dyn rag::Embedder>> {
     factory_body!(name, url, port, model_name, context_window)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(any(feature = "openai", feature = "fireworks", feature = "anthropic"))]
+    fn test_tool_call_to_invocation() {
+        let inv = tool_call_to_invocation(
+            "save-memory",
+            r#"{"key": "note", "payload": "hello", "count": 3}"#,
+        )
+        .unwrap();
+
+        assert_eq!(inv.action, "save-memory");
+        assert_eq!(inv.payload, Some("hello".to_string()));
+        let attributes = inv.attributes.unwrap();
+        assert_eq!(attributes.get("key").unwrap(), "note");
+        assert_eq!(attributes.get("count").unwrap(), "3");
+
+        let inv = tool_call_to_invocation("clear-plan", "").unwrap();
+        assert!(inv.attributes.is_none() && inv.payload.is_none());
+
+        assert!(tool_call_to_invocation("", "{}").is_err());
+        assert!(tool_call_to_invocation("x", "[1]").is_err());
+    }
+
+    #[test]
+    fn test_tool_from_action() {
+        let memory = crate::agent::namespaces::NAMESPACES["memory"]();
+        let action = memory
+            .actions
+            .iter()
+            .find(|a| a.name() == "save-memory")
+            .unwrap();
+
+        let tool = Tool::from_action(action);
+        assert_eq!(tool.name, "save-memory");
+        assert!(!tool.description.ends_with(':'));
+        assert_eq!(tool.parameters["type"], "object");
+        assert_eq!(tool.parameters["properties"]["key"]["type"], "string");
+        assert_eq!(tool.parameters["properties"]["payload"]["type"], "string");
+        assert_eq!(
+            tool.parameters["required"],
+            serde_json::json!(["key", "payload"])
+        );
+    }
+}
@@ -1,14 +1,46 @@
 use anyhow::Result;
 use async_trait::async_trait;
+use futures::StreamExt;
 use ollama_rs::{
     generation::{
-        chat::{request::ChatMessageRequest, ChatMessage},
+        chat::{
+            request::ChatMessageRequest, ChatMessage, ChatMessageFinalResponseData, MessageRole,
+        },
+        images::Image,
         options::GenerationOptions,
     },
     Ollama,
 };
 
-use super::{Client, Message, Options};
+use super::{ChatResponse, Client, Message, Options, TokenUsage};
+use crate::agent::rag;
+
+fn to_usage(data: &ChatMessageFinalResponseData) -> TokenUsage {
+    TokenUsage::new(data.prompt_eval_count as u64, data.eval_count as u64)
+}
+
+// the num_ctx set in the modelfile if any, the context length the model was trained with otherwise
+fn context_length(info: &serde_json::Value) -> Option<u32> {
+    let from_params = info["parameters"].as_str().and_then(|params| {
+        params.lines().find_map(|line| {
+            let mut parts = line.split_whitespace();
+            match (parts.next(), parts.next()) {
+                (Some("num_ctx"), Some(value)) => value.parse::<u32>().ok(),
+                _ => None,
+            }
+        })
+    });
+
+    from_params.or_else(|| {
+        info["model_info"].as_object().and_then(|model_info| {
+            model_info
+                .iter()
+                .find(|(key, _)| key.ends_with(".context_length"))
+                .and_then(|(_, value)| value.as_u64())
+                .and_then(|value| u32::try_from(value).ok())
+        })
+    })
+}
 
 pub struct OllamaClient {
     model: String,
@@ -16,6 +48,70 @@ pub struct OllamaClient {
     client: Ollama,
 }
 
+impl OllamaClient {
+    fn to_request(&self, options: &Options) -> ChatMessageRequest {
+        // build chat history:
+        //    - system prompt
+        //    - user prompt
+        //    - msg 0
+        //    - msg 1
+        //    - ...
+        //    - msg n
+        let mut chat_history = vec![
+            ChatMessage::system(options.system_prompt.trim().to_string()),
+            ChatMessage::user(options.prompt.to_string()),
+        ];
+
+        for m in &options.history {
+            chat_history.push(match m {
+                Message::Agent(data, _) => ChatMessage::assistant(data.trim().to_string()),
+                Message::Feedback(data, _) => ChatMessage::user(data.trim().to_string()),
+            });
+        }
+
+        // attach the images to the latest user message, that is the result of the action loading them
+        if !options.images.is_empty() {
+            let images = options
+                .images
+                .iter()
+                .map(|image| Image::from_base64(image))
+                .collect();
+            match chat_history.last_mut() {
+                Some(last) if last.role == MessageRole::User => last.images = Some(images),
+                _ => chat_history.push(
+                    ChatMessage::user("Here are the images.".to_string()).with_images(images),
+                ),
+            }
+        }
+
+        // Only provide the sampling options that have been explicitly configured so that we'll use whatever
+        // was specified in the modelfile for the rest.
+        let mut generation = self.options.clone();
+        if let Some(temperature) = options.generation.temperature {
+            generation = generation.temperature(temperature);
+        }
+        if let Some(top_k) = options.generation.top_k {
+            generation = generation.top_k(top_k);
+        }
+        if let Some(top_p) = options.generation.top_p {
+            generation = generation.top_p(top_p);
+        }
+        if let Some(repeat_penalty) = options.generation.repeat_penalty {
+            generation = generation.repeat_penalty(repeat_penalty);
+        }
+        if let Some(seed) = options.generation.seed {
+            generation = generation.seed(seed);
+        }
+
+        let mut request =
+            ChatMessageRequest::new(self.model.to_string(), chat_history).options(generation);
+
+        request.model_name.clone_from(&self.model);
+
+        request
+    }
+}
+
 #[async_trait]
 impl Client for OllamaClient {
     fn new(url: &str, port: u16, model_name: &str, context_window: u32) -> anyhow::Result<Self>
@@ -41,61 +137,183 @@ impl Client for OllamaClient {
         })
     }
 
-    async fn chat(&self, options: &Options) -> anyhow::Result<String> {
-        /*
-        pub struct GenerationRequest {
-            ...
-            TODO: images for multimodal (see todo for screenshot action)
-            pub images: Vec<Image>,
-            ...
+    async fn chat(&self, options: &Options) -> anyhow::Result<ChatResponse> {
+        let request = self.to_request(options);
+        let res = self.client.send_chat_messages(request).await?;
+
+        if let Some(msg) = res.message {
+            Ok(ChatResponse {
+                usage: res.final_data.as_ref().map(to_usage),
+                ..msg.content.into()
+            })
+        } else {
+            log::warn!("model returned an empty message.");
+            Ok(ChatResponse::default())
         }
-        */
+    }
 
-        // build chat history:
-        //    - system prompt
-        //    - user prompt
-        //    - msg 0
-        //    - msg 1
-        //    - ...
-        //    - msg n
-        let mut chat_history = vec![
-            ChatMessage::system(options.system_prompt.trim().to_string()),
-            ChatMessage::user(options.prompt.to_string()),
-        ];
+    async fn detect_context_window(&mut self) -> anyhow::Result<Option<u32>> {
+        let info: serde_json::Value = reqwest::Client::new()
+            .post(format!("{}/api/show", self.client.uri()))
+            .json(&serde_json::json!({ "name": self.model }))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
 
-        for m in &options.history {
-            chat_history.push(match m {
-                Message::Agent(data, _) => ChatMessage::assistant(data.trim().to_string()),
-                Message::Feedback(data, _) => ChatMessage::user(data.trim().to_string()),
-            });
+        let size = context_length(&info);
+        if let Some(size) = size {
+            self.options = self.options.clone().num_ctx(size);
         }
 
-        // Do not provide model options other than the context window size so that we'll use whatever was
-        // specified in the modelfile.
-        let mut request = ChatMessageRequest::new(self.model.to_string(), chat_history)
-            .options(self.options.clone());
+        Ok(size)
+    }
 
-        request.model_name.clone_from(&self.model);
+    async fn chat_stream(
+        &self,
+        options: &Options,
+        on_chunk: &(dyn for<'c> Fn(&'c str) + Send + Sync),
+    ) -> anyhow::Result<ChatResponse> {
+        let request = self.to_request(options);
+        let mut stream = self.client.send_chat_messages_stream(request).await?;
 
-        let res = self.client.send_chat_messages(request).await?;
+        let mut content = String::new();
+        let mut usage = None;
+        while let Some(res) = stream.next().await {
+            let res = res.map_err(|_| anyhow!("error while streaming the response"))?;
+            if let Some(msg) = res.message {
+                on_chunk(&msg.content);
+                content.push_str(&msg.content);
+            }
+            if res.done {
+                // only the last message has the token counts
+                usage = res.final_data.as_ref().map(to_usage);
+                break;
+            }
+        }
 
-        if let Some(msg) = res.message {
-            Ok(msg.content)
-        } else {
+        if content.is_empty() {
             log::warn!("model returned an empty message.");
-            Ok("".to_string())
         }
+
+        Ok(ChatResponse {
+            usage,
+            ..content.into()
+        })
     }
 }
 
 #[async_trait]
-impl mini_rag::Embedder for OllamaClient {
-    async fn embed(&self, text: &str) -> Result<mini_rag::Embeddings> {
+impl rag::Embedder for OllamaClient {
+    async fn embed(&self, text: &str) -> Result<rag::Embeddings> {
         let resp = self
             .client
             .generate_embeddings(self.model.to_string(), text.to_string(), None)
             .await?;
 
-        Ok(mini_rag::Embeddings::from(resp.embeddings))
+        Ok(rag::Embeddings::from(resp.embeddings))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::generator::GenerationConfig;
+
+    #[test]
+    fn test_generation_options() {
+        let client = OllamaClient::new("localhost", 11434, "llama3", 4096).unwrap();
+        let mut options = Options::new("system".to_string(), "prompt".to_string(), vec![]);
+
+        // nothing but the context window by default
+        let request = serde_json::to_value(client.to_request(&options).options).unwrap();
+        assert_eq!(request["num_ctx"], 4096);
+        assert!(request["temperature"].is_null());
+        assert!(request["seed"].is_null());
+
+        options.generation = GenerationConfig {
+            temperature: Some(0.0),
+            top_k: Some(20),
+            top_p: Some(0.5),
+            repeat_penalty: Some(1.5),
+            seed: Some(42),
+        };
+
+        let request = serde_json::to_value(client.to_request(&options).options).unwrap();
+        assert_eq!(request["num_ctx"], 4096);
+        assert_eq!(request["temperature"], 0.0);
+        assert_eq!(request["top_k"], 20);
+        assert_eq!(request["top_p"], 0.5);
+        assert_eq!(request["repeat_penalty"], 1.5);
+        assert_eq!(request["seed"], 42);
+    }
+
+    #[test]
+    fn test_context_length() {
+        assert_eq!(
+            context_length(&serde_json::json!({
+                "parameters": "stop \"<|eot_id|>\"\nnum_ctx                        2048",
+                "model_info": {"llama.context_length": 131072}
+            })),
+            Some(2048)
+        );
+        assert_eq!(
+            context_length(&serde_json::json!({
+                "model_info": {"general.architecture": "llama", "llama.context_length": 131072}
+            })),
+            Some(131072)
+        );
+        assert_eq!(context_length(&serde_json::json!({"license": "MIT"})), None);
+    }
+
+    #[tokio::test]
+    async fn test_detect_context_window() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // a server answering any request with the model info
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 4096];
+            let _ = stream.read(&mut buf).await.unwrap();
+
+            let body = r#"{"model_info": {"llama.context_length": 32768}}"#;
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            stream.write_all(response.as_bytes()).await.unwrap();
+        });
+
+        let mut client = OllamaClient::new("127.0.0.1", port, "llama3", 4096).unwrap();
+        assert_eq!(client.detect_context_window().await.unwrap(), Some(32768));
+
+        let options = Options::new("system".to_string(), "prompt".to_string(), vec![]);
+        let request = serde_json::to_value(client.to_request(&options).options).unwrap();
+        assert_eq!(request["num_ctx"], 32768);
+    }
+
+    #[test]
+    fn test_images() {
+        let client = OllamaClient::new("localhost", 11434, "llava", 4096).unwrap();
+        let mut options = Options::new(
+            "system".to_string(),
+            "prompt".to_string(),
+            vec![Message::Feedback("image attached".to_string(), None)],
+        );
+
+        let request = client.to_request(&options);
+        assert!(request.messages.iter().all(|m| m.images.is_none()));
+
+        options.images = vec!["aW1hZ2U=".to_string()];
+
+        let request = serde_json::to_value(client.to_request(&options)).unwrap();
+        let messages = request["messages"].as_array().unwrap();
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[2]["content"], "image attached");
+        assert_eq!(messages[2]["images"], serde_json::json!(["aW1hZ2U="]));
     }
 }
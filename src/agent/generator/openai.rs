@@ -1,109 +1,355 @@
 use anyhow::Result;
 use async_trait::async_trait;
-use openai_api_rust::chat::*;
-use openai_api_rust::embeddings::EmbeddingsApi;
-use openai_api_rust::*;
+use serde_json::{json, Value};
 
-use super::{Client, Message, Options};
+use super::{tool_call_to_invocation, ChatResponse, Client, Message, Options, TokenUsage};
+use crate::agent::rag;
 
+const DEFAULT_BASE_URL: &str = "https://api.openai.com/v1";
+
+// client for any OpenAI compatible chat completions API
 pub struct OpenAIClient {
     model: String,
-    client: OpenAI,
+    base_url: String,
+    api_key: String,
+    client: reqwest::Client,
+}
+
+impl OpenAIClient {
+    pub fn custom(base_url: &str, api_key: &str, model_name: &str) -> Self {
+        let base_url = base_url.trim_end_matches('/').to_string();
+        let api_key = api_key.to_string();
+        let model = model_name.to_string();
+        let client = reqwest::Client::new();
+
+        Self {
+            model,
+            base_url,
+            api_key,
+            client,
+        }
+    }
+
+    async fn post(&self, endpoint: &str, body: &Value) -> Result<Value> {
+        let mut request = self.client.post(format!("{}/{}", &self.base_url, endpoint));
+        // local servers usually don't need a key
+        if !self.api_key.is_empty() {
+            request = request.bearer_auth(&self.api_key);
+        }
+        let resp = request.json(body).send().await?;
+
+        let status = resp.status();
+        let text = resp.text().await?;
+        if !status.is_success() {
+            // try to report the error message only
+            let error = serde_json::from_str::<Value>(&text)
+                .ok()
+                .and_then(|v| v["error"]["message"].as_str().map(|s| s.to_string()))
+                .unwrap_or(text);
+
+            return Err(anyhow!("{}: {}", status, error));
+        }
+
+        Ok(serde_json::from_str(&text)?)
+    }
+}
+
+// resolve the base url of the api from the generator string, either the public OpenAI api, a
+// full base url or a host and port of a local OpenAI compatible server
+fn base_url(url: &str, port: u16) -> Result<String> {
+    if url.is_empty() {
+        return Ok(DEFAULT_BASE_URL.to_string());
+    }
+
+    let raw = if url.contains("://") {
+        url.to_string()
+    } else {
+        format!("http://{}:{}/v1", url, port)
+    };
+
+    let parsed = reqwest::Url::parse(&raw)
+        .map_err(|e| anyhow!("invalid OpenAI compatible base url '{}': {}", raw, e))?;
+    if parsed.scheme() != "http" && parsed.scheme() != "https" {
+        return Err(anyhow!(
+            "invalid OpenAI compatible base url '{}': scheme must be http or https",
+            raw
+        ));
+    } else if parsed.host_str().is_none() {
+        return Err(anyhow!(
+            "invalid OpenAI compatible base url '{}': missing host",
+            raw
+        ));
+    }
+
+    Ok(raw.trim_end_matches('/').to_string())
 }
 
 #[async_trait]
 impl Client for OpenAIClient {
-    fn new(_: &str, _: u16, model_name: &str, _: u32) -> anyhow::Result<Self>
+    fn new(url: &str, port: u16, model_name: &str, _: u32) -> anyhow::Result<Self>
     where
         Self: Sized,
     {
-        // Load API key from environment OPENAI_API_KEY.
-        // You can also hadcode through `Auth::new(<your_api_key>)`, but it is not recommended.
-        let auth = Auth::from_env().map_err(|e| anyhow!(e))?;
-        let client = OpenAI::new(auth, "https://api.openai.com/v1/");
-        let model = model_name.to_string();
+        let base_url = base_url(url, port)?;
+        let api_key = if base_url == DEFAULT_BASE_URL {
+            std::env::var("OPENAI_API_KEY")
+                .map_err(|_| anyhow!("Missing OPENAI_API_KEY".to_string()))?
+        } else {
+            // optional for custom endpoints
+            std::env::var("OPENAI_API_KEY").unwrap_or_default()
+        };
+
+        Ok(Self::custom(&base_url, &api_key, model_name))
+    }
 
-        Ok(Self { model, client })
+    fn supports_tools(&self) -> bool {
+        true
     }
 
-    async fn chat(&self, options: &Options) -> anyhow::Result<String> {
+    async fn chat(&self, options: &Options) -> anyhow::Result<ChatResponse> {
         let mut chat_history = vec![
-            openai_api_rust::Message {
-                role: Role::System,
-                content: options.system_prompt.trim().to_string(),
-            },
-            openai_api_rust::Message {
-                role: Role::User,
-                content: options.prompt.trim().to_string(),
-            },
+            json!({"role": "system", "content": options.system_prompt.trim()}),
+            json!({"role": "user", "content": options.prompt.trim()}),
         ];
 
         for m in &options.history {
             chat_history.push(match m {
-                Message::Agent(data, _) => openai_api_rust::Message {
-                    role: Role::Assistant,
-                    content: data.trim().to_string(),
-                },
-                Message::Feedback(data, _) => openai_api_rust::Message {
-                    role: Role::User,
-                    content: data.trim().to_string(),
-                },
+                Message::Agent(data, _) => json!({"role": "assistant", "content": data.trim()}),
+                Message::Feedback(data, _) => json!({"role": "user", "content": data.trim()}),
             });
         }
 
-        let body = ChatBody {
-            model: self.model.to_string(),
-            max_tokens: None,
-            temperature: None,
-            top_p: None,
-            n: None,
-            stream: Some(false),
-            stop: None,
-            presence_penalty: None,
-            frequency_penalty: None,
-            logit_bias: None,
-            user: None,
-            messages: chat_history,
+        let mut body = json!({
+            "model": &self.model,
+            "stream": false,
+            "messages": chat_history,
+        });
+        if let Some(temperature) = options.generation.temperature {
+            body["temperature"] = json!(temperature);
+        }
+        if let Some(top_p) = options.generation.top_p {
+            body["top_p"] = json!(top_p);
+        }
+        if let Some(seed) = options.generation.seed {
+            body["seed"] = json!(seed);
+        }
+        if !options.tools.is_empty() {
+            body["tools"] = options
+                .tools
+                .iter()
+                .map(|tool| {
+                    json!({
+                        "type": "function",
+                        "function": {
+                            "name": tool.name,
+                            "description": tool.description,
+                            "parameters": tool.parameters,
+                        }
+                    })
+                })
+                .collect();
+        }
+
+        let resp = match self.post("chat/completions", &body).await {
+            Ok(resp) => resp,
+            Err(error) => {
+                return if self.check_rate_limit(&error.to_string()).await {
+                    self.chat(options).await
+                } else {
+                    Err(error)
+                };
+            }
         };
-        let resp = self.client.chat_completion_create(&body);
-
-        if let Err(error) = resp {
-            return if self.check_rate_limit(&error.to_string()).await {
-                self.chat(options).await
-            } else {
-                Err(anyhow!(error))
-            };
+
+        let message = &resp["choices"][0]["message"];
+        let content = message["content"].as_str().unwrap_or("").to_string();
+
+        // native function calling models return structured calls instead of text
+        let mut invocations = vec![];
+        if let Some(tool_calls) = message["tool_calls"].as_array() {
+            for call in tool_calls {
+                let function = &call["function"];
+                let name = function["name"].as_str().unwrap_or("");
+                let arguments = function["arguments"].as_str().unwrap_or("{}");
+
+                match tool_call_to_invocation(name, arguments) {
+                    Ok(invocation) => invocations.push(invocation),
+                    Err(err) => log::error!("can't parse tool call {:?}: {}", call, err),
+                }
+            }
         }
 
-        let choice = resp.unwrap().choices;
-        let message = &choice[0].message.as_ref().unwrap();
+        let usage = &resp["usage"];
+        let usage = usage["prompt_tokens"]
+            .as_u64()
+            .zip(usage["completion_tokens"].as_u64())
+            .map(|(prompt, completion)| TokenUsage::new(prompt, completion));
 
-        Ok(message.content.to_string())
+        Ok(ChatResponse {
+            content,
+            invocations,
+            usage,
+        })
     }
 }
 
 #[async_trait]
-impl mini_rag::Embedder for OpenAIClient {
-    async fn embed(&self, text: &str) -> Result<mini_rag::Embeddings> {
-        let body = embeddings::EmbeddingsBody {
-            model: self.model.to_string(),
-            input: vec![text.to_string()],
-            user: None,
+impl rag::Embedder for OpenAIClient {
+    async fn embed(&self, text: &str) -> Result<rag::Embeddings> {
+        let body = json!({
+            "model": &self.model,
+            "input": [text],
+        });
+
+        let resp = match self.post("embeddings", &body).await {
+            Ok(resp) => resp,
+            Err(error) => {
+                return if self.check_rate_limit(&error.to_string()).await {
+                    self.embed(text).await
+                } else {
+                    Err(error)
+                };
+            }
+        };
+
+        Ok(resp["data"][0]["embedding"]
+            .as_array()
+            .map(|values| values.iter().filter_map(|v| v.as_f64()).collect())
+            .unwrap_or_default())
+    }
+
+    async fn embed_batch(&self, texts: &[String]) -> Result<Vec<rag::Embeddings>> {
+        let body = json!({
+            "model": &self.model,
+            "input": texts,
+        });
+
+        let resp = match self.post("embeddings", &body).await {
+            Ok(resp) => resp,
+            Err(error) => {
+                return if self.check_rate_limit(&error.to_string()).await {
+                    self.embed_batch(texts).await
+                } else {
+                    Err(error)
+                };
+            }
         };
-        let resp = self.client.embeddings_create(&body);
-        if let Err(error) = resp {
-            return if self.check_rate_limit(&error.to_string()).await {
-                self.embed(text).await
-            } else {
-                Err(anyhow!(error))
-            };
+
+        // results are not guaranteed to be in the same order as the inputs
+        let mut embeddings = vec![vec![]; texts.len()];
+        for item in resp["data"].as_array().unwrap_or(&vec![]) {
+            let index = item["index"].as_u64().unwrap_or(0) as usize;
+            if let Some(slot) = embeddings.get_mut(index) {
+                *slot = item["embedding"]
+                    .as_array()
+                    .map(|values| values.iter().filter_map(|v| v.as_f64()).collect())
+                    .unwrap_or_default();
+            }
         }
 
-        let embeddings = resp.unwrap().data;
-        let embedding = embeddings.as_ref().unwrap().first().unwrap();
+        Ok(embeddings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::{generator::Tool, Invocation};
+
+    // one shot server answering with the given body, returns the request it received
+    async fn mock_server(body: &'static str) -> (u16, tokio::task::JoinHandle<String>) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let handle = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+
+            // read the headers and then as much body as they announce
+            let mut request = vec![];
+            let mut buf = [0u8; 4096];
+            loop {
+                let n = stream.read(&mut buf).await.unwrap();
+                request.extend_from_slice(&buf[..n]);
+                let text = String::from_utf8_lossy(&request).to_string();
+                if let Some((headers, body)) = text.split_once("\r\n\r\n") {
+                    let length = headers
+                        .lines()
+                        .find_map(|line| {
+                            line.to_lowercase()
+                                .strip_prefix("content-length:")
+                                .map(|v| v.trim().parse::<usize>().unwrap())
+                        })
+                        .unwrap_or(0);
+                    if body.len() >= length {
+                        break;
+                    }
+                }
+                if n == 0 {
+                    break;
+                }
+            }
+
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            stream.write_all(response.as_bytes()).await.unwrap();
+
+            String::from_utf8_lossy(&request).to_string()
+        });
+
+        (port, handle)
+    }
+
+    #[tokio::test]
+    async fn test_tool_calls() {
+        let (port, server) = mock_server(
+            r#"{"choices": [{"message": {"content": null, "tool_calls": [{"id": "call_0", "type": "function", "function": {"name": "save-memory", "arguments": "{\"key\": \"note\", \"payload\": \"hello\"}"}}]}}]}"#,
+        )
+        .await;
+
+        let client = OpenAIClient::custom(&format!("http://127.0.0.1:{port}/v1"), "", "test");
+        assert!(client.supports_tools());
+
+        let mut options = Options::new("system".to_string(), "prompt".to_string(), vec![]);
+        options.tools = vec![Tool {
+            name: "save-memory".to_string(),
+            description: "save a memory".to_string(),
+            parameters: serde_json::json!({"type": "object"}),
+        }];
+
+        let response = client.chat(&options).await.unwrap();
+        assert_eq!(
+            response.invocations,
+            vec![Invocation::new(
+                "save-memory".to_string(),
+                Some([("key".to_string(), "note".to_string())].into()),
+                Some("hello".to_string())
+            )]
+        );
+
+        // the tools were advertised as functions
+        let request = server.await.unwrap();
+        let (_, body) = request.split_once("\r\n\r\n").unwrap();
+        let body: Value = serde_json::from_str(body).unwrap();
+        assert_eq!(body["tools"][0]["type"], "function");
+        assert_eq!(body["tools"][0]["function"]["name"], "save-memory");
+    }
 
-        Ok(mini_rag::Embeddings::from(
-            embedding.embedding.as_ref().unwrap_or(&vec![]).clone(),
-        ))
+    #[test]
+    fn test_base_url() {
+        assert_eq!(base_url("", 0).unwrap(), DEFAULT_BASE_URL);
+        assert_eq!(
+            base_url("http://localhost:8080/v1/", 0).unwrap(),
+            "http://localhost:8080/v1"
+        );
+        assert_eq!(
+            base_url("localhost", 1234).unwrap(),
+            "http://localhost:1234/v1"
+        );
+        assert!(base_url("ftp://localhost/v1", 0).is_err());
+        assert!(base_url("http://", 0).is_err());
     }
 }
@@ -0,0 +1,120 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+lazy_static! {
+    // http status as reported by the clients, either "429 Too Many Requests: ..." or "status code 503"
+    static ref STATUS_PARSER: Regex = Regex::new(r"(?i)(^|status( code)?:?\s*)([1-5]\d\d)\b").unwrap();
+}
+
+// messages of errors that are worth retrying
+const TRANSIENT_ERRORS: &[&str] = &[
+    "timed out",
+    "timeout",
+    "connection reset",
+    "connection refused",
+    "connection closed",
+    "error sending request",
+    "broken pipe",
+    "overloaded",
+    "rate limit",
+    "temporarily unavailable",
+];
+
+// how failed generations are retried
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    // total number of attempts, 1 to never retry
+    pub max_attempts: usize,
+    // delay before the first retry
+    pub base_delay: Duration,
+    // factor the delay is multiplied by at every retry
+    pub multiplier: f64,
+    // random fraction of the delay added or removed, from 0.0 to 1.0
+    pub jitter: f64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay: Duration::from_secs(1),
+            multiplier: 2.0,
+            jitter: 0.2,
+        }
+    }
+}
+
+impl RetryPolicy {
+    // timeouts, connection errors, rate limits and server errors can be retried, while other
+    // client errors such as invalid requests or authentication failures can't
+    pub fn is_retryable(&self, error: &anyhow::Error) -> bool {
+        let message = format!("{:#}", error);
+        if let Some(caps) = STATUS_PARSER.captures(&message) {
+            let status: u16 = caps.get(3).unwrap().as_str().parse().unwrap();
+            return status == 408 || status == 429 || status >= 500;
+        }
+
+        let message = message.to_lowercase();
+        TRANSIENT_ERRORS.iter().any(|e| message.contains(e))
+    }
+
+    // delay before the given retry, starting from 1
+    pub fn delay(&self, retry: usize) -> Duration {
+        let exp = self
+            .multiplier
+            .max(1.0)
+            .powi(retry.saturating_sub(1).min(16) as i32);
+        let delay = self.base_delay.as_secs_f64() * exp;
+
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        let delay = if jitter > 0.0 {
+            // good enough source of randomness for this
+            let nanos = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.subsec_nanos())
+                .unwrap_or(0);
+            let random = (nanos % 1000) as f64 / 1000.0;
+            delay * (1.0 + jitter * (2.0 * random - 1.0))
+        } else {
+            delay
+        };
+
+        Duration::from_secs_f64(delay.max(0.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_retryable() {
+        let policy = RetryPolicy::default();
+
+        assert!(policy.is_retryable(&anyhow!("429 Too Many Requests: slow down")));
+        assert!(policy.is_retryable(&anyhow!("503 Service Unavailable: overloaded")));
+        assert!(policy.is_retryable(&anyhow!("error sending request for url")));
+        assert!(policy.is_retryable(&anyhow!("operation timed out")));
+        assert!(!policy.is_retryable(&anyhow!("401 Unauthorized: invalid api key")));
+        assert!(!policy.is_retryable(&anyhow!("400 Bad Request: rate limit field unknown")));
+        assert!(!policy.is_retryable(&anyhow!("model not found")));
+    }
+
+    #[test]
+    fn test_delay() {
+        let policy = RetryPolicy {
+            jitter: 0.0,
+            ..Default::default()
+        };
+
+        assert_eq!(policy.delay(1), Duration::from_secs(1));
+        assert_eq!(policy.delay(2), Duration::from_secs(2));
+        assert_eq!(policy.delay(3), Duration::from_secs(4));
+
+        let policy = RetryPolicy::default();
+        let delay = policy.delay(2);
+        assert!(delay >= Duration::from_secs_f64(1.6) && delay <= Duration::from_secs_f64(2.4));
+    }
+}
@@ -1,22 +1,32 @@
-use std::{collections::HashMap, sync::Arc, time::Duration};
+use std::{collections::HashMap, path::PathBuf, sync::Arc, time::Duration};
 
 use anyhow::Result;
-use mini_rag::Embedder;
 use serde::{Deserialize, Serialize};
 
 use events::Event;
-use generator::{Client, Options};
-use namespaces::Action;
-use serialization::xml::serialize;
-use state::{SharedState, State};
+use generator::{
+    retry::RetryPolicy, ChatResponse, Client, GenerationConfig, Message, Options, TokenPrices,
+    TokenUsage, Tool, UsageSummary,
+};
+use namespaces::{Action, ActionOutput};
+use rag::Embedder;
+use serialization::xml::{
+    parsing::{DuplicateAttributes, OnParseError, StreamParser},
+    serialize,
+};
+use state::{secrets::Secrets, truncation::Truncation, OnLoop, OnMaxSteps, SharedState, State};
 use task::Task;
 
+pub mod cancellation;
+pub mod ensemble;
 pub mod events;
 pub mod generator;
 pub mod namespaces;
+pub mod rag;
 pub mod serialization;
 pub mod state;
 pub mod task;
+pub mod tokenizer;
 
 #[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Invocation {
@@ -53,26 +63,179 @@ impl Invocation {
     }
 }
 
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    // maximum number of steps or 0 for no limit
+    pub max_iterations: usize,
+    // what to do when max_iterations is reached
+    pub on_max_iterations: OnMaxSteps,
+    // maximum duration of the run
+    pub max_duration: Option<Duration>,
+    // maximum total cost of the executed actions or 0 for no limit
+    pub max_cost: u32,
+    // number of reasoning only steps that don't count against max_iterations
+    pub reasoning_budget: usize,
+    // maximum number of consecutive responses without actions or 0 for no limit
+    pub max_unparsed: usize,
+    // number of recent invocations considered for loop detection
+    pub loop_window: usize,
+    // maximum executions of the same invocation within loop_window or 0 for no limit
+    pub loop_threshold: usize,
+    // what to do when a loop is detected
+    pub on_loop: OnLoop,
+    // maximum length of an action result in the history or 0 for no limit
+    pub max_result_length: usize,
+    // query the generator for the model context window instead of using the configured one
+    pub detect_context_window: bool,
+    // approximate maximum number of tokens of the chat history or 0 for no limit
+    pub max_history_tokens: usize,
+    // measures the history against max_history_tokens, estimated if not set
+    pub tokenizer: Option<Arc<dyn tokenizer::Tokenizer>>,
+    // summarize the oldest executions when the history is longer than this or 0 to never
+    pub summarize_after: usize,
+    // what part of oversized results to keep
+    pub truncation: Truncation,
+    // secrets that actions can reference by name
+    pub secrets: Secrets,
+    // advertise the actions as tools to backends supporting them
+    pub native_tools: bool,
+    // contents of a custom system prompt template
+    pub system_prompt_template: Option<String>,
+    // how the available actions are described in the system prompt
+    pub actions_style: serialization::ActionsStyle,
+    // how actions are described to the model and parsed from its responses
+    pub invocation_format: serialization::InvocationFormat,
+    // how to handle attributes specified more than once
+    pub duplicate_attributes: DuplicateAttributes,
+    // what to do with responses that can't be parsed
+    pub on_parse_error: OnParseError,
+    // number of responses to generate at each step
+    pub ensemble_size: usize,
+    // stream the response while it's generated
+    pub stream: bool,
+    // how failed generations are retried
+    pub retry_policy: RetryPolicy,
+    // run consecutive side effect free actions concurrently
+    pub parallel_actions: bool,
+    // maximum duration of a single generation attempt
+    pub generation_timeout: Option<Duration>,
+    // price per 1000 tokens used to estimate the cost of the run
+    pub token_prices: Option<TokenPrices>,
+    // sampling parameters
+    pub generation: GenerationConfig,
+    // how to select the invocations among the ensemble responses
+    pub ensemble_strategy: ensemble::Strategy,
+    // save the state to this file after every step
+    pub checkpoint: Option<PathBuf>,
+    // write the transcript of the executions to this file at the end of the run
+    pub transcript: Option<PathBuf>,
+    // how the transcript is rendered
+    pub transcript_format: state::history::TranscriptFormat,
+    // override the sampling, retry and concurrency settings for reproducible runs
+    pub deterministic: bool,
+}
+
+// used in deterministic mode when no seed is specified
+const DETERMINISTIC_SEED: i32 = 42;
+
+impl Config {
+    // greedy sampling with a fixed seed, retries without jitter and actions executed one at a
+    // time in the order they were returned. What remains out of our control: backends ignoring
+    // the seed or batching requests nondeterministically, the wall clock (max_duration, timeouts
+    // and the time namespace) and side effects of the actions themselves.
+    fn make_deterministic(&mut self) {
+        self.generation.temperature = Some(0.0);
+        self.generation.seed = Some(self.generation.seed.unwrap_or(DETERMINISTIC_SEED));
+        self.retry_policy.jitter = 0.0;
+        self.parallel_actions = false;
+    }
+}
+
+const SUMMARY_PROMPT: &str = include_str!("summary.prompt");
+
+// compare values regardless of case and spacing
+fn normalize_example(value: &str) -> String {
+    value
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+// numbers, booleans and short words in the examples are often legit values as well
+fn is_placeholder(value: &str) -> bool {
+    value.len() > 4 && value.chars().any(|c| c.is_alphabetic()) && value != "false"
+}
+
+// returns the placeholder if the model copied the action example instead of using actual
+// values, that is if the payload and attributes it passed all match the example ones
+#[allow(clippy::borrowed_box)]
+fn parroted_example(invocation: &Invocation, action: &Box<dyn Action>) -> Option<String> {
+    let mut compared = vec![];
+
+    if let (Some(payload), Some(example)) = (&invocation.payload, action.example_payload()) {
+        compared.push((normalize_example(payload), normalize_example(example)));
+    }
+
+    if let (Some(attrs), Some(examples)) = (&invocation.attributes, action.attributes()) {
+        for (name, example) in &examples {
+            if let Some(value) = attrs.get(name) {
+                compared.push((normalize_example(value), normalize_example(example)));
+            }
+        }
+    }
+
+    if compared.iter().all(|(value, example)| value == example) {
+        compared
+            .into_iter()
+            .map(|(value, _)| value)
+            .find(|value| is_placeholder(value))
+    } else {
+        None
+    }
+}
+
 pub struct Agent {
     events_chan: events::Sender,
     generator: Box<dyn Client>,
     state: SharedState,
     max_history: u16,
     task_timeout: Option<Duration>,
+    config: Config,
+    unparsed_in_a_row: usize,
+    observer: Option<Box<dyn events::Observer>>,
+    // set to stop the run before the next step or invocation
+    cancellation: cancellation::CancellationToken,
+    // set once the observer has been told about the completion
+    completion_observed: bool,
 }
 
 impl Agent {
     pub async fn new(
         events_chan: events::Sender,
-        generator: Box<dyn Client>,
+        mut generator: Box<dyn Client>,
         embedder: Box<dyn Embedder>,
         task: Box<dyn Task>,
-        max_iterations: usize,
+        mut config: Config,
+        // notified of the progress of the run, for programmatic consumers
+        observer: Option<Box<dyn events::Observer>>,
     ) -> Result<Self> {
+        if config.deterministic {
+            config.make_deterministic();
+        }
+
+        if config.detect_context_window {
+            match generator.detect_context_window().await {
+                Ok(Some(size)) => log::info!("using a context window of {} tokens", size),
+                Ok(None) => log::debug!("context window detection not supported"),
+                Err(e) => log::warn!("can't detect the context window, using the default: {}", e),
+            }
+        }
+
         let max_history = task.max_history_visibility();
         let task_timeout = task.get_timeout();
         let state = Arc::new(tokio::sync::Mutex::new(
-            State::new(events_chan.clone(), task, embedder, max_iterations).await?,
+            State::new(events_chan.clone(), task, embedder, &config).await?,
         ));
 
         Ok(Self {
@@ -81,14 +244,51 @@ impl Agent {
             state,
             max_history,
             task_timeout,
+            config,
+            unparsed_in_a_row: 0,
+            observer,
+            cancellation: cancellation::CancellationToken::default(),
+            completion_observed: false,
         })
     }
 
+    pub fn cancellation_token(&self) -> cancellation::CancellationToken {
+        self.cancellation.clone()
+    }
+
+    // set the task as complete if the run has been cancelled, returns true if so
+    async fn on_cancellation(&self) -> Result<bool> {
+        if !self.cancellation.is_cancelled() {
+            return Ok(false);
+        }
+
+        let mut state = self.state.lock().await;
+        if !state.is_complete() {
+            log::warn!("run cancelled");
+            state.on_complete(false, Some("cancelled".to_string()))?;
+        }
+        Ok(true)
+    }
+
+    // tell the observer about the completion, once
+    async fn observe_completion(&mut self) {
+        if let Some(observer) = &self.observer {
+            if !self.completion_observed {
+                if let Some((impossible, reason)) = self.state.lock().await.get_completion() {
+                    observer.on_complete(impossible, reason.as_deref());
+                    self.completion_observed = true;
+                }
+            }
+        }
+    }
+
     #[allow(clippy::borrowed_box)]
     pub fn validate(&self, invocation: &Invocation, action: &Box<dyn Action>) -> Result<()> {
         // validate prerequisites
         let payload_required = action.example_payload().is_some();
-        let attrs_required = action.attributes().is_some();
+        let required_attrs = action.required_attributes();
+        let attrs_required = action.attributes().is_some() || !required_attrs.is_empty();
+        let attrs_allowed = attrs_required || action.optional_attributes().is_some();
         let has_payload = invocation.payload.is_some();
         let has_attributes = invocation.attributes.is_some();
 
@@ -101,13 +301,14 @@ impl Agent {
         } else if attrs_required && !has_attributes {
             // attributes required and not specified at all
             return Err(anyhow!(
-                "no xml attributes specified for '{}'",
-                invocation.action
+                "no xml attributes specified for '{}', the required attributes are: {}",
+                invocation.action,
+                required_attrs.join(", ")
             ));
         } else if !payload_required && has_payload {
             // payload not required but specified
             return Err(anyhow!("no xml content needed for '{}'", invocation.action));
-        } else if !attrs_required && has_attributes {
+        } else if !attrs_allowed && has_attributes {
             // attributes not required but specified
             return Err(anyhow!(
                 "no xml attributes needed for '{}'",
@@ -115,33 +316,27 @@ impl Agent {
             ));
         }
 
-        if attrs_required {
-            // validate each required attribute
-            let required_attrs: Vec<String> = action
-                .attributes()
-                .unwrap()
-                .keys()
-                .map(|s| s.to_owned())
-                .collect();
-            let passed_attrs: Vec<String> = invocation
-                .clone()
-                .attributes
-                .unwrap()
-                .keys()
-                .map(|s| s.to_owned())
-                .collect();
-
-            for required in required_attrs {
-                if !passed_attrs.contains(&required) {
-                    return Err(anyhow!(
-                        "no '{}' xml attribute specified for '{}'",
-                        required,
-                        invocation.action
-                    ));
-                }
+        // validate each required attribute
+        let passed_attrs = invocation.attributes.clone().unwrap_or_default();
+        for required in &required_attrs {
+            if !passed_attrs.contains_key(required) {
+                return Err(anyhow!(
+                    "no '{}' xml attribute specified for '{}', the required attributes are: {}",
+                    required,
+                    invocation.action,
+                    required_attrs.join(", ")
+                ));
             }
         }
 
+        if let Some(example) = parroted_example(invocation, action) {
+            return Err(anyhow!(
+                "'{}' is just the example value of '{}', use the actual value instead",
+                example,
+                invocation.action
+            ));
+        }
+
         Ok(())
     }
 
@@ -157,7 +352,7 @@ impl Agent {
                 .state
                 .lock()
                 .await
-                .to_chat_history(self.max_history as usize)?;
+                .to_chat_history(self.max_history as usize, self.config.max_history_tokens)?;
         }
 
         self.on_event(events::Event::StateUpdate(opts))
@@ -172,13 +367,18 @@ impl Agent {
         self.on_event(events::Event::EmptyResponse).unwrap();
     }
 
-    async fn on_invalid_response(&self, response: &str) {
+    async fn on_invalid_response(&self, response: &str, parse_error: Option<String>) {
         let mut mut_state = self.state.lock().await;
         mut_state.metrics.errors.unparsed_responses += 1;
-        mut_state.add_unparsed_response_to_history(
-        response,
-        "I could not parse any valid actions from your response, please correct it according to the instructions.".to_string(),
-    );
+        // if there isn't even a tag in the response, the model is just talking
+        let error = if let Some(parse_error) = parse_error {
+            format!("Your response is not valid ({parse_error}), please correct it according to the instructions.")
+        } else if response.contains('<') {
+            "I could not parse any valid actions from your response, please correct it according to the instructions.".to_string()
+        } else {
+            "You explained but took no action, you must respond with an action using the required format.".to_string()
+        };
+        mut_state.add_unparsed_response_to_history(response, error);
         self.on_event(events::Event::InvalidResponse(response.to_string()))
             .unwrap();
     }
@@ -193,19 +393,47 @@ impl Agent {
         // tell the model that the action name is wrong
         let name = invocation.action.clone();
 
-        mut_state.add_error_to_history(
-            invocation.clone(),
-            error
-                .clone()
-                .unwrap_or(format!("'{name}' is not a valid action name")),
+        let error = error.unwrap_or_else(|| {
+            format!(
+                "action '{name}' is not available; available actions: {}",
+                mut_state.get_action_names().join(", ")
+            )
+        });
+
+        mut_state.add_error_to_history(invocation.clone(), error.clone());
+
+        self.on_event(events::Event::InvalidAction {
+            invocation,
+            error: Some(error),
+        })
+        .unwrap();
+    }
+
+    async fn on_looping_action(&self, invocation: Invocation) -> Result<()> {
+        let mut mut_state = self.state.lock().await;
+        mut_state.metrics.errors.looping_actions += 1;
+
+        let error = format!(
+            "'{}' has already been executed with the same arguments {} times in the last {} actions, you are repeating yourself, try a different approach",
+            invocation.action, self.config.loop_threshold, self.config.loop_window
         );
+        if self.config.on_loop == OnLoop::Abort {
+            return Err(anyhow!("loop detected: {}", invocation));
+        }
 
-        self.on_event(events::Event::InvalidAction { invocation, error })
-            .unwrap();
+        // tell the model it's going in circles
+        mut_state.add_error_to_history(invocation.clone(), error.clone());
+
+        self.on_event(events::Event::InvalidAction {
+            invocation,
+            error: Some(error),
+        })
     }
 
-    async fn on_valid_action(&self) {
-        self.state.lock().await.metrics.valid_actions += 1;
+    async fn on_valid_action(&self, cost: u32) {
+        let mut mut_state = self.state.lock().await;
+        mut_state.metrics.valid_actions += 1;
+        mut_state.metrics.cost += cost;
     }
 
     async fn on_timed_out_action(&self, invocation: Invocation, start: &std::time::Instant) {
@@ -213,6 +441,9 @@ impl Agent {
         mut_state.metrics.errors.timedout_actions += 1;
         // tell the model about the timeout
         mut_state.add_error_to_history(invocation.clone(), "action timed out".to_string());
+        if let Some(observer) = &self.observer {
+            observer.on_action_result(&invocation, None, Some("action timed out"));
+        }
 
         self.events_chan
             .send(events::Event::ActionTimeout {
@@ -225,32 +456,63 @@ impl Agent {
     async fn on_executed_action(
         &self,
         invocation: Invocation,
-        ret: Result<Option<String>>,
+        ret: Result<ActionOutput>,
         start: &std::time::Instant,
     ) {
+        // unsuccessful outputs are reported to the model as errors
+        let (ret, data) = match ret {
+            Ok(output) if !output.success => {
+                let error = output
+                    .to_text()
+                    .unwrap_or_else(|| "action failed".to_string());
+                (Err(anyhow!(error)), output.data)
+            }
+            Ok(output) => (Ok(output.to_text()), output.data),
+            Err(err) => (Err(err), None),
+        };
+
         let mut mut_state = self.state.lock().await;
+        let data = data.map(|data| mut_state.redact_data(data));
         let mut error = None;
         let mut result = None;
 
         if let Err(err) = ret {
             mut_state.metrics.errors.errored_actions += 1;
-            // tell the model about the error
-            mut_state.add_error_to_history(invocation.clone(), err.to_string());
+            // tell the model about the error, cut to size if needed
+            let err = mut_state.redact(err.to_string());
+            let truncated = self
+                .config
+                .truncation
+                .apply(err.clone(), self.config.max_result_length);
+            mut_state.add_error_to_history(invocation.clone(), truncated);
 
-            error = Some(err.to_string());
+            error = Some(err);
         } else {
-            let ret = ret.unwrap();
+            let ret = ret.unwrap().map(|out| mut_state.redact(out));
             mut_state.metrics.success_actions += 1;
-            // tell the model about the output
-            mut_state.add_success_to_history(invocation.clone(), ret.clone());
+            // tell the model about the output, cut to size if needed
+            let truncated = ret.clone().map(|out| {
+                self.config
+                    .truncation
+                    .apply(out, self.config.max_result_length)
+            });
+            mut_state.add_success_to_history(invocation.clone(), truncated, data.clone());
 
             result = ret;
         }
 
+        if let Some(observer) = &self.observer {
+            observer.on_action_result(&invocation, result.as_deref(), error.as_deref());
+            if let Some(data) = &data {
+                observer.on_action_data(&invocation, data);
+            }
+        }
+
         self.on_event(events::Event::ActionExecuted {
             invocation,
             result,
             error,
+            data,
             elapsed: start.elapsed(),
         })
         .unwrap();
@@ -260,90 +522,564 @@ impl Agent {
         self.state.lock().await.metrics.clone()
     }
 
-    async fn prepare_step(&mut self) -> Result<Options> {
+    // tokens consumed so far and their estimated cost
+    pub async fn usage(&self) -> UsageSummary {
+        let tokens = self.state.lock().await.metrics.tokens;
+        UsageSummary {
+            tokens,
+            cost: self.config.token_prices.map(|prices| tokens.cost(&prices)),
+        }
+    }
+
+    async fn on_token_usage(&self, usage: Option<TokenUsage>) {
+        if let Some(usage) = usage {
+            self.state.lock().await.metrics.tokens.add(&usage);
+        }
+    }
+
+    // compress the oldest half of the history into a summary the model sees in its system prompt
+    async fn summarize_history(&self) -> Result<()> {
+        let (count, prompt) = {
+            let state = self.state.lock().await;
+            let len = state.history_len();
+            if self.config.summarize_after == 0 || len <= self.config.summarize_after {
+                return Ok(());
+            }
+
+            let count = len - (self.config.summarize_after / 2).max(1);
+            let mut prompt = String::new();
+            if let Some(summary) = state.get_history_summary() {
+                prompt += &format!("Previous summary:\n\n{}\n\n", summary);
+            }
+            prompt += &format!("Actions:\n\n{}", state.oldest_history_transcript(count));
+
+            (count, prompt)
+        };
+
+        let mut options = Options::new(SUMMARY_PROMPT.to_string(), prompt, vec![]);
+        options.generation = self.config.generation.clone();
+
+        // not being able to summarize is not fatal, the history is just kept as it is
+        match self.generate(&options).await {
+            Ok(response) if !response.content.trim().is_empty() => {
+                log::info!("summarized the oldest {} actions", count);
+                self.state
+                    .lock()
+                    .await
+                    .on_history_summarized(count, &response.content)
+            }
+            Ok(_) => {
+                log::warn!("the model returned an empty summary of the history");
+                Ok(())
+            }
+            Err(error) => {
+                log::warn!("can't summarize the history: {}", error);
+                Ok(())
+            }
+        }
+    }
+
+    async fn prepare_step(&mut self) -> Result<Option<Options>> {
         let mut mut_state = self.state.lock().await;
 
-        mut_state.on_step()?;
+        if !mut_state.on_step()? {
+            return Ok(None);
+        }
+
+        let system_prompt = serialization::state_to_system_prompt(&mut_state)?;
+        let fingerprint = serialization::system_prompt_fingerprint(&system_prompt);
+        if mut_state.metrics.system_prompt_fingerprint.as_ref() == Some(&fingerprint) {
+            mut_state.metrics.system_prompt_reuses += 1;
+        }
+        mut_state.metrics.system_prompt_fingerprint = Some(fingerprint);
 
+        if let Some(observer) = &self.observer {
+            observer.on_step_start(mut_state.metrics.current_step, mut_state.metrics.max_steps);
+        }
+        self.on_event(events::Event::StepStarted {
+            step: mut_state.metrics.current_step,
+            max_steps: mut_state.metrics.max_steps,
+        })?;
         self.on_event(events::Event::MetricsUpdate(mut_state.metrics.clone()))?;
 
-        let system_prompt = serialization::state_to_system_prompt(&mut_state)?;
         let prompt = mut_state.to_prompt()?;
-        let history = mut_state.to_chat_history(self.max_history as usize)?;
-        let options = Options::new(system_prompt, prompt, history);
+        let mut history =
+            mut_state.to_chat_history(self.max_history as usize, self.config.max_history_tokens)?;
+        if mut_state.is_concluding() {
+            history.push(Message::Feedback(
+                "You are out of steps, summarize your findings and conclude the task.".to_string(),
+                None,
+            ));
+        }
+        let mut options = Options::new(system_prompt, prompt, history);
+        options.generation = self.config.generation.clone();
+        options.images = mut_state.take_images();
+        if self.config.native_tools && self.generator.supports_tools() {
+            options.tools = mut_state
+                .get_namespaces()
+                .iter()
+                .flat_map(|ns| ns.actions.iter().map(Tool::from_action))
+                .collect();
+        }
+
+        Ok(Some(options))
+    }
+
+    // use the structured tool calls if the backend returned any, or parse them from the text
+    fn parse_response(&self, response: ChatResponse) -> (String, Result<Vec<Invocation>>) {
+        let content = response.content.trim().to_string();
+        if !response.invocations.is_empty() {
+            (content, Ok(response.invocations))
+        } else {
+            let invocations = self.config.invocation_format.try_parse(
+                &content,
+                self.config.duplicate_attributes,
+                self.config.on_parse_error != OnParseError::Ignore,
+            );
+            (content, invocations)
+        }
+    }
+
+    // run model inference, retrying on transient errors according to the policy
+    async fn generate(&self, options: &Options) -> Result<ChatResponse> {
+        let response = self.with_retries(|| self.generator.chat(options)).await?;
+        self.on_token_usage(response.usage).await;
+        Ok(response)
+    }
+
+    // stream the response as events while parsing the invocations as soon as they are complete
+    async fn generate_stream(
+        &self,
+        options: &Options,
+    ) -> Result<(String, Result<Vec<Invocation>>)> {
+        self.with_retries(|| async {
+            let parsing = std::sync::Mutex::new((
+                StreamParser::new(
+                    self.config.duplicate_attributes,
+                    self.config.on_parse_error != OnParseError::Ignore,
+                ),
+                None,
+            ));
+
+            // json is only parsed once the response is complete
+            let incremental = self.config.invocation_format == serialization::InvocationFormat::Xml;
+
+            let on_chunk = |chunk: &str| {
+                let _ = self.on_event(events::Event::ResponseChunk(chunk.to_string()));
+
+                let mut parsing = parsing.lock().unwrap();
+                if incremental && parsing.1.is_none() {
+                    match parsing.0.push(chunk) {
+                        Ok(parsed) => {
+                            for inv in parsed {
+                                log::debug!("streamed {}", inv);
+                            }
+                        }
+                        Err(error) => parsing.1 = Some(error),
+                    }
+                }
+            };
+
+            let response = self.generator.chat_stream(options, &on_chunk).await?;
+            self.on_token_usage(response.usage).await;
+            let (parser, error) = parsing.into_inner().unwrap();
+            let content = response.content.trim().to_string();
+
+            Ok(if !response.invocations.is_empty() {
+                (content, Ok(response.invocations))
+            } else if let Some(error) = error {
+                (content, Err(error))
+            } else if !incremental {
+                let invocations = self.config.invocation_format.try_parse(
+                    &content,
+                    self.config.duplicate_attributes,
+                    self.config.on_parse_error != OnParseError::Ignore,
+                );
+                (content, invocations)
+            } else {
+                (content, parser.finish())
+            })
+        })
+        .await
+    }
+
+    async fn with_retries<T, F, Fut>(&self, mut generate: F) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: std::future::Future<Output = Result<T>>,
+    {
+        let policy = &self.config.retry_policy;
+        let mut attempt = 1;
+        loop {
+            let result = if let Some(timeout) = self.config.generation_timeout {
+                // the error message makes the timeout retryable
+                tokio::time::timeout(timeout, generate())
+                    .await
+                    .unwrap_or_else(|_| Err(anyhow!("generation timed out after {:?}", timeout)))
+            } else {
+                generate().await
+            };
+
+            match result {
+                Ok(response) => return Ok(response),
+                Err(error) if attempt < policy.max_attempts && policy.is_retryable(&error) => {
+                    let delay = policy.delay(attempt);
+                    log::warn!(
+                        "generation failed ({}), retrying in {:?} (attempt {}/{}) ...",
+                        error,
+                        delay,
+                        attempt + 1,
+                        policy.max_attempts
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                Err(error) => return Err(error),
+            }
+        }
+    }
+
+    // query the model multiple times in parallel and vote on the invocations to execute
+    async fn generate_ensemble(
+        &self,
+        options: &Options,
+    ) -> Result<(String, Result<Vec<Invocation>>)> {
+        // with a fixed seed every member would return the same response
+        let members: Vec<Options> = (0..self.config.ensemble_size)
+            .map(|idx| {
+                let mut options = options.clone();
+                options.generation.seed = options
+                    .generation
+                    .seed
+                    .map(|seed| seed.wrapping_add(idx as i32));
+                options
+            })
+            .collect();
+        let results =
+            futures::future::join_all(members.iter().map(|options| self.generate(options))).await;
+
+        let mut responses = vec![];
+        let mut candidates = vec![];
+        let mut parse_errors = vec![];
+        let mut last_error = None;
+        for ret in results {
+            match ret {
+                Ok(response) => {
+                    let (response, invocations) = self.parse_response(response);
+                    let invocations = match invocations {
+                        Ok(invocations) => invocations,
+                        Err(error) if self.config.on_parse_error == OnParseError::Abort => {
+                            return Err(error);
+                        }
+                        Err(error) => {
+                            parse_errors.push(error);
+                            vec![]
+                        }
+                    };
+                    responses.push(response);
+                    candidates.push(invocations);
+                }
+                Err(err) => {
+                    log::warn!("ensemble generation failed: {}", err);
+                    last_error = Some(err);
+                }
+            }
+        }
+
+        if responses.is_empty() {
+            return Err(last_error.unwrap());
+        }
 
-        Ok(options)
+        match ensemble::select(&candidates, self.config.ensemble_strategy) {
+            ensemble::Selection::Selected(idx) => {
+                Ok((responses.swap_remove(idx), Ok(candidates.swap_remove(idx))))
+            }
+            // let the usual invalid response logic handle it
+            ensemble::Selection::Empty => Ok((
+                responses.swap_remove(0),
+                parse_errors.into_iter().next().map_or(Ok(vec![]), Err),
+            )),
+            ensemble::Selection::NoConsensus => {
+                log::warn!("no consensus among {} responses", responses.len());
+                Ok((
+                    responses.swap_remove(0),
+                    Err(anyhow!(
+                        "{} attempts disagree on the actions to execute",
+                        candidates.len()
+                    )),
+                ))
+            }
+        }
     }
 
     pub fn on_event(&self, event: Event) -> Result<()> {
         self.events_chan.send(event).map_err(|e| anyhow!(e))
     }
 
+    // continue from the state saved by a previous run of the same task
+    pub async fn resume(&mut self, path: &std::path::Path) -> Result<()> {
+        let checkpoint = state::checkpoint::Checkpoint::load(path)?;
+
+        log::info!(
+            "resuming from step {} of {}",
+            checkpoint.current_step(),
+            path.display()
+        );
+
+        self.state.lock().await.restore(checkpoint)
+    }
+
+    async fn save_checkpoint(&self) -> Result<()> {
+        if let Some(path) = &self.config.checkpoint {
+            self.state.lock().await.to_checkpoint()?.save(path)?;
+        }
+        Ok(())
+    }
+
+    async fn save_transcript(&self) -> Result<()> {
+        if let Some(path) = &self.config.transcript {
+            let transcript = self
+                .state
+                .lock()
+                .await
+                .export_transcript(self.config.transcript_format);
+
+            std::fs::write(path, transcript)
+                .map_err(|e| anyhow!("can't write transcript to {}: {}", path.display(), e))?;
+        }
+        Ok(())
+    }
+
+    // keep going until the task is complete or a fatal error is reached, progress is reported
+    // as events on the channel
+    pub async fn run(&mut self) -> Result<()> {
+        while !self.is_done().await {
+            let mut ret = self.step().await;
+            if ret.is_ok() {
+                ret = self.save_checkpoint().await;
+            }
+
+            if let Err(error) = ret {
+                // the receiver might be gone already
+                let _ = self.on_event(events::Event::Error(error.to_string()));
+                // failed runs are the ones worth auditing
+                if let Err(e) = self.save_transcript().await {
+                    log::error!("{}", e);
+                }
+                return Err(error);
+            }
+        }
+
+        self.save_transcript().await
+    }
+
+    // resolve what's needed to run a valid invocation
+    #[allow(clippy::borrowed_box)]
+    async fn prepare_execution(
+        &self,
+        inv: &Invocation,
+        action: &Box<dyn Action>,
+    ) -> Result<(Duration, Option<HashMap<String, String>>, Option<String>)> {
+        self.on_valid_action(action.cost()).await;
+
+        // determine if we have a timeout
+        let timeout = if let Some(action_tm) = action.timeout().as_ref() {
+            *action_tm
+        } else if let Some(task_tm) = self.task_timeout.as_ref() {
+            *task_tm
+        } else {
+            // one month by default :D
+            Duration::from_secs(60 * 60 * 24 * 30)
+        };
+
+        // resolve secrets references, the invocation in the history keeps the names
+        let (attributes, payload) = {
+            let state = self.state.lock().await;
+            let secrets = state.get_secrets();
+            (
+                inv.attributes.as_ref().map(|attrs| {
+                    attrs
+                        .iter()
+                        .map(|(k, v)| (k.to_owned(), secrets.resolve(v)))
+                        .collect()
+                }),
+                inv.payload.as_ref().map(|p| secrets.resolve(p)),
+            )
+        };
+
+        if let Some(observer) = &self.observer {
+            observer.on_invocation(inv);
+        }
+        self.on_event(events::Event::ActionStarted {
+            invocation: inv.clone(),
+        })?;
+
+        Ok((timeout, attributes, payload))
+    }
+
+    async fn on_execution_result(
+        &self,
+        inv: Invocation,
+        ret: std::result::Result<Result<ActionOutput>, tokio::time::error::Elapsed>,
+        start: &std::time::Instant,
+    ) {
+        if let Ok(ret) = ret {
+            self.on_executed_action(inv, ret, start).await;
+        } else {
+            self.on_timed_out_action(inv, start).await;
+        }
+    }
+
+    async fn execute(&self, inv: Invocation, action: Box<dyn Action>) -> Result<()> {
+        let (timeout, attributes, payload) = self.prepare_execution(&inv, &action).await?;
+
+        // execute with timeout
+        let start = std::time::Instant::now();
+        let ret = tokio::time::timeout(
+            timeout,
+            action.run_structured(self.state.clone(), attributes, payload),
+        )
+        .await;
+
+        self.on_execution_result(inv, ret, &start).await;
+
+        Ok(())
+    }
+
+    // run the pending side effect free invocations concurrently, their results are added to
+    // the history in the same order as the model requested them
+    async fn execute_batch(&self, batch: &mut Vec<(Invocation, Box<dyn Action>)>) -> Result<()> {
+        let batch = std::mem::take(batch);
+        if batch.len() <= 1 {
+            for (inv, action) in batch {
+                self.execute(inv, action).await?;
+            }
+            return Ok(());
+        }
+
+        log::debug!("running {} actions concurrently", batch.len());
+
+        let mut runs = vec![];
+        for (inv, action) in &batch {
+            let (timeout, attributes, payload) = self.prepare_execution(inv, action).await?;
+            runs.push(async move {
+                let start = std::time::Instant::now();
+                let ret = tokio::time::timeout(
+                    timeout,
+                    action.run_structured(self.state.clone(), attributes, payload),
+                )
+                .await;
+                (ret, start)
+            });
+        }
+
+        let results = futures::future::join_all(runs).await;
+        for ((inv, _), (ret, start)) in batch.into_iter().zip(results) {
+            self.on_execution_result(inv, ret, &start).await;
+        }
+
+        Ok(())
+    }
+
     pub async fn step(&mut self) -> Result<()> {
-        let options = self.prepare_step().await?;
+        if self.on_cancellation().await? {
+            self.observe_completion().await;
+            return Ok(());
+        }
+
+        self.summarize_history().await?;
+
+        let options = if let Some(options) = self.prepare_step().await? {
+            options
+        } else {
+            self.observe_completion().await;
+            return Ok(());
+        };
 
         self.on_state_update(&options, false).await?;
 
-        // run model inference
-        let response = self.generator.chat(&options).await?.trim().to_string();
+        // run model inference and parse the response(s) into invocations
+        let mut parse_error = None;
+        let (response, invocations) = if self.config.ensemble_size > 1 {
+            // parse errors of the members are handled while voting
+            self.generate_ensemble(&options).await?
+        } else {
+            let (response, invocations) = if self.config.stream {
+                self.generate_stream(&options).await?
+            } else {
+                self.parse_response(self.generate(&options).await?)
+            };
+            match invocations {
+                Err(error) if self.config.on_parse_error == OnParseError::Abort => {
+                    return Err(error);
+                }
+                invocations => (response, invocations),
+            }
+        };
+        let invocations = invocations.unwrap_or_else(|error| {
+            parse_error = Some(error.to_string());
+            vec![]
+        });
 
-        // parse the model response into invocations
-        let invocations = serialization::xml::parsing::try_parse(&response)?;
+        if let Some(observer) = &self.observer {
+            observer.on_model_response(&response);
+        }
 
         // nothing parsed, report the problem to the model
         if invocations.is_empty() {
             if response.is_empty() {
                 self.on_empty_response().await;
             } else {
-                self.on_invalid_response(&response).await;
+                self.on_invalid_response(&response, parse_error).await;
+            }
+
+            self.unparsed_in_a_row += 1;
+            if self.config.max_unparsed > 0 && self.unparsed_in_a_row >= self.config.max_unparsed {
+                return Err(anyhow!(
+                    "{} consecutive responses without valid actions",
+                    self.unparsed_in_a_row
+                ));
             }
         } else {
+            self.unparsed_in_a_row = 0;
             self.on_valid_response().await;
         }
 
+        // steps where the model only reasoned can be taken from the reasoning budget
+        let mut reasoning_only = !invocations.is_empty();
+
+        // consecutive side effect free invocations executed concurrently
+        let mut batch = vec![];
+
         // for each parsed invocation
         for inv in invocations {
+            // what's left of the response is not executed
+            if self.on_cancellation().await? {
+                break;
+            }
+
             // lookup action
             let action = self.state.lock().await.get_action(&inv.action);
-            if action.is_none() {
-                self.on_invalid_action(inv.clone(), None).await;
-            } else {
+            reasoning_only &= action.as_ref().is_some_and(|a| a.is_reasoning());
+            if let Some(action) = action {
                 // validate prerequisites
-                let action = action.unwrap();
                 if let Err(err) = self.validate(&inv, &action) {
+                    self.execute_batch(&mut batch).await?;
                     self.on_invalid_action(inv.clone(), Some(err.to_string()))
                         .await;
+                } else if self.state.lock().await.is_looping(&inv) {
+                    self.execute_batch(&mut batch).await?;
+                    self.on_looping_action(inv.clone()).await?;
+                } else if self.config.parallel_actions && action.is_parallel_safe() {
+                    batch.push((inv, action));
+                    continue;
                 } else {
-                    self.on_valid_action().await;
-
-                    // determine if we have a timeout
-                    let timeout = if let Some(action_tm) = action.timeout().as_ref() {
-                        *action_tm
-                    } else if let Some(task_tm) = self.task_timeout.as_ref() {
-                        *task_tm
-                    } else {
-                        // one month by default :D
-                        Duration::from_secs(60 * 60 * 24 * 30)
-                    };
-
-                    // execute with timeout
-                    let start = std::time::Instant::now();
-                    let ret = tokio::time::timeout(
-                        timeout,
-                        action.run(
-                            self.state.clone(),
-                            inv.attributes.to_owned(),
-                            inv.payload.to_owned(),
-                        ),
-                    )
-                    .await;
-
-                    if ret.is_err() {
-                        self.on_timed_out_action(inv, &start).await;
-                    } else {
-                        self.on_executed_action(inv, ret.unwrap(), &start).await;
-                    }
+                    self.execute_batch(&mut batch).await?;
+                    self.execute(inv, action).await?;
                 }
+            } else {
+                self.execute_batch(&mut batch).await?;
+                self.on_invalid_action(inv.clone(), None).await;
             }
 
             self.on_state_update(&options, true).await?;
@@ -354,6 +1090,1734 @@ impl Agent {
             }
         }
 
+        if !batch.is_empty() {
+            self.execute_batch(&mut batch).await?;
+            self.on_state_update(&options, true).await?;
+        }
+
+        if reasoning_only && self.state.lock().await.on_reasoning_step() {
+            log::debug!("reasoning only step, not counted against the steps budget");
+        }
+
+        self.observe_completion().await;
+
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use async_trait::async_trait;
+
+    use super::*;
+    use crate::agent::state::history::TranscriptFormat;
+
+    // generator cycling through a list of responses
+    struct MockGenerator {
+        responses: Vec<String>,
+        calls: Arc<AtomicUsize>,
+        // how long each generation takes
+        delay: Duration,
+    }
+
+    #[async_trait]
+    impl Embedder for MockGenerator {
+        async fn embed(&self, _: &str) -> Result<rag::Embeddings> {
+            Ok(vec![])
+        }
+    }
+
+    #[async_trait]
+    impl Client for MockGenerator {
+        fn new(_: &str, _: u16, _: &str, _: u32) -> Result<Self> {
+            Err(anyhow!("not used"))
+        }
+
+        async fn chat(&self, options: &Options) -> Result<ChatResponse> {
+            // summaries are not counted as calls
+            if options.system_prompt == SUMMARY_PROMPT {
+                return Ok(format!("summary of {} bytes", options.prompt.len()).into());
+            }
+
+            let call = self.calls.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(self.delay).await;
+            Ok(ChatResponse {
+                // fixed usage for every generation
+                usage: Some(TokenUsage::new(100, 20)),
+                ..self.responses[call % self.responses.len()]
+                    .to_string()
+                    .into()
+            })
+        }
+    }
+
+    // returns the response selected by the seed of the generation
+    struct SeededGenerator {
+        responses: Vec<String>,
+    }
+
+    #[async_trait]
+    impl Embedder for SeededGenerator {
+        async fn embed(&self, _: &str) -> Result<rag::Embeddings> {
+            Ok(vec![])
+        }
+    }
+
+    #[async_trait]
+    impl Client for SeededGenerator {
+        fn new(_: &str, _: u16, _: &str, _: u32) -> Result<Self> {
+            Err(anyhow!("not used"))
+        }
+
+        async fn chat(&self, options: &Options) -> Result<ChatResponse> {
+            let seed = options.generation.seed.unwrap_or(0) as usize;
+            Ok(self.responses[seed % self.responses.len()]
+                .to_string()
+                .into())
+        }
+    }
+
+    async fn mock_ensemble(responses: &[&str], config: Config) -> (Agent, events::Receiver) {
+        let (events_tx, events_rx) = events::create_channel();
+        let agent = Agent::new(
+            events_tx,
+            Box::new(SeededGenerator {
+                responses: responses.iter().map(|r| r.to_string()).collect(),
+            }),
+            Box::new(rag::MockEmbedder::default()),
+            Box::new(MockTask {}),
+            Config {
+                ensemble_size: 3,
+                generation: GenerationConfig {
+                    seed: Some(0),
+                    ..Default::default()
+                },
+                ..config
+            },
+            None,
+        )
+        .await
+        .unwrap();
+
+        (agent, events_rx)
+    }
+
+    #[derive(Debug)]
+    struct MockTask {}
+
+    impl Task for MockTask {
+        fn to_system_prompt(&self) -> Result<String> {
+            Ok("you are a test".to_string())
+        }
+
+        fn to_prompt(&self) -> Result<String> {
+            Ok("do something".to_string())
+        }
+
+        fn get_functions(&self) -> Vec<namespaces::Namespace> {
+            vec![namespaces::Namespace::new_default(
+                "Test".to_string(),
+                "".to_string(),
+                vec![
+                    Box::<SlowRead>::default(),
+                    Box::<ListItems>::default(),
+                    Box::<Probe>::default(),
+                ],
+                None,
+            )]
+        }
+    }
+
+    // task with a rag configuration and no actions of its own
+    #[derive(Debug)]
+    struct MockRagTask {
+        config: rag::Configuration,
+    }
+
+    impl Task for MockRagTask {
+        fn to_system_prompt(&self) -> Result<String> {
+            Ok("you are a test".to_string())
+        }
+
+        fn to_prompt(&self) -> Result<String> {
+            Ok("find something".to_string())
+        }
+
+        fn get_functions(&self) -> Vec<namespaces::Namespace> {
+            vec![]
+        }
+
+        fn get_rag_config(&self) -> Option<rag::Configuration> {
+            Some(self.config.clone())
+        }
+    }
+
+    // task changing its namespaces as the run goes on
+    #[derive(Debug)]
+    struct MockPhasedTask {
+        phases: Vec<task::Phase>,
+    }
+
+    impl Task for MockPhasedTask {
+        fn to_system_prompt(&self) -> Result<String> {
+            Ok("you are a test".to_string())
+        }
+
+        fn to_prompt(&self) -> Result<String> {
+            Ok("do something in phases".to_string())
+        }
+
+        fn get_functions(&self) -> Vec<namespaces::Namespace> {
+            vec![]
+        }
+
+        fn phases(&self) -> Vec<task::Phase> {
+            self.phases.clone()
+        }
+    }
+
+    static RUNNING: AtomicUsize = AtomicUsize::new(0);
+    static MAX_RUNNING: AtomicUsize = AtomicUsize::new(0);
+
+    // side effect free action taking as many milliseconds as its payload says
+    #[derive(Debug, Default, Clone)]
+    struct SlowRead {}
+
+    #[async_trait]
+    impl Action for SlowRead {
+        fn name(&self) -> &str {
+            "slow-read"
+        }
+
+        fn description(&self) -> &str {
+            "reads slowly"
+        }
+
+        fn example_payload(&self) -> Option<&str> {
+            Some("100")
+        }
+
+        fn is_parallel_safe(&self) -> bool {
+            true
+        }
+
+        async fn run(
+            &self,
+            _: SharedState,
+            _: Option<HashMap<String, String>>,
+            payload: Option<String>,
+        ) -> Result<Option<String>> {
+            let running = RUNNING.fetch_add(1, Ordering::SeqCst) + 1;
+            MAX_RUNNING.fetch_max(running, Ordering::SeqCst);
+
+            let millis: u64 = payload.unwrap().parse()?;
+            tokio::time::sleep(Duration::from_millis(millis)).await;
+
+            RUNNING.fetch_sub(1, Ordering::SeqCst);
+            Ok(Some(format!("slept {millis}ms")))
+        }
+    }
+
+    // an expensive action doing nothing
+    #[derive(Debug, Default, Clone)]
+    struct Probe {}
+
+    #[async_trait]
+    impl Action for Probe {
+        fn name(&self) -> &str {
+            "probe"
+        }
+
+        fn description(&self) -> &str {
+            "probes"
+        }
+
+        fn cost(&self) -> u32 {
+            3
+        }
+
+        async fn run(
+            &self,
+            _: SharedState,
+            _: Option<HashMap<String, String>>,
+            _: Option<String>,
+        ) -> Result<Option<String>> {
+            Ok(Some("probed".to_string()))
+        }
+    }
+
+    // returns as many items as its payload says, both as text and as typed data
+    #[derive(Debug, Default, Clone)]
+    struct ListItems {}
+
+    #[async_trait]
+    impl Action for ListItems {
+        fn name(&self) -> &str {
+            "list-items"
+        }
+
+        fn description(&self) -> &str {
+            "lists items"
+        }
+
+        fn example_payload(&self) -> Option<&str> {
+            Some("2")
+        }
+
+        async fn run(
+            &self,
+            state: SharedState,
+            attributes: Option<HashMap<String, String>>,
+            payload: Option<String>,
+        ) -> Result<Option<String>> {
+            Ok(self
+                .run_structured(state, attributes, payload)
+                .await?
+                .to_text())
+        }
+
+        async fn run_structured(
+            &self,
+            _: SharedState,
+            _: Option<HashMap<String, String>>,
+            payload: Option<String>,
+        ) -> Result<ActionOutput> {
+            let count: usize = payload.unwrap().parse()?;
+            if count == 0 {
+                return Ok(ActionOutput {
+                    text: Some("no items".to_string()),
+                    data: None,
+                    success: false,
+                });
+            }
+
+            let items: Vec<String> = (1..=count).map(|i| format!("item{i}")).collect();
+            Ok(ActionOutput {
+                text: Some(items.join(", ")),
+                data: Some(serde_json::json!(items)),
+                success: true,
+            })
+        }
+    }
+
+    async fn mock_agent(
+        responses: &[&str],
+        config: Config,
+    ) -> (Agent, Arc<AtomicUsize>, events::Receiver) {
+        mock_slow_agent(responses, Duration::ZERO, config).await
+    }
+
+    async fn mock_slow_agent(
+        responses: &[&str],
+        delay: Duration,
+        config: Config,
+    ) -> (Agent, Arc<AtomicUsize>, events::Receiver) {
+        mock_observed_agent(responses, delay, config, None).await
+    }
+
+    async fn mock_observed_agent(
+        responses: &[&str],
+        delay: Duration,
+        config: Config,
+        observer: Option<Box<dyn events::Observer>>,
+    ) -> (Agent, Arc<AtomicUsize>, events::Receiver) {
+        let (events_tx, events_rx) = events::create_channel();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let generator = MockGenerator {
+            responses: responses.iter().map(|r| r.to_string()).collect(),
+            calls: calls.clone(),
+            delay,
+        };
+        let embedder = MockGenerator {
+            responses: vec![],
+            calls: Arc::new(AtomicUsize::new(0)),
+            delay: Duration::ZERO,
+        };
+
+        let agent = Agent::new(
+            events_tx,
+            Box::new(generator),
+            Box::new(embedder),
+            Box::new(MockTask {}),
+            config,
+            observer,
+        )
+        .await
+        .unwrap();
+
+        (agent, calls, events_rx)
+    }
+
+    #[tokio::test]
+    async fn test_generation_timeout() {
+        let (mut agent, calls, _events) = mock_slow_agent(
+            &["<save-memory key=\"test\">hello</save-memory>"],
+            Duration::from_secs(10),
+            Config {
+                generation_timeout: Some(Duration::from_millis(50)),
+                retry_policy: RetryPolicy {
+                    max_attempts: 2,
+                    base_delay: Duration::from_millis(1),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        )
+        .await;
+
+        let start = std::time::Instant::now();
+        let err = agent.step().await.unwrap_err();
+        assert!(err.to_string().contains("timed out"), "{}", err);
+        // retried once, then gave up
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn test_max_duration() {
+        let (mut agent, calls, _events) = mock_slow_agent(
+            &["<save-memory key=\"test\">hello</save-memory>"],
+            Duration::from_millis(20),
+            Config {
+                max_duration: Some(Duration::from_millis(100)),
+                ..Default::default()
+            },
+        )
+        .await;
+
+        // no step limit, the run is bound by the time budget only
+        let start = std::time::Instant::now();
+        agent.run().await.unwrap();
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(calls.load(Ordering::SeqCst) < 10);
+        assert!(agent.is_done().await);
+    }
+
+    // records the callbacks in the order they're invoked
+    struct RecordingObserver {
+        calls: Arc<std::sync::Mutex<Vec<String>>>,
+    }
+
+    impl events::Observer for RecordingObserver {
+        fn on_step_start(&self, step: usize, _: usize) {
+            self.calls.lock().unwrap().push(format!("step {}", step));
+        }
+
+        fn on_model_response(&self, raw: &str) {
+            self.calls.lock().unwrap().push(format!("response {}", raw));
+        }
+
+        fn on_invocation(&self, invocation: &Invocation) {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("invocation {}", invocation.action));
+        }
+
+        fn on_action_result(&self, invocation: &Invocation, _: Option<&str>, error: Option<&str>) {
+            self.calls.lock().unwrap().push(format!(
+                "result {} {}",
+                invocation.action,
+                if error.is_some() { "error" } else { "ok" }
+            ));
+        }
+
+        fn on_complete(&self, impossible: bool, reason: Option<&str>) {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("complete {} {:?}", impossible, reason));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_observer() {
+        let response = "<save-memory key=\"k\">v</save-memory><task-complete>done</task-complete>";
+        let calls = Arc::new(std::sync::Mutex::new(vec![]));
+        let (mut agent, _, _events) = mock_observed_agent(
+            &[response],
+            Duration::ZERO,
+            Config::default(),
+            Some(Box::new(RecordingObserver {
+                calls: calls.clone(),
+            })),
+        )
+        .await;
+        agent.step().await.unwrap();
+
+        assert_eq!(
+            *calls.lock().unwrap(),
+            vec![
+                "step 1".to_string(),
+                format!("response {}", response),
+                "invocation save-memory".to_string(),
+                "result save-memory ok".to_string(),
+                "invocation task-complete".to_string(),
+                "result task-complete ok".to_string(),
+                "complete false Some(\"done\")".to_string(),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cancellation() {
+        let (mut agent, calls, _events) = mock_agent(
+            &["<save-memory key=\"test\">hello</save-memory>"],
+            Config::default(),
+        )
+        .await;
+
+        // cancel from another task while the agent is running with no step limit
+        let token = agent.cancellation_token();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            token.cancel();
+        });
+
+        tokio::time::timeout(Duration::from_secs(5), agent.run())
+            .await
+            .expect("the run has not been cancelled")
+            .unwrap();
+
+        assert!(calls.load(Ordering::SeqCst) > 0);
+        assert_eq!(
+            agent.state.lock().await.get_completion(),
+            Some((false, Some("cancelled".to_string())))
+        );
+        // nothing is generated once cancelled
+        let generated = calls.load(Ordering::SeqCst);
+        agent.step().await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), generated);
+    }
+
+    #[tokio::test]
+    async fn test_parallel_actions() {
+        let response = &["<slow-read>200</slow-read><slow-read>10</slow-read>"];
+
+        for parallel_actions in [true, false] {
+            MAX_RUNNING.store(0, Ordering::SeqCst);
+
+            let (mut agent, _, _events) = mock_agent(
+                response,
+                Config {
+                    parallel_actions,
+                    ..Default::default()
+                },
+            )
+            .await;
+            agent.step().await.unwrap();
+
+            assert_eq!(
+                MAX_RUNNING.load(Ordering::SeqCst),
+                if parallel_actions { 2 } else { 1 }
+            );
+
+            // the results are in the order the model requested them
+            let history = format!(
+                "{:?}",
+                agent.state.lock().await.to_chat_history(10, 0).unwrap()
+            );
+            let first = history.find("slept 200ms").unwrap();
+            let second = history.find("slept 10ms").unwrap();
+            assert!(first < second);
+        }
+    }
+
+    #[test]
+    fn test_parroted_example() {
+        let action = |namespace: &str, name: &str| {
+            namespaces::NAMESPACES.get(namespace).unwrap()()
+                .actions
+                .into_iter()
+                .find(|action| action.name() == name)
+                .unwrap()
+        };
+        let invocation = |name: &str, attributes: &[(&str, &str)], payload: Option<&str>| {
+            Invocation::new(
+                name.to_string(),
+                if attributes.is_empty() {
+                    None
+                } else {
+                    Some(
+                        attributes
+                            .iter()
+                            .map(|(k, v)| (k.to_string(), v.to_string()))
+                            .collect(),
+                    )
+                },
+                payload.map(|p| p.to_string()),
+            )
+        };
+
+        // whitespace and case variants of the placeholder are caught
+        let read_folder = action("filesystem", "read-folder");
+        assert!(parroted_example(
+            &invocation("read-folder", &[], Some("  /PATH/to/folder\n")),
+            &read_folder
+        )
+        .is_some());
+        assert!(
+            parroted_example(&invocation("read-folder", &[], Some("/etc")), &read_folder).is_none()
+        );
+        // payloads that merely resemble the example are fine
+        assert!(parroted_example(
+            &invocation("read-folder", &[], Some("/path/to/folder/src")),
+            &read_folder
+        )
+        .is_none());
+
+        // internal whitespace is collapsed as well
+        let search = action("rag", "search");
+        assert_eq!(
+            parroted_example(
+                &invocation(
+                    "search",
+                    &[],
+                    Some("What is  the BIGGEST\n\tcity in the world?\n")
+                ),
+                &search
+            ),
+            Some("what is the biggest city in the world?".to_string())
+        );
+        assert!(parroted_example(
+            &invocation(
+                "search",
+                &[],
+                Some("what is the biggest city in the world today?")
+            ),
+            &search
+        )
+        .is_none());
+        assert!(parroted_example(
+            &invocation("search", &[], Some("whatis the biggest city in the world?")),
+            &search
+        )
+        .is_none());
+
+        // only if every value is the example one
+        let read_file = action("filesystem", "read-file");
+        assert!(parroted_example(
+            &invocation(
+                "read-file",
+                &[("start", "1"), ("end", "100")],
+                Some("/path/to/file/to/read")
+            ),
+            &read_file
+        )
+        .is_some());
+        assert!(parroted_example(
+            &invocation(
+                "read-file",
+                &[("start", "1"), ("end", "100")],
+                Some("/etc/hosts")
+            ),
+            &read_file
+        )
+        .is_none());
+
+        // example numbers are legit values
+        let delete_step = action("planning", "delete-plan-step");
+        assert!(parroted_example(
+            &invocation("delete-plan-step", &[], Some("2")),
+            &delete_step
+        )
+        .is_none());
+
+        // nothing to compare for actions without examples
+        let list_processes = action("system", "list-processes");
+        assert!(
+            parroted_example(&invocation("list-processes", &[], None), &list_processes).is_none()
+        );
+        assert!(parroted_example(
+            &invocation("list-processes", &[], Some("/path/to/folder")),
+            &list_processes
+        )
+        .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_parroted_example_rejected() {
+        let (mut agent, _, _events) = mock_agent(
+            &["<read-folder>  /PATH/TO/FOLDER\n</read-folder>"],
+            Config::default(),
+        )
+        .await;
+        agent
+            .state
+            .lock()
+            .await
+            .enable_namespace("filesystem")
+            .unwrap();
+        agent.step().await.unwrap();
+
+        assert!(agent
+            .state
+            .lock()
+            .await
+            .export_transcript(TranscriptFormat::Text)
+            .contains(
+                "'/path/to/folder' is just the example value of 'read-folder', use the actual value instead"
+            ));
+    }
+
+    #[tokio::test]
+    async fn test_add_guidance() {
+        let mut responses = vec![
+            "<add-guidance>Check the exit code.</add-guidance>".to_string(),
+            "<add-guidance>  check the exit code. </add-guidance>".to_string(),
+            format!("<add-guidance>{}</add-guidance>", "a".repeat(301)),
+        ];
+        for i in 1..=20 {
+            responses.push(format!("<add-guidance>rule number {i}</add-guidance>"));
+        }
+        let responses: Vec<&str> = responses.iter().map(|r| r.as_str()).collect();
+
+        let (mut agent, _, _events) = mock_agent(&responses, Config::default()).await;
+        agent
+            .state
+            .lock()
+            .await
+            .enable_namespace("guidance")
+            .unwrap();
+        for _ in 0..responses.len() {
+            agent.step().await.unwrap();
+        }
+
+        let state = agent.state.lock().await;
+        let rules: Vec<String> = state
+            .get_storage(namespaces::guidance::STORAGE_NAME)
+            .unwrap()
+            .values()
+            .map(|e| e.data.to_string())
+            .collect();
+        assert_eq!(rules.len(), 20);
+        assert_eq!(rules[0], "Check the exit code.");
+        assert_eq!(rules[19], "rule number 19");
+
+        let transcript = state.export_transcript(TranscriptFormat::Text);
+        assert!(transcript.contains("this rule is already part of your guidance"));
+        assert!(transcript.contains("rules can't be longer than 300 characters"));
+        assert!(transcript.contains("the maximum number of 20 rules has been reached"));
+    }
+
+    #[tokio::test]
+    async fn test_prose_only_responses() {
+        let (mut agent, calls, _events) = mock_agent(
+            &["I would start by looking at the files."],
+            Config {
+                max_unparsed: 3,
+                ..Default::default()
+            },
+        )
+        .await;
+
+        agent.step().await.unwrap();
+        agent.step().await.unwrap();
+        assert!(agent
+            .state
+            .lock()
+            .await
+            .export_transcript(TranscriptFormat::Text)
+            .contains("You explained but took no action, you must respond with an action using the required format."));
+
+        // the third one in a row aborts the run
+        let err = agent.step().await.unwrap_err().to_string();
+        assert_eq!(err, "3 consecutive responses without valid actions");
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        assert_eq!(
+            agent.state.lock().await.metrics.errors.unparsed_responses,
+            3
+        );
+    }
+
+    #[tokio::test]
+    async fn test_max_iterations() {
+        let response = &["<save-memory key=\"test\">hello</save-memory>"];
+
+        let (mut agent, calls, _events) = mock_agent(
+            response,
+            Config {
+                max_iterations: 2,
+                on_max_iterations: OnMaxSteps::Complete,
+                ..Default::default()
+            },
+        )
+        .await;
+        agent.run().await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert_eq!(agent.state.lock().await.metrics.current_step, 2);
+        assert_eq!(
+            agent.state.lock().await.get_completion(),
+            Some((false, Some("maximum number of steps reached".to_string())))
+        );
+
+        let (mut agent, calls, _events) = mock_agent(
+            response,
+            Config {
+                max_iterations: 2,
+                on_max_iterations: OnMaxSteps::Error,
+                ..Default::default()
+            },
+        )
+        .await;
+        assert!(agent.run().await.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_reasoning_budget() {
+        let config = Config {
+            max_iterations: 2,
+            reasoning_budget: 2,
+            on_max_iterations: OnMaxSteps::Complete,
+            ..Default::default()
+        };
+
+        // thinking only steps are refunded until the budget runs out, and still numbered
+        let (mut agent, calls, _events) = mock_agent(&["<think>hmm</think>"], config.clone()).await;
+        agent
+            .state
+            .lock()
+            .await
+            .enable_namespace("reasoning")
+            .unwrap();
+        agent.run().await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+        let metrics = agent.get_metrics().await;
+        assert_eq!(metrics.current_step, 4);
+        assert_eq!(metrics.reasoning_steps, 2);
+        assert_eq!(metrics.counted_steps(), 2);
+
+        // a step that also acts is not refunded
+        let (mut agent, calls, _events) =
+            mock_agent(&["<think>hmm</think> <probe/>"], config).await;
+        agent
+            .state
+            .lock()
+            .await
+            .enable_namespace("reasoning")
+            .unwrap();
+        agent.run().await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        let metrics = agent.get_metrics().await;
+        assert_eq!(metrics.current_step, 2);
+        assert_eq!(metrics.reasoning_steps, 0);
+    }
+
+    #[tokio::test]
+    async fn test_max_iterations_conclude() {
+        let config = Config {
+            max_iterations: 3,
+            on_max_iterations: OnMaxSteps::Conclude,
+            ..Default::default()
+        };
+        let response = &["<save-memory key=\"test\">hello</save-memory>"];
+
+        // only the last step asks the model to conclude
+        let (mut agent, _, _events) = mock_agent(response, config.clone()).await;
+        for step in 1..=3 {
+            let options = agent.prepare_step().await.unwrap().unwrap();
+            let concluding = options.history.last().is_some_and(|message| {
+                message.content()
+                    == "You are out of steps, summarize your findings and conclude the task."
+            });
+            assert_eq!(concluding, step == 3, "step {}", step);
+        }
+
+        // the concluding response is still generated and executed
+        let (mut agent, calls, _events) = mock_agent(response, config).await;
+        agent.run().await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        let state = agent.state.lock().await;
+        assert_eq!(state.metrics.valid_actions, 3);
+        assert_eq!(
+            state.get_completion(),
+            Some((false, Some("maximum number of steps reached".to_string())))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_error_truncation() {
+        let key = "k".repeat(200);
+        let response = format!("<delete-memory key=\"{}\"/>", key);
+        let (mut agent, _, _events) = mock_agent(
+            &[&response],
+            Config {
+                max_result_length: 50,
+                ..Default::default()
+            },
+        )
+        .await;
+        agent.step().await.unwrap();
+
+        let transcript = agent
+            .state
+            .lock()
+            .await
+            .export_transcript(TranscriptFormat::Text);
+        assert!(transcript.contains("memory 'kkk"));
+        assert!(transcript.contains(state::truncation::ELISION));
+        assert!(!transcript.contains(&format!("memory '{}' not found", key)));
+    }
+
+    #[tokio::test]
+    async fn test_ensemble() {
+        let save = "<save-memory key=\"a\">one</save-memory>";
+        let other = "<save-memory key=\"b\">two</save-memory>";
+        let broken = "<save-memory key=\"c\">three</save-memory> <search site:x</search>";
+
+        // every member gets its own seed, the majority wins
+        let (mut agent, _events) = mock_ensemble(&[other, save, save], Config::default()).await;
+        agent.step().await.unwrap();
+        let state = agent.state.lock().await;
+        assert_eq!(
+            state.get_storage("memories").unwrap().get_tagged("a"),
+            Some("one".to_string())
+        );
+        assert_eq!(state.get_storage("memories").unwrap().get_tagged("b"), None);
+        drop(state);
+
+        // no consensus is reported to the model like an invalid response
+        let (mut agent, _events) = mock_ensemble(
+            &[save, other, "<think>hmm</think>"],
+            Config {
+                ensemble_strategy: ensemble::Strategy::Unanimous,
+                max_unparsed: 2,
+                ..Default::default()
+            },
+        )
+        .await;
+        agent.step().await.unwrap();
+        let err = agent.step().await.unwrap_err().to_string();
+        assert_eq!(err, "2 consecutive responses without valid actions");
+        let state = agent.state.lock().await;
+        assert_eq!(state.metrics.errors.unparsed_responses, 2);
+        assert!(state
+            .export_transcript(TranscriptFormat::Text)
+            .contains("3 attempts disagree on the actions to execute"));
+        assert!(state.get_storage("memories").unwrap().is_empty());
+        drop(state);
+
+        // members that can't be parsed follow on_parse_error
+        let (mut agent, _events) = mock_ensemble(
+            &[broken],
+            Config {
+                on_parse_error: OnParseError::Report,
+                ..Default::default()
+            },
+        )
+        .await;
+        agent.step().await.unwrap();
+        assert!(agent
+            .state
+            .lock()
+            .await
+            .export_transcript(TranscriptFormat::Text)
+            .contains("Your response is not valid (can't parse '<search site:x</search>'"));
+
+        let (mut agent, _events) = mock_ensemble(
+            &[save, broken, save],
+            Config {
+                on_parse_error: OnParseError::Abort,
+                ..Default::default()
+            },
+        )
+        .await;
+        assert!(agent.step().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_max_cost() {
+        let (mut agent, calls, _events) = mock_agent(
+            &["<probe/>"],
+            Config {
+                max_cost: 7,
+                ..Default::default()
+            },
+        )
+        .await;
+        agent.run().await.unwrap();
+
+        // 3 + 3 is still within the budget, the third probe exceeds it
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        let state = agent.state.lock().await;
+        assert_eq!(state.metrics.cost, 9);
+        assert_eq!(
+            state.get_completion(),
+            Some((false, Some("maximum actions cost of 7 reached".to_string())))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unknown_action() {
+        let (mut agent, _, _events) = mock_agent(
+            &["<reed-file>/etc/hosts</reed-file>"],
+            Config {
+                max_iterations: 1,
+                on_max_iterations: OnMaxSteps::Complete,
+                ..Default::default()
+            },
+        )
+        .await;
+        agent.run().await.unwrap();
+        assert_eq!(agent.get_metrics().await.errors.unknown_actions, 1);
+
+        let transcript = agent
+            .state
+            .lock()
+            .await
+            .export_transcript(TranscriptFormat::Text);
+        assert!(transcript.contains("action 'reed-file' is not available; available actions: "));
+        assert!(transcript.contains("save-memory"));
+    }
+
+    #[tokio::test]
+    async fn test_token_usage() {
+        let (mut agent, _, _events) = mock_agent(
+            &["<save-memory key=\"a\">hello</save-memory>"],
+            Config {
+                max_iterations: 3,
+                on_max_iterations: OnMaxSteps::Complete,
+                token_prices: Some(TokenPrices {
+                    prompt: 1.0,
+                    completion: 2.0,
+                }),
+                ..Default::default()
+            },
+        )
+        .await;
+        assert_eq!(
+            agent.usage().await,
+            UsageSummary {
+                tokens: TokenUsage::default(),
+                cost: Some(0.0)
+            }
+        );
+
+        agent.run().await.unwrap();
+        let usage = agent.usage().await;
+        assert_eq!(usage.tokens, TokenUsage::new(300, 60));
+        assert!((usage.cost.unwrap() - 0.42).abs() < 1e-9);
+
+        // no cost estimate without prices
+        let (mut agent, _, _events) = mock_agent(
+            &["<save-memory key=\"a\">hello</save-memory>"],
+            Config {
+                max_iterations: 1,
+                on_max_iterations: OnMaxSteps::Complete,
+                ..Default::default()
+            },
+        )
+        .await;
+        agent.run().await.unwrap();
+        assert_eq!(agent.usage().await.tokens.total(), 120);
+        assert_eq!(agent.usage().await.cost, None);
+    }
+
+    #[tokio::test]
+    async fn test_summarize_history() {
+        let (mut agent, calls, _events) = mock_agent(
+            &["<save-memory key=\"a\">hello</save-memory>"],
+            Config {
+                max_iterations: 6,
+                on_max_iterations: OnMaxSteps::Complete,
+                summarize_after: 4,
+                ..Default::default()
+            },
+        )
+        .await;
+        agent.run().await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 6);
+
+        let state = agent.state.lock().await;
+        // 5 actions before the 6th step, the oldest 3 were summarized
+        assert_eq!(state.history_len(), 3);
+        let summary = state.get_history_summary().unwrap();
+        assert!(summary.starts_with("summary of "));
+        assert!(serialization::state_to_system_prompt(&state)
+            .unwrap()
+            .contains(&summary));
+        assert!(!state
+            .to_chat_history(10, 0)
+            .unwrap()
+            .iter()
+            .any(|m| m.content().contains("summary of")));
+    }
+
+    #[tokio::test]
+    async fn test_toggle_namespaces() {
+        let (agent, _, _events) = mock_agent(&["<think>hmm</think>"], Config::default()).await;
+        let mut state = agent.state.lock().await;
+        let prompt = |state: &State| serialization::state_to_system_prompt(state).unwrap();
+
+        assert!(prompt(&state).contains("save-memory"));
+        assert!(!prompt(&state).contains("read-file"));
+
+        state.disable_namespace("memory").unwrap();
+        assert!(!prompt(&state).contains("save-memory"));
+        assert!(state.get_action("save-memory").is_none());
+        assert!(state.disable_namespace("memory").is_err());
+
+        // by registry key or by name
+        state.enable_namespace("Memory").unwrap();
+        assert!(prompt(&state).contains("save-memory"));
+        state.enable_namespace("filesystem").unwrap();
+        assert!(prompt(&state).contains("read-file"));
+        assert!(state.get_action("read-file").is_some());
+        // already enabled
+        state.enable_namespace("filesystem").unwrap();
+        assert_eq!(
+            state
+                .get_namespaces()
+                .iter()
+                .filter(|ns| ns.name == "Filesystem")
+                .count(),
+            1
+        );
+
+        assert!(state.enable_namespace("nope").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_namespace_phases() {
+        let path = std::env::temp_dir().join(format!("nerve-phases-{}.json", std::process::id()));
+        let new_agent = || async {
+            let (events_tx, events_rx) = events::create_channel();
+            let generator = MockGenerator {
+                responses: vec!["<save-memory key=\"a\">hello</save-memory>".to_string()],
+                calls: Arc::new(AtomicUsize::new(0)),
+                delay: Duration::ZERO,
+            };
+            let task = MockPhasedTask {
+                phases: vec![task::Phase {
+                    step: 2,
+                    using: vec!["filesystem".to_string()],
+                    not_using: vec!["memory".to_string()],
+                }],
+            };
+            let agent = Agent::new(
+                events_tx,
+                Box::new(generator),
+                Box::new(rag::MockEmbedder::default()),
+                Box::new(task),
+                Config {
+                    checkpoint: Some(path.clone()),
+                    ..Default::default()
+                },
+                None,
+            )
+            .await
+            .unwrap();
+            (agent, events_rx)
+        };
+        let prompt = |state: &State| serialization::state_to_system_prompt(state).unwrap();
+
+        let (mut agent, _events) = new_agent().await;
+        agent.step().await.unwrap();
+        assert!(prompt(&*agent.state.lock().await).contains("save-memory"));
+        assert!(!prompt(&*agent.state.lock().await).contains("read-file"));
+
+        // the namespaces change from the second step
+        agent.step().await.unwrap();
+        let state = agent.state.lock().await;
+        assert!(!prompt(&state).contains("save-memory"));
+        assert!(prompt(&state).contains("read-file"));
+        assert!(state
+            .export_transcript(TranscriptFormat::Text)
+            .contains("action 'save-memory' is not available"));
+        drop(state);
+        agent.save_checkpoint().await.unwrap();
+
+        // and stay changed once resumed
+        let (mut resumed, _events) = new_agent().await;
+        resumed.resume(&path).await.unwrap();
+        let state = resumed.state.lock().await;
+        assert!(!prompt(&state).contains("save-memory"));
+        assert!(prompt(&state).contains("read-file"));
+        assert_eq!(
+            state.get_storage("memories").unwrap().get_tagged("a"),
+            Some("hello".to_string())
+        );
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_missing_required_attribute() {
+        let (mut agent, _, _events) = mock_agent(
+            &["<save-memory name=\"a\">hello</save-memory>"],
+            Config {
+                max_iterations: 1,
+                on_max_iterations: OnMaxSteps::Complete,
+                ..Default::default()
+            },
+        )
+        .await;
+        agent.run().await.unwrap();
+
+        let state = agent.state.lock().await;
+        // the action never ran
+        assert!(state.get_storage("memories").unwrap().is_empty());
+        assert!(state.export_transcript(TranscriptFormat::Text).contains(
+            "no 'key' xml attribute specified for 'save-memory', the required attributes are: key"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_duplicated_attributes_error() {
+        let (mut agent, _, _events) = mock_agent(
+            &["<save-memory key=\"a\" key=\"b\">hello</save-memory>"],
+            Config {
+                duplicate_attributes: DuplicateAttributes::Error,
+                max_iterations: 1,
+                on_max_iterations: OnMaxSteps::Complete,
+                ..Default::default()
+            },
+        )
+        .await;
+        agent.run().await.unwrap();
+
+        let state = agent.state.lock().await;
+        assert!(state.get_storage("memories").unwrap().is_empty());
+        assert_eq!(state.metrics.errors.unparsed_responses, 1);
+        assert!(state
+            .export_transcript(TranscriptFormat::Text)
+            .contains("Your response is not valid (duplicated attributes key in <save-memory"));
+    }
+
+    #[tokio::test]
+    async fn test_structured_output() {
+        let (mut agent, _, mut events) = mock_agent(
+            &["<list-items>2</list-items>", "<list-items>0</list-items>"],
+            Config::default(),
+        )
+        .await;
+        agent.step().await.unwrap();
+        agent.step().await.unwrap();
+
+        let mut executed = vec![];
+        while let Ok(event) = events.try_recv() {
+            if let Event::ActionExecuted {
+                result,
+                error,
+                data,
+                ..
+            } = event
+            {
+                executed.push((result, error, data));
+            }
+        }
+
+        assert_eq!(
+            executed,
+            vec![
+                (
+                    Some("item1, item2".to_string()),
+                    None,
+                    Some(serde_json::json!(["item1", "item2"]))
+                ),
+                // unsuccessful outputs are errors
+                (None, Some("no items".to_string()), None),
+            ]
+        );
+
+        let transcript = agent
+            .state
+            .lock()
+            .await
+            .export_transcript(TranscriptFormat::Text);
+        assert!(transcript.contains("item1, item2"));
+        assert!(transcript.contains("no items"));
+    }
+
+    #[test]
+    fn test_action_output_text() {
+        let output: ActionOutput = Some("done".to_string()).into();
+        assert!(output.success);
+        assert_eq!(output.to_text(), Some("done".to_string()));
+
+        // without text the data is rendered
+        let output = ActionOutput {
+            text: None,
+            data: Some(serde_json::json!({"a": 1})),
+            success: true,
+        };
+        assert_eq!(output.to_text(), Some("{\n  \"a\": 1\n}".to_string()));
+        assert_eq!(ActionOutput::new(None).to_text(), None);
+    }
+
+    #[tokio::test]
+    async fn test_json_invocation_format() {
+        let (mut agent, _, _events) = mock_agent(
+            &[r#"{"action": "save-memory", "attributes": {"key": "test"}, "payload": "hello"}"#],
+            Config {
+                invocation_format: serialization::InvocationFormat::Json,
+                ..Default::default()
+            },
+        )
+        .await;
+        agent.step().await.unwrap();
+
+        let state = agent.state.lock().await;
+        assert_eq!(
+            state.get_storage("memories").unwrap().get_tagged("test"),
+            Some("hello".to_string())
+        );
+
+        let prompt = serialization::state_to_system_prompt(&state).unwrap();
+        assert!(prompt.contains(r#"{"action": "save-memory", "attributes": {"key": "#));
+        assert!(!prompt.contains("<save-memory"));
+    }
+
+    #[tokio::test]
+    async fn test_system_prompt_template() {
+        let dir = std::env::temp_dir().join(format!("nerve-template-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let incomplete = dir.join("incomplete.tpl");
+        std::fs::write(&incomplete, "{system_prompt}\n{available_actions}").unwrap();
+        let err = serialization::load_system_prompt_template(&incomplete)
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.contains("missing the {iterations}, {storages}, {guidance} placeholder"),
+            "{}",
+            err
+        );
+
+        let custom = dir.join("custom.tpl");
+        std::fs::write(
+            &custom,
+            "ROLE: {system_prompt}\n{storages}{iterations}\nTOOLS {\"json\": true}\n{available_actions}\nRULES\n{guidance}",
+        )
+        .unwrap();
+        let template = serialization::load_system_prompt_template(&custom).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let (agent, _, _events) = mock_agent(
+            &["<think>hmm</think>"],
+            Config {
+                system_prompt_template: Some(template),
+                ..Default::default()
+            },
+        )
+        .await;
+        let state = agent.state.lock().await;
+        let prompt = serialization::state_to_system_prompt(&state).unwrap();
+
+        assert!(prompt.starts_with("ROLE: you are a test\n"));
+        // other braces are left as they are
+        assert!(prompt.contains("TOOLS {\"json\": true}\n"));
+        assert!(prompt.contains("<save-memory key=\"my-note\">"));
+        assert!(!prompt.contains("# Guidance"));
+        assert!(!prompt.contains("{guidance}"));
+    }
+
+    #[tokio::test]
+    async fn test_compact_actions() {
+        let (verbose, _, _events) = mock_agent(&["<think>hmm</think>"], Config::default()).await;
+        let (compact, _, _events) = mock_agent(
+            &["<think>hmm</think>"],
+            Config {
+                actions_style: serialization::ActionsStyle::Compact,
+                ..Default::default()
+            },
+        )
+        .await;
+
+        let verbose = verbose.state.lock().await;
+        let compact = compact.state.lock().await;
+        let verbose_prompt = serialization::state_to_system_prompt(&verbose).unwrap();
+        let compact_prompt = serialization::state_to_system_prompt(&compact).unwrap();
+
+        assert!(compact_prompt.len() < verbose_prompt.len());
+        for name in verbose.get_action_names() {
+            assert!(verbose_prompt.contains(name), "{}", name);
+            assert!(compact_prompt.contains(name), "{}", name);
+        }
+        assert!(compact_prompt.contains("- <save-memory key=\"...\">...</save-memory> "));
+        assert!(!compact_prompt.contains("my-note"));
+    }
+
+    #[tokio::test]
+    async fn test_system_prompt_fingerprint() {
+        let (mut agent, _, _events) = mock_agent(
+            &[
+                "<think>one</think>",
+                "<think>two</think>",
+                "<save-memory key=\"k\">v</save-memory>",
+                "<think>three</think>",
+            ],
+            Config::default(),
+        )
+        .await;
+
+        let fingerprint = || async {
+            let (agent, _, _events) = mock_agent(&[], Config::default()).await;
+            let state = agent.state.lock().await;
+            serialization::system_prompt_fingerprint(
+                &serialization::state_to_system_prompt(&state).unwrap(),
+            )
+        };
+        // attributes and namespaces are rendered in the same order every time
+        let initial = fingerprint().await;
+        assert_eq!(initial, fingerprint().await);
+
+        let mut reuses = vec![];
+        let mut fingerprints = vec![];
+        for _ in 0..4 {
+            agent.step().await.unwrap();
+            let state = agent.state.lock().await;
+            reuses.push(state.metrics.system_prompt_reuses);
+            fingerprints.push(state.metrics.system_prompt_fingerprint.clone().unwrap());
+        }
+
+        assert_eq!(fingerprints[0], initial);
+        assert_eq!(fingerprints[1], initial);
+        assert_eq!(fingerprints[2], initial);
+        // the saved memory is part of the system prompt from the next step
+        assert_ne!(fingerprints[3], initial);
+        assert_eq!(reuses, vec![0, 1, 2, 2]);
+    }
+
+    #[tokio::test]
+    async fn test_hidden_action_examples() {
+        let dir = std::env::temp_dir().join(format!("nerve-examples-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("task.yml"),
+            "system_prompt: you are a test\nprompt: do something\nusing: [memory]\naction_examples: [save-memory]\n",
+        )
+        .unwrap();
+        let tasklet = task::tasklet::Tasklet::from_path(dir.to_str().unwrap(), &vec![]).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let (events_tx, _events) = events::create_channel();
+        let agent = Agent::new(
+            events_tx,
+            Box::new(MockGenerator {
+                responses: vec![],
+                calls: Arc::new(AtomicUsize::new(0)),
+                delay: Duration::ZERO,
+            }),
+            Box::new(rag::MockEmbedder::default()),
+            Box::new(tasklet),
+            Config::default(),
+            None,
+        )
+        .await
+        .unwrap();
+
+        let state = agent.state.lock().await;
+        let prompt = serialization::state_to_system_prompt(&state).unwrap();
+
+        // the example is only shown for the listed action, the others keep their signature
+        assert!(prompt.contains("<save-memory key=\"my-note\">put here the custom data"));
+        assert!(
+            prompt.contains("<delete-memory key=\"...\"/>"),
+            "{}",
+            prompt
+        );
+        assert!(!prompt.contains("key=\"my-note\"/>"));
+    }
+
+    #[tokio::test]
+    async fn test_tasklist() {
+        let (mut agent, _, _events) = mock_agent(
+            &[
+                "<add-subtask>write the parser</add-subtask>",
+                "<add-subtask>write the tests</add-subtask>",
+                "<complete-subtask>2</complete-subtask>",
+                "<complete-subtask>5</complete-subtask>",
+            ],
+            Config::default(),
+        )
+        .await;
+        agent
+            .state
+            .lock()
+            .await
+            .enable_namespace("tasklist")
+            .unwrap();
+
+        for _ in 0..4 {
+            agent.step().await.unwrap();
+        }
+
+        let state = agent.state.lock().await;
+        let subtasks = state.get_storage("subtasks").unwrap();
+        assert_eq!(subtasks.len(), 2);
+        assert!(!subtasks.get("1").unwrap().complete);
+        assert!(subtasks.get("2").unwrap().complete);
+        assert!(state
+            .export_transcript(TranscriptFormat::Text)
+            .contains("no subtask with number 5"));
+
+        let prompt = serialization::state_to_system_prompt(&state).unwrap();
+        assert!(prompt.contains("  - [ ] 1. write the parser\n  - [x] 2. write the tests\n  (1 of 2 completed, 1 remaining)"));
+    }
+
+    #[tokio::test]
+    async fn test_search_knowledge() {
+        let root =
+            std::env::temp_dir().join(format!("nerve-search-knowledge-{}", std::process::id()));
+        let docs = root.join("docs");
+        let data = root.join("data");
+        std::fs::create_dir_all(&docs).unwrap();
+        std::fs::create_dir_all(&data).unwrap();
+        std::fs::write(docs.join("cats.txt"), "cats are cats").unwrap();
+        std::fs::write(docs.join("dogs.txt"), "dogs").unwrap();
+        std::fs::write(docs.join("birds.txt"), "birds fly").unwrap();
+
+        let (events_tx, _events) = events::create_channel();
+        let generator = MockGenerator {
+            responses: vec![
+                "<search-knowledge>cats</search-knowledge>".to_string(),
+                "<search-knowledge top-k=\"1\">dog</search-knowledge>".to_string(),
+                "<search-knowledge top-k=\"0\">dog</search-knowledge>".to_string(),
+            ],
+            calls: Arc::new(AtomicUsize::new(0)),
+            delay: Duration::ZERO,
+        };
+        let task = MockRagTask {
+            config: rag::Configuration {
+                source_path: docs.display().to_string(),
+                data_path: data.display().to_string(),
+                max_results: Some(2),
+                ..Default::default()
+            },
+        };
+
+        let mut agent = Agent::new(
+            events_tx,
+            Box::new(generator),
+            Box::new(rag::MockEmbedder::default()),
+            Box::new(task),
+            Config::default(),
+            None,
+        )
+        .await
+        .unwrap();
+
+        for _ in 0..3 {
+            agent.step().await.unwrap();
+        }
+
+        let transcript = agent
+            .state
+            .lock()
+            .await
+            .export_transcript(TranscriptFormat::Text);
+        // three documents are indexed but the results are capped by max_results
+        assert!(transcript.contains(&format!(
+            "2 documents found for 'cats':\n\n1. {} (score ",
+            docs.join("cats.txt").display()
+        )));
+        assert!(transcript.contains("cats are cats"));
+        assert!(transcript.contains(&format!(
+            "1 documents found for 'dog':\n\n1. {} (score ",
+            docs.join("dogs.txt").display()
+        )));
+        assert!(transcript.contains("invalid top-k '0'"));
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_load_context() {
+        let root = std::env::temp_dir().join(format!("nerve-load-context-{}", std::process::id()));
+        let docs = root.join("docs");
+        let data = root.join("data");
+        std::fs::create_dir_all(&docs).unwrap();
+        std::fs::create_dir_all(&data).unwrap();
+        std::fs::write(docs.join("cats.txt"), "cats are cats").unwrap();
+        std::fs::write(docs.join("dogs.txt"), "dogs").unwrap();
+
+        let (events_tx, _events) = events::create_channel();
+        let generator = MockGenerator {
+            responses: vec![
+                "<load-context>cats</load-context>".to_string(),
+                "<load-context top-k=\"5\">pets</load-context>".to_string(),
+                "<load-context top-k=\"none\">dogs</load-context>".to_string(),
+            ],
+            calls: Arc::new(AtomicUsize::new(0)),
+            delay: Duration::ZERO,
+        };
+        let task = MockRagTask {
+            config: rag::Configuration {
+                source_path: docs.display().to_string(),
+                data_path: data.display().to_string(),
+                ..Default::default()
+            },
+        };
+
+        let mut agent = Agent::new(
+            events_tx,
+            Box::new(generator),
+            Box::new(rag::MockEmbedder::default()),
+            Box::new(task),
+            Config::default(),
+            None,
+        )
+        .await
+        .unwrap();
+
+        for _ in 0..3 {
+            agent.step().await.unwrap();
+        }
+
+        let state = agent.state.lock().await;
+        let context = state.get_storage("context").unwrap();
+        assert_eq!(
+            context.get_tagged("cats"),
+            Some("cats are cats".to_string())
+        );
+        // every document up to top-k
+        let pets = context.get_tagged("pets").unwrap();
+        assert!(
+            pets.contains("cats are cats") && pets.contains("dogs"),
+            "{}",
+            pets
+        );
+        assert_eq!(context.get_tagged("dogs"), None);
+        assert!(state
+            .export_transcript(TranscriptFormat::Text)
+            .contains("invalid top-k 'none'"));
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_deterministic() {
+        let responses = &[
+            "<save-memory key=\"a\">hello</save-memory>",
+            "<add-plan-step>do this</add-plan-step> <list-items></list-items>",
+            "nothing to do",
+            "<recall-memory key=\"a\"></recall-memory>",
+        ];
+
+        let mut runs = vec![];
+        for _ in 0..2 {
+            let (mut agent, _, _events) = mock_agent(
+                responses,
+                Config {
+                    deterministic: true,
+                    parallel_actions: true,
+                    generation: GenerationConfig {
+                        temperature: Some(0.8),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+            )
+            .await;
+
+            assert_eq!(agent.config.generation.temperature, Some(0.0));
+            assert_eq!(agent.config.generation.seed, Some(DETERMINISTIC_SEED));
+            assert_eq!(agent.config.retry_policy.jitter, 0.0);
+            assert!(!agent.config.parallel_actions);
+
+            for _ in 0..responses.len() {
+                agent.step().await.unwrap();
+            }
+
+            let state = agent.state.lock().await;
+            runs.push((
+                state.export_transcript(TranscriptFormat::Text),
+                serialization::state_to_system_prompt(&state).unwrap(),
+            ));
+        }
+
+        assert_eq!(runs[0], runs[1]);
+    }
+
+    #[tokio::test]
+    async fn test_loop_detection() {
+        // A, B, A, B, ...
+        let responses = &[
+            "<save-memory key=\"a\">hello</save-memory>",
+            "<save-memory key=\"b\">world</save-memory>",
+        ];
+
+        // the third A is refused and reported to the model
+        let (mut agent, _, _events) = mock_agent(
+            responses,
+            Config {
+                max_iterations: 5,
+                on_max_iterations: OnMaxSteps::Complete,
+                loop_window: 4,
+                loop_threshold: 2,
+                ..Default::default()
+            },
+        )
+        .await;
+        agent.run().await.unwrap();
+        let metrics = agent.get_metrics().await;
+        assert_eq!(metrics.success_actions, 4);
+        assert_eq!(metrics.errors.looping_actions, 1);
+
+        let (mut agent, calls, _events) = mock_agent(
+            responses,
+            Config {
+                loop_window: 4,
+                loop_threshold: 2,
+                on_loop: OnLoop::Abort,
+                ..Default::default()
+            },
+        )
+        .await;
+        assert!(agent.run().await.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 5);
+    }
+
+    #[tokio::test]
+    async fn test_checkpoint_resume() {
+        let responses = &[
+            "<save-memory key=\"a\">hello</save-memory>",
+            "<save-memory key=\"b\">world</save-memory>",
+        ];
+        let path =
+            std::env::temp_dir().join(format!("nerve-checkpoint-{}.json", std::process::id()));
+        let config = Config {
+            max_iterations: 3,
+            on_max_iterations: OnMaxSteps::Complete,
+            checkpoint: Some(path.clone()),
+            ..Default::default()
+        };
+
+        let (mut agent, _, _events) = mock_agent(responses, config.clone()).await;
+        agent.step().await.unwrap();
+        agent.step().await.unwrap();
+        agent.save_checkpoint().await.unwrap();
+
+        let (mut resumed, calls, _events) = mock_agent(responses, config).await;
+        resumed.resume(&path).await.unwrap();
+
+        let saved = agent.state.lock().await.to_chat_history(10, 0).unwrap();
+        let restored = resumed.state.lock().await.to_chat_history(10, 0).unwrap();
+        assert_eq!(resumed.get_metrics().await.current_step, 2);
+        assert_eq!(restored.len(), 4);
+        assert_eq!(
+            serde_json::to_string(&restored).unwrap(),
+            serde_json::to_string(&saved).unwrap()
+        );
+        assert_eq!(
+            resumed
+                .state
+                .lock()
+                .await
+                .get_storage("memories")
+                .unwrap()
+                .get_tagged("b"),
+            Some("world".to_string())
+        );
+
+        // only the remaining step is executed
+        resumed.run().await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
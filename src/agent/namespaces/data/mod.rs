@@ -0,0 +1,454 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use serde_json::Value;
+
+use super::{filesystem::jail, Action, Namespace};
+use crate::agent::state::SharedState;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Selector {
+    Key(String),
+    Index(usize),
+    All,
+}
+
+fn parse_path(path: &str) -> Result<Vec<Selector>> {
+    let mut selectors = vec![];
+    let chars: Vec<char> = path.trim().chars().collect();
+    let mut i = 0;
+
+    while i < chars.len() {
+        match chars[i] {
+            '.' => {
+                i += 1;
+            }
+            '[' => {
+                let close = chars[i..]
+                    .iter()
+                    .position(|c| *c == ']')
+                    .map(|p| p + i)
+                    .ok_or_else(|| anyhow!("unterminated '[' in path '{}'", path))?;
+                let inner: String = chars[i + 1..close].iter().collect();
+                let inner = inner.trim();
+
+                selectors.push(if inner.is_empty() || inner == "*" {
+                    Selector::All
+                } else if let Ok(index) = inner.parse::<usize>() {
+                    Selector::Index(index)
+                } else {
+                    Selector::Key(inner.trim_matches(|c| c == '"' || c == '\'').to_string())
+                });
+
+                i = close + 1;
+            }
+            _ => {
+                let end = chars[i..]
+                    .iter()
+                    .position(|c| *c == '.' || *c == '[')
+                    .map(|p| p + i)
+                    .unwrap_or(chars.len());
+                let key: String = chars[i..end].iter().collect();
+
+                selectors.push(if key == "*" {
+                    Selector::All
+                } else {
+                    Selector::Key(key)
+                });
+
+                i = end;
+            }
+        }
+    }
+
+    Ok(selectors)
+}
+
+fn select<'a>(root: &'a Value, selectors: &[Selector]) -> Vec<&'a Value> {
+    let mut current = vec![root];
+
+    for selector in selectors {
+        let mut next = vec![];
+        for value in current {
+            match (selector, value) {
+                (Selector::Key(key), Value::Object(map)) => {
+                    if let Some(v) = map.get(key) {
+                        next.push(v);
+                    }
+                }
+                (Selector::Index(index), Value::Array(list)) => {
+                    if let Some(v) = list.get(*index) {
+                        next.push(v);
+                    }
+                }
+                (Selector::All, Value::Array(list)) => next.extend(list.iter()),
+                (Selector::All, Value::Object(map)) => next.extend(map.values()),
+                _ => {}
+            }
+        }
+        current = next;
+    }
+
+    current
+}
+
+// the payload is either the JSON document itself or the path of a file containing it, files
+// are confined to the task jail
+fn load_json(jail: Option<&str>, payload: &str) -> Result<Value> {
+    let payload = payload.trim();
+    let data = if payload.starts_with(['{', '[']) {
+        payload.to_string()
+    } else {
+        let path = jail::confine(jail, payload)?;
+        if !path.is_file() {
+            return Err(anyhow!("'{}' is neither JSON nor a file", payload));
+        }
+        std::fs::read_to_string(&path)
+            .map_err(|e| anyhow!("can't read {}: {}", path.display(), e))?
+    };
+
+    serde_json::from_str(&data).map_err(|e| anyhow!("can't parse payload as JSON: {}", e))
+}
+
+fn value_to_string(value: &Value) -> String {
+    match value {
+        // return strings as they are, without quotes
+        Value::String(s) => s.to_string(),
+        _ => serde_json::to_string_pretty(value).unwrap_or_else(|_| value.to_string()),
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+struct JsonQuery {}
+
+#[async_trait]
+impl Action for JsonQuery {
+    fn name(&self) -> &str {
+        "json-query"
+    }
+
+    fn is_parallel_safe(&self) -> bool {
+        true
+    }
+
+    fn description(&self) -> &str {
+        include_str!("json_query.prompt")
+    }
+
+    fn attributes(&self) -> Option<HashMap<String, String>> {
+        let mut attributes = HashMap::new();
+
+        attributes.insert("path".to_string(), ".items[0].name".to_string());
+
+        Some(attributes)
+    }
+
+    fn example_payload(&self) -> Option<&str> {
+        Some("{\"items\": [{\"name\": \"foo\"}]}")
+    }
+
+    async fn run(
+        &self,
+        state: SharedState,
+        attributes: Option<HashMap<String, String>>,
+        payload: Option<String>,
+    ) -> Result<Option<String>> {
+        let attrs = attributes.unwrap();
+        let path = attrs.get("path").unwrap();
+        let jail = state.lock().await.get_task().get_jail();
+        let json = load_json(jail.as_deref(), &payload.unwrap())?;
+
+        let selected = select(&json, &parse_path(path)?);
+        if selected.is_empty() {
+            Err(anyhow!("no values found for path '{}'", path))
+        } else {
+            Ok(Some(
+                selected
+                    .into_iter()
+                    .map(value_to_string)
+                    .collect::<Vec<String>>()
+                    .join("\n"),
+            ))
+        }
+    }
+}
+
+// maximum number of matches returned by regex-extract
+const MAX_MATCHES: usize = 100;
+
+// extract the capture groups of every match, as a json list where each element is the match
+// itself (no groups), the only group, a list of groups or an object for named groups
+fn regex_extract(pattern: &str, text: &str) -> Result<(Vec<Value>, bool)> {
+    let re = regex::Regex::new(pattern).map_err(|e| anyhow!("invalid pattern: {}", e))?;
+    let named: Vec<&str> = re.capture_names().flatten().collect();
+    let groups = re.captures_len() - 1;
+
+    let mut matches = vec![];
+    let mut truncated = false;
+    for caps in re.captures_iter(text) {
+        if matches.len() >= MAX_MATCHES {
+            truncated = true;
+            break;
+        }
+
+        let group = |idx: usize| {
+            caps.get(idx)
+                .map_or(Value::Null, |m| Value::String(m.as_str().to_string()))
+        };
+
+        matches.push(if !named.is_empty() {
+            Value::Object(
+                named
+                    .iter()
+                    .map(|name| {
+                        (
+                            name.to_string(),
+                            caps.name(name)
+                                .map_or(Value::Null, |m| Value::String(m.as_str().to_string())),
+                        )
+                    })
+                    .collect(),
+            )
+        } else if groups == 0 {
+            group(0)
+        } else if groups == 1 {
+            group(1)
+        } else {
+            Value::Array((1..=groups).map(group).collect())
+        });
+    }
+
+    Ok((matches, truncated))
+}
+
+#[derive(Debug, Default, Clone)]
+struct RegexExtract {}
+
+#[async_trait]
+impl Action for RegexExtract {
+    fn name(&self) -> &str {
+        "regex-extract"
+    }
+
+    fn is_parallel_safe(&self) -> bool {
+        true
+    }
+
+    fn description(&self) -> &str {
+        include_str!("regex_extract.prompt")
+    }
+
+    fn attributes(&self) -> Option<HashMap<String, String>> {
+        let mut attributes = HashMap::new();
+
+        attributes.insert("pattern".to_string(), r"version (\d+\.\d+)".to_string());
+
+        Some(attributes)
+    }
+
+    fn example_payload(&self) -> Option<&str> {
+        Some("running version 1.2 of the server")
+    }
+
+    async fn run(
+        &self,
+        _: SharedState,
+        attributes: Option<HashMap<String, String>>,
+        payload: Option<String>,
+    ) -> Result<Option<String>> {
+        let attrs = attributes.unwrap();
+        let pattern = attrs.get("pattern").unwrap();
+        let payload = payload.unwrap();
+        let text = if std::path::Path::new(payload.trim()).is_file() {
+            std::fs::read_to_string(payload.trim())?
+        } else {
+            payload
+        };
+
+        let (matches, truncated) = regex_extract(pattern, &text)?;
+        if matches.is_empty() {
+            return Err(anyhow!("no matches found for '{}'", pattern));
+        }
+
+        let mut output = serde_json::to_string_pretty(&matches)?;
+        if truncated {
+            output += &format!("\n(only the first {} matches are shown)", MAX_MATCHES);
+        }
+
+        Ok(Some(output))
+    }
+}
+
+// wrap text at this column
+const TEXT_WIDTH: usize = 120;
+
+fn html_to_text(html: &str) -> Result<String> {
+    // links are preserved as numbered references at the end of the text
+    html2text::config::plain()
+        .string_from_read(html.as_bytes(), TEXT_WIDTH)
+        .map_err(|e| anyhow!("can't parse html: {:?}", e))
+}
+
+#[derive(Debug, Default, Clone)]
+struct HtmlToText {}
+
+#[async_trait]
+impl Action for HtmlToText {
+    fn name(&self) -> &str {
+        "html-to-text"
+    }
+
+    fn is_parallel_safe(&self) -> bool {
+        true
+    }
+
+    fn description(&self) -> &str {
+        include_str!("html_to_text.prompt")
+    }
+
+    fn example_payload(&self) -> Option<&str> {
+        Some("/path/to/page.html")
+    }
+
+    async fn run(
+        &self,
+        _: SharedState,
+        _: Option<HashMap<String, String>>,
+        payload: Option<String>,
+    ) -> Result<Option<String>> {
+        let payload = payload.unwrap();
+        let payload = payload.trim();
+        let html = if !payload.starts_with('<') && std::path::Path::new(payload).is_file() {
+            std::fs::read_to_string(payload)?
+        } else {
+            payload.to_string()
+        };
+
+        let text = html_to_text(&html)?;
+        if text.trim().is_empty() {
+            Ok(Some("no text found in the document".to_string()))
+        } else {
+            Ok(Some(text.trim().to_string()))
+        }
+    }
+}
+
+pub(crate) fn get_namespace() -> Namespace {
+    Namespace::new_non_default(
+        "Data".to_string(),
+        include_str!("ns.prompt").to_string(),
+        vec![
+            Box::<JsonQuery>::default(),
+            Box::<RegexExtract>::default(),
+            Box::<HtmlToText>::default(),
+        ],
+        None,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_path() {
+        assert_eq!(
+            parse_path(".items[0].name").unwrap(),
+            vec![
+                Selector::Key("items".to_string()),
+                Selector::Index(0),
+                Selector::Key("name".to_string())
+            ]
+        );
+        assert_eq!(
+            parse_path("items[*][\"first name\"]").unwrap(),
+            vec![
+                Selector::Key("items".to_string()),
+                Selector::All,
+                Selector::Key("first name".to_string())
+            ]
+        );
+        assert!(parse_path(".items[0").is_err());
+    }
+
+    #[test]
+    fn test_select() {
+        let json: Value =
+            serde_json::from_str(r#"{"items": [{"name": "foo", "id": 1}, {"name": "bar"}]}"#)
+                .unwrap();
+
+        let single = select(&json, &parse_path(".items[1].name").unwrap());
+        assert_eq!(single.len(), 1);
+        assert_eq!(value_to_string(single[0]), "bar");
+
+        let many = select(&json, &parse_path(".items[].name").unwrap());
+        assert_eq!(
+            many.into_iter().map(value_to_string).collect::<Vec<_>>(),
+            vec!["foo", "bar"]
+        );
+
+        assert!(select(&json, &parse_path(".items[5]").unwrap()).is_empty());
+        assert!(select(&json, &parse_path(".nope").unwrap()).is_empty());
+    }
+
+    #[test]
+    fn test_load_json() {
+        let jail = std::env::temp_dir().join(format!("nerve-json-query-{}", std::process::id()));
+        std::fs::create_dir_all(&jail).unwrap();
+        std::fs::write(jail.join("data.json"), r#"{"items": [{"name": "foo"}]}"#).unwrap();
+        let jail_str = jail.to_str().unwrap();
+
+        let inline = load_json(Some(jail_str), r#" {"items": [{"name": "foo"}]} "#).unwrap();
+        assert_eq!(load_json(Some(jail_str), "data.json").unwrap(), inline);
+        assert_eq!(
+            load_json(None, jail.join("data.json").to_str().unwrap()).unwrap(),
+            inline
+        );
+        // files outside of the jail can't be read
+        assert!(load_json(Some(jail_str), "/etc/passwd").is_err());
+        assert!(load_json(Some(jail_str), "missing.json").is_err());
+
+        std::fs::remove_dir_all(&jail).unwrap();
+    }
+
+    #[test]
+    fn test_regex_extract() {
+        let text = "10.0.0.1:80 10.0.0.2:443";
+
+        let (matches, _) = regex_extract(r"(\d+\.\d+\.\d+\.\d+):(\d+)", text).unwrap();
+        assert_eq!(
+            serde_json::to_string(&matches).unwrap(),
+            r#"[["10.0.0.1","80"],["10.0.0.2","443"]]"#
+        );
+
+        let (matches, _) = regex_extract(r":(\d+)", text).unwrap();
+        assert_eq!(serde_json::to_string(&matches).unwrap(), r#"["80","443"]"#);
+
+        let (matches, _) = regex_extract(r"(?P<port>\d+)$", text).unwrap();
+        assert_eq!(
+            serde_json::to_string(&matches).unwrap(),
+            r#"[{"port":"443"}]"#
+        );
+
+        let (matches, truncated) = regex_extract(r"\d", &"1".repeat(MAX_MATCHES + 1)).unwrap();
+        assert_eq!(matches.len(), MAX_MATCHES);
+        assert!(truncated);
+
+        assert!(regex_extract(r"(", text).is_err());
+    }
+
+    #[test]
+    fn test_html_to_text() {
+        let text = html_to_text(
+            "<html><head><style>body { color: red; }</style><script>alert(1)</script></head><body><h1>Title</h1><p>Hello <a href=\"https://example.com\">world</a></p></body></html>",
+        )
+        .unwrap();
+
+        assert!(text.contains("Title"));
+        assert!(text.contains("Hello"));
+        assert!(text.contains("https://example.com"));
+        assert!(!text.contains("alert"));
+        assert!(!text.contains("color: red"));
+    }
+}
@@ -0,0 +1,118 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::Deserialize;
+
+use super::{Action, Namespace};
+use crate::agent::state::SharedState;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Configuration {
+    // names of the variables that can be read
+    #[serde(default)]
+    pub allow: Vec<String>,
+}
+
+fn get_env(config: &Configuration, name: &str) -> Result<String> {
+    let name = name.trim();
+    if !config.allow.iter().any(|allowed| allowed == name) {
+        return Err(anyhow!(
+            "'{}' is not allowed, allowed variables are: {}",
+            name,
+            if config.allow.is_empty() {
+                "none".to_string()
+            } else {
+                config.allow.join(", ")
+            }
+        ));
+    }
+
+    std::env::var(name).map_err(|_| anyhow!("'{}' is not set", name))
+}
+
+#[derive(Debug, Default, Clone)]
+struct GetEnv {}
+
+#[async_trait]
+impl Action for GetEnv {
+    fn name(&self) -> &str {
+        "get-env"
+    }
+
+    fn is_parallel_safe(&self) -> bool {
+        true
+    }
+
+    fn description(&self) -> &str {
+        include_str!("get_env.prompt")
+    }
+
+    fn example_payload(&self) -> Option<&str> {
+        Some("API_BASE_URL")
+    }
+
+    async fn run(
+        &self,
+        state: SharedState,
+        _: Option<HashMap<String, String>>,
+        payload: Option<String>,
+    ) -> Result<Option<String>> {
+        let config = state
+            .lock()
+            .await
+            .get_task()
+            .get_env_config()
+            .unwrap_or_default();
+
+        Ok(Some(get_env(&config, &payload.unwrap())?))
+    }
+}
+
+pub(crate) fn get_namespace() -> Namespace {
+    Namespace::new_non_default(
+        "Environment".to_string(),
+        include_str!("ns.prompt").to_string(),
+        vec![Box::<GetEnv>::default()],
+        None,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> Configuration {
+        Configuration {
+            allow: vec![
+                "NERVE_TEST_ENV_SET".to_string(),
+                "NERVE_TEST_ENV_UNSET".to_string(),
+            ],
+        }
+    }
+
+    #[test]
+    fn test_get_env_allowed() {
+        std::env::set_var("NERVE_TEST_ENV_SET", "http://localhost:8080");
+        assert_eq!(
+            get_env(&config(), " NERVE_TEST_ENV_SET\n").unwrap(),
+            "http://localhost:8080"
+        );
+    }
+
+    #[test]
+    fn test_get_env_denied() {
+        std::env::set_var("NERVE_TEST_ENV_DENIED", "secret");
+        let err = get_env(&config(), "NERVE_TEST_ENV_DENIED").unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("'NERVE_TEST_ENV_DENIED' is not allowed"));
+        assert!(get_env(&Configuration::default(), "NERVE_TEST_ENV_SET").is_err());
+    }
+
+    #[test]
+    fn test_get_env_unset() {
+        let err = get_env(&config(), "NERVE_TEST_ENV_UNSET").unwrap_err();
+        assert_eq!(err.to_string(), "'NERVE_TEST_ENV_UNSET' is not set");
+    }
+}
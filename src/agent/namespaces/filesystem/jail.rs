@@ -0,0 +1,118 @@
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::Result;
+
+// resolve a path from the model and make sure it lies within the jail, if any; symlinks are
+// resolved so that they can't be used to escape it, the path itself doesn't need to exist
+pub(crate) fn confine(jail: Option<&str>, path: &str) -> Result<PathBuf> {
+    let jail = if let Some(jail) = jail {
+        Path::new(jail)
+            .canonicalize()
+            .map_err(|e| anyhow!("can't resolve jail folder {}: {}", jail, e))?
+    } else {
+        return Ok(PathBuf::from(path));
+    };
+
+    // relative paths are relative to the jail
+    let requested = Path::new(path);
+    let requested = if requested.is_absolute() {
+        requested.to_path_buf()
+    } else {
+        jail.join(requested)
+    };
+
+    // canonicalize the longest existing ancestor and append the rest
+    let mut existing = requested.as_path();
+    let mut rest = vec![];
+    let resolved = loop {
+        match existing.canonicalize() {
+            Ok(resolved) => break resolved,
+            Err(_) => {
+                // a dangling symlink can't be resolved but writing through it would follow it
+                if existing
+                    .symlink_metadata()
+                    .is_ok_and(|meta| meta.file_type().is_symlink())
+                {
+                    return Err(anyhow!(
+                        "access to {} denied, {} is a symlink to a missing path",
+                        path,
+                        existing.display()
+                    ));
+                }
+
+                if let (Some(parent), Some(name)) = (existing.parent(), existing.file_name()) {
+                    rest.push(name.to_owned());
+                    existing = parent;
+                } else {
+                    break PathBuf::new();
+                }
+            }
+        }
+    };
+
+    let mut resolved = resolved;
+    for name in rest.into_iter().rev() {
+        resolved.push(name);
+    }
+
+    // leftover .. components of non existing parts could still escape
+    if resolved.components().any(|c| c == Component::ParentDir) || !resolved.starts_with(&jail) {
+        return Err(anyhow!(
+            "access to {} denied, only paths inside {} are allowed",
+            path,
+            jail.display()
+        ));
+    }
+
+    Ok(resolved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_confine() {
+        let jail = std::env::temp_dir().join(format!("nerve-jail-test-{}", std::process::id()));
+        std::fs::create_dir_all(jail.join("sub")).unwrap();
+        let jail_path = jail.canonicalize().unwrap();
+        let jail_str = jail.to_str().unwrap();
+
+        assert_eq!(
+            confine(None, "/etc/passwd").unwrap(),
+            PathBuf::from("/etc/passwd")
+        );
+        assert_eq!(
+            confine(Some(jail_str), "sub/new.txt").unwrap(),
+            jail_path.join("sub/new.txt")
+        );
+        assert!(confine(Some(jail_str), "/etc/passwd").is_err());
+        assert!(confine(Some(jail_str), "sub/../../escape.txt").is_err());
+        assert!(confine(Some(jail_str), "missing/../../escape.txt").is_err());
+
+        std::os::unix::fs::symlink("/etc", jail.join("link")).unwrap();
+        assert!(confine(Some(jail_str), "link/passwd").is_err());
+
+        // dangling symlinks, as the target or as a parent folder
+        std::os::unix::fs::symlink("/tmp/nerve-jail-escape", jail.join("dangling")).unwrap();
+        assert!(confine(Some(jail_str), "dangling").is_err());
+        assert!(confine(Some(jail_str), "dangling/new.txt").is_err());
+        assert!(confine(Some(jail_str), "sub/../dangling").is_err());
+
+        std::fs::remove_dir_all(&jail).unwrap();
+    }
+
+    #[test]
+    fn test_confine_system_files() {
+        let jail = std::env::temp_dir().join(format!("work-{}", std::process::id()));
+        std::fs::create_dir_all(&jail).unwrap();
+        let jail_str = jail.to_str().unwrap();
+
+        assert!(confine(Some(jail_str), "/etc/passwd").is_err());
+        assert!(confine(Some(jail_str), "../../../../etc/passwd").is_err());
+        assert!(confine(Some(jail_str), "notes/../../etc/passwd").is_err());
+        assert!(confine(Some(jail_str), "notes.txt").is_ok());
+
+        std::fs::remove_dir_all(&jail).unwrap();
+    }
+}
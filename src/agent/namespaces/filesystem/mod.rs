@@ -1,6 +1,8 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs::{self, FileType};
-use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt};
+use std::path::PathBuf;
 
 use async_trait::async_trait;
 use chrono::{DateTime, Local};
@@ -9,7 +11,20 @@ use libc::{S_IRGRP, S_IROTH, S_IRUSR, S_IWGRP, S_IWOTH, S_IWUSR, S_IXGRP, S_IXOT
 use anyhow::Result;
 
 use super::{Action, Namespace};
-use crate::agent::state::SharedState;
+use crate::agent::state::{FollowedFile, SharedState};
+
+pub(crate) mod jail;
+
+// resolve a path from the model within the jail of the task, if any
+async fn confined(state: &SharedState, path: &str) -> Result<PathBuf> {
+    let jail = state.lock().await.get_task().get_jail();
+    jail::confine(jail.as_deref(), path)
+}
+
+// boolean attributes are only set if explicitly true
+fn is_true(attrs: &HashMap<String, String>, name: &str) -> bool {
+    attrs.get(name).map(|v| v.trim() == "true") == Some(true)
+}
 
 // cast needed for Darwin apparently
 #[allow(clippy::unnecessary_cast)]
@@ -55,6 +70,112 @@ fn triplet(mode: u32, read: u32, write: u32, execute: u32) -> String {
     .to_string()
 }
 
+// describe a folder entry, fields that can't be read (broken symlinks, filesystems without
+// modification times, ...) are replaced by '?'
+fn describe_entry(entry: &fs::DirEntry) -> String {
+    let path = entry.path();
+    let full_path = path.canonicalize().unwrap_or_else(|e| {
+        log::warn!("can't resolve {}: {}", path.display(), e);
+        path.clone()
+    });
+
+    // for broken symlinks describe the link itself
+    let metadata = entry.metadata().or_else(|_| fs::symlink_metadata(&path));
+    match metadata {
+        Ok(metadata) => {
+            let modified = match metadata.modified() {
+                Ok(modified) => DateTime::<Local>::from(modified)
+                    .format("%_d %b %H:%M")
+                    .to_string(),
+                Err(e) => {
+                    log::warn!("can't get modification time of {}: {}", path.display(), e);
+                    "?".to_string()
+                }
+            };
+
+            format!(
+                "{} {:>5} {} [{}] {}\n",
+                parse_permissions(metadata.permissions().mode()),
+                metadata.len(),
+                modified,
+                parse_type(metadata.file_type()),
+                full_path.display()
+            )
+        }
+        Err(e) => {
+            log::warn!("can't read metadata of {}: {}", path.display(), e);
+            format!("? ? ? [unknown] {}\n", full_path.display())
+        }
+    }
+}
+
+// maximum number of entries listed by read-folder
+const MAX_FOLDER_ENTRIES: usize = 1000;
+
+// list the entries of a folder and of its subfolders up to max_depth levels, returns whether
+// the entries limit was reached
+fn list_folder(
+    path: &std::path::Path,
+    depth: usize,
+    max_depth: usize,
+    visited: &mut HashSet<PathBuf>,
+    output: &mut String,
+    entries: &mut usize,
+) -> Result<bool> {
+    // symlinks could lead to a folder that is being listed already
+    if let Ok(canonical) = path.canonicalize() {
+        if !visited.insert(canonical) {
+            return Ok(false);
+        }
+    }
+
+    for entry in fs::read_dir(path)? {
+        match entry {
+            Ok(entry) => {
+                if *entries >= MAX_FOLDER_ENTRIES {
+                    return Ok(true);
+                }
+
+                *output += &describe_entry(&entry);
+                *entries += 1;
+
+                if depth < max_depth && entry.path().is_dir() {
+                    let sub = entry.path();
+                    match list_folder(&sub, depth + 1, max_depth, visited, output, entries) {
+                        Ok(true) => return Ok(true),
+                        Ok(false) => {}
+                        Err(e) => log::error!("can't read {}: {}", sub.display(), e),
+                    }
+                }
+            }
+            Err(e) => log::error!("can't read entry of {}: {}", path.display(), e),
+        }
+    }
+
+    Ok(false)
+}
+
+fn read_folder(folder: &str, path: &std::path::Path, max_depth: usize) -> Result<String> {
+    let mut output = format!("Contents of {} :\n\n", folder);
+    let mut entries = 0;
+
+    let truncated = list_folder(
+        path,
+        1,
+        max_depth,
+        &mut HashSet::new(),
+        &mut output,
+        &mut entries,
+    )
+    .map_err(|e| anyhow!("can't read {}: {}", folder, e))?;
+
+    if truncated {
+        output += &format!("\n(only the first {} entries are shown)\n", entries);
+    }
+
+    Ok(output)
+}
+
 #[derive(Debug, Default, Clone)]
 struct ReadFolder {}
 
@@ -64,50 +185,117 @@ impl Action for ReadFolder {
         "read-folder"
     }
 
+    fn is_parallel_safe(&self) -> bool {
+        true
+    }
+
     fn description(&self) -> &str {
         include_str!("read_folder.prompt")
     }
 
+    fn optional_attributes(&self) -> Option<HashMap<String, String>> {
+        let mut attributes = HashMap::new();
+
+        attributes.insert("recursive".to_string(), "true".to_string());
+        attributes.insert("max-depth".to_string(), "3".to_string());
+
+        Some(attributes)
+    }
+
     fn example_payload(&self) -> Option<&str> {
         Some("/path/to/folder")
     }
 
     async fn run(
         &self,
-        _: SharedState,
-        _: Option<HashMap<String, String>>,
+        state: SharedState,
+        attributes: Option<HashMap<String, String>>,
         payload: Option<String>,
     ) -> Result<Option<String>> {
         // adapted from https://gist.github.com/mre/91ebb841c34df69671bd117ead621a8b
         let folder = payload.unwrap();
-        let ret = fs::read_dir(&folder);
-        if let Ok(paths) = ret {
-            let mut output = format!("Contents of {} :\n\n", &folder);
-
-            for path in paths {
-                if let Ok(entry) = path {
-                    let full_path = entry.path().canonicalize().unwrap();
-                    let metadata = entry.metadata().unwrap();
-                    let size = metadata.len();
-                    let modified: DateTime<Local> = DateTime::from(metadata.modified().unwrap());
-                    let mode = metadata.permissions().mode();
-
-                    output += &format!(
-                        "{} {:>5} {} [{}] {}\n",
-                        parse_permissions(mode),
-                        size,
-                        modified.format("%_d %b %H:%M"),
-                        parse_type(metadata.file_type()),
-                        full_path.display()
-                    );
-                } else {
-                    log::error!("{:?}", path);
-                }
-            }
+        let path = confined(&state, &folder).await?;
 
-            Ok(Some(output))
+        let attrs = attributes.unwrap_or_default();
+        let recursive = is_true(&attrs, "recursive");
+        let max_depth = if !recursive {
+            1
+        } else if let Some(depth) = attrs.get("max-depth") {
+            depth
+                .trim()
+                .parse::<usize>()
+                .ok()
+                .filter(|depth| *depth > 0)
+                .ok_or_else(|| anyhow!("invalid max-depth '{}'", depth))?
         } else {
-            Err(anyhow!("can't read {}: {:?}", folder, ret))
+            // bound by the entries limit
+            usize::MAX
+        };
+
+        Ok(Some(read_folder(&folder, &path, max_depth)?))
+    }
+}
+
+// part of a file to read
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ReadRange {
+    // 1-based lines, both included
+    Lines(usize, Option<usize>),
+    // byte offsets, end excluded
+    Bytes(usize, usize),
+}
+
+impl ReadRange {
+    fn parse(start: Option<&String>, end: Option<&String>) -> Result<Option<Self>> {
+        let parse_num = |what: &str, value: &str| {
+            value
+                .trim()
+                .parse::<usize>()
+                .map_err(|_| anyhow!("invalid {} value '{}'", what, value))
+        };
+
+        if let Some(bytes) = start.and_then(|s| s.trim().strip_prefix("bytes:")) {
+            let (from, to) = bytes
+                .split_once('-')
+                .ok_or_else(|| anyhow!("invalid byte range '{}', use bytes:START-END", bytes))?;
+            return Ok(Some(Self::Bytes(
+                parse_num("start", from)?,
+                parse_num("end", to)?,
+            )));
+        }
+
+        match (start, end) {
+            (None, None) => Ok(None),
+            (start, end) => Ok(Some(Self::Lines(
+                start
+                    .map(|s| parse_num("start", s))
+                    .transpose()?
+                    .unwrap_or(1),
+                end.map(|e| parse_num("end", e)).transpose()?,
+            ))),
+        }
+    }
+
+    // out of range values are clamped to the size of the data
+    fn apply(&self, data: &[u8]) -> String {
+        match *self {
+            Self::Bytes(from, to) => {
+                let to = to.min(data.len());
+                let from = from.min(to);
+                String::from_utf8_lossy(&data[from..to]).to_string()
+            }
+            Self::Lines(from, to) => {
+                let text = String::from_utf8_lossy(data);
+                let from = from.max(1);
+                let to = to.unwrap_or(usize::MAX);
+                if to < from {
+                    return "".to_string();
+                }
+                text.split_inclusive('\n')
+                    .skip(from - 1)
+                    .take(to - from + 1)
+                    .collect()
+            }
         }
     }
 }
@@ -121,36 +309,1059 @@ impl Action for ReadFile {
         "read-file"
     }
 
+    fn is_parallel_safe(&self) -> bool {
+        true
+    }
+
     fn description(&self) -> &str {
         include_str!("read_file.prompt")
     }
 
+    fn optional_attributes(&self) -> Option<HashMap<String, String>> {
+        let mut attributes = HashMap::new();
+
+        attributes.insert("start".to_string(), "1".to_string());
+        attributes.insert("end".to_string(), "100".to_string());
+
+        Some(attributes)
+    }
+
     fn example_payload(&self) -> Option<&str> {
         Some("/path/to/file/to/read")
     }
 
     async fn run(
         &self,
-        _: SharedState,
+        state: SharedState,
+        attributes: Option<HashMap<String, String>>,
+        payload: Option<String>,
+    ) -> Result<Option<String>> {
+        let filepath = confined(&state, &payload.unwrap()).await?;
+        let range = if let Some(attrs) = &attributes {
+            ReadRange::parse(attrs.get("start"), attrs.get("end"))?
+        } else {
+            None
+        };
+
+        if let Some(range) = range {
+            let raw = std::fs::read(filepath)?;
+            let contents = range.apply(&raw);
+            if contents.is_empty() {
+                Ok(Some(format!(
+                    "nothing to read in the given range, the file is {} bytes",
+                    raw.len()
+                )))
+            } else {
+                Ok(Some(contents))
+            }
+        } else {
+            let ret = std::fs::read_to_string(filepath);
+            if let Ok(contents) = ret {
+                Ok(Some(contents))
+            } else {
+                let err = ret.err().unwrap();
+                Err(anyhow!(err))
+            }
+        }
+    }
+}
+
+// read the complete lines added to a file since the previous read, starting over if the file
+// has been rotated or truncated in the meantime
+fn follow(
+    path: &std::path::Path,
+    previous: Option<FollowedFile>,
+) -> Result<(FollowedFile, Vec<u8>)> {
+    let mut file = fs::File::open(path)?;
+    let metadata = file.metadata()?;
+    let inode = metadata.ino();
+    let size = metadata.len();
+
+    let mut offset = 0;
+    if let Some(previous) = previous {
+        if previous.inode != inode {
+            log::info!(
+                "{} has been rotated, reading from the start",
+                path.display()
+            );
+        } else if previous.offset > size {
+            log::info!(
+                "{} has been truncated, reading from the start",
+                path.display()
+            );
+        } else {
+            offset = previous.offset;
+        }
+    }
+
+    file.seek(SeekFrom::Start(offset))?;
+    let mut raw = vec![];
+    file.read_to_end(&mut raw)?;
+
+    // only consume complete lines, a partial one will be returned once terminated
+    let consumed = if let Some(last_newline) = raw.iter().rposition(|b| *b == b'\n') {
+        last_newline + 1
+    } else {
+        0
+    };
+    raw.truncate(consumed);
+
+    Ok((
+        FollowedFile {
+            inode,
+            offset: offset + consumed as u64,
+        },
+        raw,
+    ))
+}
+
+#[derive(Debug, Default, Clone)]
+struct FollowFile {}
+
+#[async_trait]
+impl Action for FollowFile {
+    fn name(&self) -> &str {
+        "follow-file"
+    }
+
+    fn description(&self) -> &str {
+        include_str!("follow_file.prompt")
+    }
+
+    fn example_payload(&self) -> Option<&str> {
+        Some("/path/to/file/to/follow.log")
+    }
+
+    async fn run(
+        &self,
+        state: SharedState,
         _: Option<HashMap<String, String>>,
         payload: Option<String>,
     ) -> Result<Option<String>> {
         let filepath = payload.unwrap();
-        let ret = std::fs::read_to_string(filepath);
-        if let Ok(contents) = ret {
-            Ok(Some(contents))
+        // the same file can be referred to in different ways
+        let path = confined(&state, &filepath).await?.canonicalize()?;
+        let key = path.display().to_string();
+
+        let previous = state.lock().await.get_followed_file(&key);
+        let (followed, lines) = follow(&path, previous)?;
+
+        state
+            .lock()
+            .await
+            .set_followed_file(&key, followed.inode, followed.offset);
+
+        if lines.is_empty() {
+            Ok(Some(format!("no new lines in {}", &filepath)))
         } else {
-            let err = ret.err().unwrap();
-            Err(anyhow!(err))
+            Ok(Some(String::from_utf8_lossy(&lines).to_string()))
         }
     }
 }
 
+// write the data to a file creating the parent folders if needed
+fn write_file(path: &std::path::Path, data: &str) -> Result<usize> {
+    if let Some(parent) = path.parent() {
+        if !parent.as_os_str().is_empty() {
+            fs::create_dir_all(parent)
+                .map_err(|e| anyhow!("can't create {}: {}", parent.display(), e))?;
+        }
+    }
+
+    fs::write(path, data).map_err(|e| anyhow!("can't write {}: {}", path.display(), e))?;
+
+    Ok(data.len())
+}
+
+#[derive(Debug, Default, Clone)]
+struct WriteFile {}
+
+#[async_trait]
+impl Action for WriteFile {
+    fn name(&self) -> &str {
+        "write-file"
+    }
+
+    fn description(&self) -> &str {
+        include_str!("write_file.prompt")
+    }
+
+    fn attributes(&self) -> Option<HashMap<String, String>> {
+        let mut attributes = HashMap::new();
+
+        attributes.insert("path".to_string(), "/path/to/file.txt".to_string());
+
+        Some(attributes)
+    }
+
+    fn example_payload(&self) -> Option<&str> {
+        Some("the content of the file")
+    }
+
+    async fn run(
+        &self,
+        state: SharedState,
+        attributes: Option<HashMap<String, String>>,
+        payload: Option<String>,
+    ) -> Result<Option<String>> {
+        let attrs = attributes.unwrap();
+        let path = confined(&state, attrs.get("path").unwrap()).await?;
+
+        let written = write_file(&path, &payload.unwrap())?;
+
+        Ok(Some(format!(
+            "{} bytes written to {}",
+            written,
+            path.display()
+        )))
+    }
+}
+
+// make sure the data parses in the given format before it's written
+fn validate_structured(format: &str, data: &str) -> Result<()> {
+    match format.to_lowercase().as_str() {
+        "json" => {
+            serde_json::from_str::<serde_json::Value>(data)
+                .map_err(|e| anyhow!("invalid json: {}", e))?;
+        }
+        "csv" => {
+            let mut reader = csv::ReaderBuilder::new()
+                .has_headers(false)
+                .from_reader(data.as_bytes());
+            let mut rows = 0;
+            for record in reader.records() {
+                record.map_err(|e| anyhow!("invalid csv: {}", e))?;
+                rows += 1;
+            }
+            if rows == 0 {
+                return Err(anyhow!("invalid csv: no rows"));
+            }
+        }
+        _ => return Err(anyhow!("unsupported format '{}', use csv or json", format)),
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Default, Clone)]
+struct WriteStructured {}
+
+#[async_trait]
+impl Action for WriteStructured {
+    fn name(&self) -> &str {
+        "write-structured"
+    }
+
+    fn description(&self) -> &str {
+        include_str!("write_structured.prompt")
+    }
+
+    fn attributes(&self) -> Option<HashMap<String, String>> {
+        let mut attributes = HashMap::new();
+
+        attributes.insert("path".to_string(), "/path/to/output.json".to_string());
+        attributes.insert("format".to_string(), "json".to_string());
+
+        Some(attributes)
+    }
+
+    fn example_payload(&self) -> Option<&str> {
+        Some("{\"name\": \"foo\"}")
+    }
+
+    async fn run(
+        &self,
+        state: SharedState,
+        attributes: Option<HashMap<String, String>>,
+        payload: Option<String>,
+    ) -> Result<Option<String>> {
+        let attrs = attributes.unwrap();
+        let path = confined(&state, attrs.get("path").unwrap()).await?;
+        let format = attrs.get("format").unwrap();
+        let data = format!("{}\n", payload.unwrap().trim());
+
+        validate_structured(format, &data)?;
+
+        fs::write(&path, &data)?;
+
+        Ok(Some(format!(
+            "{} bytes written to {}",
+            data.len(),
+            path.display()
+        )))
+    }
+}
+
+// check that the source is a file and that the destination can be written, creating its
+// parent folders if needed
+fn prepare_transfer(from: &std::path::Path, to: &std::path::Path, overwrite: bool) -> Result<()> {
+    if !from.exists() {
+        return Err(anyhow!("{} does not exist", from.display()));
+    } else if !from.is_file() {
+        return Err(anyhow!("{} is not a file", from.display()));
+    } else if to.is_dir() {
+        return Err(anyhow!("{} is a folder", to.display()));
+    } else if to.exists() && !overwrite {
+        return Err(anyhow!(
+            "{} already exists, set overwrite to true to replace it",
+            to.display()
+        ));
+    }
+
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|e| anyhow!("can't create folder {}: {}", parent.display(), e))?;
+    }
+
+    Ok(())
+}
+
+// copy a file in chunks, refusing to replace the destination unless overwrite is set
+fn copy_file(from: &std::path::Path, to: &std::path::Path, overwrite: bool) -> Result<u64> {
+    prepare_transfer(from, to, overwrite)?;
+    // creating the destination would truncate the source
+    if to.exists() && from.canonicalize()? == to.canonicalize()? {
+        return Err(anyhow!(
+            "{} and {} are the same file",
+            from.display(),
+            to.display()
+        ));
+    }
+
+    let mut reader = BufReader::new(fs::File::open(from)?);
+    let mut writer = BufWriter::new(fs::File::create(to)?);
+    let copied = std::io::copy(&mut reader, &mut writer)?;
+    writer.flush()?;
+
+    Ok(copied)
+}
+
+#[derive(Debug, Default, Clone)]
+struct CopyFile {}
+
+#[async_trait]
+impl Action for CopyFile {
+    fn name(&self) -> &str {
+        "copy-file"
+    }
+
+    fn description(&self) -> &str {
+        include_str!("copy_file.prompt")
+    }
+
+    fn attributes(&self) -> Option<HashMap<String, String>> {
+        let mut attributes = HashMap::new();
+
+        attributes.insert("from".to_string(), "/path/to/source".to_string());
+        attributes.insert("to".to_string(), "/path/to/destination".to_string());
+
+        Some(attributes)
+    }
+
+    fn optional_attributes(&self) -> Option<HashMap<String, String>> {
+        let mut attributes = HashMap::new();
+
+        attributes.insert("overwrite".to_string(), "false".to_string());
+
+        Some(attributes)
+    }
+
+    async fn run(
+        &self,
+        state: SharedState,
+        attributes: Option<HashMap<String, String>>,
+        _: Option<String>,
+    ) -> Result<Option<String>> {
+        let attrs = attributes.unwrap();
+        let from = confined(&state, attrs.get("from").unwrap()).await?;
+        let to = confined(&state, attrs.get("to").unwrap()).await?;
+        let overwrite = is_true(&attrs, "overwrite");
+
+        let copied = copy_file(&from, &to, overwrite)?;
+
+        Ok(Some(format!("copied {} bytes to {}", copied, to.display())))
+    }
+}
+
+// default maximum number of matches returned by find-in-files
+const MAX_FIND_MATCHES: usize = 100;
+
+// search the lines matching the pattern in every file, returns them as path:line:text and
+// whether there were more matches than the limit
+fn find_in_files(
+    pattern: &str,
+    paths: impl Iterator<Item = PathBuf>,
+    limit: usize,
+) -> Result<(Vec<String>, bool)> {
+    let re = regex::Regex::new(pattern).map_err(|e| anyhow!("invalid pattern: {}", e))?;
+    let mut matches = vec![];
+
+    for path in paths {
+        if !path.is_file() {
+            continue;
+        }
+
+        // binary and unreadable files are skipped
+        let data = match fs::read_to_string(&path) {
+            Ok(data) => data,
+            Err(e) => {
+                log::debug!("skipping {}: {}", path.display(), e);
+                continue;
+            }
+        };
+
+        for (idx, line) in data.lines().enumerate() {
+            if re.is_match(line) {
+                if matches.len() >= limit {
+                    return Ok((matches, true));
+                }
+                matches.push(format!(
+                    "{}:{}: {}",
+                    path.display(),
+                    idx + 1,
+                    line.trim_end()
+                ));
+            }
+        }
+    }
+
+    Ok((matches, false))
+}
+
+#[derive(Debug, Default, Clone)]
+struct FindInFiles {}
+
+#[async_trait]
+impl Action for FindInFiles {
+    fn name(&self) -> &str {
+        "find-in-files"
+    }
+
+    fn is_parallel_safe(&self) -> bool {
+        true
+    }
+
+    fn description(&self) -> &str {
+        include_str!("find_in_files.prompt")
+    }
+
+    fn attributes(&self) -> Option<HashMap<String, String>> {
+        let mut attributes = HashMap::new();
+
+        attributes.insert("pattern".to_string(), r"fn \w+\(".to_string());
+
+        Some(attributes)
+    }
+
+    fn optional_attributes(&self) -> Option<HashMap<String, String>> {
+        let mut attributes = HashMap::new();
+
+        attributes.insert("limit".to_string(), MAX_FIND_MATCHES.to_string());
+
+        Some(attributes)
+    }
+
+    fn example_payload(&self) -> Option<&str> {
+        Some("/path/to/project/**/*.rs")
+    }
+
+    async fn run(
+        &self,
+        state: SharedState,
+        attributes: Option<HashMap<String, String>>,
+        payload: Option<String>,
+    ) -> Result<Option<String>> {
+        let attrs = attributes.unwrap();
+        let pattern = attrs.get("pattern").unwrap();
+        let limit = match attrs.get("limit") {
+            Some(limit) => limit
+                .trim()
+                .parse::<usize>()
+                .map_err(|_| anyhow!("invalid limit '{}'", limit))?,
+            None => MAX_FIND_MATCHES,
+        };
+
+        let jail = state.lock().await.get_task().get_jail();
+        let files = confined(&state, payload.unwrap().trim()).await?;
+        let files = files
+            .to_str()
+            .ok_or_else(|| anyhow!("invalid path {}", files.display()))?;
+
+        // matched paths are confined as well since symlinks could point outside of the jail
+        let paths = glob::glob(files)
+            .map_err(|e| anyhow!("invalid glob pattern '{}': {}", files, e))?
+            .flatten()
+            .filter(|path| match path.to_str() {
+                Some(path) => jail::confine(jail.as_deref(), path).is_ok(),
+                None => false,
+            });
+
+        let (matches, truncated) = find_in_files(pattern, paths, limit)?;
+        if matches.is_empty() {
+            return Err(anyhow!("no matches found for '{}' in {}", pattern, files));
+        }
+
+        let mut output = matches.join("\n");
+        if truncated {
+            output += &format!("\n(only the first {} matches are shown)", limit);
+        }
+
+        Ok(Some(output))
+    }
+}
+
+// rename the file, or copy it and delete the source when moving across filesystems
+fn move_file(from: &std::path::Path, to: &std::path::Path, overwrite: bool) -> Result<()> {
+    prepare_transfer(from, to, overwrite)?;
+
+    match fs::rename(from, to) {
+        Ok(_) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => copy_file(from, to, true)
+            .and_then(|_| fs::remove_file(from).map_err(anyhow::Error::from))
+            .map_err(|e| {
+                anyhow!(
+                    "can't move {} to {} across filesystems: {}",
+                    from.display(),
+                    to.display(),
+                    e
+                )
+            }),
+        Err(e) => Err(anyhow!(
+            "can't move {} to {}: {}",
+            from.display(),
+            to.display(),
+            e
+        )),
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+struct MoveFile {}
+
+#[async_trait]
+impl Action for MoveFile {
+    fn name(&self) -> &str {
+        "move-file"
+    }
+
+    fn description(&self) -> &str {
+        include_str!("move_file.prompt")
+    }
+
+    fn attributes(&self) -> Option<HashMap<String, String>> {
+        let mut attributes = HashMap::new();
+
+        attributes.insert("from".to_string(), "/path/to/source".to_string());
+        attributes.insert("to".to_string(), "/path/to/destination".to_string());
+
+        Some(attributes)
+    }
+
+    fn optional_attributes(&self) -> Option<HashMap<String, String>> {
+        let mut attributes = HashMap::new();
+
+        attributes.insert("overwrite".to_string(), "false".to_string());
+
+        Some(attributes)
+    }
+
+    async fn run(
+        &self,
+        state: SharedState,
+        attributes: Option<HashMap<String, String>>,
+        _: Option<String>,
+    ) -> Result<Option<String>> {
+        let attrs = attributes.unwrap();
+        let from = confined(&state, attrs.get("from").unwrap()).await?;
+        let to = confined(&state, attrs.get("to").unwrap()).await?;
+        let overwrite = is_true(&attrs, "overwrite");
+
+        move_file(&from, &to, overwrite)?;
+
+        Ok(Some(format!(
+            "moved {} to {}",
+            from.display(),
+            to.display()
+        )))
+    }
+}
+
+// delete a file or a symlink, only if confirmed
+fn delete_file(path: &std::path::Path, confirm: bool) -> Result<()> {
+    let metadata = fs::symlink_metadata(path)
+        .map_err(|e| anyhow!("can't access {}: {}", path.display(), e))?;
+    if metadata.is_dir() {
+        return Err(anyhow!(
+            "{} is a folder, use delete-folder instead",
+            path.display()
+        ));
+    } else if !confirm {
+        return Err(anyhow!(
+            "deleting {} can't be undone, set confirm to true if you are sure",
+            path.display()
+        ));
+    }
+
+    fs::remove_file(path).map_err(|e| anyhow!("can't delete {}: {}", path.display(), e))
+}
+
+// delete a folder only if confirmed, and its contents only if recursive
+// root is the folder the agent works in, the jail or the current one, which is never deleted
+fn delete_folder(
+    path: &std::path::Path,
+    root: &std::path::Path,
+    confirm: bool,
+    recursive: bool,
+) -> Result<()> {
+    let metadata = fs::symlink_metadata(path)
+        .map_err(|e| anyhow!("can't access {}: {}", path.display(), e))?;
+    let resolved = path.canonicalize()?;
+    if resolved.parent().is_none() || resolved == root {
+        return Err(anyhow!(
+            "deleting {} is not allowed, only its subfolders can be deleted",
+            path.display()
+        ));
+    } else if !metadata.is_dir() {
+        return Err(anyhow!(
+            "{} is not a folder, use delete-file instead",
+            path.display()
+        ));
+    } else if !confirm {
+        return Err(anyhow!(
+            "deleting {} can't be undone, set confirm to true if you are sure",
+            path.display()
+        ));
+    }
+
+    let empty = fs::read_dir(path)?.next().is_none();
+    if empty {
+        fs::remove_dir(path)
+    } else if recursive {
+        fs::remove_dir_all(path)
+    } else {
+        return Err(anyhow!(
+            "{} is not empty, set recursive to true to also delete its contents",
+            path.display()
+        ));
+    }
+    .map_err(|e| anyhow!("can't delete {}: {}", path.display(), e))
+}
+
+#[derive(Debug, Default, Clone)]
+struct DeleteFile {}
+
+#[async_trait]
+impl Action for DeleteFile {
+    fn name(&self) -> &str {
+        "delete-file"
+    }
+
+    fn description(&self) -> &str {
+        include_str!("delete_file.prompt")
+    }
+
+    // confirm is left out of the examples so that it's not set without a reason
+    fn optional_attributes(&self) -> Option<HashMap<String, String>> {
+        let mut attributes = HashMap::new();
+
+        attributes.insert("confirm".to_string(), "false".to_string());
+
+        Some(attributes)
+    }
+
+    fn example_payload(&self) -> Option<&str> {
+        Some("/path/to/file/to/delete")
+    }
+
+    async fn run(
+        &self,
+        state: SharedState,
+        attributes: Option<HashMap<String, String>>,
+        payload: Option<String>,
+    ) -> Result<Option<String>> {
+        let attrs = attributes.unwrap_or_default();
+        let path = confined(&state, payload.unwrap().trim()).await?;
+
+        delete_file(&path, is_true(&attrs, "confirm"))?;
+
+        Ok(Some(format!("deleted {}", path.display())))
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+struct DeleteFolder {}
+
+#[async_trait]
+impl Action for DeleteFolder {
+    fn name(&self) -> &str {
+        "delete-folder"
+    }
+
+    fn description(&self) -> &str {
+        include_str!("delete_folder.prompt")
+    }
+
+    fn optional_attributes(&self) -> Option<HashMap<String, String>> {
+        let mut attributes = HashMap::new();
+
+        attributes.insert("confirm".to_string(), "false".to_string());
+        attributes.insert("recursive".to_string(), "false".to_string());
+
+        Some(attributes)
+    }
+
+    fn example_payload(&self) -> Option<&str> {
+        Some("/path/to/folder/to/delete")
+    }
+
+    async fn run(
+        &self,
+        state: SharedState,
+        attributes: Option<HashMap<String, String>>,
+        payload: Option<String>,
+    ) -> Result<Option<String>> {
+        let attrs = attributes.unwrap_or_default();
+        let path = confined(&state, payload.unwrap().trim()).await?;
+        let root = match state.lock().await.get_task().get_jail() {
+            Some(jail) => std::path::Path::new(&jail).canonicalize(),
+            None => std::env::current_dir(),
+        }?;
+
+        delete_folder(
+            &path,
+            &root,
+            is_true(&attrs, "confirm"),
+            is_true(&attrs, "recursive"),
+        )?;
+
+        Ok(Some(format!("deleted {}", path.display())))
+    }
+}
+
 pub(crate) fn get_namespace() -> Namespace {
     Namespace::new_non_default(
         "Filesystem".to_string(),
         include_str!("ns.prompt").to_string(),
-        vec![Box::<ReadFile>::default(), Box::<ReadFolder>::default()],
+        vec![
+            Box::<ReadFile>::default(),
+            Box::<ReadFolder>::default(),
+            Box::<FindInFiles>::default(),
+            Box::<FollowFile>::default(),
+            Box::<WriteFile>::default(),
+            Box::<WriteStructured>::default(),
+            Box::<CopyFile>::default(),
+            Box::<MoveFile>::default(),
+            Box::<DeleteFile>::default(),
+            Box::<DeleteFolder>::default(),
+        ],
         None,
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_structured() {
+        assert!(validate_structured("json", "{\"a\": [1, 2]}").is_ok());
+        assert!(validate_structured("json", "{\"a\": [1, 2}").is_err());
+        assert!(validate_structured("CSV", "name,age\nfoo,1\n\"bar, baz\",2\n").is_ok());
+        assert!(validate_structured("csv", "name,age\nfoo\n").is_err());
+        assert!(validate_structured("xml", "<a/>").is_err());
+    }
+
+    #[test]
+    fn test_read_range() {
+        let data = b"one\ntwo\nthree\nfour\n";
+        let parse = |start: Option<&str>, end: Option<&str>| {
+            ReadRange::parse(
+                start.map(|s| s.to_string()).as_ref(),
+                end.map(|e| e.to_string()).as_ref(),
+            )
+        };
+
+        assert_eq!(parse(None, None).unwrap(), None);
+        assert!(parse(Some("nope"), None).is_err());
+        assert!(parse(Some("bytes:10"), None).is_err());
+
+        // lines
+        let range = parse(Some("2"), Some("3")).unwrap().unwrap();
+        assert_eq!(range.apply(data), "two\nthree\n");
+        let range = parse(None, Some("1")).unwrap().unwrap();
+        assert_eq!(range.apply(data), "one\n");
+        let range = parse(Some("3"), None).unwrap().unwrap();
+        assert_eq!(range.apply(data), "three\nfour\n");
+
+        // bytes
+        let range = parse(Some("bytes:4-7"), None).unwrap().unwrap();
+        assert_eq!(range, ReadRange::Bytes(4, 7));
+        assert_eq!(range.apply(data), "two");
+
+        // past the end of the file
+        let range = parse(Some("3"), Some("100")).unwrap().unwrap();
+        assert_eq!(range.apply(data), "three\nfour\n");
+        let range = parse(Some("10"), Some("20")).unwrap().unwrap();
+        assert_eq!(range.apply(data), "");
+        let range = parse(Some("bytes:14-1000"), None).unwrap().unwrap();
+        assert_eq!(range.apply(data), "four\n");
+        let range = parse(Some("bytes:1000-2000"), None).unwrap().unwrap();
+        assert_eq!(range.apply(data), "");
+    }
+
+    #[test]
+    fn test_follow() {
+        let dir = std::env::temp_dir().join(format!("nerve-follow-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("app.log");
+        let read = |previous| {
+            let (followed, lines) = follow(&path, previous).unwrap();
+            (followed, String::from_utf8(lines).unwrap())
+        };
+
+        fs::write(&path, "one\ntwo\npart").unwrap();
+        let (followed, lines) = read(None);
+        assert_eq!(lines, "one\ntwo\n");
+        assert_eq!(followed.offset, 8);
+
+        // appended lines, the partial one is returned once terminated
+        fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b"ial\nthree\n")
+            .unwrap();
+        let (followed, lines) = read(Some(followed));
+        assert_eq!(lines, "partial\nthree\n");
+        let (followed, lines) = read(Some(followed));
+        assert_eq!(lines, "");
+
+        // truncated
+        fs::write(&path, "new\n").unwrap();
+        let (followed, lines) = read(Some(followed));
+        assert_eq!(lines, "new\n");
+        assert_eq!(followed.offset, 4);
+
+        // rotated, the new file is already longer than the previous offset
+        fs::rename(&path, dir.join("app.log.1")).unwrap();
+        fs::write(&path, "rotated\nfile\n").unwrap();
+        let (rotated, lines) = read(Some(followed));
+        assert_ne!(rotated.inode, followed.inode);
+        assert_eq!(lines, "rotated\nfile\n");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_read_folder_dangling_symlink() {
+        let dir = std::env::temp_dir().join(format!("nerve-folder-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("file.txt"), "hello").unwrap();
+        std::os::unix::fs::symlink(dir.join("missing"), dir.join("dangling")).unwrap();
+
+        let output = read_folder("test", &dir, 1).unwrap();
+        assert!(output.contains("file.txt"));
+        assert!(output.contains("[symlink]"));
+        assert!(output.contains("dangling"));
+        assert!(read_folder("missing", &dir.join("missing"), 1).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_read_folder_recursive() {
+        let dir = std::env::temp_dir().join(format!("nerve-tree-test-{}", std::process::id()));
+        fs::create_dir_all(dir.join("a/b/c")).unwrap();
+        fs::write(dir.join("a/one.txt"), "1").unwrap();
+        fs::write(dir.join("a/b/two.txt"), "2").unwrap();
+        fs::write(dir.join("a/b/c/three.txt"), "3").unwrap();
+        // cycle back to the root
+        std::os::unix::fs::symlink(&dir, dir.join("a/b/loop")).unwrap();
+
+        let output = read_folder("test", &dir, 1).unwrap();
+        assert!(output.contains("/a\n"));
+        assert!(!output.contains("one.txt"));
+
+        let output = read_folder("test", &dir, 2).unwrap();
+        assert!(output.contains("one.txt"));
+        assert!(!output.contains("two.txt"));
+
+        // the symlink is listed but not followed again
+        let output = read_folder("test", &dir, usize::MAX).unwrap();
+        assert!(output.contains("three.txt"));
+        assert_eq!(output.matches("one.txt").count(), 1);
+        assert!(!output.contains("only the first"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_write_file() {
+        let dir = std::env::temp_dir().join(format!("nerve-write-test-{}", std::process::id()));
+        let path = dir.join("sub/folder/notes.txt");
+
+        assert_eq!(write_file(&path, "hello\nworld").unwrap(), 11);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "hello\nworld");
+        // a folder can't be written
+        assert!(write_file(&dir.join("sub"), "nope").is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_copy_file() {
+        let dir = std::env::temp_dir().join(format!("nerve-copy-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let from = dir.join("from.txt");
+        let to = dir.join("to.txt");
+        fs::write(&from, "hello").unwrap();
+
+        assert_eq!(copy_file(&from, &to, false).unwrap(), 5);
+        assert_eq!(fs::read_to_string(&to).unwrap(), "hello");
+        // refuse to overwrite
+        assert!(copy_file(&from, &to, false).is_err());
+        assert_eq!(copy_file(&from, &to, true).unwrap(), 5);
+        assert!(copy_file(&dir.join("missing"), &to, true).is_err());
+        // parent folders are created
+        assert_eq!(copy_file(&from, &dir.join("a/b/to.txt"), false).unwrap(), 5);
+        // copying a file onto itself leaves it untouched
+        let err = copy_file(&from, &dir.join("a/../from.txt"), true).unwrap_err();
+        assert!(err.to_string().contains("are the same file"), "{}", err);
+        assert_eq!(fs::read_to_string(&from).unwrap(), "hello");
+
+        assert_eq!(CopyFile {}.required_attributes(), vec!["from", "to"]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_move_file() {
+        let dir = std::env::temp_dir().join(format!("nerve-move-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let from = dir.join("from.txt");
+        let to = dir.join("sub/to.txt");
+        fs::write(&from, "hello").unwrap();
+
+        move_file(&from, &to, false).unwrap();
+        assert!(!from.exists());
+        assert_eq!(fs::read_to_string(&to).unwrap(), "hello");
+
+        // refuse to overwrite
+        fs::write(&from, "world").unwrap();
+        let err = move_file(&from, &to, false).unwrap_err();
+        assert!(err.to_string().contains("already exists"));
+        assert!(from.exists());
+        move_file(&from, &to, true).unwrap();
+        assert_eq!(fs::read_to_string(&to).unwrap(), "world");
+
+        let err = move_file(&from, &to, true).unwrap_err();
+        assert!(err.to_string().ends_with("does not exist"));
+
+        assert_eq!(MoveFile {}.required_attributes(), vec!["from", "to"]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_find_in_files() {
+        let dir = std::env::temp_dir().join(format!("nerve-find-test-{}", std::process::id()));
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::write(dir.join("src/main.rs"), "fn main() {\n    run();\n}\n").unwrap();
+        fs::write(dir.join("src/lib.rs"), "pub fn run() {}\n").unwrap();
+        fs::write(dir.join("notes.txt"), "fn in a text file\n").unwrap();
+        fs::write(dir.join("src/data.bin"), [0xff, 0xfe, 0x00, 0x66]).unwrap();
+
+        let find = |files: &str, pattern: &str, limit: usize| {
+            let pattern_path = dir.join(files);
+            let paths = glob::glob(pattern_path.to_str().unwrap())
+                .unwrap()
+                .flatten();
+            find_in_files(pattern, paths, limit)
+        };
+
+        let (mut matches, truncated) = find("src/**/*", r"fn \w+\(", 10).unwrap();
+        matches.sort();
+        assert!(!truncated);
+        assert_eq!(
+            matches,
+            vec![
+                format!("{}:1: pub fn run() {{}}", dir.join("src/lib.rs").display()),
+                format!("{}:1: fn main() {{", dir.join("src/main.rs").display()),
+            ]
+        );
+
+        let (matches, _) = find("src/*.rs", r"run\(\)", 10).unwrap();
+        assert_eq!(matches.len(), 2);
+        assert!(matches
+            .iter()
+            .any(|m| m.starts_with(&format!("{}:2:", dir.join("src/main.rs").display()))));
+
+        // capped
+        let (matches, truncated) = find("**/*", "fn", 1).unwrap();
+        assert_eq!(matches.len(), 1);
+        assert!(truncated);
+
+        assert!(find("**/*", "nope", 10).unwrap().0.is_empty());
+        assert!(find("**/*", "(", 10).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_delete() {
+        let dir = std::env::temp_dir().join(format!("nerve-delete-test-{}", std::process::id()));
+        fs::create_dir_all(dir.join("full/sub")).unwrap();
+        fs::create_dir_all(dir.join("empty")).unwrap();
+        let file = dir.join("file.txt");
+        fs::write(&file, "hello").unwrap();
+        fs::write(dir.join("full/sub/file.txt"), "hello").unwrap();
+        let root = dir.canonicalize().unwrap();
+
+        // not confirmed
+        let err = delete_file(&file, false).unwrap_err();
+        assert!(err.to_string().contains("set confirm to true"));
+        assert!(file.exists());
+        assert!(delete_folder(&dir.join("empty"), &root, false, true).is_err());
+        assert!(dir.join("empty").exists());
+
+        // wrong kind
+        assert!(delete_file(&dir.join("empty"), true).is_err());
+        assert!(delete_folder(&file, &root, true, true).is_err());
+
+        // the root of the sandbox and the filesystem one are never deleted
+        for path in [dir.clone(), dir.join("full/.."), dir.join("empty/..")] {
+            let err = delete_folder(&path, &root, true, true).unwrap_err();
+            assert!(err.to_string().contains("is not allowed"), "{}", err);
+        }
+        let err = delete_folder(std::path::Path::new("/"), &root, false, false).unwrap_err();
+        assert!(err.to_string().contains("is not allowed"), "{}", err);
+        assert!(dir.join("full/sub/file.txt").exists());
+
+        // not empty and not recursive
+        let err = delete_folder(&dir.join("full"), &root, true, false).unwrap_err();
+        assert!(err.to_string().contains("set recursive to true"));
+        assert!(dir.join("full/sub/file.txt").exists());
+
+        delete_file(&file, true).unwrap();
+        assert!(!file.exists());
+        delete_folder(&dir.join("empty"), &root, true, false).unwrap();
+        assert!(!dir.join("empty").exists());
+        delete_folder(&dir.join("full"), &root, true, true).unwrap();
+        assert!(!dir.join("full").exists());
+        assert!(delete_file(&file, true).is_err());
+
+        // confirm is neither required nor suggested
+        for action in [
+            Box::new(DeleteFile {}) as Box<dyn Action>,
+            Box::new(DeleteFolder {}),
+        ] {
+            assert!(action.required_attributes().is_empty());
+            assert_eq!(
+                action.optional_attributes().unwrap().get("confirm"),
+                Some(&"false".to_string())
+            );
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
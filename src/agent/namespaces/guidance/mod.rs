@@ -0,0 +1,120 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use async_trait::async_trait;
+
+use super::{Action, Namespace, StorageDescriptor};
+use crate::agent::{state::SharedState, task::merge_guidance};
+
+// name of the storage holding the rules added at runtime
+pub(crate) const STORAGE_NAME: &str = "guidance";
+// maximum number of rules that can be added at runtime
+const MAX_RULES: usize = 20;
+// maximum length of a rule in characters
+const MAX_RULE_LENGTH: usize = 300;
+
+#[derive(Debug, Default, Clone)]
+struct AddGuidance {}
+
+#[async_trait]
+impl Action for AddGuidance {
+    fn name(&self) -> &str {
+        "add-guidance"
+    }
+
+    fn description(&self) -> &str {
+        include_str!("add.prompt")
+    }
+
+    fn example_payload(&self) -> Option<&str> {
+        Some("always verify the contents of a file before overwriting it")
+    }
+
+    async fn run(
+        &self,
+        state: SharedState,
+        _: Option<HashMap<String, String>>,
+        payload: Option<String>,
+    ) -> Result<Option<String>> {
+        let rule = payload.unwrap().trim().to_string();
+        if rule.is_empty() {
+            return Err(anyhow!("the rule can't be empty"));
+        }
+        if rule.chars().count() > MAX_RULE_LENGTH {
+            return Err(anyhow!(
+                "rules can't be longer than {} characters, make it more concise",
+                MAX_RULE_LENGTH
+            ));
+        }
+
+        let mut state = state.lock().await;
+        let existing: Vec<String> = state
+            .get_storage(STORAGE_NAME)?
+            .values()
+            .map(|e| e.data.to_string())
+            .collect();
+
+        if existing.len() >= MAX_RULES {
+            return Err(anyhow!(
+                "the maximum number of {} rules has been reached",
+                MAX_RULES
+            ));
+        }
+
+        // merging skips duplicates, if nothing changes the rule is already known
+        let current = merge_guidance(vec![state.get_task().guidance()?, existing]);
+        if merge_guidance(vec![current.clone(), vec![rule.clone()]]).len() == current.len() {
+            return Err(anyhow!("this rule is already part of your guidance"));
+        }
+
+        state.get_storage_mut(STORAGE_NAME)?.add_untagged(&rule);
+
+        Ok(Some("guidance updated".to_string()))
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+struct ListGuidance {}
+
+#[async_trait]
+impl Action for ListGuidance {
+    fn name(&self) -> &str {
+        "list-guidance"
+    }
+
+    fn description(&self) -> &str {
+        include_str!("list.prompt")
+    }
+
+    async fn run(
+        &self,
+        state: SharedState,
+        _: Option<HashMap<String, String>>,
+        _: Option<String>,
+    ) -> Result<Option<String>> {
+        let state = state.lock().await;
+        let rules: Vec<String> = state
+            .get_storage(STORAGE_NAME)?
+            .values()
+            .map(|e| format!("- {}", e.data))
+            .collect();
+
+        if rules.is_empty() {
+            Ok(Some("no rules have been added yet".to_string()))
+        } else {
+            Ok(Some(rules.join("\n")))
+        }
+    }
+}
+
+pub(crate) fn get_namespace() -> Namespace {
+    Namespace::new_non_default(
+        "Guidance".to_string(),
+        include_str!("ns.prompt").to_string(),
+        vec![
+            Box::<AddGuidance>::default(),
+            Box::<ListGuidance>::default(),
+        ],
+        Some(vec![StorageDescriptor::untagged(STORAGE_NAME)]),
+    )
+}
@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, time::Duration};
 
 use anyhow::Result;
 use async_trait::async_trait;
@@ -6,6 +6,10 @@ use async_trait::async_trait;
 use super::{Action, Namespace, StorageDescriptor};
 use crate::agent::state::SharedState;
 
+// memories are rendered in the system prompt, keep it from growing unbounded over long runs
+pub(crate) const MAX_MEMORIES: usize = 100;
+pub(crate) const MEMORIES_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
 #[derive(Debug, Default, Clone)]
 struct SaveMemory {}
 
@@ -93,6 +97,8 @@ impl Action for DeleteMemory {
     }
 }
 
+// memories are already part of the system prompt, this action is not exposed for now
+#[allow(dead_code)]
 #[derive(Debug, Default, Clone)]
 struct RecallMemory {}
 
@@ -136,6 +142,8 @@ pub(crate) fn get_namespace() -> Namespace {
         "Memory".to_string(),
         include_str!("ns.prompt").to_string(),
         vec![Box::<SaveMemory>::default(), Box::<DeleteMemory>::default()],
-        Some(vec![StorageDescriptor::tagged("memories")]),
+        Some(vec![StorageDescriptor::tagged("memories")
+            .with_max_entries(MAX_MEMORIES)
+            .with_ttl(MEMORIES_TTL)]),
     )
 }
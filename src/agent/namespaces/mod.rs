@@ -9,12 +9,23 @@ use super::state::{storage::StorageType, SharedState};
 
 // TODO: add more namespaces of actions: take screenshot (multimodal), networking, move mouse, ui interactions, etc
 
+pub(crate) mod data;
+pub(crate) mod env;
 pub(crate) mod filesystem;
 pub(crate) mod goal;
+pub(crate) mod guidance;
 pub(crate) mod memory;
+pub(crate) mod network;
 pub(crate) mod planning;
 pub(crate) mod rag;
+pub(crate) mod reasoning;
+pub(crate) mod search;
+pub(crate) mod shell;
+pub(crate) mod system;
 pub(crate) mod task;
+pub(crate) mod tasklist;
+pub(crate) mod time;
+pub(crate) mod vision;
 
 lazy_static! {
     // Available namespaces.
@@ -25,8 +36,19 @@ lazy_static! {
         map.insert("goal".to_string(), goal::get_namespace as fn() -> Namespace);
         map.insert("planning".to_string(), planning::get_namespace as fn() -> Namespace);
         map.insert("task".to_string(), task::get_namespace as fn() -> Namespace);
+        map.insert("tasklist".to_string(), tasklist::get_namespace as fn() -> Namespace);
         map.insert("filesystem".to_string(), filesystem::get_namespace as fn() -> Namespace);
         map.insert("rag".to_string(), rag::get_namespace as fn() -> Namespace);
+        map.insert("data".to_string(), data::get_namespace as fn() -> Namespace);
+        map.insert("system".to_string(), system::get_namespace as fn() -> Namespace);
+        map.insert("env".to_string(), env::get_namespace as fn() -> Namespace);
+        map.insert("time".to_string(), time::get_namespace as fn() -> Namespace);
+        map.insert("network".to_string(), network::get_namespace as fn() -> Namespace);
+        map.insert("guidance".to_string(), guidance::get_namespace as fn() -> Namespace);
+        map.insert("reasoning".to_string(), reasoning::get_namespace as fn() -> Namespace);
+        map.insert("search".to_string(), search::get_namespace as fn() -> Namespace);
+        map.insert("shell".to_string(), shell::get_namespace as fn() -> Namespace);
+        map.insert("vision".to_string(), vision::get_namespace as fn() -> Namespace);
 
         map
     };
@@ -36,32 +58,54 @@ lazy_static! {
 pub struct StorageDescriptor {
     pub name: String,
     pub type_: StorageType,
+    // maximum number of entries, the oldest ones are evicted first
+    pub max_entries: Option<usize>,
+    // entries older than this are not rendered and eventually dropped
+    pub ttl: Option<Duration>,
 }
 
 #[allow(dead_code)]
 impl StorageDescriptor {
-    pub fn tagged(name: &str) -> Self {
+    fn new(name: &str, type_: StorageType) -> Self {
         let name = name.to_string();
-        let type_ = StorageType::Tagged;
-        Self { name, type_ }
+        let max_entries = None;
+        let ttl = None;
+        Self {
+            name,
+            type_,
+            max_entries,
+            ttl,
+        }
+    }
+
+    pub fn tagged(name: &str) -> Self {
+        Self::new(name, StorageType::Tagged)
     }
 
     pub fn untagged(name: &str) -> Self {
-        let name = name.to_string();
-        let type_ = StorageType::Untagged;
-        Self { name, type_ }
+        Self::new(name, StorageType::Untagged)
     }
 
     pub fn previous_current(name: &str) -> Self {
-        let name = name.to_string();
-        let type_ = StorageType::CurrentPrevious;
-        Self { name, type_ }
+        Self::new(name, StorageType::CurrentPrevious)
     }
 
     pub fn completion(name: &str) -> Self {
-        let name = name.to_string();
-        let type_ = StorageType::Completion;
-        Self { name, type_ }
+        Self::new(name, StorageType::Completion)
+    }
+
+    pub fn tasklist(name: &str) -> Self {
+        Self::new(name, StorageType::Tasklist)
+    }
+
+    pub fn with_max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = Some(max_entries);
+        self
+    }
+
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
     }
 }
 
@@ -108,6 +152,44 @@ impl Namespace {
     }
 }
 
+// the result of an action, rendered as text for the model while the typed data is kept for
+// programmatic consumers
+#[derive(Debug, Clone, PartialEq)]
+pub struct ActionOutput {
+    pub text: Option<String>,
+    pub data: Option<serde_json::Value>,
+    pub success: bool,
+}
+
+impl ActionOutput {
+    pub fn new(text: Option<String>) -> Self {
+        let data = None;
+        let success = true;
+        Self {
+            text,
+            data,
+            success,
+        }
+    }
+
+    // what the model sees, the data itself if there's no text
+    pub fn to_text(&self) -> Option<String> {
+        if self.text.is_some() {
+            self.text.clone()
+        } else {
+            self.data
+                .as_ref()
+                .map(|data| serde_json::to_string_pretty(data).unwrap_or_else(|_| data.to_string()))
+        }
+    }
+}
+
+impl From<Option<String>> for ActionOutput {
+    fn from(text: Option<String>) -> Self {
+        Self::new(text)
+    }
+}
+
 #[async_trait]
 pub(crate) trait Action: std::fmt::Debug + Sync + Send + ActionClone {
     fn name(&self) -> &str;
@@ -116,9 +198,37 @@ pub(crate) trait Action: std::fmt::Debug + Sync + Send + ActionClone {
         None
     }
 
+    // relative cost of running the action, shown to the model and accounted against the budget
+    fn cost(&self) -> u32 {
+        1
+    }
+
+    // true for actions that don't act on anything, like thinking
+    fn is_reasoning(&self) -> bool {
+        false
+    }
+
+    // true for actions without side effects that can run concurrently with other ones
+    fn is_parallel_safe(&self) -> bool {
+        false
+    }
+
+    // attributes that must be specified, with example values
     fn attributes(&self) -> Option<HashMap<String, String>> {
         None
     }
+
+    // names of the attributes checked before running the action, all the attributes by default
+    fn required_attributes(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.attributes().unwrap_or_default().into_keys().collect();
+        names.sort();
+        names
+    }
+
+    // attributes that can be omitted, with example values
+    fn optional_attributes(&self) -> Option<HashMap<String, String>> {
+        None
+    }
     fn example_payload(&self) -> Option<&str> {
         None
     }
@@ -131,6 +241,16 @@ pub(crate) trait Action: std::fmt::Debug + Sync + Send + ActionClone {
         attributes: Option<HashMap<String, String>>,
         payload: Option<String>,
     ) -> Result<Option<String>>;
+
+    // actions with typed results override this, by default the text output is wrapped
+    async fn run_structured(
+        &self,
+        state: SharedState,
+        attributes: Option<HashMap<String, String>>,
+        payload: Option<String>,
+    ) -> Result<ActionOutput> {
+        Ok(self.run(state, attributes, payload).await?.into())
+    }
 }
 
 // https://stackoverflow.com/questions/30353462/how-to-clone-a-struct-storing-a-boxed-trait-object
@@ -0,0 +1,134 @@
+use std::{collections::HashMap, time::Duration};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use tokio::net::TcpStream;
+
+use super::{Action, Namespace};
+use crate::agent::state::SharedState;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PortState {
+    Open,
+    Closed,
+    Filtered,
+}
+
+impl std::fmt::Display for PortState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                PortState::Open => "open",
+                PortState::Closed => "closed",
+                PortState::Filtered => "filtered",
+            }
+        )
+    }
+}
+
+async fn check_port(host: &str, port: u16, timeout: Duration) -> Result<PortState> {
+    let addr = tokio::net::lookup_host((host, port))
+        .await
+        .map_err(|e| anyhow!("can't resolve {host}: {e}"))?
+        .next()
+        .ok_or_else(|| anyhow!("can't resolve {host}"))?;
+
+    match tokio::time::timeout(timeout, TcpStream::connect(addr)).await {
+        Ok(Ok(_)) => Ok(PortState::Open),
+        Ok(Err(err)) if err.kind() == std::io::ErrorKind::ConnectionRefused => {
+            Ok(PortState::Closed)
+        }
+        Ok(Err(err)) => Err(anyhow!("can't connect to {addr}: {err}")),
+        Err(_) => Ok(PortState::Filtered),
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+struct CheckPort {}
+
+#[async_trait]
+impl Action for CheckPort {
+    fn name(&self) -> &str {
+        "check-port"
+    }
+
+    fn is_parallel_safe(&self) -> bool {
+        true
+    }
+
+    fn cost(&self) -> u32 {
+        3
+    }
+
+    fn description(&self) -> &str {
+        include_str!("check_port.prompt")
+    }
+
+    fn attributes(&self) -> Option<HashMap<String, String>> {
+        let mut attributes = HashMap::new();
+
+        attributes.insert("host".to_string(), "example.com".to_string());
+        attributes.insert("port".to_string(), "443".to_string());
+
+        Some(attributes)
+    }
+
+    async fn run(
+        &self,
+        _: SharedState,
+        attributes: Option<HashMap<String, String>>,
+        _: Option<String>,
+    ) -> Result<Option<String>> {
+        let attrs = attributes.unwrap();
+        let host = attrs.get("host").unwrap();
+        let port = attrs
+            .get("port")
+            .unwrap()
+            .parse::<u16>()
+            .map_err(|e| anyhow!("invalid port: {e}"))?;
+
+        let state = check_port(host, port, CONNECT_TIMEOUT).await?;
+
+        Ok(Some(format!("{host}:{port} is {state}")))
+    }
+}
+
+pub(crate) fn get_namespace() -> Namespace {
+    Namespace::new_non_default(
+        "Network".to_string(),
+        include_str!("ns.prompt").to_string(),
+        vec![Box::<CheckPort>::default()],
+        None,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_check_port_open_and_closed() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        assert_eq!(
+            check_port("127.0.0.1", port, CONNECT_TIMEOUT)
+                .await
+                .unwrap(),
+            PortState::Open
+        );
+
+        drop(listener);
+
+        assert_eq!(
+            check_port("127.0.0.1", port, CONNECT_TIMEOUT)
+                .await
+                .unwrap(),
+            PortState::Closed
+        );
+    }
+}
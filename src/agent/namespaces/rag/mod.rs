@@ -5,7 +5,7 @@ use async_trait::async_trait;
 
 use crate::agent::state::SharedState;
 
-use super::{Action, Namespace};
+use super::{Action, Namespace, StorageDescriptor};
 
 #[derive(Debug, Default, Clone)]
 struct Search {}
@@ -36,6 +36,11 @@ impl Action for Search {
         let mut docs = state.lock().await.rag_query(&query, 1).await?;
 
         if !docs.is_empty() {
+            let mut data = vec![];
+            for (doc, _) in docs.iter_mut() {
+                data.push(doc.get_data()?.to_string());
+            }
+
             log::info!(
                 "rag search for '{}': {} results in {:?}",
                 query,
@@ -45,10 +50,7 @@ impl Action for Search {
 
             Ok(Some(format!(
                 "Here is some supporting information:\n\n{}",
-                docs.iter_mut()
-                    .map(|(doc, _)| doc.get_data().unwrap().to_string())
-                    .collect::<Vec<String>>()
-                    .join("\n")
+                data.join("\n")
             )))
         } else {
             log::info!(
@@ -60,11 +62,203 @@ impl Action for Search {
     }
 }
 
+// documents longer than this are truncated in the search-knowledge results
+const MAX_SNIPPET_LENGTH: usize = 1000;
+
+fn snippet(data: &str) -> String {
+    let data = data.trim();
+    if data.chars().count() > MAX_SNIPPET_LENGTH {
+        format!(
+            "{} ...",
+            data.chars().take(MAX_SNIPPET_LENGTH).collect::<String>()
+        )
+    } else {
+        data.to_string()
+    }
+}
+
+// the number of documents requested with the top-k attribute, capped by max_results
+fn top_k(
+    attributes: &Option<HashMap<String, String>>,
+    default: usize,
+    max_results: usize,
+) -> Result<usize> {
+    match attributes.as_ref().and_then(|attrs| attrs.get("top-k")) {
+        Some(top_k) => match top_k.trim().parse::<usize>() {
+            Ok(top_k) if top_k > 0 => Ok(top_k.min(max_results)),
+            _ => Err(anyhow!("invalid top-k '{}'", top_k)),
+        },
+        None => Ok(default),
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+struct SearchKnowledge {}
+
+#[async_trait]
+impl Action for SearchKnowledge {
+    fn name(&self) -> &str {
+        "search-knowledge"
+    }
+
+    fn description(&self) -> &str {
+        include_str!("search_knowledge.prompt")
+    }
+
+    fn optional_attributes(&self) -> Option<HashMap<String, String>> {
+        let mut attributes = HashMap::new();
+
+        attributes.insert("top-k".to_string(), "3".to_string());
+
+        Some(attributes)
+    }
+
+    fn example_payload(&self) -> Option<&str> {
+        Some("what is the biggest city in the world?")
+    }
+
+    async fn run(
+        &self,
+        state: SharedState,
+        attributes: Option<HashMap<String, String>>,
+        payload: Option<String>,
+    ) -> Result<Option<String>> {
+        let query = payload.unwrap();
+        let query = query.trim();
+        let mut state = state.lock().await;
+        let max_results = state.get_rag_max_results();
+        let top_k = top_k(&attributes, max_results, max_results)?;
+
+        let mut docs = state.rag_query(query, top_k).await?;
+        if docs.is_empty() {
+            return Ok(Some("no documents for this query".to_string()));
+        }
+
+        let mut results = vec![];
+        for (idx, (doc, score)) in docs.iter_mut().enumerate() {
+            let data = snippet(doc.get_data()?);
+            results.push(format!(
+                "{}. {} (score {:.4})\n{}",
+                idx + 1,
+                doc.get_path(),
+                score,
+                data
+            ));
+        }
+
+        Ok(Some(format!(
+            "{} documents found for '{}':\n\n{}",
+            results.len(),
+            query,
+            results.join("\n\n")
+        )))
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+struct LoadContext {}
+
+#[async_trait]
+impl Action for LoadContext {
+    fn name(&self) -> &str {
+        "load-context"
+    }
+
+    fn description(&self) -> &str {
+        include_str!("load_context.prompt")
+    }
+
+    fn optional_attributes(&self) -> Option<HashMap<String, String>> {
+        let mut attributes = HashMap::new();
+
+        attributes.insert("top-k".to_string(), "1".to_string());
+
+        Some(attributes)
+    }
+
+    fn example_payload(&self) -> Option<&str> {
+        Some("what is the biggest city in the world?")
+    }
+
+    async fn run(
+        &self,
+        state: SharedState,
+        attributes: Option<HashMap<String, String>>,
+        payload: Option<String>,
+    ) -> Result<Option<String>> {
+        let query = payload.unwrap();
+        let mut state = state.lock().await;
+        let top_k = top_k(&attributes, 1, state.get_rag_max_results())?;
+        let mut docs = state.rag_query(&query, top_k).await?;
+
+        if docs.is_empty() {
+            return Ok(Some("no documents for this query".to_string()));
+        }
+
+        let mut data = vec![];
+        for (doc, _) in docs.iter_mut() {
+            data.push(doc.get_data()?.to_string());
+        }
+
+        state
+            .get_storage_mut("context")?
+            .add_tagged(&query, &data.join("\n"));
+
+        Ok(Some(format!(
+            "{} documents loaded into your context",
+            docs.len()
+        )))
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+struct DropContext {}
+
+#[async_trait]
+impl Action for DropContext {
+    fn name(&self) -> &str {
+        "drop-context"
+    }
+
+    fn description(&self) -> &str {
+        include_str!("drop_context.prompt")
+    }
+
+    fn example_payload(&self) -> Option<&str> {
+        Some("what is the biggest city in the world?")
+    }
+
+    async fn run(
+        &self,
+        state: SharedState,
+        _: Option<HashMap<String, String>>,
+        payload: Option<String>,
+    ) -> Result<Option<String>> {
+        let query = payload.unwrap();
+        if state
+            .lock()
+            .await
+            .get_storage_mut("context")?
+            .del_tagged(&query)
+            .is_some()
+        {
+            Ok(Some("context dropped".to_string()))
+        } else {
+            Err(anyhow!("no context loaded for '{}'", query))
+        }
+    }
+}
+
 pub(crate) fn get_namespace() -> Namespace {
     Namespace::new_non_default(
         "Knowledge".to_string(),
         include_str!("ns.prompt").to_string(),
-        vec![Box::<Search>::default()],
-        None,
+        vec![
+            Box::<Search>::default(),
+            Box::<SearchKnowledge>::default(),
+            Box::<LoadContext>::default(),
+            Box::<DropContext>::default(),
+        ],
+        Some(vec![StorageDescriptor::tagged("context")]),
     )
 }
@@ -0,0 +1,47 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use async_trait::async_trait;
+
+use super::{Action, Namespace};
+use crate::agent::state::SharedState;
+
+#[derive(Debug, Default, Clone)]
+struct Think {}
+
+#[async_trait]
+impl Action for Think {
+    fn name(&self) -> &str {
+        "think"
+    }
+
+    fn description(&self) -> &str {
+        include_str!("think.prompt")
+    }
+
+    fn example_payload(&self) -> Option<&str> {
+        Some("your reasoning")
+    }
+
+    fn is_reasoning(&self) -> bool {
+        true
+    }
+
+    async fn run(
+        &self,
+        _: SharedState,
+        _: Option<HashMap<String, String>>,
+        _: Option<String>,
+    ) -> Result<Option<String>> {
+        Ok(Some("ok, now take an action".to_string()))
+    }
+}
+
+pub(crate) fn get_namespace() -> Namespace {
+    Namespace::new_non_default(
+        "Reasoning".to_string(),
+        include_str!("ns.prompt").to_string(),
+        vec![Box::<Think>::default()],
+        None,
+    )
+}
@@ -0,0 +1,267 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::Value;
+
+use super::{Action, Namespace};
+use crate::agent::state::SharedState;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(15);
+// maximum length of each snippet
+const MAX_SNIPPET_LENGTH: usize = 300;
+
+fn default_results() -> usize {
+    5
+}
+
+fn default_query_param() -> String {
+    "q".to_string()
+}
+
+fn default_results_key() -> String {
+    "results".to_string()
+}
+
+fn default_title_key() -> String {
+    "title".to_string()
+}
+
+fn default_url_key() -> String {
+    "url".to_string()
+}
+
+fn default_snippet_key() -> String {
+    "content".to_string()
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Provider {
+    // a SearXNG instance
+    #[default]
+    Searxng,
+    // any endpoint returning a json list of results
+    Json,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Configuration {
+    #[serde(default)]
+    pub provider: Provider,
+    // base url of the searxng instance or full url of the json endpoint
+    pub url: String,
+    // number of results to return
+    #[serde(default = "default_results")]
+    pub results: usize,
+    // name of the query string parameter
+    #[serde(default = "default_query_param")]
+    pub query_param: String,
+    // keys of the results list and of each result fields in the json response
+    #[serde(default = "default_results_key")]
+    pub results_key: String,
+    #[serde(default = "default_title_key")]
+    pub title_key: String,
+    #[serde(default = "default_url_key")]
+    pub url_key: String,
+    #[serde(default = "default_snippet_key")]
+    pub snippet_key: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct SearchResult {
+    title: String,
+    url: String,
+    snippet: String,
+}
+
+fn parse_results(config: &Configuration, response: &Value) -> Vec<SearchResult> {
+    let field = |item: &Value, key: &str| {
+        item.get(key)
+            .and_then(|v| v.as_str())
+            .unwrap_or("")
+            .split_whitespace()
+            .collect::<Vec<&str>>()
+            .join(" ")
+    };
+
+    response
+        .get(&config.results_key)
+        .or(if response.is_array() {
+            Some(response)
+        } else {
+            None
+        })
+        .and_then(|results| results.as_array())
+        .map(|results| {
+            results
+                .iter()
+                .map(|item| SearchResult {
+                    title: field(item, &config.title_key),
+                    url: field(item, &config.url_key),
+                    snippet: field(item, &config.snippet_key),
+                })
+                .filter(|res| !res.url.is_empty())
+                .take(config.results)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn format_results(query: &str, results: &[SearchResult]) -> String {
+    if results.is_empty() {
+        return format!("no results found for '{}'", query);
+    }
+
+    let mut output = format!("Results for '{}':\n", query);
+    for (idx, res) in results.iter().enumerate() {
+        output += &format!("\n{}. {}\n   {}\n", idx + 1, res.title, res.url);
+        if !res.snippet.is_empty() {
+            let snippet: String = res.snippet.chars().take(MAX_SNIPPET_LENGTH).collect();
+            output += &format!(
+                "   {}{}\n",
+                snippet,
+                if snippet.len() < res.snippet.len() {
+                    " ..."
+                } else {
+                    ""
+                }
+            );
+        }
+    }
+
+    output
+}
+
+async fn search(config: &Configuration, query: &str) -> Result<Vec<SearchResult>> {
+    let url = match config.provider {
+        Provider::Searxng => format!("{}/search", config.url.trim_end_matches('/')),
+        Provider::Json => config.url.to_string(),
+    };
+    let mut url =
+        reqwest::Url::parse(&url).map_err(|e| anyhow!("invalid search url '{}': {}", url, e))?;
+    url.query_pairs_mut()
+        .append_pair(&config.query_param, query);
+    if config.provider == Provider::Searxng {
+        url.query_pairs_mut().append_pair("format", "json");
+    }
+
+    let resp = reqwest::Client::new()
+        .get(url)
+        .timeout(REQUEST_TIMEOUT)
+        .send()
+        .await?;
+    let status = resp.status();
+    if !status.is_success() {
+        return Err(anyhow!("provider returned {}", status));
+    }
+
+    let response: Value = resp
+        .json()
+        .await
+        .map_err(|e| anyhow!("can't parse provider response: {}", e))?;
+
+    Ok(parse_results(config, &response))
+}
+
+#[derive(Debug, Default, Clone)]
+struct Search {}
+
+#[async_trait]
+impl Action for Search {
+    fn name(&self) -> &str {
+        "search"
+    }
+
+    fn is_parallel_safe(&self) -> bool {
+        true
+    }
+
+    fn description(&self) -> &str {
+        include_str!("search.prompt")
+    }
+
+    fn example_payload(&self) -> Option<&str> {
+        Some("latest rust release")
+    }
+
+    async fn run(
+        &self,
+        state: SharedState,
+        _: Option<HashMap<String, String>>,
+        payload: Option<String>,
+    ) -> Result<Option<String>> {
+        let config = state
+            .lock()
+            .await
+            .get_task()
+            .get_search_config()
+            .ok_or_else(|| anyhow!("no search provider configured for this task"))?;
+        let query = payload.unwrap();
+        let query = query.trim();
+
+        // provider errors are reported to the model so that it can try something else
+        Ok(Some(match search(&config, query).await {
+            Ok(results) => format_results(query, &results),
+            Err(error) => {
+                log::error!("search for '{}' failed: {}", query, error);
+                format!(
+                    "search failed ({}), try again later or with different terms",
+                    error
+                )
+            }
+        }))
+    }
+}
+
+pub(crate) fn get_namespace() -> Namespace {
+    Namespace::new_non_default(
+        "Search".to_string(),
+        include_str!("ns.prompt").to_string(),
+        vec![Box::<Search>::default()],
+        None,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(provider: &str, results: usize) -> Configuration {
+        serde_json::from_value(serde_json::json!({
+            "provider": provider,
+            "url": "http://localhost:8888",
+            "results": results,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_parse_and_format_results() {
+        let response = serde_json::json!({
+            "results": [
+                {"title": "Rust 1.80", "url": "https://blog.rust-lang.org/", "content": "Rust 1.80\n  is out"},
+                {"title": "No url"},
+                {"title": "Other", "url": "https://example.com/"},
+                {"title": "Too many", "url": "https://example.org/"},
+            ]
+        });
+
+        let results = parse_results(&config("searxng", 2), &response);
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].snippet, "Rust 1.80 is out");
+
+        assert_eq!(
+            format_results("rust", &results),
+            "Results for 'rust':\n\n1. Rust 1.80\n   https://blog.rust-lang.org/\n   Rust 1.80 is out\n\n2. Other\n   https://example.com/\n"
+        );
+        assert_eq!(format_results("nope", &[]), "no results found for 'nope'");
+
+        // json endpoints can return the list directly
+        let response = serde_json::json!([{"title": "A", "url": "https://a.com/"}]);
+        assert_eq!(parse_results(&config("json", 5), &response).len(), 1);
+        assert!(parse_results(&config("json", 5), &serde_json::json!({})).is_empty());
+    }
+}
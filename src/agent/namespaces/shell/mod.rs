@@ -0,0 +1,170 @@
+use std::collections::HashMap;
+use std::process::Stdio;
+use std::time::Duration;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use duration_string::DurationString;
+use serde::Deserialize;
+
+use super::{Action, Namespace};
+use crate::agent::state::SharedState;
+
+// used if the task doesn't specify a timeout
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Configuration {
+    // names of the programs that can be executed
+    #[serde(default)]
+    pub allow: Vec<String>,
+    // maximum duration of each command
+    pub timeout: Option<String>,
+}
+
+impl Configuration {
+    fn get_timeout(&self) -> Result<Duration> {
+        if let Some(timeout) = &self.timeout {
+            Ok(*timeout
+                .parse::<DurationString>()
+                .map_err(|e| anyhow!("can't parse '{}' as duration string: {}", timeout, e))?)
+        } else {
+            Ok(DEFAULT_TIMEOUT)
+        }
+    }
+}
+
+// run the command if the program is allowed, the command line is split into arguments but not
+// interpreted by a shell
+async fn exec(config: &Configuration, command: &str) -> Result<String> {
+    let args = shell_words::split(command.trim())
+        .map_err(|e| anyhow!("can't parse command line: {}", e))?;
+    let program = if let Some(program) = args.first() {
+        program
+    } else {
+        return Err(anyhow!("no command specified"));
+    };
+
+    if !config.allow.contains(program) {
+        return Err(anyhow!(
+            "'{}' is not allowed, allowed programs are: {}",
+            program,
+            if config.allow.is_empty() {
+                "none".to_string()
+            } else {
+                config.allow.join(", ")
+            }
+        ));
+    }
+
+    let timeout = config.get_timeout()?;
+    let child = tokio::process::Command::new(program)
+        .args(&args[1..])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        // the process is killed if the timeout is reached
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| anyhow!("can't execute {}: {}", program, e))?;
+
+    let output = tokio::time::timeout(timeout, child.wait_with_output())
+        .await
+        .map_err(|_| anyhow!("'{}' timed out after {:?}", command.trim(), timeout))??;
+
+    let mut result = format!(
+        "exit code: {}\n",
+        output
+            .status
+            .code()
+            .map_or("none (killed by a signal)".to_string(), |c| c.to_string())
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !stdout.trim().is_empty() {
+        result += &format!("\nstdout:\n{}\n", stdout.trim_end());
+    }
+    if !stderr.trim().is_empty() {
+        result += &format!("\nstderr:\n{}\n", stderr.trim_end());
+    }
+
+    Ok(result)
+}
+
+#[derive(Debug, Default, Clone)]
+struct Exec {}
+
+#[async_trait]
+impl Action for Exec {
+    fn name(&self) -> &str {
+        "exec"
+    }
+
+    fn description(&self) -> &str {
+        include_str!("exec.prompt")
+    }
+
+    fn example_payload(&self) -> Option<&str> {
+        Some("ls -la /tmp")
+    }
+
+    async fn run(
+        &self,
+        state: SharedState,
+        _: Option<HashMap<String, String>>,
+        payload: Option<String>,
+    ) -> Result<Option<String>> {
+        let config = state
+            .lock()
+            .await
+            .get_task()
+            .get_shell_config()
+            .unwrap_or_default();
+
+        Ok(Some(exec(&config, &payload.unwrap()).await?))
+    }
+}
+
+pub(crate) fn get_namespace() -> Namespace {
+    Namespace::new_non_default(
+        "Shell".to_string(),
+        include_str!("ns.prompt").to_string(),
+        vec![Box::<Exec>::default()],
+        None,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(timeout: &str) -> Configuration {
+        Configuration {
+            allow: vec!["echo".to_string(), "sleep".to_string()],
+            timeout: Some(timeout.to_string()),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_exec_allowed() {
+        let output = exec(&config("10s"), "echo 'hello world'").await.unwrap();
+        assert_eq!(output, "exit code: 0\n\nstdout:\nhello world\n");
+    }
+
+    #[tokio::test]
+    async fn test_exec_denied() {
+        let err = exec(&config("10s"), "rm -rf /tmp/nope").await.unwrap_err();
+        assert!(err.to_string().starts_with("'rm' is not allowed"));
+        // no shell interpretation
+        assert!(exec(&config("10s"), "sh -c 'echo hi'").await.is_err());
+        assert!(exec(&Configuration::default(), "echo hi").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_exec_timeout() {
+        let start = std::time::Instant::now();
+        let err = exec(&config("200ms"), "sleep 5").await.unwrap_err();
+        assert!(err.to_string().contains("timed out"));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
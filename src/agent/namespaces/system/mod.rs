@@ -0,0 +1,201 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use sysinfo::System;
+
+use super::{Action, Namespace};
+use crate::agent::state::SharedState;
+
+// maximum number of processes to report
+const MAX_PROCESSES: usize = 50;
+
+// what is reported for each process
+struct ProcessInfo {
+    pid: String,
+    cpu: f32,
+    memory: u64,
+    name: String,
+}
+
+// list the processes using the most memory first, at most max of them
+fn format_processes(mut processes: Vec<ProcessInfo>, max: usize) -> String {
+    processes.sort_by_key(|p| std::cmp::Reverse(p.memory));
+
+    let mut output = format!(
+        "{} running processes{}:\n\n{:>8} {:>6} {:>10} NAME\n",
+        processes.len(),
+        if processes.len() > max {
+            format!(" (showing top {max} by memory)")
+        } else {
+            "".to_string()
+        },
+        "PID",
+        "CPU%",
+        "MEM"
+    );
+
+    for process in processes.iter().take(max) {
+        output += &format!(
+            "{:>8} {:>6.1} {:>10} {}\n",
+            process.pid,
+            process.cpu,
+            human_bytes::human_bytes(process.memory as f64),
+            process.name
+        );
+    }
+
+    output
+}
+
+#[derive(Debug, Default, Clone)]
+struct ListProcesses {}
+
+#[async_trait]
+impl Action for ListProcesses {
+    fn name(&self) -> &str {
+        "list-processes"
+    }
+
+    fn is_parallel_safe(&self) -> bool {
+        true
+    }
+
+    fn cost(&self) -> u32 {
+        2
+    }
+
+    fn description(&self) -> &str {
+        include_str!("list_processes.prompt")
+    }
+
+    async fn run(
+        &self,
+        _: SharedState,
+        _: Option<HashMap<String, String>>,
+        _: Option<String>,
+    ) -> Result<Option<String>> {
+        let mut sys = System::new_all();
+        // cpu usage is computed between two refreshes
+        tokio::time::sleep(sysinfo::MINIMUM_CPU_UPDATE_INTERVAL).await;
+        sys.refresh_processes();
+
+        let processes = sys
+            .processes()
+            .values()
+            .map(|process| ProcessInfo {
+                pid: process.pid().to_string(),
+                cpu: process.cpu_usage(),
+                memory: process.memory(),
+                name: process.name().to_string(),
+            })
+            .collect();
+
+        Ok(Some(format_processes(processes, MAX_PROCESSES)))
+    }
+}
+
+fn system_info() -> String {
+    let mut sys = System::new();
+    sys.refresh_memory();
+
+    let unknown = || "unknown".to_string();
+
+    format!(
+        "os: {}\nversion: {}\nkernel: {}\narch: {}\nhostname: {}\ncpus: {}\ntotal memory: {}\navailable memory: {}",
+        System::name().unwrap_or_else(unknown),
+        System::long_os_version().unwrap_or_else(unknown),
+        System::kernel_version().unwrap_or_else(unknown),
+        std::env::consts::ARCH,
+        System::host_name().unwrap_or_else(unknown),
+        std::thread::available_parallelism().map_or(1, |n| n.get()),
+        human_bytes::human_bytes(sys.total_memory() as f64),
+        human_bytes::human_bytes(sys.available_memory() as f64),
+    )
+}
+
+#[derive(Debug, Default, Clone)]
+struct SystemInfo {}
+
+#[async_trait]
+impl Action for SystemInfo {
+    fn name(&self) -> &str {
+        "system-info"
+    }
+
+    fn is_parallel_safe(&self) -> bool {
+        true
+    }
+
+    fn description(&self) -> &str {
+        include_str!("system_info.prompt")
+    }
+
+    async fn run(
+        &self,
+        _: SharedState,
+        _: Option<HashMap<String, String>>,
+        _: Option<String>,
+    ) -> Result<Option<String>> {
+        Ok(Some(system_info()))
+    }
+}
+
+pub(crate) fn get_namespace() -> Namespace {
+    Namespace::new_non_default(
+        "System".to_string(),
+        include_str!("ns.prompt").to_string(),
+        vec![
+            Box::<SystemInfo>::default(),
+            Box::<ListProcesses>::default(),
+        ],
+        None,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_system_info() {
+        let info = system_info();
+        for field in ["os", "kernel", "arch", "hostname", "total memory"] {
+            let line = info
+                .lines()
+                .find(|l| l.starts_with(&format!("{field}: ")))
+                .unwrap();
+            assert!(line.len() > field.len() + 2);
+        }
+        assert!(info.contains(std::env::consts::ARCH));
+    }
+
+    #[test]
+    fn test_format_processes() {
+        let processes = |count: u64| {
+            (1..=count)
+                .map(|n| ProcessInfo {
+                    pid: n.to_string(),
+                    cpu: 0.5,
+                    memory: n * 1024,
+                    name: format!("proc{n}"),
+                })
+                .collect::<Vec<ProcessInfo>>()
+        };
+
+        let output = format_processes(processes(3), 10);
+        assert!(output.starts_with("3 running processes:\n"));
+        let names: Vec<&str> = output
+            .lines()
+            .skip(3)
+            .map(|line| line.split_whitespace().last().unwrap())
+            .collect();
+        assert_eq!(names, vec!["proc3", "proc2", "proc1"]);
+
+        let output = format_processes(processes(60), 50);
+        assert!(output.starts_with("60 running processes (showing top 50 by memory):\n"));
+        assert_eq!(output.lines().skip(3).count(), 50);
+        assert!(output.contains(" proc60\n"));
+        assert!(!output.contains(" proc10\n"));
+    }
+}
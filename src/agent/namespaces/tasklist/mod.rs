@@ -0,0 +1,154 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use async_trait::async_trait;
+
+use super::{Action, Namespace, StorageDescriptor};
+use crate::agent::state::SharedState;
+
+const STORAGE_NAME: &str = "subtasks";
+
+#[derive(Debug, Default, Clone)]
+struct AddSubtask {}
+
+#[async_trait]
+impl Action for AddSubtask {
+    fn name(&self) -> &str {
+        "add-subtask"
+    }
+
+    fn description(&self) -> &str {
+        include_str!("add.prompt")
+    }
+
+    fn example_payload(&self) -> Option<&str> {
+        Some("describe the subtask")
+    }
+
+    async fn run(
+        &self,
+        state: SharedState,
+        _: Option<HashMap<String, String>>,
+        payload: Option<String>,
+    ) -> Result<Option<String>> {
+        let payload = payload.unwrap();
+        let subtask = payload.trim();
+        if subtask.is_empty() {
+            return Err(anyhow!("the subtask can't be empty"));
+        }
+
+        let mut state = state.lock().await;
+        let storage = state.get_storage_mut(STORAGE_NAME)?;
+        storage.add_completion(subtask);
+
+        Ok(Some(format!("subtask {} added", storage.len())))
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+struct CompleteSubtask {}
+
+#[async_trait]
+impl Action for CompleteSubtask {
+    fn name(&self) -> &str {
+        "complete-subtask"
+    }
+
+    fn description(&self) -> &str {
+        include_str!("complete.prompt")
+    }
+
+    fn example_payload(&self) -> Option<&str> {
+        Some("1")
+    }
+
+    async fn run(
+        &self,
+        state: SharedState,
+        _: Option<HashMap<String, String>>,
+        payload: Option<String>,
+    ) -> Result<Option<String>> {
+        let payload = payload.unwrap();
+        let pos = payload
+            .trim()
+            .parse::<usize>()
+            .map_err(|_| anyhow!("'{}' is not a subtask number", payload.trim()))?;
+
+        match state
+            .lock()
+            .await
+            .get_storage_mut(STORAGE_NAME)?
+            .set_complete(pos)
+        {
+            Some(true) => Ok(Some(format!("subtask {} was already completed", pos))),
+            Some(false) => Ok(Some(format!("subtask {} marked as completed", pos))),
+            None => Err(anyhow!("no subtask with number {}", pos)),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+struct ClearSubtasks {}
+
+#[async_trait]
+impl Action for ClearSubtasks {
+    fn name(&self) -> &str {
+        "clear-subtasks"
+    }
+
+    fn description(&self) -> &str {
+        include_str!("clear.prompt")
+    }
+
+    async fn run(
+        &self,
+        state: SharedState,
+        _: Option<HashMap<String, String>>,
+        _: Option<String>,
+    ) -> Result<Option<String>> {
+        state.lock().await.get_storage_mut(STORAGE_NAME)?.clear();
+        Ok(Some("subtasks cleared".to_string()))
+    }
+}
+
+pub(crate) fn get_namespace() -> Namespace {
+    Namespace::new_non_default(
+        "Tasklist".to_string(),
+        include_str!("ns.prompt").to_string(),
+        vec![
+            Box::<AddSubtask>::default(),
+            Box::<CompleteSubtask>::default(),
+            Box::<ClearSubtasks>::default(),
+        ],
+        Some(vec![StorageDescriptor::tasklist(STORAGE_NAME)]),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::{
+        events::create_channel,
+        serialization,
+        state::storage::{Storage, StorageType},
+    };
+
+    #[test]
+    fn test_render_checklist() {
+        let (tx, _rx) = create_channel();
+        let mut storage = Storage::new(STORAGE_NAME, StorageType::Tasklist, tx);
+
+        // empty storages are not rendered at all
+        assert_eq!(serialization::xml::serialize::storage(&storage), "");
+
+        storage.add_completion("write the parser");
+        storage.add_completion("write the tests");
+        assert_eq!(storage.set_complete(1), Some(false));
+        assert_eq!(storage.set_complete(3), None);
+
+        assert_eq!(
+            serialization::xml::serialize::storage(&storage),
+            "<subtasks>\n  - [x] 1. write the parser\n  - [ ] 2. write the tests\n  (1 of 2 completed, 1 remaining)\n</subtasks>"
+        );
+    }
+}
@@ -0,0 +1,134 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, Local, SecondsFormat, Utc};
+
+use super::{Action, Namespace};
+use crate::agent::state::SharedState;
+
+// maximum number of seconds the model can sleep for
+const MAX_SLEEP_SECS: u64 = 60;
+
+fn current_time(now: DateTime<Utc>) -> String {
+    format!(
+        "local: {}\nutc: {}",
+        now.with_timezone(&Local)
+            .to_rfc3339_opts(SecondsFormat::Secs, false),
+        now.to_rfc3339_opts(SecondsFormat::Secs, true)
+    )
+}
+
+#[derive(Debug, Default, Clone)]
+struct CurrentTime {}
+
+#[async_trait]
+impl Action for CurrentTime {
+    fn name(&self) -> &str {
+        "current-time"
+    }
+
+    fn is_parallel_safe(&self) -> bool {
+        true
+    }
+
+    fn description(&self) -> &str {
+        include_str!("current_time.prompt")
+    }
+
+    async fn run(
+        &self,
+        _: SharedState,
+        _: Option<HashMap<String, String>>,
+        _: Option<String>,
+    ) -> Result<Option<String>> {
+        Ok(Some(current_time(Utc::now())))
+    }
+}
+
+// parse the seconds to sleep for, capped so that the model can't stall the agent
+fn sleep_duration(payload: &str) -> Result<Duration> {
+    let secs = payload
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|secs| secs.is_finite() && *secs >= 0.0)
+        .ok_or_else(|| anyhow!("invalid number of seconds '{}'", payload.trim()))?;
+
+    Ok(Duration::from_secs_f64(secs.min(MAX_SLEEP_SECS as f64)))
+}
+
+#[derive(Debug, Default, Clone)]
+struct Sleep {}
+
+#[async_trait]
+impl Action for Sleep {
+    fn name(&self) -> &str {
+        "sleep"
+    }
+
+    fn description(&self) -> &str {
+        include_str!("sleep.prompt")
+    }
+
+    fn example_payload(&self) -> Option<&str> {
+        Some("5")
+    }
+
+    async fn run(
+        &self,
+        _: SharedState,
+        _: Option<HashMap<String, String>>,
+        payload: Option<String>,
+    ) -> Result<Option<String>> {
+        let duration = sleep_duration(&payload.unwrap())?;
+
+        tokio::time::sleep(duration).await;
+
+        Ok(Some(format!("slept for {:?}", duration)))
+    }
+}
+
+pub(crate) fn get_namespace() -> Namespace {
+    Namespace::new_non_default(
+        "Time".to_string(),
+        include_str!("ns.prompt").to_string(),
+        vec![Box::<CurrentTime>::default(), Box::<Sleep>::default()],
+        None,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_current_time() {
+        let now = DateTime::parse_from_rfc3339("2024-06-01T12:30:45Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let output = current_time(now);
+        let lines: Vec<&str> = output.lines().collect();
+
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1], "utc: 2024-06-01T12:30:45Z");
+
+        let local =
+            DateTime::parse_from_rfc3339(lines[0].strip_prefix("local: ").unwrap()).unwrap();
+        assert_eq!(local.with_timezone(&Utc), now);
+    }
+
+    #[test]
+    fn test_sleep_duration() {
+        assert_eq!(sleep_duration(" 2 ").unwrap(), Duration::from_secs(2));
+        assert_eq!(sleep_duration("0.5").unwrap(), Duration::from_millis(500));
+        assert_eq!(
+            sleep_duration("86400").unwrap(),
+            Duration::from_secs(MAX_SLEEP_SECS)
+        );
+        assert!(sleep_duration("-1").is_err());
+        assert!(sleep_duration("forever").is_err());
+        assert!(sleep_duration("inf").is_err());
+    }
+}
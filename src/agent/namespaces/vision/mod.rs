@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use base64::Engine;
+
+use super::{Action, Namespace};
+use crate::agent::state::SharedState;
+
+// formats supported by most vision models
+fn image_format(data: &[u8]) -> Option<&'static str> {
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("png")
+    } else if data.starts_with(&[0xff, 0xd8, 0xff]) {
+        Some("jpeg")
+    } else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+        Some("gif")
+    } else if data.len() >= 12 && &data[..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+        Some("webp")
+    } else {
+        None
+    }
+}
+
+// read an image and encode it as base64
+fn load_image(path: &str) -> Result<(&'static str, String)> {
+    let data = std::fs::read(path).map_err(|e| anyhow!("can't read image {}: {}", path, e))?;
+    let format = image_format(&data).ok_or_else(|| {
+        anyhow!(
+            "{} is not a supported image, only png, jpeg, gif and webp files can be used",
+            path
+        )
+    })?;
+
+    Ok((
+        format,
+        base64::engine::general_purpose::STANDARD.encode(&data),
+    ))
+}
+
+#[derive(Debug, Default, Clone)]
+struct DescribeImage {}
+
+#[async_trait]
+impl Action for DescribeImage {
+    fn name(&self) -> &str {
+        "describe-image"
+    }
+
+    fn description(&self) -> &str {
+        include_str!("describe_image.prompt")
+    }
+
+    fn example_payload(&self) -> Option<&str> {
+        Some("/path/to/image.png")
+    }
+
+    async fn run(
+        &self,
+        state: SharedState,
+        _: Option<HashMap<String, String>>,
+        payload: Option<String>,
+    ) -> Result<Option<String>> {
+        let payload = payload.unwrap();
+        let path = payload.trim();
+        let (format, image) = load_image(path)?;
+
+        state.lock().await.add_image(image);
+
+        Ok(Some(format!(
+            "{} image {} attached to this message",
+            format, path
+        )))
+    }
+}
+
+pub(crate) fn get_namespace() -> Namespace {
+    Namespace::new_non_default(
+        "Vision".to_string(),
+        include_str!("ns.prompt").to_string(),
+        vec![Box::<DescribeImage>::default()],
+        None,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_image() {
+        let dir = std::env::temp_dir().join(format!("nerve-vision-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let png = dir.join("image.png");
+        std::fs::write(&png, b"\x89PNG\r\n\x1a\n....").unwrap();
+        let (format, image) = load_image(&png.display().to_string()).unwrap();
+        assert_eq!(format, "png");
+        assert_eq!(image, "iVBORw0KGgouLi4u");
+
+        let text = dir.join("image.txt");
+        std::fs::write(&text, "not an image").unwrap();
+        assert!(load_image(&text.display().to_string()).is_err());
+
+        assert!(load_image(&dir.join("missing.png").display().to_string()).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
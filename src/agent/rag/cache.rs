@@ -0,0 +1,118 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use anyhow::Result;
+use async_trait::async_trait;
+
+use super::{Embedder, Embeddings};
+
+#[derive(Default)]
+struct Cache {
+    // logical clock used to track when each query was last used
+    clock: u64,
+    entries: HashMap<String, (Embeddings, u64)>,
+}
+
+// remembers the embeddings of the most recent queries so that repeated retrievals don't hit
+// the embedding model again, documents are always embedded by the wrapped embedder
+pub struct CachedEmbedder {
+    embedder: Box<dyn Embedder>,
+    capacity: usize,
+    cache: Mutex<Cache>,
+}
+
+impl CachedEmbedder {
+    pub fn new(embedder: Box<dyn Embedder>, capacity: usize) -> Self {
+        Self {
+            embedder,
+            capacity,
+            cache: Mutex::new(Cache::default()),
+        }
+    }
+
+    fn get(&self, query: &str) -> Option<Embeddings> {
+        let mut cache = self.cache.lock().unwrap();
+        cache.clock += 1;
+        let clock = cache.clock;
+        cache.entries.get_mut(query).map(|(embeddings, used)| {
+            *used = clock;
+            embeddings.clone()
+        })
+    }
+
+    fn put(&self, query: &str, embeddings: Embeddings) {
+        let mut cache = self.cache.lock().unwrap();
+        if cache.entries.len() >= self.capacity && !cache.entries.contains_key(query) {
+            // evict the least recently used query
+            if let Some(oldest) = cache
+                .entries
+                .iter()
+                .min_by_key(|(_, (_, used))| *used)
+                .map(|(query, _)| query.to_string())
+            {
+                cache.entries.remove(&oldest);
+            }
+        }
+        cache.clock += 1;
+        let clock = cache.clock;
+        cache.entries.insert(query.to_string(), (embeddings, clock));
+    }
+}
+
+#[async_trait]
+impl Embedder for CachedEmbedder {
+    async fn embed(&self, text: &str) -> Result<Embeddings> {
+        if let Some(embeddings) = self.get(text) {
+            log::debug!("query embeddings cache hit");
+            return Ok(embeddings);
+        }
+
+        let embeddings = self.embedder.embed(text).await?;
+        self.put(text, embeddings.clone());
+        Ok(embeddings)
+    }
+
+    async fn embed_batch(&self, texts: &[String]) -> Result<Vec<Embeddings>> {
+        self.embedder.embed_batch(texts).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+
+    use super::super::{naive::tests::MockEmbedder, Configuration};
+
+    #[tokio::test]
+    async fn test_query_cache() {
+        let embedder = MockEmbedder::default();
+        let embeds = embedder.embeds.clone();
+
+        let root = std::env::temp_dir().join(format!("nerve-cache-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("docs")).unwrap();
+        std::fs::write(root.join("docs/doc.txt"), "[1.0, 0.0]").unwrap();
+        let mut store = super::super::create(
+            Box::new(embedder),
+            Configuration {
+                source_path: root.join("docs").display().to_string(),
+                data_path: root.display().to_string(),
+                query_cache_size: Some(1),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        store.import_new_documents().await.unwrap();
+        let indexing = embeds.load(Ordering::SeqCst);
+
+        store.retrieve("[1.0, 0.0]", 1, None).await.unwrap();
+        store.retrieve("[1.0, 0.0]", 1, None).await.unwrap();
+        assert_eq!(embeds.load(Ordering::SeqCst), indexing + 1);
+
+        // the least recently used query is evicted
+        store.retrieve("[0.0, 1.0]", 1, None).await.unwrap();
+        store.retrieve("[1.0, 0.0]", 1, None).await.unwrap();
+        assert_eq!(embeds.load(Ordering::SeqCst), indexing + 3);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
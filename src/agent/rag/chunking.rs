@@ -0,0 +1,209 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+
+lazy_static! {
+    // an empty line, possibly with spaces
+    static ref PARAGRAPH_SEPARATOR: Regex = Regex::new(r"\n[ \t\r]*\n\s*").unwrap();
+}
+
+// split text in chunks of chunk_size characters where consecutive chunks share chunk_overlap
+// characters, chunk_overlap must be smaller than chunk_size
+pub(crate) fn char_chunks(text: &str, chunk_size: usize, chunk_overlap: usize) -> Vec<String> {
+    let chars = text.chars().collect::<Vec<char>>();
+    let mut chunks = vec![];
+    let mut start = 0;
+    while start < chars.len() {
+        let end = (start + chunk_size).min(chars.len());
+        chunks.push(chars[start..end].iter().collect::<String>());
+        if end == chars.len() {
+            break;
+        }
+        start = end - chunk_overlap;
+    }
+
+    chunks
+}
+
+fn split_paragraphs(text: &str) -> Vec<&str> {
+    PARAGRAPH_SEPARATOR
+        .split(text)
+        .map(|p| p.trim())
+        .filter(|p| !p.is_empty())
+        .collect()
+}
+
+// a sentence ends with one or more terminators, optionally followed by closing quotes or
+// brackets, and then whitespace
+fn split_sentences(text: &str) -> Vec<&str> {
+    let mut sentences = vec![];
+    let mut start = 0;
+    let mut chars = text.char_indices().peekable();
+
+    while let Some((idx, c)) = chars.next() {
+        if !matches!(c, '.' | '!' | '?') {
+            continue;
+        }
+
+        let mut end = idx + c.len_utf8();
+        while let Some(&(idx, c)) = chars.peek() {
+            if matches!(c, '.' | '!' | '?' | '"' | '\'' | ')' | ']') {
+                end = idx + c.len_utf8();
+                chars.next();
+            } else {
+                break;
+            }
+        }
+
+        if chars.peek().is_none_or(|(_, c)| c.is_whitespace()) {
+            let sentence = text[start..end].trim();
+            if !sentence.is_empty() {
+                sentences.push(sentence);
+            }
+            start = end;
+        }
+    }
+
+    let rest = text[start..].trim();
+    if !rest.is_empty() {
+        sentences.push(rest);
+    }
+
+    sentences
+}
+
+// split text in chunks of at most chunk_size units as measured by size, breaking at paragraphs
+// if possible, then at sentences and only then anywhere with hard_split. Consecutive chunks
+// share the trailing paragraphs or sentences that fit in chunk_overlap units.
+pub(crate) fn boundary_chunks(
+    text: &str,
+    chunk_size: usize,
+    chunk_overlap: usize,
+    size: impl Fn(&str) -> usize,
+    hard_split: impl Fn(&str) -> Vec<String>,
+) -> Vec<String> {
+    // the pieces to pack together, each with the separator that joins it to the previous one
+    let mut pieces: Vec<(&str, String)> = vec![];
+    for paragraph in split_paragraphs(text) {
+        if size(paragraph) <= chunk_size {
+            pieces.push(("\n\n", paragraph.to_string()));
+            continue;
+        }
+
+        for (idx, sentence) in split_sentences(paragraph).into_iter().enumerate() {
+            let separator = if idx == 0 { "\n\n" } else { " " };
+            if size(sentence) <= chunk_size {
+                pieces.push((separator, sentence.to_string()));
+            } else {
+                for (idx, part) in hard_split(sentence).into_iter().enumerate() {
+                    pieces.push((if idx == 0 { separator } else { "" }, part));
+                }
+            }
+        }
+    }
+
+    let join = |pieces: &[(&str, String)]| -> String {
+        let mut joined = String::new();
+        for (idx, (separator, piece)) in pieces.iter().enumerate() {
+            if idx > 0 {
+                joined += separator;
+            }
+            joined += piece;
+        }
+        joined
+    };
+
+    let mut chunks = vec![];
+    let mut current: Vec<(&str, String)> = vec![];
+    for piece in pieces {
+        current.push(piece);
+        if current.len() == 1 || size(&join(&current)) <= chunk_size {
+            continue;
+        }
+
+        let piece = current.pop().unwrap();
+        chunks.push(join(&current));
+
+        // carry over the trailing pieces within the overlap, as long as the new one still fits
+        let mut keep = 0;
+        while keep < current.len()
+            && size(&join(&current[current.len() - keep - 1..])) <= chunk_overlap
+        {
+            keep += 1;
+        }
+        current.drain(..current.len() - keep);
+        current.push(piece);
+        while current.len() > 1 && size(&join(&current)) > chunk_size {
+            current.remove(0);
+        }
+    }
+
+    if !current.is_empty() {
+        chunks.push(join(&current));
+    }
+
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn by_chars(text: &str, chunk_size: usize, chunk_overlap: usize) -> Vec<String> {
+        boundary_chunks(
+            text,
+            chunk_size,
+            chunk_overlap,
+            |s| s.chars().count(),
+            |s| char_chunks(s, chunk_size, 0),
+        )
+    }
+
+    #[test]
+    fn test_split_sentences() {
+        assert_eq!(
+            split_sentences("Hi there. Is v1.2 out?! He said \"yes.\" (Really.) ok"),
+            vec![
+                "Hi there.",
+                "Is v1.2 out?!",
+                "He said \"yes.\"",
+                "(Really.)",
+                "ok"
+            ]
+        );
+        assert_eq!(
+            split_paragraphs("\n first\nline \n  \n\nsecond\r\n\r\nthird\n"),
+            vec!["first\nline", "second", "third"]
+        );
+    }
+
+    #[test]
+    fn test_boundary_chunks() {
+        let text = "The cat sat on the mat. It was a sunny day. The dog barked!\n\nA new paragraph starts here. It is short.\n\nEnd.";
+
+        let chunks = by_chars(text, 50, 0);
+        assert_eq!(
+            chunks,
+            vec![
+                "The cat sat on the mat. It was a sunny day.",
+                "The dog barked!",
+                "A new paragraph starts here. It is short.\n\nEnd."
+            ]
+        );
+        for chunk in &chunks {
+            assert!(chunk.chars().count() <= 50);
+            assert!(chunk.ends_with(['.', '!']), "{:?}", chunk);
+        }
+
+        // trailing sentences are repeated when they fit in the overlap
+        assert_eq!(
+            by_chars("One. Two. Three. Four.", 11, 5),
+            vec!["One. Two.", "Two. Three.", "Four."]
+        );
+
+        // sentences larger than the chunk are split anyway
+        assert_eq!(
+            by_chars("Short one. abcdefghijklmnopqrstuvwxyz.", 12, 0),
+            vec!["Short one.", "abcdefghijkl", "mnopqrstuvwx", "yz."]
+        );
+    }
+}
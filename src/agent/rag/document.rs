@@ -0,0 +1,254 @@
+use std::{collections::BTreeMap, io::Read, path::Path};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use super::{chunking, ChunkBy, ChunkStrategy};
+use crate::agent::tokenizer::CL100K;
+
+// key value pairs describing a document, used to filter retrieval
+pub type Metadata = BTreeMap<String, String>;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Document {
+    path: String,
+    ident: String,
+    // the text that has been embedded, stored with the index since chunks don't exist on disk
+    #[serde(default)]
+    data: Option<String>,
+    #[serde(default)]
+    metadata: Metadata,
+}
+
+impl Document {
+    pub fn from_reader<R>(path: &Path, mut reader: R) -> Result<Self>
+    where
+        R: Read,
+    {
+        let path = std::fs::canonicalize(path.display().to_string())?
+            .display()
+            .to_string();
+
+        let mut data = String::new();
+
+        reader.read_to_string(&mut data)?;
+
+        let data = Some(data);
+        let ident = sha256::digest(data.as_ref().unwrap());
+        Ok(Self {
+            path,
+            data,
+            ident,
+            metadata: Metadata::new(),
+        })
+    }
+
+    pub fn set_metadata(&mut self, key: &str, value: &str) {
+        self.metadata.insert(key.to_string(), value.to_string());
+    }
+
+    // true if the document has all the filter values, comma separated metadata like tags match
+    // if any of their elements does
+    pub fn matches(&self, filter: &Metadata) -> bool {
+        filter.iter().all(|(key, expected)| {
+            self.metadata.get(key).is_some_and(|value| {
+                value
+                    .split(',')
+                    .any(|v| v.trim().eq_ignore_ascii_case(expected.trim()))
+            })
+        })
+    }
+
+    pub fn get_ident(&self) -> &str {
+        &self.ident
+    }
+
+    pub fn get_path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn get_data(&mut self) -> Result<&str> {
+        // indexed by an older version that didn't store the text
+        if self.data.is_none() {
+            self.data = Some(std::fs::read_to_string(&self.path)?);
+        }
+
+        Ok(self.data.as_ref().unwrap())
+    }
+
+    pub fn chunks(
+        mut self,
+        chunk_size: usize,
+        chunk_overlap: usize,
+        chunk_by: ChunkBy,
+        strategy: ChunkStrategy,
+    ) -> Result<Vec<Document>> {
+        let chunk_size = chunk_size.max(1);
+        // consecutive chunks must not be the same
+        let chunk_overlap = chunk_overlap.min(chunk_size - 1);
+
+        let data = self.get_data()?;
+        let chunks = match (strategy, chunk_by) {
+            (ChunkStrategy::Fixed, ChunkBy::Chars) => {
+                chunking::char_chunks(data, chunk_size, chunk_overlap)
+            }
+            (ChunkStrategy::Fixed, ChunkBy::Tokens) => {
+                CL100K.chunks(data, chunk_size, chunk_overlap)
+            }
+            (ChunkStrategy::Boundaries, ChunkBy::Chars) => chunking::boundary_chunks(
+                data,
+                chunk_size,
+                chunk_overlap,
+                |s| s.chars().count(),
+                |s| chunking::char_chunks(s, chunk_size, 0),
+            ),
+            (ChunkStrategy::Boundaries, ChunkBy::Tokens) => chunking::boundary_chunks(
+                data,
+                chunk_size,
+                chunk_overlap,
+                |s| CL100K.count(s),
+                |s| CL100K.chunks(s, chunk_size, 0),
+            ),
+        };
+
+        // so that changing how documents are chunked indexes them again
+        let kind = match (strategy, chunk_by) {
+            (ChunkStrategy::Fixed, ChunkBy::Chars) => "",
+            (ChunkStrategy::Fixed, ChunkBy::Tokens) => "t",
+            (ChunkStrategy::Boundaries, ChunkBy::Chars) => "b",
+            (ChunkStrategy::Boundaries, ChunkBy::Tokens) => "bt",
+        };
+
+        Ok(chunks
+            .into_iter()
+            .enumerate()
+            .map(|(idx, chunk)| Document {
+                ident: format!("{}@{}{}", self.ident, kind, idx),
+                path: format!("{}@{}{}", self.path, kind, idx),
+                data: Some(chunk),
+                metadata: self.metadata.clone(),
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_vs_char_chunks() {
+        let data = "Ünïcödé テキスト 🦀🚀 ".repeat(8);
+
+        let doc = Document {
+            path: "test".to_string(),
+            ident: "test".to_string(),
+            data: Some(data.clone()),
+            metadata: Metadata::new(),
+        };
+
+        // chunks by chars overshoot the token limit with multibyte text
+        let by_chars = doc
+            .clone()
+            .chunks(16, 0, ChunkBy::Chars, ChunkStrategy::Fixed)
+            .unwrap();
+        assert!(by_chars
+            .into_iter()
+            .any(|mut c| CL100K.count(c.get_data().unwrap()) > 16));
+
+        let mut rebuilt = String::new();
+        for mut chunk in doc
+            .clone()
+            .chunks(16, 0, ChunkBy::Tokens, ChunkStrategy::Fixed)
+            .unwrap()
+        {
+            let text = chunk.get_data().unwrap().to_string();
+            // allow for characters split across tokens
+            assert!(CL100K.count(&text) <= 16 + 3);
+            rebuilt += &text;
+        }
+        assert_eq!(rebuilt, data);
+    }
+
+    #[test]
+    fn test_boundary_chunks() {
+        let data = "Nerve is a tool that creates LLM agents. Agents are defined in yaml files! \
+            They can use a set of actions to complete their task.\n\n\
+            Documents can be imported into a knowledge base. Are they split into chunks? \
+            Only when chunk_size is set, and then they are embedded one by one.\n"
+            .to_string();
+        let doc = Document {
+            path: "test".to_string(),
+            ident: "test".to_string(),
+            data: Some(data),
+            metadata: Metadata::new(),
+        };
+
+        for (chunk_by, chunk_size) in [(ChunkBy::Chars, 80), (ChunkBy::Tokens, 20)] {
+            let chunks = doc
+                .clone()
+                .chunks(chunk_size, 0, chunk_by, ChunkStrategy::Boundaries)
+                .unwrap();
+            assert!(chunks.len() > 2);
+            for mut chunk in chunks {
+                assert!(chunk.get_ident().contains("@b"));
+                let text = chunk.get_data().unwrap();
+                assert!(text.ends_with(['.', '!', '?']), "{:?}", text);
+                assert!(text.starts_with(char::is_uppercase), "{:?}", text);
+            }
+        }
+
+        // the fixed strategy cuts sentences
+        assert!(doc
+            .chunks(80, 0, ChunkBy::Chars, ChunkStrategy::Fixed)
+            .unwrap()
+            .into_iter()
+            .any(|mut c| !c.get_data().unwrap().ends_with('.')));
+    }
+
+    #[test]
+    fn test_chunk_overlap() {
+        let doc = Document {
+            path: "test".to_string(),
+            ident: "test".to_string(),
+            data: Some("abcdefghij".to_string()),
+            metadata: Metadata::new(),
+        };
+
+        let chunks: Vec<String> = doc
+            .clone()
+            .chunks(4, 2, ChunkBy::Chars, ChunkStrategy::Fixed)
+            .unwrap()
+            .into_iter()
+            .map(|mut c| c.get_data().unwrap().to_string())
+            .collect();
+        // the last chunk is not repeated
+        assert_eq!(chunks, vec!["abcd", "cdef", "efgh", "ghij"]);
+
+        // overlap can't be larger than the chunk
+        let chunks = doc
+            .clone()
+            .chunks(4, 10, ChunkBy::Chars, ChunkStrategy::Fixed)
+            .unwrap();
+        assert_eq!(chunks.len(), 7);
+
+        let data = "the quick brown fox jumps over the lazy dog, ".repeat(4);
+        let doc = Document {
+            data: Some(data),
+            ..doc
+        };
+        let chunks: Vec<String> = doc
+            .chunks(8, 3, ChunkBy::Tokens, ChunkStrategy::Fixed)
+            .unwrap()
+            .into_iter()
+            .map(|mut c| c.get_data().unwrap().to_string())
+            .collect();
+        assert!(chunks.len() > 1);
+        let bpe = tiktoken_rs::cl100k_base().unwrap();
+        for pair in chunks.windows(2) {
+            let tokens = bpe.encode_ordinary(&pair[0]);
+            let tail = bpe.decode(tokens[tokens.len() - 3..].to_vec()).unwrap();
+            assert!(pair[1].starts_with(&tail), "{:?}", pair);
+        }
+    }
+}
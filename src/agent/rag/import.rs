@@ -0,0 +1,425 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use encoding_rs::Encoding;
+use glob::glob;
+
+use super::{metrics, Configuration, Document, Embedder, Embeddings, Metadata};
+
+// extensions imported when the configuration doesn't specify them
+pub(crate) const DEFAULT_EXTENSIONS: &[&str] = &["txt", "md", "markdown", "html", "htm", "pdf"];
+
+// wrap the text extracted from html at this column
+const HTML_TEXT_WIDTH: usize = 200;
+
+// turns the raw contents of a file into text
+type Extractor = fn(&Path, &[u8], &'static Encoding, bool) -> Result<String>;
+
+fn extractor_for(ext: &str) -> Option<Extractor> {
+    match ext {
+        "txt" | "md" | "markdown" => Some(decode),
+        "html" | "htm" => Some(extract_html),
+        #[cfg(feature = "pdf")]
+        "pdf" => Some(extract_pdf),
+        _ => None,
+    }
+}
+
+// decode raw text, a byte order mark takes precedence over the configured encoding
+fn decode(path: &Path, raw: &[u8], encoding: &'static Encoding, lossy: bool) -> Result<String> {
+    let (text, used, had_errors) = encoding.decode(raw);
+    if had_errors {
+        if !lossy {
+            return Err(anyhow!(
+                "{} is not valid {} text",
+                path.display(),
+                used.name()
+            ));
+        }
+        log::warn!(
+            "{} contains invalid {} sequences, they have been replaced",
+            path.display(),
+            used.name()
+        );
+    }
+
+    Ok(text.to_string())
+}
+
+// strip the tags and keep the text, links are kept as references
+fn extract_html(
+    path: &Path,
+    raw: &[u8],
+    encoding: &'static Encoding,
+    lossy: bool,
+) -> Result<String> {
+    let html = decode(path, raw, encoding, lossy)?;
+
+    html2text::config::plain()
+        .string_from_read(html.as_bytes(), HTML_TEXT_WIDTH)
+        .map_err(|e| anyhow!("can't parse {} as html: {:?}", path.display(), e))
+}
+
+#[cfg(feature = "pdf")]
+fn extract_pdf(path: &Path, raw: &[u8], _: &'static Encoding, _: bool) -> Result<String> {
+    let pdf = lopdf::Document::load_mem(raw)?;
+    let pages = pdf.get_pages();
+    let mut parts = vec![];
+
+    for (i, _) in pages.iter().enumerate() {
+        let page_number = (i + 1) as u32;
+        let page_text = pdf.extract_text(&[page_number]).map_err(|e| {
+            anyhow!(
+                "can't parse page {} of {}: {:?}",
+                page_number,
+                path.display(),
+                e
+            )
+        })?;
+        parts.push(page_text);
+    }
+
+    Ok(parts.join("\n\n"))
+}
+
+// metadata from the yaml block at the beginning of a markdown document, lists are joined by commas
+fn front_matter(text: &str) -> Metadata {
+    let mut metadata = Metadata::new();
+    let text = text.trim_start_matches('\u{feff}');
+    if let Some(rest) = text.strip_prefix("---") {
+        if let Some(end) = rest.find("\n---") {
+            match serde_yaml::from_str::<serde_yaml::Mapping>(&rest[..end]) {
+                Ok(mapping) => {
+                    for (key, value) in mapping {
+                        let value = match value {
+                            serde_yaml::Value::Sequence(items) => items
+                                .iter()
+                                .filter_map(yaml_scalar)
+                                .collect::<Vec<String>>()
+                                .join(", "),
+                            other => yaml_scalar(&other).unwrap_or_default(),
+                        };
+                        if let Some(key) = yaml_scalar(&key) {
+                            metadata.insert(key, value);
+                        }
+                    }
+                }
+                Err(err) => log::debug!("invalid front matter: {}", err),
+            }
+        }
+    }
+    metadata
+}
+
+fn yaml_scalar(value: &serde_yaml::Value) -> Option<String> {
+    match value {
+        serde_yaml::Value::String(s) => Some(s.to_string()),
+        serde_yaml::Value::Number(n) => Some(n.to_string()),
+        serde_yaml::Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+pub(crate) fn file_extension(path: &Path) -> String {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or("")
+        .to_lowercase()
+}
+
+pub(crate) fn import_document_from(
+    path: &PathBuf,
+    encoding: &'static Encoding,
+    lossy: bool,
+) -> Result<Document> {
+    let ext = file_extension(path);
+    let extract =
+        extractor_for(&ext).ok_or_else(|| anyhow!("file extension '{ext}' not handled"))?;
+
+    let raw = std::fs::read(path)?;
+    let text = extract(path, &raw, encoding, lossy)?;
+    let metadata = if ext == "md" || ext == "markdown" {
+        front_matter(&text)
+    } else {
+        Metadata::new()
+    };
+
+    // https://stackoverflow.com/questions/32674905/pass-string-to-function-taking-read-trait
+    let mut document = Document::from_reader(path, VecDeque::from(text.into_bytes()))?;
+    document.set_metadata("type", &ext);
+    for (key, value) in metadata {
+        document.set_metadata(&key, &value);
+    }
+
+    Ok(document)
+}
+
+// storage operations the import relies on
+#[async_trait]
+pub(crate) trait Index: Send {
+    fn get_config(&self) -> &Configuration;
+    // modification time of the source file when it was last imported
+    fn get_source_modified(&self, path: &str) -> Result<Option<u64>>;
+    fn get_source_paths(&self) -> Result<Vec<String>>;
+    // record the documents a source file has been split into, the documents of its previous
+    // version that no other source shares are removed
+    fn set_source(&mut self, path: &str, modified: u64, documents: Vec<String>) -> Result<()>;
+    // forget a source file and the documents no other source shares, returns how many
+    // documents have been removed
+    fn remove_source(&mut self, path: &str) -> Result<usize>;
+    // embed and add the documents not indexed yet without persisting them
+    async fn add_documents(&mut self, documents: Vec<Document>) -> Result<usize>;
+    fn save(&mut self) -> Result<()>;
+}
+
+// load the contents of the documents to embed
+pub(crate) fn document_texts(documents: &mut [Document]) -> Result<Vec<String>> {
+    let mut texts = vec![];
+    for document in documents.iter_mut() {
+        let data = document.get_data()?.to_string();
+        log::info!(
+            "indexing new document '{}' ({} bytes) ...",
+            document.get_path(),
+            data.len()
+        );
+        texts.push(data);
+    }
+    Ok(texts)
+}
+
+pub(crate) async fn embed_documents(
+    embedder: &dyn Embedder,
+    texts: &[String],
+    normalize: bool,
+) -> Result<Vec<Embeddings>> {
+    let mut embeddings = embedder.embed_batch(texts).await?;
+    if embeddings.len() != texts.len() {
+        return Err(anyhow!(
+            "expected {} embeddings, got {}",
+            texts.len(),
+            embeddings.len()
+        ));
+    }
+    if normalize {
+        embeddings.iter_mut().for_each(metrics::normalize);
+    }
+    Ok(embeddings)
+}
+
+fn modified_time(path: &Path) -> Result<u64> {
+    let modified = std::fs::metadata(path)?.modified()?;
+    Ok(modified
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0))
+}
+
+// documents waiting to be embedded and the source files they come from
+#[derive(Default)]
+struct Batch {
+    documents: Vec<Document>,
+    sources: Vec<(String, u64, Vec<String>)>,
+}
+
+impl Batch {
+    fn add(&mut self, path: String, modified: u64, documents: Vec<Document>) {
+        let doc_ids = documents
+            .iter()
+            .map(|doc| doc.get_ident().to_string())
+            .collect();
+        self.sources.push((path, modified, doc_ids));
+        self.documents.extend(documents);
+    }
+
+    // embed the pending documents and record the sources they come from, sources are only
+    // recorded once all of their documents are stored so that they're imported again if this fails
+    async fn flush(&mut self, index: &mut impl Index) -> Result<usize> {
+        let documents = std::mem::take(&mut self.documents);
+        let sources = std::mem::take(&mut self.sources);
+
+        match index.add_documents(documents).await {
+            Ok(added) => {
+                for (path, modified, doc_ids) in sources {
+                    index.set_source(&path, modified, doc_ids)?;
+                }
+                Ok(added)
+            }
+            Err(err) => {
+                log::error!(
+                    "storing {}: {}",
+                    sources
+                        .iter()
+                        .map(|(path, _, _)| path.to_string())
+                        .collect::<Vec<String>>()
+                        .join(", "),
+                    err
+                );
+                Ok(0)
+            }
+        }
+    }
+}
+
+// index the files of the source path that are new or changed since the last import
+pub(crate) async fn import_new_documents(index: &mut impl Index) -> Result<()> {
+    let config = index.get_config().clone();
+    let root = std::fs::canonicalize(&config.source_path)?;
+    let expr = format!("{}/**/*.*", root.display());
+    let start = Instant::now();
+    let checkpoint_every = config.get_checkpoint_every();
+    let batch_size = config.get_embedding_batch_size();
+    let encoding = config.get_encoding()?;
+    let mut new = 0;
+    let mut unsaved = 0;
+    let mut seen = HashSet::new();
+    let mut batch = Batch::default();
+
+    for path in (glob(&expr)?).flatten() {
+        if !config.is_supported(&path) {
+            log::debug!("skipping {}", path.display());
+            continue;
+        }
+
+        let source_path = path.display().to_string();
+        let modified = match modified_time(&path) {
+            Ok(modified) => modified,
+            Err(err) => {
+                log::warn!("{} {err}", path.display());
+                continue;
+            }
+        };
+        seen.insert(source_path.to_string());
+
+        if let Some(last_modified) = index.get_source_modified(&source_path)? {
+            if last_modified == modified {
+                continue;
+            }
+            log::info!("{} changed, indexing it again ...", &source_path);
+        }
+
+        match import_document_from(&path, encoding, config.lossy) {
+            Ok(mut doc) => {
+                // so that retrieval can be scoped to a folder of the knowledge base
+                if let Some(folder) = path
+                    .parent()
+                    .and_then(|parent| parent.strip_prefix(&root).ok())
+                    .map(|folder| folder.display().to_string())
+                    .filter(|folder| !folder.is_empty())
+                {
+                    doc.set_metadata("folder", &folder);
+                }
+
+                let docs = if let Some(chunk_size) = config.chunk_size {
+                    doc.chunks(
+                        chunk_size,
+                        config.chunk_overlap.unwrap_or(0),
+                        config.chunk_by,
+                        config.chunk_strategy,
+                    )?
+                } else {
+                    vec![doc]
+                };
+
+                batch.add(source_path, modified, docs);
+            }
+            Err(err) => log::warn!("{} {err}", path.display()),
+        }
+
+        if batch.documents.len() >= batch_size {
+            let added = batch.flush(index).await?;
+            new += added;
+            // the sources changed even if their documents were already indexed
+            unsaved += added.max(1);
+
+            // checkpoint so that an interrupted import can resume from here
+            if unsaved >= checkpoint_every {
+                index.save()?;
+                unsaved = 0;
+            }
+        }
+    }
+
+    if !batch.sources.is_empty() {
+        let added = batch.flush(index).await?;
+        new += added;
+        unsaved += added.max(1);
+    }
+
+    if config.prune_deleted {
+        for path in index.get_source_paths()? {
+            if !seen.contains(&path) {
+                let removed = index.remove_source(&path)?;
+                log::info!("{} has been deleted, {} documents removed", path, removed);
+                unsaved += 1;
+            }
+        }
+    }
+
+    if unsaved > 0 {
+        index.save()?;
+    }
+
+    if new > 0 {
+        log::info!("{} new documents indexed in {:?}\n", new, start.elapsed(),);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_import_by_type() {
+        let dir = std::env::temp_dir().join(format!("nerve-import-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let markdown = dir.join("notes.MD");
+        std::fs::write(&markdown, "# Notes\n\nsome *markdown*").unwrap();
+        let mut doc = import_document_from(&markdown, encoding_rs::UTF_8, false).unwrap();
+        assert_eq!(doc.get_data().unwrap(), "# Notes\n\nsome *markdown*");
+
+        let html = dir.join("page.html");
+        std::fs::write(
+            &html,
+            "<html><head><script>alert(1)</script></head><body><p>hello world</p></body></html>",
+        )
+        .unwrap();
+        let mut doc = import_document_from(&html, encoding_rs::UTF_8, false).unwrap();
+        assert_eq!(doc.get_data().unwrap().trim(), "hello world");
+
+        let other = dir.join("image.png");
+        std::fs::write(&other, b"\x89PNG").unwrap();
+        assert!(import_document_from(&other, encoding_rs::UTF_8, false).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_decode() {
+        let path = Path::new("test.txt");
+        // "caffè" in latin-1
+        let latin1 = b"caff\xe8";
+
+        assert_eq!(
+            decode(path, latin1, Encoding::for_label(b"latin1").unwrap(), false).unwrap(),
+            "caffè"
+        );
+        assert!(decode(path, latin1, encoding_rs::UTF_8, false).is_err());
+        assert_eq!(
+            decode(path, latin1, encoding_rs::UTF_8, true).unwrap(),
+            "caff\u{FFFD}"
+        );
+        // utf-16 with byte order mark
+        assert_eq!(
+            decode(path, b"\xff\xfeh\x00i\x00", encoding_rs::UTF_8, false).unwrap(),
+            "hi"
+        );
+    }
+}
@@ -0,0 +1,139 @@
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::Embeddings;
+
+// how the query and document embeddings are compared
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DistanceMetric {
+    #[default]
+    Cosine,
+    Euclidean,
+    // better suited for models producing normalized embeddings
+    Dot,
+}
+
+impl DistanceMetric {
+    pub fn score(&self, vec_a: &Embeddings, vec_b: &Embeddings) -> f64 {
+        match self {
+            Self::Cosine => cosine(vec_a, vec_b),
+            Self::Euclidean => euclidean(vec_a, vec_b),
+            Self::Dot => dot(vec_a, vec_b),
+        }
+    }
+
+    // same as score for vectors of unit length, cosine reduces to a dot product
+    pub fn score_normalized(&self, vec_a: &Embeddings, vec_b: &Embeddings) -> f64 {
+        match self {
+            Self::Cosine => 1.0 - dot(vec_a, vec_b),
+            _ => self.score(vec_a, vec_b),
+        }
+    }
+
+    // true if the score is a similarity rather than a distance
+    pub fn higher_is_better(&self) -> bool {
+        matches!(self, Self::Dot)
+    }
+
+    // order two scores so that the most relevant comes first and the ones that can't be scored
+    // (zero vectors with cosine for instance) last
+    pub fn compare(&self, a: f64, b: f64) -> std::cmp::Ordering {
+        match (a.is_nan(), b.is_nan()) {
+            (true, true) => std::cmp::Ordering::Equal,
+            (true, false) => std::cmp::Ordering::Greater,
+            (false, true) => std::cmp::Ordering::Less,
+            _ if self.higher_is_better() => b.partial_cmp(&a).unwrap(),
+            _ => a.partial_cmp(&b).unwrap(),
+        }
+    }
+}
+
+/// Scale a vector to unit length, zero vectors are left as they are
+pub fn normalize(vec: &mut Embeddings) {
+    let magnitude: f64 = vec.par_iter().map(|a| a * a).sum::<f64>().sqrt();
+    if magnitude > 0.0 {
+        vec.par_iter_mut().for_each(|a| *a /= magnitude);
+    }
+}
+
+/// Cosine distance between two vectors
+///
+/// When the features distances lengths don't match, the longer feature vector is truncated to
+/// shorter one when the distance is calculated
+///  
+#[inline]
+pub fn cosine(vec_a: &Embeddings, vec_b: &Embeddings) -> f64 {
+    assert_eq!(vec_a.len(), vec_b.len());
+
+    let dot_product: f64 = vec_a
+        .par_iter()
+        .zip(vec_b.par_iter())
+        .map(|(a, b)| a * b)
+        .sum();
+    let magnitude1: f64 = vec_a.par_iter().map(|a| a * a).sum::<f64>().sqrt();
+    let magnitude2: f64 = vec_b.par_iter().map(|b| b * b).sum::<f64>().sqrt();
+
+    1.0 - dot_product / (magnitude1 * magnitude2)
+}
+
+/// Euclidean (L2) distance between two vectors
+#[inline]
+pub fn euclidean(vec_a: &Embeddings, vec_b: &Embeddings) -> f64 {
+    assert_eq!(vec_a.len(), vec_b.len());
+
+    vec_a
+        .par_iter()
+        .zip(vec_b.par_iter())
+        .map(|(a, b)| (a - b) * (a - b))
+        .sum::<f64>()
+        .sqrt()
+}
+
+/// Dot product of two vectors, unlike the other metrics this is a similarity
+#[inline]
+pub fn dot(vec_a: &Embeddings, vec_b: &Embeddings) -> f64 {
+    assert_eq!(vec_a.len(), vec_b.len());
+
+    vec_a
+        .par_iter()
+        .zip(vec_b.par_iter())
+        .map(|(a, b)| a * b)
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metrics() {
+        let a = vec![1.0, 0.0];
+        let b = vec![0.0, 2.0];
+        let c = vec![3.0, 4.0];
+
+        assert!(cosine(&a, &a).abs() < 1e-9);
+        assert!((cosine(&a, &b) - 1.0).abs() < 1e-9);
+        assert!((cosine(&a, &c) - 0.4).abs() < 1e-9);
+
+        assert_eq!(euclidean(&a, &a), 0.0);
+        assert_eq!(euclidean(&b, &c), 13.0_f64.sqrt());
+        assert_eq!(euclidean(&a, &c), 20.0_f64.sqrt());
+
+        assert_eq!(dot(&a, &b), 0.0);
+        assert_eq!(dot(&a, &c), 3.0);
+        assert_eq!(dot(&b, &c), 8.0);
+
+        assert_eq!(DistanceMetric::Dot.score(&b, &c), 8.0);
+
+        let mut unit = c.clone();
+        normalize(&mut unit);
+        assert_eq!(unit, vec![0.6, 0.8]);
+        assert!((DistanceMetric::Cosine.score_normalized(&a, &unit) - 0.4).abs() < 1e-9);
+        let mut zero = vec![0.0, 0.0];
+        normalize(&mut zero);
+        assert_eq!(zero, vec![0.0, 0.0]);
+        assert!(DistanceMetric::Dot.higher_is_better());
+        assert!(!DistanceMetric::default().higher_is_better());
+    }
+}
@@ -0,0 +1,456 @@
+use std::path::Path;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+mod cache;
+mod chunking;
+mod document;
+mod import;
+pub(crate) mod metrics;
+mod naive;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+pub use document::*;
+pub use naive::*;
+#[cfg(feature = "sqlite")]
+pub use sqlite::*;
+
+#[cfg(test)]
+pub(crate) use naive::tests::MockEmbedder;
+
+pub type Embeddings = Vec<f64>;
+
+#[async_trait]
+pub trait Embedder: Send + Sync {
+    async fn embed(&self, text: &str) -> Result<Embeddings>;
+
+    // one request for multiple texts where the backend supports it
+    async fn embed_batch(&self, texts: &[String]) -> Result<Vec<Embeddings>> {
+        let mut embeddings = vec![];
+        for text in texts {
+            embeddings.push(self.embed(text).await?);
+        }
+        Ok(embeddings)
+    }
+}
+
+#[async_trait]
+pub trait VectorStore: Send {
+    // index the files of the source path that are new or changed since the last import
+    async fn import_new_documents(&mut self) -> Result<()>;
+    // embed and store a single document, false if it was already indexed
+    #[allow(dead_code)]
+    async fn add(&mut self, document: Document) -> Result<bool>;
+    // remove a document and its embedding, false if it wasn't indexed
+    #[allow(dead_code)]
+    fn remove(&mut self, doc_id: &str) -> Result<bool>;
+    // remove every document, sources will be imported again
+    fn clear(&mut self) -> Result<()>;
+    // the top_k documents most relevant to the query with their scores
+    async fn retrieve(
+        &mut self,
+        query: &str,
+        top_k: usize,
+        filter: Option<&Metadata>,
+    ) -> Result<Vec<(Document, f64)>>;
+}
+
+// where the index is kept
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StoreType {
+    // everything in memory, persisted to a single file
+    #[default]
+    Naive,
+    // a sqlite database updated as documents are indexed
+    Sqlite,
+}
+
+pub fn create(embedder: Box<dyn Embedder>, config: Configuration) -> Result<Box<dyn VectorStore>> {
+    let capacity = config.get_query_cache_size();
+    let embedder: Box<dyn Embedder> = if capacity > 0 {
+        Box::new(cache::CachedEmbedder::new(embedder, capacity))
+    } else {
+        embedder
+    };
+
+    let reindex = config.reindex;
+    let mut store: Box<dyn VectorStore> = match config.store {
+        StoreType::Naive => Box::new(NaiveVectorStore::new(embedder, config)?),
+        #[cfg(feature = "sqlite")]
+        StoreType::Sqlite => Box::new(SqliteVectorStore::new(embedder, config)?),
+        #[cfg(not(feature = "sqlite"))]
+        StoreType::Sqlite => return Err(anyhow!("nerve has been built without sqlite support")),
+    };
+
+    if reindex {
+        log::info!("rebuilding the index from scratch ...");
+        store.clear()?;
+    }
+
+    Ok(store)
+}
+
+// unit of measure of chunk_size
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChunkBy {
+    #[default]
+    Chars,
+    // cl100k_base tokens, closer to the actual limit of the embedding model
+    Tokens,
+}
+
+// where documents are split into chunks
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChunkStrategy {
+    // every chunk_size units, regardless of the contents
+    #[default]
+    Fixed,
+    // at paragraph and then sentence boundaries, anywhere only for sentences longer than chunk_size
+    Boundaries,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Configuration {
+    pub source_path: String,
+    pub data_path: String,
+    // embedder string as <type>://<model name>@<host>:<port>, --embedder is used if not specified
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub embedder: Option<String>,
+    #[serde(default)]
+    pub store: StoreType,
+    pub chunk_size: Option<usize>,
+    // number of units (see chunk_by) shared by consecutive chunks
+    pub chunk_overlap: Option<usize>,
+    #[serde(default)]
+    pub chunk_by: ChunkBy,
+    #[serde(default)]
+    pub chunk_strategy: ChunkStrategy,
+    // how documents are ranked against the query
+    #[serde(default)]
+    pub distance: metrics::DistanceMetric,
+    // scale the embeddings to unit length before storing them, documents already indexed are
+    // not normalized retroactively
+    #[serde(default)]
+    pub normalize: bool,
+    // maximum number of embeddings to keep in memory, least recently retrieved ones are evicted
+    pub max_documents: Option<usize>,
+    // save evicted embeddings to disk instead of just dropping them
+    #[serde(default)]
+    pub spill_evicted: bool,
+    // remove the documents of deleted source files from the index
+    #[serde(default)]
+    pub prune_deleted: bool,
+    // number of documents embedded at once during import
+    pub embedding_batch_size: Option<usize>,
+    // number of query embeddings to keep in memory, 0 to disable the cache
+    pub query_cache_size: Option<usize>,
+    // maximum number of documents returned by search-knowledge
+    pub max_results: Option<usize>,
+    // during import, persist the index every this number of new documents
+    pub checkpoint_every: Option<usize>,
+    // discard the existing index and embed every document again, for instance after changing
+    // the embedding model
+    #[serde(default)]
+    pub reindex: bool,
+    // extensions of the files to import, txt, md, markdown, html, htm and pdf if not specified
+    pub extensions: Option<Vec<String>>,
+    // encoding of the text documents, utf-8 if not specified
+    pub encoding: Option<String>,
+    // replace invalid sequences instead of skipping the document
+    #[serde(default = "default_lossy")]
+    pub lossy: bool,
+}
+
+fn default_lossy() -> bool {
+    true
+}
+
+// same defaults as a configuration file only setting the paths
+impl Default for Configuration {
+    fn default() -> Self {
+        Self {
+            source_path: String::new(),
+            data_path: String::new(),
+            embedder: None,
+            store: StoreType::default(),
+            chunk_size: None,
+            chunk_overlap: None,
+            chunk_by: ChunkBy::default(),
+            chunk_strategy: ChunkStrategy::default(),
+            distance: metrics::DistanceMetric::default(),
+            normalize: false,
+            max_documents: None,
+            spill_evicted: false,
+            prune_deleted: false,
+            embedding_batch_size: None,
+            query_cache_size: None,
+            max_results: None,
+            checkpoint_every: None,
+            reindex: false,
+            extensions: None,
+            encoding: None,
+            lossy: default_lossy(),
+        }
+    }
+}
+
+impl Configuration {
+    // load the configuration from a yaml (or json) file, relative paths are relative to its folder
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let data = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("can't read rag configuration {}: {}", path.display(), e))?;
+        let value: serde_yaml::Value = serde_yaml::from_str(&data)
+            .map_err(|e| anyhow!("can't parse rag configuration {}: {}", path.display(), e))?;
+
+        for field in ["source_path", "data_path"] {
+            match value.get(field) {
+                Some(serde_yaml::Value::String(s)) if !s.trim().is_empty() => {}
+                _ => {
+                    return Err(anyhow!(
+                        "rag configuration {}: {} is required",
+                        path.display(),
+                        field
+                    ))
+                }
+            }
+        }
+
+        let mut config: Self = serde_yaml::from_value(value)
+            .map_err(|e| anyhow!("invalid rag configuration {}: {}", path.display(), e))?;
+
+        let folder = std::fs::canonicalize(path.parent().unwrap_or(Path::new(".")))?;
+        for path in [&mut config.source_path, &mut config.data_path] {
+            if Path::new(path.as_str()).is_relative() {
+                *path = folder.join(path.as_str()).display().to_string();
+            }
+        }
+
+        Ok(config)
+    }
+
+    pub fn is_supported(&self, path: &std::path::Path) -> bool {
+        let ext = import::file_extension(path);
+        if let Some(extensions) = &self.extensions {
+            extensions
+                .iter()
+                .any(|e| e.trim_start_matches('.').eq_ignore_ascii_case(&ext))
+        } else {
+            import::DEFAULT_EXTENSIONS.contains(&ext.as_str())
+        }
+    }
+
+    pub fn get_encoding(&self) -> Result<&'static encoding_rs::Encoding> {
+        if let Some(label) = &self.encoding {
+            encoding_rs::Encoding::for_label(label.trim().as_bytes())
+                .ok_or_else(|| anyhow!("unknown encoding '{}'", label))
+        } else {
+            Ok(encoding_rs::UTF_8)
+        }
+    }
+
+    pub fn get_embedding_batch_size(&self) -> usize {
+        self.embedding_batch_size.unwrap_or(16).max(1)
+    }
+
+    pub fn get_query_cache_size(&self) -> usize {
+        self.query_cache_size.unwrap_or(64)
+    }
+
+    pub fn get_max_results(&self) -> usize {
+        self.max_results.unwrap_or(5).max(1)
+    }
+
+    pub fn get_checkpoint_every(&self) -> usize {
+        self.checkpoint_every.unwrap_or(50).max(1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_file() {
+        let dir =
+            std::env::temp_dir().join(format!("nerve-rag-config-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let dir = std::fs::canonicalize(dir).unwrap();
+
+        let config = Configuration {
+            source_path: "docs".to_string(),
+            data_path: "/var/tmp/nerve-index".to_string(),
+            store: StoreType::Sqlite,
+            chunk_size: Some(512),
+            chunk_overlap: Some(64),
+            chunk_by: ChunkBy::Tokens,
+            distance: metrics::DistanceMetric::Euclidean,
+            extensions: Some(vec!["md".to_string()]),
+            ..Default::default()
+        };
+        let path = dir.join("rag.yml");
+        std::fs::write(&path, serde_yaml::to_string(&config).unwrap()).unwrap();
+
+        let loaded = Configuration::from_file(&path).unwrap();
+        // relative paths are resolved against the folder of the file
+        assert_eq!(loaded.source_path, dir.join("docs").display().to_string());
+        assert_eq!(loaded.data_path, "/var/tmp/nerve-index");
+        assert_eq!(
+            serde_yaml::to_value(Configuration {
+                source_path: loaded.source_path.clone(),
+                ..config
+            })
+            .unwrap(),
+            serde_yaml::to_value(&loaded).unwrap()
+        );
+
+        std::fs::write(&path, "source_path: a\ndata_path: b\n").unwrap();
+        let loaded = Configuration::from_file(&path).unwrap();
+        assert!(loaded.lossy);
+        assert_eq!(
+            serde_yaml::to_value(Configuration {
+                source_path: loaded.source_path.clone(),
+                data_path: loaded.data_path.clone(),
+                ..Default::default()
+            })
+            .unwrap(),
+            serde_yaml::to_value(&loaded).unwrap()
+        );
+
+        std::fs::write(&path, "source_path: docs\nchunk_size: 10\n").unwrap();
+        let err = Configuration::from_file(&path).unwrap_err().to_string();
+        assert!(err.ends_with("data_path is required"), "{}", err);
+
+        std::fs::write(&path, "{\"source_path\": \"\", \"data_path\": \"x\"}").unwrap();
+        let err = Configuration::from_file(&path).unwrap_err().to_string();
+        assert!(err.ends_with("source_path is required"), "{}", err);
+
+        std::fs::write(&path, "source_path: a\ndata_path: b\nchunk_size: lots\n").unwrap();
+        assert!(Configuration::from_file(&path).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_retrieve_chunks() {
+        let root = std::env::temp_dir().join(format!("nerve-chunks-{}", std::process::id()));
+        let source = root.join("docs");
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::write(source.join("doc.txt"), "aaaaaaaaaazzzzzzzzzz").unwrap();
+
+        #[allow(unused_mut)]
+        let mut stores = vec![StoreType::Naive];
+        #[cfg(feature = "sqlite")]
+        stores.push(StoreType::Sqlite);
+
+        for store in stores {
+            let data = root.join(format!("{:?}", store));
+            std::fs::create_dir_all(&data).unwrap();
+            let config = Configuration {
+                source_path: source.display().to_string(),
+                data_path: data.display().to_string(),
+                store,
+                chunk_size: Some(10),
+                ..Default::default()
+            };
+
+            // the text of the chunk is returned, also once loaded back from disk
+            for _ in 0..2 {
+                let mut index = create(Box::new(MockEmbedder::default()), config.clone()).unwrap();
+                index.import_new_documents().await.unwrap();
+
+                let mut docs = index.retrieve("zzz", 1, None).await.unwrap();
+                assert!(docs[0].0.get_path().ends_with("doc.txt@1"));
+                assert_eq!(docs[0].0.get_data().unwrap(), "zzzzzzzzzz");
+            }
+        }
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_retrieve_with_encoding() {
+        let root = std::env::temp_dir().join(format!("nerve-encoding-{}", std::process::id()));
+        let source = root.join("docs");
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::write(source.join("menu.txt"), b"caff\xe8 espresso").unwrap();
+
+        #[allow(unused_mut)]
+        let mut stores = vec![StoreType::Naive];
+        #[cfg(feature = "sqlite")]
+        stores.push(StoreType::Sqlite);
+
+        for store in stores {
+            let data = root.join(format!("{:?}", store));
+            std::fs::create_dir_all(&data).unwrap();
+            let config = Configuration {
+                source_path: source.display().to_string(),
+                data_path: data.display().to_string(),
+                store,
+                encoding: Some("latin1".to_string()),
+                lossy: false,
+                ..Default::default()
+            };
+
+            // the decoded text is returned rather than the raw bytes of the file
+            for _ in 0..2 {
+                let mut index = create(Box::new(MockEmbedder::default()), config.clone()).unwrap();
+                index.import_new_documents().await.unwrap();
+
+                let mut docs = index.retrieve("espresso", 1, None).await.unwrap();
+                assert!(docs[0].0.get_path().ends_with("menu.txt"));
+                assert_eq!(docs[0].0.get_data().unwrap(), "caff\u{e8} espresso");
+            }
+        }
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reindex() {
+        let root = std::env::temp_dir().join(format!("nerve-reindex-{}", std::process::id()));
+        let source = root.join("docs");
+        let data = root.join("data");
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::create_dir_all(&data).unwrap();
+        std::fs::write(source.join("a.txt"), "[1.0, 0.0]").unwrap();
+
+        #[allow(unused_mut)]
+        let mut stores = vec![StoreType::Naive];
+        #[cfg(feature = "sqlite")]
+        stores.push(StoreType::Sqlite);
+
+        for store in stores {
+            // embeddings of the query and, unless already indexed, of the document
+            for (reindex, expected) in [(false, 2), (false, 1), (true, 2)] {
+                let embedder = MockEmbedder::default();
+                let embeds = embedder.embeds.clone();
+                let mut index = create(
+                    Box::new(embedder),
+                    Configuration {
+                        source_path: source.display().to_string(),
+                        data_path: data.display().to_string(),
+                        store,
+                        query_cache_size: Some(0),
+                        reindex,
+                        ..Default::default()
+                    },
+                )
+                .unwrap();
+                index.import_new_documents().await.unwrap();
+                assert_eq!(
+                    index.retrieve("[1.0, 0.0]", 5, None).await.unwrap().len(),
+                    1
+                );
+                assert_eq!(embeds.load(std::sync::atomic::Ordering::SeqCst), expected);
+            }
+        }
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
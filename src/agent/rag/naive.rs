@@ -0,0 +1,1211 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::{BufReader, BufWriter, Read, Write},
+    path::PathBuf,
+    time::Instant,
+};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    import,
+    metrics::{self, DistanceMetric},
+    Embedder, VectorStore,
+};
+
+use super::{Configuration, Document, Embeddings, Metadata};
+
+fn score(metric: DistanceMetric, normalized: bool, a: &Embeddings, b: &Embeddings) -> f64 {
+    if normalized {
+        metric.score_normalized(a, b)
+    } else {
+        metric.score(a, b)
+    }
+}
+
+// score every embedding accepted by the filter against the query, the most relevant first
+fn rank<'a>(
+    metric: DistanceMetric,
+    normalized: bool,
+    query_vector: &Embeddings,
+    embeddings: &'a HashMap<String, Embeddings>,
+    filter: impl Fn(&str) -> bool + Sync,
+) -> Vec<(&'a String, f64)> {
+    let mut scores: Vec<(&String, f64)> = embeddings
+        .par_iter()
+        .filter(|(doc_id, _)| filter(doc_id))
+        .map(|(doc_id, doc_embedding)| {
+            (
+                doc_id,
+                score(metric, normalized, query_vector, doc_embedding),
+            )
+        })
+        .collect();
+
+    scores.par_sort_by(|(_, a), (_, b)| metric.compare(*a, *b));
+
+    scores
+}
+
+// an imported file and the documents it has been split into
+#[derive(Serialize, Deserialize)]
+struct Source {
+    // modification time in nanoseconds since the epoch
+    modified: u64,
+    documents: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Store {
+    documents: HashMap<String, Document>,
+    embeddings: HashMap<String, Embeddings>,
+    // logical clock used to track when each embedding was last retrieved or added
+    clock: u64,
+    last_used: HashMap<String, u64>,
+    // imported files by path
+    sources: HashMap<String, Source>,
+}
+
+impl Store {
+    fn new() -> Self {
+        let documents = HashMap::new();
+        let embeddings = HashMap::new();
+        let clock = 0;
+        let last_used = HashMap::new();
+        let sources = HashMap::new();
+        Self {
+            documents,
+            embeddings,
+            clock,
+            last_used,
+            sources,
+        }
+    }
+
+    fn remove_document(&mut self, doc_id: &str) -> bool {
+        let removed = self.documents.remove(doc_id).is_some();
+        self.embeddings.remove(doc_id);
+        self.last_used.remove(doc_id);
+        removed
+    }
+
+    // forget a source file and the documents no other source shares
+    fn remove_source(&mut self, path: &str, keep: &[String]) -> usize {
+        let mut removed = 0;
+        if let Some(source) = self.sources.remove(path) {
+            for doc_id in source.documents {
+                let shared = keep.contains(&doc_id)
+                    || self
+                        .sources
+                        .values()
+                        .any(|other| other.documents.contains(&doc_id));
+                if !shared {
+                    self.remove_document(&doc_id);
+                    removed += 1;
+                }
+            }
+        }
+        removed
+    }
+
+    fn touch(&mut self, doc_id: &str) {
+        self.clock += 1;
+        self.last_used.insert(doc_id.to_string(), self.clock);
+    }
+
+    // evict the least recently used embeddings until at most max are left
+    fn evict(&mut self, max: usize) -> HashMap<String, Embeddings> {
+        let mut evicted = HashMap::new();
+        if self.embeddings.len() <= max {
+            return evicted;
+        }
+
+        let mut by_usage: Vec<(String, u64)> = self
+            .embeddings
+            .keys()
+            .map(|doc_id| {
+                (
+                    doc_id.to_string(),
+                    *self.last_used.get(doc_id).unwrap_or(&0),
+                )
+            })
+            .collect();
+        by_usage.sort_by_key(|(_, used)| *used);
+
+        let to_evict = self.embeddings.len() - max;
+        for (doc_id, _) in by_usage.into_iter().take(to_evict) {
+            // the document itself is kept so that it won't be indexed again
+            if let Some(embeddings) = self.embeddings.remove(&doc_id) {
+                evicted.insert(doc_id.to_string(), embeddings);
+            }
+            self.last_used.remove(&doc_id);
+        }
+
+        evicted
+    }
+
+    fn from_data_path(path: &str) -> Result<Self> {
+        let path = PathBuf::from(path).join("rag.bin");
+        if path.exists() {
+            let raw = std::fs::read(&path)?;
+            match bitcode::deserialize(&raw) {
+                Ok(store) => Ok(store),
+                Err(err) => {
+                    // most likely created by an older version, documents will be indexed again
+                    log::warn!(
+                        "can't load {}, rebuilding the index: {:?}",
+                        path.display(),
+                        err
+                    );
+                    Ok(Store::new())
+                }
+            }
+        } else {
+            Ok(Store::new())
+        }
+    }
+
+    fn to_data_path(&self, path: &str) -> Result<()> {
+        let path = PathBuf::from(path).join("rag.bin");
+        let raw = bitcode::serialize(&self)?;
+
+        std::fs::write(path, raw)?;
+
+        Ok(())
+    }
+
+    // spilled embeddings are appended as length prefixed records, so that they can be
+    // scored one at a time instead of loading the whole file back into memory
+    fn spilled_path(path: &str) -> PathBuf {
+        PathBuf::from(path).join("rag.evicted.bin")
+    }
+
+    fn write_spilled(writer: &mut impl Write, record: &(String, Embeddings)) -> Result<()> {
+        let raw = bitcode::serialize(record)?;
+        writer.write_all(&(raw.len() as u64).to_le_bytes())?;
+        writer.write_all(&raw)?;
+        Ok(())
+    }
+
+    fn spill_to_data_path(path: &str, evicted: HashMap<String, Embeddings>) -> Result<()> {
+        let file = File::options()
+            .create(true)
+            .append(true)
+            .open(Self::spilled_path(path))?;
+        let mut writer = BufWriter::new(file);
+        for record in evicted {
+            Self::write_spilled(&mut writer, &record)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    // visit the spilled embeddings reading a single record at a time
+    fn scan_spilled(
+        path: &str,
+        mut visit: impl FnMut(String, Embeddings) -> Result<()>,
+    ) -> Result<()> {
+        let path = Self::spilled_path(path);
+        if !path.exists() {
+            return Ok(());
+        }
+
+        let mut reader = BufReader::new(File::open(&path)?);
+        loop {
+            let mut size = [0u8; 8];
+            match reader.read_exact(&mut size) {
+                Ok(()) => {}
+                Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => break,
+                Err(err) => return Err(err.into()),
+            }
+
+            let size = u64::from_le_bytes(size);
+            let mut raw = vec![];
+            (&mut reader).take(size).read_to_end(&mut raw)?;
+            if raw.len() as u64 != size {
+                // interrupted while spilling, the last embedding is lost
+                log::warn!("truncated record in {}", path.display());
+                break;
+            }
+
+            let (doc_id, embeddings) = bitcode::deserialize(&raw)?;
+            visit(doc_id, embeddings)?;
+        }
+
+        Ok(())
+    }
+
+    // rewrite the spill file without the given documents
+    fn unspill(path: &str, doc_ids: &HashSet<String>) -> Result<()> {
+        let spilled = Self::spilled_path(path);
+        if !spilled.exists() {
+            return Ok(());
+        }
+
+        let temporary = spilled.with_extension("bin.tmp");
+        let mut writer = BufWriter::new(File::create(&temporary)?);
+        Self::scan_spilled(path, |doc_id, embeddings| {
+            if !doc_ids.contains(&doc_id) {
+                Self::write_spilled(&mut writer, &(doc_id, embeddings))?;
+            }
+            Ok(())
+        })?;
+        writer.flush()?;
+        drop(writer);
+
+        std::fs::rename(temporary, spilled)?;
+        Ok(())
+    }
+}
+
+// keeps the whole index in memory and persists it to a single file
+pub struct NaiveVectorStore {
+    config: Configuration,
+    embedder: Box<dyn Embedder>,
+    store: Store,
+    // number of times the store has been persisted
+    saves: usize,
+}
+
+impl NaiveVectorStore {
+    // keep at most max_documents embeddings in memory, the evicted ones are either spilled to
+    // disk and loaded back when relevant or dropped, in which case their sources are kept so
+    // that unchanged files are not embedded again
+    fn enforce_max_documents(&mut self) -> Result<()> {
+        if let Some(max) = self.config.max_documents {
+            let evicted = self.store.evict(max);
+            if !evicted.is_empty() {
+                log::debug!("evicted {} embeddings", evicted.len());
+                if self.config.spill_evicted {
+                    Store::spill_to_data_path(&self.config.data_path, evicted)?;
+                } else {
+                    for doc_id in evicted.keys() {
+                        self.store.remove_document(doc_id);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn new(embedder: Box<dyn Embedder>, config: Configuration) -> Result<Self> {
+        let store = Store::from_data_path(&config.data_path)?;
+        Ok(Self {
+            config,
+            embedder,
+            store,
+            saves: 0,
+        })
+    }
+}
+
+#[async_trait]
+impl import::Index for NaiveVectorStore {
+    fn get_config(&self) -> &Configuration {
+        &self.config
+    }
+
+    fn get_source_modified(&self, path: &str) -> Result<Option<u64>> {
+        Ok(self.store.sources.get(path).map(|source| source.modified))
+    }
+
+    fn get_source_paths(&self) -> Result<Vec<String>> {
+        Ok(self.store.sources.keys().cloned().collect())
+    }
+
+    fn set_source(&mut self, path: &str, modified: u64, documents: Vec<String>) -> Result<()> {
+        // replace the documents of the previous version
+        self.store.remove_source(path, &documents);
+        self.store.sources.insert(
+            path.to_string(),
+            Source {
+                modified,
+                documents,
+            },
+        );
+        Ok(())
+    }
+
+    fn remove_source(&mut self, path: &str) -> Result<usize> {
+        Ok(self.store.remove_source(path, &[]))
+    }
+
+    async fn add_documents(&mut self, documents: Vec<Document>) -> Result<usize> {
+        let mut ids = HashSet::new();
+        let mut documents: Vec<Document> = documents
+            .into_iter()
+            .filter(|doc| {
+                let doc_id = doc.get_ident();
+                if self.store.documents.contains_key(doc_id) {
+                    log::debug!("document with id '{}' already indexed", doc_id);
+                    false
+                } else {
+                    ids.insert(doc_id.to_string())
+                }
+            })
+            .collect();
+
+        if documents.is_empty() {
+            return Ok(0);
+        }
+
+        let texts = import::document_texts(&mut documents)?;
+
+        let start = Instant::now();
+        let embeddings =
+            import::embed_documents(self.embedder.as_ref(), &texts, self.config.normalize).await?;
+
+        let added = documents.len();
+        for (document, embeddings) in documents.into_iter().zip(embeddings) {
+            let doc_id = document.get_ident().to_string();
+            self.store.documents.insert(doc_id.to_string(), document);
+            self.store.embeddings.insert(doc_id.to_string(), embeddings);
+            self.store.touch(&doc_id);
+        }
+
+        self.enforce_max_documents()?;
+
+        log::debug!("time={:?} documents={}", start.elapsed(), added);
+
+        Ok(added)
+    }
+
+    fn save(&mut self) -> Result<()> {
+        self.saves += 1;
+        self.store.to_data_path(&self.config.data_path)
+    }
+}
+
+#[async_trait]
+impl VectorStore for NaiveVectorStore {
+    async fn import_new_documents(&mut self) -> Result<()> {
+        import::import_new_documents(self).await?;
+        log::debug!("index saved {} times", self.saves);
+        Ok(())
+    }
+
+    async fn add(&mut self, document: Document) -> Result<bool> {
+        let added = import::Index::add_documents(self, vec![document]).await? > 0;
+        if added {
+            import::Index::save(self)?;
+        }
+        Ok(added)
+    }
+
+    fn remove(&mut self, doc_id: &str) -> Result<bool> {
+        if !self.store.remove_document(doc_id) {
+            return Ok(false);
+        }
+
+        // the source is kept so that the document is not imported again until it changes
+        for source in self.store.sources.values_mut() {
+            source.documents.retain(|id| id != doc_id);
+        }
+
+        if self.config.spill_evicted {
+            Store::unspill(&self.config.data_path, &HashSet::from([doc_id.to_string()]))?;
+        }
+
+        import::Index::save(self)?;
+        Ok(true)
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        self.store = Store::new();
+        let spilled = Store::spilled_path(&self.config.data_path);
+        if spilled.exists() {
+            std::fs::remove_file(spilled)?;
+        }
+        import::Index::save(self)
+    }
+
+    async fn retrieve(
+        &mut self,
+        query: &str,
+        top_k: usize,
+        filter: Option<&Metadata>,
+    ) -> Result<Vec<(Document, f64)>> {
+        log::debug!("{} (top {} filter {:?})", query, top_k, filter);
+
+        let mut query_vector = self.embedder.embed(query).await?;
+        if self.config.normalize {
+            metrics::normalize(&mut query_vector);
+        }
+        let mut results = vec![];
+
+        let documents = &self.store.documents;
+        let accepted = |doc_id: &str| {
+            filter.is_none_or(|filter| documents.get(doc_id).is_some_and(|doc| doc.matches(filter)))
+        };
+        let metric = self.config.distance;
+        let normalized = self.config.normalize;
+
+        // evicted embeddings compete with the ones in memory, they are scored while reading
+        // the spill file and only the best top_k candidates are kept around
+        let mut spilled: Vec<(String, Embeddings, f64)> = vec![];
+        if self.config.spill_evicted {
+            let keep = top_k.max(1);
+            Store::scan_spilled(&self.config.data_path, |doc_id, embeddings| {
+                if documents.contains_key(&doc_id)
+                    && !self.store.embeddings.contains_key(&doc_id)
+                    && accepted(&doc_id)
+                {
+                    let score = score(metric, normalized, &query_vector, &embeddings);
+                    spilled.push((doc_id, embeddings, score));
+                    if spilled.len() >= keep * 2 {
+                        spilled.sort_by(|(_, _, a), (_, _, b)| metric.compare(*a, *b));
+                        spilled.truncate(keep);
+                    }
+                }
+                Ok(())
+            })?;
+        }
+
+        let mut distances = rank(
+            metric,
+            normalized,
+            &query_vector,
+            &self.store.embeddings,
+            accepted,
+        );
+        distances.extend(spilled.iter().map(|(doc_id, _, score)| (doc_id, *score)));
+        distances.sort_by(|(_, a), (_, b)| metric.compare(*a, *b));
+
+        for (doc_id, score) in distances {
+            if let Some(document) = self.store.documents.get(doc_id) {
+                results.push((document.clone(), score));
+                if results.len() >= top_k {
+                    break;
+                }
+            }
+        }
+
+        // the spilled embeddings just retrieved are loaded back into memory
+        let mut reloaded = HashSet::new();
+        for (document, _) in &results {
+            let doc_id = document.get_ident();
+            if let Some(index) = spilled.iter().position(|(id, _, _)| id == doc_id) {
+                let (id, embeddings, _) = spilled.swap_remove(index);
+                self.store.embeddings.insert(id.to_string(), embeddings);
+                reloaded.insert(id);
+            }
+            self.store.touch(doc_id);
+        }
+
+        if !reloaded.is_empty() {
+            log::debug!("{} spilled embeddings loaded back", reloaded.len());
+            Store::unspill(&self.config.data_path, &reloaded)?;
+            self.enforce_max_documents()?;
+            import::Index::save(self)?;
+        }
+
+        Ok(results)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::super::import::Index;
+    use super::*;
+
+    // embeds each text as the vector it contains or as its letters histogram
+    #[derive(Default)]
+    pub(crate) struct MockEmbedder {
+        pub batches: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+        pub embeds: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl Embedder for MockEmbedder {
+        async fn embed_batch(&self, texts: &[String]) -> Result<Vec<Embeddings>> {
+            self.batches
+                .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            let mut embeddings = vec![];
+            for text in texts {
+                embeddings.push(self.embed(text).await?);
+            }
+            Ok(embeddings)
+        }
+
+        async fn embed(&self, text: &str) -> Result<Embeddings> {
+            self.embeds
+                .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            if let Ok(vector) = serde_json::from_str(text) {
+                return Ok(vector);
+            }
+
+            let mut histogram = vec![0.0; 26];
+            for c in text
+                .to_lowercase()
+                .chars()
+                .filter(|c| c.is_ascii_lowercase())
+            {
+                histogram[(c as u8 - b'a') as usize] += 1.0;
+            }
+            Ok(histogram)
+        }
+    }
+
+    // fails like a crash would at the given batch
+    struct CrashingEmbedder {
+        inner: MockEmbedder,
+        crash_at: usize,
+    }
+
+    #[async_trait]
+    impl Embedder for CrashingEmbedder {
+        async fn embed_batch(&self, texts: &[String]) -> Result<Vec<Embeddings>> {
+            if self.inner.batches.load(std::sync::atomic::Ordering::SeqCst) + 1 == self.crash_at {
+                panic!("crash");
+            }
+            self.inner.embed_batch(texts).await
+        }
+
+        async fn embed(&self, text: &str) -> Result<Embeddings> {
+            self.inner.embed(text).await
+        }
+    }
+
+    async fn mock_store(distance: DistanceMetric, vectors: &[&str]) -> NaiveVectorStore {
+        mock_store_with(
+            Configuration {
+                data_path: "/nonexistent".to_string(),
+                distance,
+                ..Default::default()
+            },
+            vectors,
+        )
+        .await
+    }
+
+    async fn mock_store_with(config: Configuration, vectors: &[&str]) -> NaiveVectorStore {
+        let mut store = NaiveVectorStore::new(Box::new(MockEmbedder::default()), config).unwrap();
+
+        for vector in vectors {
+            let doc = Document::from_reader(std::path::Path::new("."), vector.as_bytes()).unwrap();
+            assert_eq!(store.add_documents(vec![doc]).await.unwrap(), 1);
+        }
+
+        store
+    }
+
+    async fn retrieve_ranking(store: &mut NaiveVectorStore, query: &str) -> Vec<String> {
+        let mut ranking = vec![];
+        for (doc, _) in store.retrieve(query, 10, None).await.unwrap() {
+            let ident = doc.get_ident();
+            let vector = store
+                .store
+                .embeddings
+                .get(ident)
+                .map(|e| format!("{:?}", e))
+                .unwrap();
+            ranking.push(vector);
+        }
+        ranking
+    }
+
+    #[tokio::test]
+    async fn test_retrieve_most_relevant_first() {
+        let docs = &["[0.0, 1.0]", "[0.9, 0.1]", "[0.5, 0.5]"];
+
+        for distance in [
+            DistanceMetric::Cosine,
+            DistanceMetric::Euclidean,
+            DistanceMetric::Dot,
+        ] {
+            let mut store = mock_store(distance, docs).await;
+            assert_eq!(
+                retrieve_ranking(&mut store, "[1.0, 0.0]").await,
+                vec!["[0.9, 0.1]", "[0.5, 0.5]", "[0.0, 1.0]"],
+                "{:?}",
+                distance
+            );
+        }
+
+        // documents that can't be scored don't break the ranking
+        let mut store = mock_store(DistanceMetric::Cosine, &["[0.0, 0.0]", "[0.9, 0.1]"]).await;
+        assert_eq!(
+            retrieve_ranking(&mut store, "[1.0, 0.0]").await,
+            vec!["[0.9, 0.1]", "[0.0, 0.0]"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_normalized_ranking() {
+        // the large vector points away from the query but dominates the raw dot product
+        let docs = &["[1000.0, 1000.0]", "[0.9, 0.1]", "[0.0, 500.0]"];
+        let ident = |vector: &str| {
+            Document::from_reader(std::path::Path::new("."), vector.as_bytes())
+                .unwrap()
+                .get_ident()
+                .to_string()
+        };
+
+        let mut store = mock_store(DistanceMetric::Dot, docs).await;
+        assert_eq!(
+            retrieve_ranking(&mut store, "[1.0, 0.0]").await,
+            vec!["[1000.0, 1000.0]", "[0.9, 0.1]", "[0.0, 500.0]"]
+        );
+
+        for distance in [DistanceMetric::Dot, DistanceMetric::Cosine] {
+            let mut store = mock_store_with(
+                Configuration {
+                    data_path: "/nonexistent".to_string(),
+                    distance,
+                    normalize: true,
+                    ..Default::default()
+                },
+                docs,
+            )
+            .await;
+
+            // stored as unit vectors
+            for embeddings in store.store.embeddings.values() {
+                let magnitude: f64 = embeddings.iter().map(|a| a * a).sum::<f64>().sqrt();
+                assert!((magnitude - 1.0).abs() < 1e-9);
+            }
+
+            // the query magnitude doesn't matter either
+            for query in ["[1.0, 0.0]", "[5000.0, 0.0]"] {
+                let ranking: Vec<String> = store
+                    .retrieve(query, 10, None)
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .map(|(doc, _)| doc.get_ident().to_string())
+                    .collect();
+                assert_eq!(
+                    ranking,
+                    [docs[1], docs[0], docs[2]].map(ident),
+                    "{:?} {}",
+                    distance,
+                    query
+                );
+            }
+        }
+
+        // same scores as the cosine distance of the raw vectors
+        let mut raw = mock_store(DistanceMetric::Cosine, docs).await;
+        let raw = raw.retrieve("[3.0, 4.0]", 3, None).await.unwrap();
+        let mut normalized = mock_store_with(
+            Configuration {
+                data_path: "/nonexistent".to_string(),
+                normalize: true,
+                ..Default::default()
+            },
+            docs,
+        )
+        .await;
+        let normalized = normalized.retrieve("[3.0, 4.0]", 3, None).await.unwrap();
+        for ((doc_a, a), (doc_b, b)) in raw.iter().zip(normalized.iter()) {
+            assert_eq!(doc_a.get_ident(), doc_b.get_ident());
+            assert!((a - b).abs() < 1e-9);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_remove_document() {
+        let root = std::env::temp_dir().join(format!("nerve-remove-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+
+        let mut store = NaiveVectorStore::new(
+            Box::new(MockEmbedder::default()),
+            Configuration {
+                data_path: root.display().to_string(),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        for data in ["[1.0, 0.0]", "[0.0, 1.0]"] {
+            let doc = Document::from_reader(std::path::Path::new("."), data.as_bytes()).unwrap();
+            assert!(store.add(doc).await.unwrap());
+        }
+
+        let doc_id = sha256::digest("[1.0, 0.0]");
+        assert!(store.remove(&doc_id).unwrap());
+        assert!(!store.remove(&doc_id).unwrap());
+
+        let docs = store.retrieve("[1.0, 0.0]", 10, None).await.unwrap();
+        assert_eq!(docs.len(), 1);
+        assert_ne!(docs[0].0.get_ident(), doc_id);
+
+        // the removal has been persisted
+        let mut store = NaiveVectorStore::new(
+            Box::new(MockEmbedder::default()),
+            Configuration {
+                data_path: root.display().to_string(),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            store.retrieve("[1.0, 0.0]", 10, None).await.unwrap().len(),
+            1
+        );
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_clear() {
+        let root = std::env::temp_dir().join(format!("nerve-clear-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+
+        let mut store = NaiveVectorStore::new(
+            Box::new(MockEmbedder::default()),
+            Configuration {
+                data_path: root.display().to_string(),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        for data in ["[1.0, 0.0]", "[0.0, 1.0]"] {
+            let doc = Document::from_reader(std::path::Path::new("."), data.as_bytes()).unwrap();
+            assert_eq!(store.add_documents(vec![doc]).await.unwrap(), 1);
+        }
+        store.set_source("one.txt", 1, vec![]).unwrap();
+        store.save().unwrap();
+        assert_eq!(
+            store.retrieve("[1.0, 0.0]", 10, None).await.unwrap().len(),
+            2
+        );
+
+        store.clear().unwrap();
+        assert!(store
+            .retrieve("[1.0, 0.0]", 10, None)
+            .await
+            .unwrap()
+            .is_empty());
+        assert_eq!(store.get_source_modified("one.txt").unwrap(), None);
+
+        // the empty index has been persisted
+        let mut store = NaiveVectorStore::new(
+            Box::new(MockEmbedder::default()),
+            Configuration {
+                data_path: root.display().to_string(),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert!(store
+            .retrieve("[1.0, 0.0]", 10, None)
+            .await
+            .unwrap()
+            .is_empty());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_evict_least_recently_used() {
+        let mut store = Store::new();
+        for doc_id in ["a", "b", "c"] {
+            store.embeddings.insert(doc_id.to_string(), vec![1.0]);
+            store.touch(doc_id);
+        }
+
+        // "a" has been retrieved recently, "b" is now the least recently used
+        store.touch("a");
+
+        let evicted = store.evict(2);
+
+        assert_eq!(evicted.len(), 1);
+        assert!(evicted.contains_key("b"));
+        assert!(store.embeddings.contains_key("a"));
+        assert!(store.embeddings.contains_key("c"));
+        assert!(store.evict(2).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_retrieve_evicted() {
+        let root = std::env::temp_dir().join(format!("nerve-evicted-{}", std::process::id()));
+        let source = root.join("docs");
+        let data = root.join("data");
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::create_dir_all(&data).unwrap();
+        std::fs::write(source.join("a.txt"), "[1.0, 0.0]").unwrap();
+        std::fs::write(source.join("b.txt"), "[0.0, 1.0]").unwrap();
+
+        for spill_evicted in [true, false] {
+            let _ = std::fs::remove_file(data.join("rag.bin"));
+            let _ = std::fs::remove_file(data.join("rag.evicted.bin"));
+
+            let config = Configuration {
+                source_path: source.display().to_string(),
+                data_path: data.display().to_string(),
+                max_documents: Some(1),
+                spill_evicted,
+                ..Default::default()
+            };
+            let mut store =
+                NaiveVectorStore::new(Box::new(MockEmbedder::default()), config.clone()).unwrap();
+            store.import_new_documents().await.unwrap();
+            assert_eq!(store.store.embeddings.len(), 1);
+
+            let retrieved = |store: &NaiveVectorStore, results: &[(Document, f64)]| {
+                store
+                    .store
+                    .embeddings
+                    .get(results[0].0.get_ident())
+                    .map(|e| format!("{:?}", e))
+            };
+
+            if spill_evicted {
+                // whichever was evicted, both documents can be retrieved
+                for query in ["[1.0, 0.0]", "[0.0, 1.0]", "[1.0, 0.0]"] {
+                    let results = store.retrieve(query, 1, None).await.unwrap();
+                    assert_eq!(results.len(), 1);
+                    assert_eq!(retrieved(&store, &results), Some(query.to_string()));
+                    assert_eq!(store.store.embeddings.len(), 1);
+                }
+
+                // a spilled embedding reloaded from disk survives a restart
+                let mut store =
+                    NaiveVectorStore::new(Box::new(MockEmbedder::default()), config).unwrap();
+                let results = store.retrieve("[0.0, 1.0]", 1, None).await.unwrap();
+                assert_eq!(
+                    store
+                        .store
+                        .embeddings
+                        .get(results[0].0.get_ident())
+                        .map(|e| format!("{:?}", e)),
+                    Some("[0.0, 1.0]".to_string())
+                );
+            } else {
+                // the evicted document is dropped, only the one in memory can be retrieved
+                assert_eq!(store.store.documents.len(), 1);
+                assert_eq!(store.store.sources.len(), 2);
+                let kept = store.store.embeddings.values().next().unwrap().clone();
+                for query in ["[1.0, 0.0]", "[0.0, 1.0]"] {
+                    let results = store.retrieve(query, 1, None).await.unwrap();
+                    assert_eq!(retrieved(&store, &results), Some(format!("{:?}", kept)));
+                }
+            }
+        }
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_evicted_not_embedded_again() {
+        let root = std::env::temp_dir().join(format!("nerve-reimport-{}", std::process::id()));
+        let source = root.join("docs");
+        let data = root.join("data");
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::create_dir_all(&data).unwrap();
+        for i in 0..3 {
+            std::fs::write(source.join(format!("{i}.txt")), format!("document {i}")).unwrap();
+        }
+
+        for spill_evicted in [true, false] {
+            let _ = std::fs::remove_file(data.join("rag.bin"));
+            let _ = std::fs::remove_file(data.join("rag.evicted.bin"));
+
+            let config = Configuration {
+                source_path: source.display().to_string(),
+                data_path: data.display().to_string(),
+                max_documents: Some(1),
+                spill_evicted,
+                ..Default::default()
+            };
+
+            let embedder = MockEmbedder::default();
+            let embeds = embedder.embeds.clone();
+            let mut store = NaiveVectorStore::new(Box::new(embedder), config.clone()).unwrap();
+            store.import_new_documents().await.unwrap();
+            assert_eq!(embeds.load(std::sync::atomic::Ordering::SeqCst), 3);
+            assert_eq!(store.store.embeddings.len(), 1);
+
+            store.import_new_documents().await.unwrap();
+            assert_eq!(embeds.load(std::sync::atomic::Ordering::SeqCst), 3);
+
+            // same after a restart
+            let embedder = MockEmbedder::default();
+            let embeds = embedder.embeds.clone();
+            let mut store = NaiveVectorStore::new(Box::new(embedder), config).unwrap();
+            store.import_new_documents().await.unwrap();
+            assert_eq!(embeds.load(std::sync::atomic::Ordering::SeqCst), 0);
+        }
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_scan_spilled() {
+        let data = std::env::temp_dir().join(format!("nerve-spilled-{}", std::process::id()));
+        std::fs::create_dir_all(&data).unwrap();
+        let path = data.display().to_string();
+
+        let spill = |doc_id: &str, embeddings: Embeddings| {
+            Store::spill_to_data_path(&path, HashMap::from([(doc_id.to_string(), embeddings)]))
+                .unwrap();
+        };
+        let scan = || {
+            let mut spilled = vec![];
+            Store::scan_spilled(&path, |doc_id, embeddings| {
+                spilled.push((doc_id, embeddings));
+                Ok(())
+            })
+            .unwrap();
+            spilled
+        };
+
+        spill("a", vec![1.0]);
+        spill("b", vec![2.0]);
+        spill("c", vec![3.0]);
+        assert_eq!(scan().len(), 3);
+
+        Store::unspill(&path, &HashSet::from(["b".to_string()])).unwrap();
+        assert_eq!(
+            scan(),
+            vec![("a".to_string(), vec![1.0]), ("c".to_string(), vec![3.0])]
+        );
+
+        // a record cut short by a crash is skipped
+        let mut file = File::options()
+            .append(true)
+            .open(Store::spilled_path(&path))
+            .unwrap();
+        file.write_all(&64u64.to_le_bytes()).unwrap();
+        file.write_all(&[1, 2, 3]).unwrap();
+        assert_eq!(scan().len(), 2);
+
+        std::fs::remove_dir_all(&data).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_import_markdown_and_html() {
+        let root = std::env::temp_dir().join(format!("nerve-rag-{}", std::process::id()));
+        let source = root.join("docs");
+        let data = root.join("data");
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::create_dir_all(&data).unwrap();
+
+        std::fs::write(source.join("rust.md"), "# Rust\n\nborrow checker").unwrap();
+        std::fs::write(
+            source.join("async.html"),
+            "<html><body><p>tokio runtime</p></body></html>",
+        )
+        .unwrap();
+        std::fs::write(source.join("ignored.json"), "[1]").unwrap();
+
+        let mut store = NaiveVectorStore::new(
+            Box::new(MockEmbedder::default()),
+            Configuration {
+                source_path: source.display().to_string(),
+                data_path: data.display().to_string(),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        store.import_new_documents().await.unwrap();
+        assert_eq!(store.store.documents.len(), 2);
+
+        let mut docs = store.retrieve("borrow checker", 1, None).await.unwrap();
+        assert!(docs[0].0.get_path().ends_with("rust.md"));
+        assert!(docs[0].0.get_data().unwrap().contains("borrow checker"));
+
+        // the extracted text is returned, not the markup
+        let mut docs = store.retrieve("tokio runtime", 1, None).await.unwrap();
+        assert!(docs[0].0.get_path().ends_with("async.html"));
+        assert_eq!(docs[0].0.get_data().unwrap().trim(), "tokio runtime");
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reindex_changed_sources() {
+        let root = std::env::temp_dir().join(format!("nerve-reindex-{}", std::process::id()));
+        let source = root.join("docs");
+        let data = root.join("data");
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::create_dir_all(&data).unwrap();
+
+        let path = source.join("notes.txt");
+        std::fs::write(&path, "alpha beta").unwrap();
+
+        let mut store = NaiveVectorStore::new(
+            Box::new(MockEmbedder::default()),
+            Configuration {
+                source_path: source.display().to_string(),
+                data_path: data.display().to_string(),
+                prune_deleted: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        store.import_new_documents().await.unwrap();
+
+        // edit the file and make sure the modification time changes
+        std::fs::write(&path, "gamma delta").unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(std::time::SystemTime::now() + std::time::Duration::from_secs(10))
+            .unwrap();
+        store.import_new_documents().await.unwrap();
+
+        // the old version is gone and the new one is retrieved
+        assert_eq!(store.store.documents.len(), 1);
+        let docs = store.retrieve("gamma delta", 1, None).await.unwrap();
+        assert_eq!(docs[0].0.get_ident(), sha256::digest("gamma delta"));
+
+        // deleted files are pruned
+        std::fs::remove_file(&path).unwrap();
+        store.import_new_documents().await.unwrap();
+        assert!(store.store.documents.is_empty());
+        assert!(store.store.embeddings.is_empty());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_import_in_batches() {
+        let root = std::env::temp_dir().join(format!("nerve-batches-{}", std::process::id()));
+        let source = root.join("docs");
+        let data = root.join("data");
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::create_dir_all(&data).unwrap();
+
+        for i in 0..5 {
+            std::fs::write(source.join(format!("{i}.txt")), format!("document {i}")).unwrap();
+        }
+
+        let embedder = MockEmbedder::default();
+        let batches = embedder.batches.clone();
+        let mut store = NaiveVectorStore::new(
+            Box::new(embedder),
+            Configuration {
+                source_path: source.display().to_string(),
+                data_path: data.display().to_string(),
+                embedding_batch_size: Some(2),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        store.import_new_documents().await.unwrap();
+
+        assert_eq!(store.store.documents.len(), 5);
+        assert_eq!(batches.load(std::sync::atomic::Ordering::SeqCst), 3);
+        // persisted once at the end of the import instead of for every document
+        assert_eq!(store.saves, 1);
+
+        // nothing changed, nothing to embed or save
+        store.import_new_documents().await.unwrap();
+        assert_eq!(batches.load(std::sync::atomic::Ordering::SeqCst), 3);
+        assert_eq!(store.saves, 1);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_resume_interrupted_import() {
+        let root = std::env::temp_dir().join(format!("nerve-resume-{}", std::process::id()));
+        let source = root.join("docs");
+        let data = root.join("data");
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::create_dir_all(&data).unwrap();
+
+        for i in 0..6 {
+            std::fs::write(source.join(format!("{i}.txt")), format!("document {i}")).unwrap();
+        }
+
+        let config = Configuration {
+            source_path: source.display().to_string(),
+            data_path: data.display().to_string(),
+            embedding_batch_size: Some(1),
+            checkpoint_every: Some(2),
+            ..Default::default()
+        };
+
+        // crash while embedding the fifth document, after the second checkpoint
+        let mut store = NaiveVectorStore::new(
+            Box::new(CrashingEmbedder {
+                inner: MockEmbedder::default(),
+                crash_at: 5,
+            }),
+            config.clone(),
+        )
+        .unwrap();
+        let import = tokio::spawn(async move { store.import_new_documents().await });
+        assert!(import.await.is_err());
+
+        // the next run only embeds what was not checkpointed
+        let embedder = MockEmbedder::default();
+        let embeds = embedder.embeds.clone();
+        let mut store = NaiveVectorStore::new(Box::new(embedder), config).unwrap();
+        assert_eq!(store.store.documents.len(), 4);
+        store.import_new_documents().await.unwrap();
+        assert_eq!(embeds.load(std::sync::atomic::Ordering::SeqCst), 2);
+        assert_eq!(store.store.documents.len(), 6);
+        assert_eq!(store.store.sources.len(), 6);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_retrieve_with_filter() {
+        let root = std::env::temp_dir().join(format!("nerve-filter-{}", std::process::id()));
+        let source = root.join("docs");
+        let data = root.join("data");
+        std::fs::create_dir_all(source.join("project")).unwrap();
+        std::fs::create_dir_all(&data).unwrap();
+
+        std::fs::write(
+            source.join("rust.md"),
+            "---\ntags: [rust, notes]\n---\nthe borrow checker",
+        )
+        .unwrap();
+        std::fs::write(
+            source.join("python.md"),
+            "---\ntags: python\n---\nthe borrow checker",
+        )
+        .unwrap();
+        std::fs::write(
+            source.join("project").join("todo.txt"),
+            "the borrow checker",
+        )
+        .unwrap();
+
+        let mut store = NaiveVectorStore::new(
+            Box::new(MockEmbedder::default()),
+            Configuration {
+                source_path: source.display().to_string(),
+                data_path: data.display().to_string(),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        store.import_new_documents().await.unwrap();
+
+        let filter = Metadata::from([("tags".to_string(), "rust".to_string())]);
+        let docs = store.retrieve("borrow", 10, Some(&filter)).await.unwrap();
+        assert_eq!(docs.len(), 1);
+        assert!(docs[0].0.get_path().ends_with("rust.md"));
+        assert!(docs[0]
+            .0
+            .matches(&Metadata::from([("type".to_string(), "md".to_string())])));
+
+        let filter = Metadata::from([("folder".to_string(), "project".to_string())]);
+        let docs = store.retrieve("borrow", 10, Some(&filter)).await.unwrap();
+        assert_eq!(docs.len(), 1);
+        assert!(docs[0].0.get_path().ends_with("todo.txt"));
+
+        let filter = Metadata::from([("tags".to_string(), "go".to_string())]);
+        assert!(store
+            .retrieve("borrow", 10, Some(&filter))
+            .await
+            .unwrap()
+            .is_empty());
+        assert_eq!(store.retrieve("borrow", 10, None).await.unwrap().len(), 3);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
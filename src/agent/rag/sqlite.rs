@@ -0,0 +1,395 @@
+use std::{collections::HashSet, path::PathBuf, time::Instant};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+
+use super::{
+    import, metrics, Configuration, Document, Embedder, Embeddings, Metadata, VectorStore,
+};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS documents (
+    id TEXT PRIMARY KEY,
+    document TEXT NOT NULL,
+    embedding BLOB NOT NULL
+);
+CREATE TABLE IF NOT EXISTS sources (
+    path TEXT PRIMARY KEY,
+    modified INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS source_documents (
+    source TEXT NOT NULL,
+    document TEXT NOT NULL,
+    PRIMARY KEY (source, document)
+);
+";
+
+fn encode_embeddings(embeddings: &Embeddings) -> Vec<u8> {
+    embeddings.iter().flat_map(|v| v.to_le_bytes()).collect()
+}
+
+fn decode_embeddings(raw: &[u8]) -> Embeddings {
+    raw.chunks_exact(8)
+        .map(|b| f64::from_le_bytes(b.try_into().unwrap()))
+        .collect()
+}
+
+// forget a source file and the documents no other source shares, then record its new version
+// if any, returns how many documents have been removed
+fn replace_source(tx: &Transaction, path: &str, new: Option<(u64, &[String])>) -> Result<usize> {
+    let previous: Vec<String> = tx
+        .prepare("SELECT document FROM source_documents WHERE source = ?1")?
+        .query_map([path], |row| row.get(0))?
+        .collect::<rusqlite::Result<_>>()?;
+
+    tx.execute("DELETE FROM source_documents WHERE source = ?1", [path])?;
+    tx.execute("DELETE FROM sources WHERE path = ?1", [path])?;
+
+    let mut keep = HashSet::new();
+    if let Some((modified, documents)) = new {
+        tx.execute(
+            "INSERT INTO sources (path, modified) VALUES (?1, ?2)",
+            params![path, modified as i64],
+        )?;
+        for doc_id in documents {
+            tx.execute(
+                "INSERT OR IGNORE INTO source_documents (source, document) VALUES (?1, ?2)",
+                params![path, doc_id],
+            )?;
+            keep.insert(doc_id.as_str());
+        }
+    }
+
+    let mut removed = 0;
+    for doc_id in &previous {
+        if keep.contains(doc_id.as_str()) {
+            continue;
+        }
+        let shared: bool = tx.query_row(
+            "SELECT EXISTS(SELECT 1 FROM source_documents WHERE document = ?1)",
+            [doc_id],
+            |row| row.get(0),
+        )?;
+        if !shared {
+            removed += tx.execute("DELETE FROM documents WHERE id = ?1", [doc_id])?;
+        }
+    }
+
+    Ok(removed)
+}
+
+// keeps the index in a sqlite database that is updated as documents are indexed, so that
+// large knowledge bases don't need to fit in memory or be rewritten on every change
+pub struct SqliteVectorStore {
+    config: Configuration,
+    embedder: Box<dyn Embedder>,
+    db: Connection,
+}
+
+impl SqliteVectorStore {
+    pub fn new(embedder: Box<dyn Embedder>, config: Configuration) -> Result<Self> {
+        let path = PathBuf::from(&config.data_path).join("rag.db");
+        let db =
+            Connection::open(&path).map_err(|e| anyhow!("can't open {}: {}", path.display(), e))?;
+
+        db.execute_batch(SCHEMA)?;
+
+        if config.max_documents.is_some() {
+            log::warn!("max_documents is ignored by the sqlite store");
+        }
+
+        Ok(Self {
+            config,
+            embedder,
+            db,
+        })
+    }
+
+    fn contains(&self, doc_id: &str) -> Result<bool> {
+        Ok(self.db.query_row(
+            "SELECT EXISTS(SELECT 1 FROM documents WHERE id = ?1)",
+            [doc_id],
+            |row| row.get(0),
+        )?)
+    }
+}
+
+#[async_trait]
+impl import::Index for SqliteVectorStore {
+    fn get_config(&self) -> &Configuration {
+        &self.config
+    }
+
+    fn get_source_modified(&self, path: &str) -> Result<Option<u64>> {
+        Ok(self
+            .db
+            .query_row(
+                "SELECT modified FROM sources WHERE path = ?1",
+                [path],
+                |row| row.get::<_, i64>(0),
+            )
+            .optional()?
+            .map(|modified| modified as u64))
+    }
+
+    fn get_source_paths(&self) -> Result<Vec<String>> {
+        Ok(self
+            .db
+            .prepare("SELECT path FROM sources")?
+            .query_map([], |row| row.get(0))?
+            .collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn set_source(&mut self, path: &str, modified: u64, documents: Vec<String>) -> Result<()> {
+        let tx = self.db.transaction()?;
+        replace_source(&tx, path, Some((modified, &documents)))?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn remove_source(&mut self, path: &str) -> Result<usize> {
+        let tx = self.db.transaction()?;
+        let removed = replace_source(&tx, path, None)?;
+        tx.commit()?;
+        Ok(removed)
+    }
+
+    async fn add_documents(&mut self, documents: Vec<Document>) -> Result<usize> {
+        let mut ids = HashSet::new();
+        let mut new = vec![];
+        for doc in documents {
+            let doc_id = doc.get_ident().to_string();
+            if self.contains(&doc_id)? {
+                log::debug!("document with id '{}' already indexed", doc_id);
+            } else if ids.insert(doc_id) {
+                new.push(doc);
+            }
+        }
+
+        if new.is_empty() {
+            return Ok(0);
+        }
+
+        let texts = import::document_texts(&mut new)?;
+
+        let start = Instant::now();
+        let embeddings =
+            import::embed_documents(self.embedder.as_ref(), &texts, self.config.normalize).await?;
+
+        let tx = self.db.transaction()?;
+        for (document, embeddings) in new.iter().zip(embeddings) {
+            tx.execute(
+                "INSERT OR IGNORE INTO documents (id, document, embedding) VALUES (?1, ?2, ?3)",
+                params![
+                    document.get_ident(),
+                    serde_json::to_string(&document)?,
+                    encode_embeddings(&embeddings)
+                ],
+            )?;
+        }
+        tx.commit()?;
+
+        log::debug!("time={:?} documents={}", start.elapsed(), new.len());
+
+        Ok(new.len())
+    }
+
+    fn save(&mut self) -> Result<()> {
+        // every change is committed as it happens
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl VectorStore for SqliteVectorStore {
+    async fn import_new_documents(&mut self) -> Result<()> {
+        import::import_new_documents(self).await
+    }
+
+    async fn add(&mut self, document: Document) -> Result<bool> {
+        Ok(import::Index::add_documents(self, vec![document]).await? > 0)
+    }
+
+    fn remove(&mut self, doc_id: &str) -> Result<bool> {
+        let tx = self.db.transaction()?;
+        let removed = tx.execute("DELETE FROM documents WHERE id = ?1", [doc_id])? > 0;
+        // the source is kept so that the document is not imported again until it changes
+        tx.execute("DELETE FROM source_documents WHERE document = ?1", [doc_id])?;
+        tx.commit()?;
+        Ok(removed)
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        self.db.execute_batch(
+            "DELETE FROM documents; DELETE FROM sources; DELETE FROM source_documents;",
+        )?;
+        Ok(())
+    }
+
+    async fn retrieve(
+        &mut self,
+        query: &str,
+        top_k: usize,
+        filter: Option<&Metadata>,
+    ) -> Result<Vec<(Document, f64)>> {
+        log::debug!("{} (top {} filter {:?})", query, top_k, filter);
+
+        let mut query_vector = self.embedder.embed(query).await?;
+        if self.config.normalize {
+            metrics::normalize(&mut query_vector);
+        }
+        let metric = self.config.distance;
+        let mut results = vec![];
+
+        let mut stmt = self
+            .db
+            .prepare("SELECT document, embedding FROM documents")?;
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            let document: Document = serde_json::from_str(row.get_ref(0)?.as_str()?)?;
+            // only score the documents accepted by the filter
+            if filter.is_some_and(|filter| !document.matches(filter)) {
+                continue;
+            }
+
+            let embeddings = decode_embeddings(row.get_ref(1)?.as_blob()?);
+            let score = if self.config.normalize {
+                metric.score_normalized(&query_vector, &embeddings)
+            } else {
+                metric.score(&query_vector, &embeddings)
+            };
+            results.push((document, score));
+        }
+
+        results.sort_by(|(_, a), (_, b)| metric.compare(*a, *b));
+        results.truncate(top_k);
+
+        Ok(results)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{naive::tests::MockEmbedder, NaiveVectorStore};
+    use super::*;
+
+    fn document(data: &str, tag: &str) -> Document {
+        let mut doc = Document::from_reader(std::path::Path::new("."), data.as_bytes()).unwrap();
+        doc.set_metadata("tag", tag);
+        doc
+    }
+
+    async fn ranking(
+        store: &mut dyn VectorStore,
+        query: &str,
+        filter: Option<&Metadata>,
+    ) -> Vec<(String, String)> {
+        store
+            .retrieve(query, 3, filter)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|(doc, score)| (doc.get_ident().to_string(), format!("{:.6}", score)))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_parity_with_naive_store() {
+        let root = std::env::temp_dir().join(format!("nerve-sqlite-{}", std::process::id()));
+        let naive_path = root.join("naive");
+        let sqlite_path = root.join("sqlite");
+        std::fs::create_dir_all(&naive_path).unwrap();
+        std::fs::create_dir_all(&sqlite_path).unwrap();
+
+        let config = |path: &std::path::Path| Configuration {
+            data_path: path.display().to_string(),
+            ..Default::default()
+        };
+        let mut naive =
+            NaiveVectorStore::new(Box::new(MockEmbedder::default()), config(&naive_path)).unwrap();
+        let mut sqlite =
+            SqliteVectorStore::new(Box::new(MockEmbedder::default()), config(&sqlite_path))
+                .unwrap();
+
+        let docs = [
+            ("[0.0, 1.0]", "a"),
+            ("[0.9, 0.1]", "b"),
+            ("[0.5, 0.5]", "a"),
+            ("[0.7, 0.2]", "b"),
+        ];
+        for (data, tag) in docs {
+            assert!(naive.add(document(data, tag)).await.unwrap());
+            assert!(sqlite.add(document(data, tag)).await.unwrap());
+        }
+        // already indexed
+        assert!(!sqlite.add(document(docs[0].0, docs[0].1)).await.unwrap());
+
+        let removed = document(docs[1].0, docs[1].1);
+        assert!(naive.remove(removed.get_ident()).unwrap());
+        assert!(sqlite.remove(removed.get_ident()).unwrap());
+        assert!(!sqlite.remove(removed.get_ident()).unwrap());
+
+        let filter = Metadata::from([("tag".to_string(), "a".to_string())]);
+        for filter in [None, Some(&filter)] {
+            let expected = ranking(&mut naive, "[1.0, 0.0]", filter).await;
+            assert_eq!(ranking(&mut sqlite, "[1.0, 0.0]", filter).await, expected);
+        }
+
+        // persisted as documents are added
+        drop(sqlite);
+        let mut sqlite =
+            SqliteVectorStore::new(Box::new(MockEmbedder::default()), config(&sqlite_path))
+                .unwrap();
+        assert_eq!(
+            ranking(&mut sqlite, "[1.0, 0.0]", None).await,
+            ranking(&mut naive, "[1.0, 0.0]", None).await
+        );
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reindex_changed_sources() {
+        let root =
+            std::env::temp_dir().join(format!("nerve-sqlite-reindex-{}", std::process::id()));
+        let source = root.join("docs");
+        let data = root.join("data");
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::create_dir_all(&data).unwrap();
+
+        let path = source.join("notes.txt");
+        std::fs::write(&path, "alpha beta").unwrap();
+
+        let mut store = SqliteVectorStore::new(
+            Box::new(MockEmbedder::default()),
+            Configuration {
+                source_path: source.display().to_string(),
+                data_path: data.display().to_string(),
+                prune_deleted: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        store.import_new_documents().await.unwrap();
+
+        std::fs::write(&path, "gamma delta").unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(std::time::SystemTime::now() + std::time::Duration::from_secs(10))
+            .unwrap();
+        store.import_new_documents().await.unwrap();
+
+        let docs = store.retrieve("gamma delta", 10, None).await.unwrap();
+        assert_eq!(docs.len(), 1);
+        assert_eq!(docs[0].0.get_ident(), sha256::digest("gamma delta"));
+
+        std::fs::remove_file(&path).unwrap();
+        store.import_new_documents().await.unwrap();
+        assert!(store.retrieve("gamma", 10, None).await.unwrap().is_empty());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,2 @@
+pub(crate) mod parsing;
+pub(crate) mod serialize;
@@ -0,0 +1,194 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use itertools::Itertools;
+use serde_json::Value;
+
+use crate::agent::Invocation;
+
+// strings are used as they are, other scalars as they are written and nulls are ignored
+fn attribute_value(value: &Value) -> Option<String> {
+    match value {
+        Value::Null => None,
+        Value::String(s) => Some(s.to_string()),
+        _ => Some(value.to_string()),
+    }
+}
+
+// structured payloads are passed to the action as json
+fn payload_value(value: &Value) -> Option<String> {
+    match value {
+        Value::Null => None,
+        Value::String(s) if s.is_empty() => None,
+        Value::String(s) => Some(s.to_string()),
+        _ => Some(value.to_string()),
+    }
+}
+
+// returns None for objects that are not meant to be invocations
+fn to_invocation(value: &Value) -> Result<Option<Invocation>> {
+    let object = if let Some(object) = value.as_object() {
+        object
+    } else {
+        return Ok(None);
+    };
+
+    let action = match object.get("action") {
+        None => return Ok(None),
+        Some(Value::String(action)) if !action.trim().is_empty() => action.trim().to_string(),
+        Some(_) => return Err(anyhow!("'action' must be a non empty string")),
+    };
+
+    let attributes = match object.get("attributes") {
+        None | Some(Value::Null) => None,
+        Some(Value::Object(map)) => {
+            let attributes: HashMap<String, String> = map
+                .iter()
+                .filter_map(|(key, value)| attribute_value(value).map(|v| (key.to_string(), v)))
+                .collect();
+            if attributes.is_empty() {
+                None
+            } else {
+                Some(attributes)
+            }
+        }
+        Some(_) => return Err(anyhow!("'attributes' of '{}' must be an object", action)),
+    };
+
+    let payload = object.get("payload").and_then(payload_value);
+
+    Ok(Some(Invocation {
+        action,
+        attributes,
+        payload,
+    }))
+}
+
+// an object with a string key or a list of objects, anything else starting with a brace is
+// just text
+fn is_json_like(ptr: &str) -> bool {
+    let mut chars = ptr.chars().filter(|c| !c.is_whitespace());
+    matches!(
+        (chars.next(), chars.next()),
+        (Some('{'), Some('"')) | (Some('['), Some('{'))
+    )
+}
+
+// in strict mode any json-like content that can't be parsed into an invocation is an error
+pub(crate) fn try_parse_with(raw: &str, strict: bool) -> Result<Vec<Invocation>> {
+    let mut ptr = raw;
+    let mut parsed = vec![];
+
+    while let Some(start) = ptr.find(['{', '[']) {
+        ptr = &ptr[start..];
+        let snippet = || -> String { ptr.lines().next().unwrap_or("").chars().take(64).collect() };
+
+        let mut stream = serde_json::Deserializer::from_str(ptr).into_iter::<Value>();
+        match stream.next() {
+            Some(Ok(value)) => {
+                // multiple actions can be specified as a list
+                let values = if let Value::Array(list) = value {
+                    list
+                } else {
+                    vec![value]
+                };
+
+                for value in &values {
+                    match to_invocation(value) {
+                        Ok(Some(invocation)) => parsed.push(invocation),
+                        Ok(None) if strict && value.is_object() => {
+                            return Err(anyhow!(
+                                "can't parse '{}': no valid action found",
+                                snippet()
+                            ))
+                        }
+                        Err(error) if strict => {
+                            return Err(anyhow!("can't parse '{}': {}", snippet(), error))
+                        }
+                        _ => {}
+                    }
+                }
+
+                ptr = &ptr[stream.byte_offset()..];
+            }
+            Some(Err(error)) if strict && is_json_like(ptr) => {
+                return Err(anyhow!("can't parse '{}': {}", snippet(), error));
+            }
+            _ => {
+                ptr = &ptr[1..];
+            }
+        }
+    }
+
+    // avoid running the same command twice in a row
+    Ok(parsed.into_iter().unique().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::serialization::xml;
+
+    fn try_parse(raw: &str) -> Result<Vec<Invocation>> {
+        try_parse_with(raw, false)
+    }
+
+    #[test]
+    fn test_parse_same_as_xml() {
+        let cases = [
+            (r#"{"action": "clear-plan"}"#, "<clear-plan></clear-plan>"),
+            (
+                r#"{"action": "add-plan-step", "payload": "do this!"}"#,
+                "<add-plan-step>do this!</add-plan-step>",
+            ),
+            (
+                r#"{"action": "save-memory", "attributes": {"key": "test"}, "payload": "hello"}"#,
+                r#"<save-memory key="test">hello</save-memory>"#,
+            ),
+        ];
+
+        for (json, xml) in cases {
+            assert_eq!(
+                try_parse(json).unwrap(),
+                xml::parsing::try_parse_with(xml, Default::default(), false).unwrap(),
+                "{json}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_mixed_stuff() {
+        let raw = "I'll save it, then {think} about it:\n```json\n{\"action\": \"save-memory\", \"attributes\": {\"key\": \"n\", \"count\": 2}, \"payload\": \"hi\"}\n```\n[{\"action\": \"think\", \"payload\": {\"a\": 1}}, {\"action\": \"think\", \"payload\": {\"a\": 1}}]";
+        let parsed = try_parse(raw).unwrap();
+
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[0].action, "save-memory");
+        let attrs = parsed[0].attributes.as_ref().unwrap();
+        assert_eq!(attrs.get("key").unwrap(), "n");
+        assert_eq!(attrs.get("count").unwrap(), "2");
+        assert_eq!(parsed[0].payload, Some("hi".to_string()));
+        // structured payloads are passed as json, duplicates are removed
+        assert_eq!(parsed[1].action, "think");
+        assert_eq!(parsed[1].payload, Some("{\"a\":1}".to_string()));
+    }
+
+    #[test]
+    fn test_parse_strict() {
+        assert!(try_parse_with("use {braces} freely", true)
+            .unwrap()
+            .is_empty());
+        assert!(try_parse_with(r#"{"action": "think", "payload": "#, true).is_err());
+        assert!(try_parse_with(r#"{"name": "think"}"#, true).is_err());
+        assert!(try_parse_with(r#"{"action": 1}"#, true).is_err());
+        assert!(try_parse_with(r#"{"action": "x", "attributes": []}"#, true).is_err());
+
+        // not strict, the broken ones are ignored
+        let parsed = try_parse_with(
+            r#"{"action": 1} {"action": "think", "payload": "ok"}"#,
+            false,
+        )
+        .unwrap();
+        assert_eq!(parsed.len(), 1);
+        assert_eq!(parsed[0].action, "think");
+    }
+}
@@ -0,0 +1,70 @@
+use itertools::Itertools;
+use serde_json::Value;
+
+use crate::agent::{namespaces::Action, Invocation};
+
+fn quote(data: &str) -> String {
+    Value::String(data.to_string()).to_string()
+}
+
+// keys in a fixed order so that the output is stable and reads like the examples
+fn object(action: &str, attributes: &[(&str, &str)], payload: Option<&str>) -> String {
+    let mut json = format!("{{\"action\": {}", quote(action));
+
+    if !attributes.is_empty() {
+        json += &format!(
+            ", \"attributes\": {{{}}}",
+            attributes
+                .iter()
+                .map(|(name, value)| format!("{}: {}", quote(name), quote(value)))
+                .join(", ")
+        );
+    }
+
+    if let Some(payload) = payload {
+        json += &format!(", \"payload\": {}", quote(payload));
+    }
+
+    json + "}"
+}
+
+pub(crate) fn invocation(inv: &Invocation) -> String {
+    let attributes: Vec<(&str, &str)> = inv
+        .attributes
+        .iter()
+        .flatten()
+        .sorted()
+        .map(|(name, value)| (name.as_str(), value.as_str()))
+        .collect();
+
+    object(&inv.action, &attributes, inv.payload.as_deref())
+}
+
+#[allow(clippy::borrowed_box)]
+pub(crate) fn action(action: &Box<dyn Action>) -> String {
+    let attributes = action.attributes().unwrap_or_default();
+    let attributes: Vec<(&str, &str)> = attributes
+        .iter()
+        .sorted()
+        .map(|(name, value)| (name.as_str(), value.as_str()))
+        .collect();
+
+    object(action.name(), &attributes, action.example_payload())
+}
+
+// same as action but without example values
+#[allow(clippy::borrowed_box)]
+pub(crate) fn action_signature(action: &Box<dyn Action>) -> String {
+    let attributes = action.attributes().unwrap_or_default();
+    let attributes: Vec<(&str, &str)> = attributes
+        .keys()
+        .sorted()
+        .map(|name| (name.as_str(), "..."))
+        .collect();
+
+    object(
+        action.name(),
+        &attributes,
+        action.example_payload().map(|_| "..."),
+    )
+}
@@ -1,9 +1,132 @@
+use std::{collections::HashMap, path::Path};
+
 use anyhow::Result;
+use itertools::Itertools;
+use lazy_static::lazy_static;
+use regex::Regex;
 
-use super::{namespaces::NAMESPACES, state::State};
+use super::{
+    namespaces::{guidance, Action, NAMESPACES},
+    state::State,
+    task::merge_guidance,
+    Invocation,
+};
 
+pub(crate) mod json;
 pub(crate) mod xml;
 
+const DEFAULT_SYSTEM_PROMPT_TEMPLATE: &str = include_str!("system.prompt");
+
+// placeholders every system prompt template must contain
+const SYSTEM_PROMPT_PLACEHOLDERS: &[&str] = &[
+    "iterations",
+    "system_prompt",
+    "storages",
+    "available_actions",
+    "guidance",
+];
+
+lazy_static! {
+    static ref PLACEHOLDER_PARSER: Regex = Regex::new(r"\{([a-z_]+)\}").unwrap();
+}
+
+// read a custom system prompt template, making sure all the placeholders are there
+pub(crate) fn load_system_prompt_template(path: &Path) -> Result<String> {
+    let template = std::fs::read_to_string(path).map_err(|e| {
+        anyhow!(
+            "can't read system prompt template {}: {}",
+            path.display(),
+            e
+        )
+    })?;
+
+    let missing: Vec<String> = SYSTEM_PROMPT_PLACEHOLDERS
+        .iter()
+        .filter(|name| !template.contains(&format!("{{{}}}", name)))
+        .map(|name| format!("{{{}}}", name))
+        .collect();
+    if !missing.is_empty() {
+        return Err(anyhow!(
+            "system prompt template {} is missing the {} placeholder(s)",
+            path.display(),
+            missing.join(", ")
+        ));
+    }
+
+    Ok(template)
+}
+
+// replace the known placeholders in a single pass so that values are never expanded and any
+// other braces are left as they are
+fn render_template(template: &str, values: &HashMap<&str, String>) -> String {
+    PLACEHOLDER_PARSER
+        .replace_all(template, |caps: &regex::Captures| {
+            values
+                .get(&caps[1])
+                .cloned()
+                .unwrap_or_else(|| caps[0].to_string())
+        })
+        .to_string()
+}
+
+// how actions are described to the model and how its responses are parsed
+#[derive(Debug, Default, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum InvocationFormat {
+    // <action attribute="value">payload</action>
+    #[default]
+    Xml,
+    // {"action": "action", "attributes": {"attribute": "value"}, "payload": "payload"}
+    Json,
+}
+
+// how much detail about the available actions goes into the system prompt
+#[derive(Debug, Default, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum ActionsStyle {
+    // namespace descriptions, full action descriptions and examples
+    #[default]
+    Verbose,
+    // one line per action with its signature and the first line of its description
+    Compact,
+}
+
+impl InvocationFormat {
+    pub(crate) fn invocation(&self, inv: &Invocation) -> String {
+        match self {
+            Self::Xml => xml::serialize::invocation(inv),
+            Self::Json => json::serialize::invocation(inv),
+        }
+    }
+
+    #[allow(clippy::borrowed_box)]
+    pub(crate) fn action(&self, action: &Box<dyn Action>) -> String {
+        match self {
+            Self::Xml => xml::serialize::action(action),
+            Self::Json => json::serialize::action(action),
+        }
+    }
+
+    #[allow(clippy::borrowed_box)]
+    pub(crate) fn action_signature(&self, action: &Box<dyn Action>) -> String {
+        match self {
+            Self::Xml => xml::serialize::action_signature(action),
+            Self::Json => json::serialize::action_signature(action),
+        }
+    }
+
+    // duplicated attributes are only detected in xml, json keeps the last value
+    pub(crate) fn try_parse(
+        &self,
+        raw: &str,
+        duplicates: xml::parsing::DuplicateAttributes,
+        strict: bool,
+    ) -> Result<Vec<Invocation>> {
+        match self {
+            Self::Xml => xml::parsing::try_parse_with(raw, duplicates, strict),
+            Self::Json => json::parsing::try_parse_with(raw, strict),
+        }
+    }
+}
+
 pub(crate) fn available_actions() -> String {
     let mut md = "".to_string();
 
@@ -25,7 +148,63 @@ pub(crate) fn available_actions() -> String {
     md.trim().to_string()
 }
 
+fn cost_suffix(action: &dyn Action) -> String {
+    if action.cost() > 1 {
+        format!(" (cost: {})", action.cost())
+    } else {
+        "".to_string()
+    }
+}
+
+fn state_compact_actions(state: &State) -> String {
+    let format = state.get_invocation_format();
+    let mut md = "".to_string();
+
+    for group in state.get_namespaces() {
+        md += &format!(
+            "## {}
+
+",
+            group.name
+        );
+        for action in &group.actions {
+            let summary = action
+                .description()
+                .lines()
+                .next()
+                .unwrap_or("")
+                .trim()
+                .trim_end_matches(':');
+            let optional = action
+                .optional_attributes()
+                .map(|attrs| attrs.into_keys().sorted().join(", "))
+                .filter(|names| !names.is_empty())
+                .map(|names| format!(" (optional: {})", names))
+                .unwrap_or_default();
+
+            md += &format!(
+                "- {} {}{}{}
+",
+                format.action_signature(action),
+                summary,
+                optional,
+                cost_suffix(action.as_ref())
+            );
+        }
+        md += "
+";
+    }
+
+    md
+}
+
 fn state_available_actions(state: &State) -> Result<String> {
+    if state.get_actions_style() == ActionsStyle::Compact {
+        return Ok(state_compact_actions(state));
+    }
+
+    let task = state.get_task();
+    let format = state.get_invocation_format();
     let mut md = "".to_string();
 
     for group in state.get_namespaces() {
@@ -35,9 +214,14 @@ fn state_available_actions(state: &State) -> Result<String> {
         }
         for action in &group.actions {
             md += &format!(
-                "{} {}\n\n",
+                "{} {}{}\n\n",
                 action.description(),
-                self::xml::serialize::action(action)
+                if task.show_action_example(action.name()) {
+                    format.action(action)
+                } else {
+                    format.action_signature(action)
+                },
+                cost_suffix(action.as_ref())
             );
         }
     }
@@ -45,43 +229,97 @@ fn state_available_actions(state: &State) -> Result<String> {
     Ok(md)
 }
 
+// a stable hash of the system prompt, useful to detect when a cacheable prefix changed
+pub(crate) fn system_prompt_fingerprint(system_prompt: &str) -> String {
+    sha256::digest(system_prompt)
+}
+
 pub(crate) fn state_to_system_prompt(state: &State) -> Result<String> {
     let task = state.get_task();
     let system_prompt = task.to_system_prompt()?;
 
     let mut storages = vec![];
     let mut sorted = state.get_storages();
-    sorted.sort_by_key(|x| x.get_type().as_u8());
+    // sort by type and then by name so that the prompt is stable across runs
+    sorted.sort_by_key(|x| (x.get_type().as_u8(), x.get_name().to_string()));
 
+    let mut dynamic_guidance = vec![];
     for storage in sorted {
-        storages.push(self::xml::serialize::storage(storage));
+        // rules added at runtime are rendered as part of the guidance
+        if storage.get_name() == guidance::STORAGE_NAME {
+            dynamic_guidance = storage.entries().map(|(_, e)| e.data.to_string()).collect();
+        } else {
+            storages.push(self::xml::serialize::storage(storage));
+        }
     }
 
     let storages = storages.join("\n\n");
-    let guidance = task
-        .guidance()?
-        .into_iter()
-        .map(|s| format!("- {}", s))
-        .collect::<Vec<String>>()
-        .join("\n");
+    // only nudge the model when there's a choice between cheap and expensive actions
+    let cost_guidance = if state
+        .get_namespaces()
+        .iter()
+        .any(|ns| ns.actions.iter().any(|a| a.cost() > 1))
+    {
+        vec!["Prefer cheaper actions when possible, the cost of expensive actions is shown next to them.".to_string()]
+    } else {
+        vec![]
+    };
+
+    // only the names of the secrets are ever shown to the model
+    let secrets = state.get_secrets();
+    let secrets_guidance = if secrets.is_empty() {
+        vec![]
+    } else {
+        vec![format!(
+            "You can reference the following secrets in action attributes and payloads, they will be replaced with their values when the action is executed: {}.",
+            secrets
+                .names()
+                .iter()
+                .map(|name| format!("${}", name))
+                .collect::<Vec<String>>()
+                .join(", ")
+        )]
+    };
+
+    let format_guidance = if state.get_invocation_format() == InvocationFormat::Json {
+        vec!["Write each action as a JSON object in the format shown for it, use a JSON list of objects to take more than one action.".to_string()]
+    } else {
+        vec![]
+    };
+
+    let guidance = merge_guidance(vec![
+        task.guidance()?,
+        format_guidance,
+        cost_guidance,
+        secrets_guidance,
+        dynamic_guidance,
+    ])
+    .into_iter()
+    .map(|s| format!("- {}", s))
+    .collect::<Vec<String>>()
+    .join("\n");
     let available_actions = state_available_actions(state)?;
 
     let iterations = if state.metrics.max_steps > 0 {
         format!(
             "You are currently at step {} of a maximum of {}.",
-            state.metrics.current_step + 1,
+            state.metrics.counted_steps() + 1,
             state.metrics.max_steps
         )
     } else {
         "".to_string()
     };
 
-    Ok(format!(
-        include_str!("system.prompt"),
-        iterations = iterations,
-        system_prompt = system_prompt,
-        storages = storages,
-        available_actions = available_actions,
-        guidance = guidance,
+    Ok(render_template(
+        state
+            .get_system_prompt_template()
+            .unwrap_or(DEFAULT_SYSTEM_PROMPT_TEMPLATE),
+        &HashMap::from([
+            ("iterations", iterations),
+            ("system_prompt", system_prompt),
+            ("storages", storages),
+            ("available_actions", available_actions),
+            ("guidance", guidance),
+        ]),
     ))
 }
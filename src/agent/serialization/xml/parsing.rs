@@ -2,54 +2,146 @@ use std::collections::HashMap;
 
 use anyhow::Result;
 use itertools::Itertools;
-use xml::{reader::XmlEvent, EventReader};
+use lazy_static::lazy_static;
+use quick_xml::{
+    events::{BytesStart, Event},
+    Reader,
+};
+use regex::Regex;
 
 use crate::agent::Invocation;
 
+lazy_static! {
+    static ref ATTRIBUTE_PARSER: Regex =
+        Regex::new(r#"([^\s=<>/"']+)\s*=\s*("[^"]*"|'[^']*'|[^\s"'<>=`]+)"#).unwrap();
+    // markdown code fences, with an optional language, on their own lines around content
+    // starting and ending with a tag
+    static ref CODE_FENCE_PARSER: Regex =
+        Regex::new(r"(?m)^[ \t]*```[\w+.\-]*[ \t]*\r?\n([ \t]*<[\s\S]*?>)[ \t]*\r?\n[ \t]*```[ \t]*$").unwrap();
+}
+
+// what to do when the same attribute is specified more than once
+#[derive(Debug, Default, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum DuplicateAttributes {
+    // reject the invocation
+    #[default]
+    Error,
+    // keep the first value
+    First,
+    // keep the last value
+    Last,
+}
+
+// what to do when the response contains tags that can't be parsed into invocations
+#[derive(Debug, Default, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum OnParseError {
+    // skip them and use what could be parsed
+    #[default]
+    Ignore,
+    // discard the response and report the error to the model
+    Report,
+    // abort the run with an error
+    Abort,
+}
+
 #[derive(Default, Debug)]
 pub struct Parsed {
     pub processed: usize,
     pub invocations: Vec<Invocation>,
+    pub errors: Vec<String>,
+}
+
+// opening tag of an invocation
+#[derive(Debug)]
+struct Element {
+    name: String,
+    attributes: Option<HashMap<String, String>>,
+}
+
+impl Element {
+    fn from_start(start: &BytesStart) -> Result<Self> {
+        let name = String::from_utf8_lossy(start.name().as_ref()).to_string();
+        let mut attributes = HashMap::new();
+        // double quoted, single quoted and unquoted values are accepted, duplicated
+        // attributes are reported as errors
+        for attr in start.html_attributes() {
+            let attr = attr?;
+            // keep the raw value if it contains invalid entities
+            let value = attr
+                .unescape_value()
+                .map(|v| v.to_string())
+                .unwrap_or_else(|_| String::from_utf8_lossy(&attr.value).to_string());
+            attributes.insert(
+                String::from_utf8_lossy(attr.key.as_ref()).to_string(),
+                value,
+            );
+        }
+
+        Ok(Self {
+            name,
+            attributes: if attributes.is_empty() {
+                None
+            } else {
+                Some(attributes)
+            },
+        })
+    }
 }
 
 fn build_invocation(
     closing_name: String,
-    element: &XmlEvent,
+    element: Option<&Element>,
     payload: &Option<String>,
 ) -> Result<Invocation> {
-    let (name, attrs) = match element {
-        xml::reader::XmlEvent::StartElement {
-            name,
-            attributes,
-            namespace: _,
-        } => (name.to_string(), attributes),
-        _ => {
-            return Err(anyhow!("unexpected element {:?}", element));
-        }
-    };
-
-    if name != closing_name {
+    let element = element.ok_or_else(|| anyhow!("unexpected closing {}", closing_name))?;
+    if element.name != closing_name {
         return Err(anyhow!(
             "unexpected closing {} while parsing {}",
             closing_name,
-            name
+            element.name
         ));
     }
 
-    let action = name.to_string();
-    let attributes = if !attrs.is_empty() {
-        let mut map = HashMap::new();
-        for attr in attrs {
-            map.insert(attr.name.to_string(), attr.value.to_string());
+    Ok(Invocation::new(
+        element.name.to_string(),
+        element.attributes.clone(),
+        payload.to_owned(),
+    ))
+}
+
+// index of the closing tag matching an element opened before the given offset, taking into
+// account nested elements with the same name
+fn closing_tag_idx(ptr: &str, name: &str, from: usize) -> Option<usize> {
+    let opening = format!("<{}", name);
+    let closing = format!("</{}>", name);
+    let mut depth = 0;
+    let mut offset = from;
+
+    loop {
+        let close_idx = offset + ptr[offset..].find(&closing)?;
+
+        // count the nested openings before this closing tag, short <tag/> excluded
+        let mut search = offset;
+        while let Some(idx) = ptr[search..close_idx].find(&opening) {
+            let idx = search + idx;
+            let rest = &ptr[idx + opening.len()..close_idx];
+            if rest.starts_with(|c: char| c.is_whitespace() || c == '>') {
+                if let Some(gt) = rest.find('>') {
+                    if !rest[..gt].ends_with('/') {
+                        depth += 1;
+                    }
+                }
+            }
+            search = idx + opening.len();
         }
 
-        Some(map)
-    } else {
-        None
-    };
-    let payload = payload.as_ref().map(|data| data.to_owned());
+        if depth == 0 {
+            return Some(close_idx);
+        }
 
-    Ok(Invocation::new(action, attributes, payload))
+        depth -= 1;
+        offset = close_idx + closing.len();
+    }
 }
 
 fn preprocess_block(ptr: &str) -> String {
@@ -58,23 +150,24 @@ fn preprocess_block(ptr: &str) -> String {
         // not a closing tag
         if ptr.as_bytes()[1] != b'/' {
             // determine tag name
-            let tag_name = &ptr[1..ptr.find(|c| c == ' ' || c == '>').unwrap()];
-            let payload_start_idx = ptr.find('>').unwrap();
-            // if not a short <tag/>
-            if !tag_name.ends_with('/') {
-                // estimate tag closing index and get payload
-                let tag_closing = format!("</{}>", &tag_name);
-                if let Some(tag_closing_idx) = ptr.find(&tag_closing) {
+            if let (Some(name_end), Some(payload_start_idx)) = (
+                ptr.find(|c: char| c.is_whitespace() || c == '>'),
+                ptr.find('>'),
+            ) {
+                let tag_name = &ptr[1..name_end];
+                // if not a short <tag/>
+                if !tag_name.ends_with('/') && !ptr[..payload_start_idx].ends_with('/') {
+                    // find the matching closing tag and get the payload, which is kept verbatim
+                    // even if it contains nested elements
                     let from = payload_start_idx + 1;
-                    let to = tag_closing_idx;
-                    // valid xml?
-                    if to > from {
-                        let payload = &ptr[payload_start_idx + 1..tag_closing_idx];
-                        if !payload.is_empty() {
+                    if let Some(to) = closing_tag_idx(ptr, tag_name, from) {
+                        // valid xml?
+                        if to > from {
+                            let payload = &ptr[from..to];
                             // if escaped payload is different, replace it
-                            let escaped = xml::escape::escape_str_pcdata(payload);
+                            let escaped = quick_xml::escape::partial_escape(payload);
                             if escaped != payload {
-                                return ptr.replace(payload, &escaped);
+                                return format!("{}{}{}", &ptr[..from], escaped, &ptr[to..]);
                             }
                         }
                     }
@@ -86,6 +179,56 @@ fn preprocess_block(ptr: &str) -> String {
     ptr.to_string()
 }
 
+// remove duplicated attributes from the opening tag according to the policy, returns the new
+// block and the number of bytes removed or an error naming the duplicated keys
+fn dedup_attributes(ptr: &str, policy: DuplicateAttributes) -> Result<(String, usize)> {
+    let tag_end = if let Some(idx) = ptr.find('>') {
+        idx
+    } else {
+        return Ok((ptr.to_string(), 0));
+    };
+    let tag = &ptr[..tag_end];
+
+    let attrs: Vec<(&str, regex::Match)> = ATTRIBUTE_PARSER
+        .captures_iter(tag)
+        .map(|caps| (caps.get(1).unwrap().as_str(), caps.get(0).unwrap()))
+        .collect();
+    if attrs.iter().map(|(key, _)| key).all_unique() {
+        return Ok((ptr.to_string(), 0));
+    }
+
+    log::warn!("duplicated attributes in {}> ({:?})", tag, policy);
+
+    let kept: Vec<&str> = match policy {
+        DuplicateAttributes::Error => {
+            let duplicated = attrs.iter().map(|(key, _)| *key).duplicates().join(", ");
+            return Err(anyhow!("duplicated attributes {} in {}>", duplicated, tag));
+        }
+        DuplicateAttributes::First => attrs
+            .iter()
+            .unique_by(|(key, _)| *key)
+            .map(|(_, m)| m.as_str())
+            .collect(),
+        DuplicateAttributes::Last => {
+            let mut kept: Vec<&str> = attrs
+                .iter()
+                .rev()
+                .unique_by(|(key, _)| *key)
+                .map(|(_, m)| m.as_str())
+                .collect();
+            kept.reverse();
+            kept
+        }
+    };
+
+    let head = &tag[..attrs.first().unwrap().1.start()];
+    let tail = &ptr[attrs.last().unwrap().1.end()..];
+    let deduped = format!("{}{}{}", head, kept.join(" "), tail);
+    let removed = ptr.len() - deduped.len();
+
+    Ok((deduped, removed))
+}
+
 fn try_parse_block(ptr: &str) -> Parsed {
     // we need some preprocessing to handle unquoted characters
     let prev = ptr.len();
@@ -97,68 +240,97 @@ fn try_parse_block(ptr: &str) -> Parsed {
         0
     };
 
-    let mut parser = EventReader::from_str(&ptr);
+    let mut reader = Reader::from_str(&ptr);
     let mut parsed = Parsed::default();
-    let src_size = parser.source().len();
 
     let mut curr_element = None;
     let mut curr_payload = None;
 
     loop {
-        let event = parser.next();
-        if let Ok(event) = event {
-            log::debug!("{:?}", &event);
-            match event {
-                xml::reader::XmlEvent::StartDocument {
-                    version: _,
-                    encoding: _,
-                    standalone: _,
-                } => {}
-                xml::reader::XmlEvent::EndDocument {} => {
-                    break;
-                }
-                xml::reader::XmlEvent::StartElement {
-                    name: _,
-                    attributes: _,
-                    namespace: _,
-                } => {
-                    curr_element = Some(event);
+        let event = reader.read_event();
+        log::debug!("{:?}", &event);
+        match event {
+            Ok(Event::Eof) => break,
+            Ok(Event::Start(start)) => match Element::from_start(&start) {
+                Ok(element) => {
+                    // the innermost element is the invocation
+                    curr_element = Some(element);
+                    curr_payload = None;
                 }
-                xml::reader::XmlEvent::Characters(data) => {
-                    curr_payload = Some(data);
+                Err(err) => {
+                    parsed.errors.push(err.to_string());
+                    break;
                 }
-                xml::reader::XmlEvent::EndElement { name } => {
-                    let ret = build_invocation(
-                        name.to_string(),
-                        curr_element.as_ref().unwrap(),
-                        &curr_payload,
-                    );
-                    if let Ok(inv) = ret {
-                        parsed.invocations.push(inv);
-                    } else {
-                        log::error!("{:?}", ret.err().unwrap());
+            },
+            Ok(Event::Empty(start)) => {
+                match Element::from_start(&start) {
+                    Ok(element) => {
+                        parsed.invocations.push(Invocation::new(
+                            element.name,
+                            element.attributes,
+                            None,
+                        ));
                     }
+                    Err(err) => parsed.errors.push(err.to_string()),
+                }
+                break;
+            }
+            Ok(Event::Text(text)) => match text.unescape() {
+                // ignore the indentation of nested elements
+                Ok(data) if !data.trim().is_empty() => curr_payload = Some(data.to_string()),
+                Ok(_) => {}
+                Err(err) => {
+                    parsed.errors.push(err.to_string());
                     break;
                 }
-                _ => {
-                    log::error!("unexpected xml element: {:?}", event);
+            },
+            Ok(Event::CData(data)) => {
+                curr_payload = Some(String::from_utf8_lossy(&data).to_string());
+            }
+            Ok(Event::End(end)) => {
+                let name = String::from_utf8_lossy(end.name().as_ref()).to_string();
+                match build_invocation(name, curr_element.as_ref(), &curr_payload) {
+                    Ok(inv) => parsed.invocations.push(inv),
+                    Err(err) => {
+                        log::error!("{:?}", err);
+                        parsed.errors.push(err.to_string());
+                    }
                 }
+                break;
+            }
+            Ok(event) => {
+                log::error!("unexpected xml element: {:?}", event);
+                parsed
+                    .errors
+                    .push(format!("unexpected xml element: {:?}", event));
+            }
+            Err(err) => {
+                parsed.errors.push(err.to_string());
+                break;
             }
-        } else {
-            break;
         }
     }
 
-    let src_size_now = parser.source().len();
-
     // amount of successfully processed bytes
-    parsed.processed = src_size - src_size_now - delta;
+    parsed.processed = (reader.buffer_position() as usize).saturating_sub(delta);
 
     parsed
 }
 
-pub(crate) fn try_parse(raw: &str) -> Result<Vec<Invocation>> {
-    let mut ptr = raw;
+// remove the markdown code fences models often wrap actions in, backticks inside of a payload
+// or fences around anything that doesn't start with a tag are left untouched
+fn strip_code_fences(raw: &str) -> String {
+    CODE_FENCE_PARSER.replace_all(raw, "$1").to_string()
+}
+
+// in strict mode any tag-like content that can't be parsed into an invocation is an error
+pub(crate) fn try_parse_with(
+    raw: &str,
+    duplicates: DuplicateAttributes,
+    strict: bool,
+) -> Result<Vec<Invocation>> {
+    let raw = strip_code_fences(raw);
+    let mut ptr = raw.as_str();
     let mut parsed = vec![];
 
     loop {
@@ -172,14 +344,43 @@ pub(crate) fn try_parse(raw: &str) -> Result<Vec<Invocation>> {
         let xml_start = open_idx.unwrap();
         ptr = &ptr[xml_start..];
 
-        let parsed_block = try_parse_block(ptr);
+        // a '<' followed by a name or a closing slash is meant to be a tag, skip anything else
+        let tag_like = ptr[1..]
+            .chars()
+            .next()
+            .is_some_and(|c| c.is_alphabetic() || c == '/');
+        if !tag_like {
+            ptr = &ptr[1..];
+            continue;
+        }
+
+        let (block, removed) = dedup_attributes(ptr, duplicates)?;
+        let parsed_block = try_parse_block(&block);
+
+        if strict
+            && (parsed_block.processed == 0
+                || parsed_block.invocations.is_empty()
+                || !parsed_block.errors.is_empty())
+        {
+            let snippet: String = ptr.lines().next().unwrap_or("").chars().take(64).collect();
+            return Err(anyhow!(
+                "can't parse '{}': {}",
+                snippet,
+                if parsed_block.errors.is_empty() {
+                    "no valid action found".to_string()
+                } else {
+                    parsed_block.errors.join(", ")
+                }
+            ));
+        }
+
         if parsed_block.processed == 0 {
             break;
         } else {
             parsed.extend(parsed_block.invocations);
 
-            // update offset
-            ptr = &ptr[parsed_block.processed..];
+            // update offset, accounting for the removed attributes
+            ptr = &ptr[(parsed_block.processed + removed).min(ptr.len())..];
         }
     }
 
@@ -187,10 +388,88 @@ pub(crate) fn try_parse(raw: &str) -> Result<Vec<Invocation>> {
     Ok(parsed.into_iter().unique().collect())
 }
 
+// end of the first complete tag-like block in the buffer, either a '<tag/>' or a '<tag>...</tag>'
+fn complete_block_end(buffer: &str) -> Option<usize> {
+    let mut offset = 0;
+    while let Some(idx) = buffer[offset..].find('<') {
+        let open_idx = offset + idx;
+        let ptr = &buffer[open_idx + 1..];
+        let name_len = ptr
+            .find(|c: char| c.is_whitespace() || c == '>' || c == '/')
+            .unwrap_or(ptr.len());
+        let name = &ptr[..name_len];
+
+        if !name.starts_with(|c: char| c.is_alphabetic()) {
+            // not a tag, keep looking
+            offset = open_idx + 1;
+            continue;
+        }
+
+        // wait for the opening tag to be complete
+        let gt = ptr.find('>')?;
+        if ptr[..gt].ends_with('/') {
+            return Some(open_idx + 1 + gt + 1);
+        }
+
+        return closing_tag_idx(ptr, name, gt)
+            .map(|close_idx| open_idx + 1 + close_idx + name.len() + 3);
+    }
+
+    None
+}
+
+// incremental parser for streamed responses, complete blocks are parsed as soon as they are
+// received while anything else is kept in the buffer until more data or the final flush
+pub(crate) struct StreamParser {
+    buffer: String,
+    duplicates: DuplicateAttributes,
+    strict: bool,
+    invocations: Vec<Invocation>,
+}
+
+impl StreamParser {
+    pub fn new(duplicates: DuplicateAttributes, strict: bool) -> Self {
+        Self {
+            buffer: String::new(),
+            duplicates,
+            strict,
+            invocations: vec![],
+        }
+    }
+
+    // returns the invocations parsed from the new data
+    pub fn push(&mut self, chunk: &str) -> Result<Vec<Invocation>> {
+        self.buffer.push_str(chunk);
+
+        let mut parsed = vec![];
+        while let Some(end) = complete_block_end(&self.buffer) {
+            let block: String = self.buffer.drain(..end).collect();
+            parsed.extend(try_parse_with(&block, self.duplicates, self.strict)?);
+        }
+
+        self.invocations.extend(parsed.iter().cloned());
+
+        Ok(parsed)
+    }
+
+    // parse what's left in the buffer and return all the invocations
+    pub fn finish(mut self) -> Result<Vec<Invocation>> {
+        let rest = std::mem::take(&mut self.buffer);
+        self.invocations
+            .extend(try_parse_with(&rest, self.duplicates, self.strict)?);
+
+        Ok(self.invocations.into_iter().unique().collect())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn try_parse(raw: &str) -> Result<Vec<Invocation>> {
+        try_parse_with(raw, DuplicateAttributes::default(), false)
+    }
+
     #[test]
     fn test_parse_simple() {
         let ptr = "<clear-plan></clear-plan>";
@@ -299,6 +578,184 @@ mod tests {
         assert_eq!(invocations[1].payload, Some("yes < no".to_string()));
     }
 
+    #[test]
+    fn test_parse_duplicated_attributes() {
+        let ptr = "<do a=\"1\" b=\"x\" a=\"2\">this!</do> <clear-plan></clear-plan>";
+
+        // refused even when not strict so that the model can be told about it
+        let error = try_parse_with(ptr, DuplicateAttributes::Error, false).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "duplicated attributes a in <do a=\"1\" b=\"x\" a=\"2\">"
+        );
+
+        let invocations = try_parse_with(ptr, DuplicateAttributes::First, false).unwrap();
+        assert_eq!(invocations.len(), 2);
+        let attrs = invocations[0].attributes.as_ref().unwrap();
+        assert_eq!(attrs.get("a").unwrap(), "1");
+        assert_eq!(attrs.get("b").unwrap(), "x");
+        assert_eq!(&invocations[1].action, "clear-plan");
+
+        let invocations = try_parse_with(ptr, DuplicateAttributes::Last, false).unwrap();
+        assert_eq!(invocations.len(), 2);
+        let attrs = invocations[0].attributes.as_ref().unwrap();
+        assert_eq!(attrs.get("a").unwrap(), "2");
+        assert_eq!(attrs.len(), 2);
+    }
+
+    #[test]
+    fn test_parse_multiline_payload() {
+        let ptr = "<write-file path=\"/tmp/test.c\">\nint main() {\n    return 1 < 2 && 3 > 1;\n}\n</write-file>";
+        let invocations = try_parse(ptr).unwrap();
+
+        assert_eq!(invocations.len(), 1);
+        assert_eq!(&invocations[0].action, "write-file");
+        assert_eq!(
+            invocations[0].payload,
+            Some("\nint main() {\n    return 1 < 2 && 3 > 1;\n}\n".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parse_attributes_with_spaces() {
+        let ptr = "<do  foo = \"bar baz\"   other='single quoted'\n>this!</do>\n\n<update-goal>test</update-goal>";
+        let invocations = try_parse(ptr).unwrap();
+
+        assert_eq!(invocations.len(), 2);
+
+        let attrs = invocations[0].attributes.as_ref().unwrap();
+        assert_eq!(attrs.get("foo").unwrap(), "bar baz");
+        assert_eq!(attrs.get("other").unwrap(), "single quoted");
+        assert_eq!(invocations[0].payload, Some("this!".to_string()));
+
+        assert_eq!(&invocations[1].action, "update-goal");
+        assert_eq!(invocations[1].payload, Some("test".to_string()));
+    }
+
+    #[test]
+    fn test_parse_nested_payload() {
+        let inner = "<path>/tmp/x</path>\n<data a=\"1\">some &amp; <b>bold</b> text</data>";
+        let ptr = format!("<save-file>{}</save-file> <clear-plan/>", inner);
+        let invocations = try_parse(&ptr).unwrap();
+
+        assert_eq!(invocations.len(), 2);
+        assert_eq!(&invocations[0].action, "save-file");
+        assert_eq!(invocations[0].payload, Some(inner.to_string()));
+        assert_eq!(&invocations[1].action, "clear-plan");
+
+        // nested elements with the same name as the invocation
+        let inner = "<note><note>x</note></note>";
+        let invocations = try_parse(&format!("<note>{}</note>", inner)).unwrap();
+        assert_eq!(invocations.len(), 1);
+        assert_eq!(invocations[0].payload, Some(inner.to_string()));
+    }
+
+    #[test]
+    fn test_parse_attribute_quoting() {
+        let attr = |ptr: &str, name: &str| {
+            let invocations = try_parse(ptr).unwrap();
+            assert_eq!(invocations.len(), 1, "{}", ptr);
+            assert_eq!(invocations[0].payload, Some("this!".to_string()));
+            invocations[0]
+                .attributes
+                .as_ref()
+                .unwrap()
+                .get(name)
+                .unwrap()
+                .to_string()
+        };
+
+        assert_eq!(attr("<do foo=\"bar\">this!</do>", "foo"), "bar");
+        assert_eq!(attr("<do foo='bar'>this!</do>", "foo"), "bar");
+        assert_eq!(attr("<do foo=bar>this!</do>", "foo"), "bar");
+        assert_eq!(attr("<do path=/tmp/x.txt>this!</do>", "path"), "/tmp/x.txt");
+        assert_eq!(
+            attr("<do foo=\"say &quot;hi&quot; &amp; bye\">this!</do>", "foo"),
+            "say \"hi\" & bye"
+        );
+        // invalid entities are kept as they are
+        assert_eq!(attr("<do foo='a & b'>this!</do>", "foo"), "a & b");
+
+        let invocations = try_parse("<do a=\"1\" b='two words' c=3 d = \"4\">this!</do>").unwrap();
+        let attrs = invocations[0].attributes.as_ref().unwrap();
+        assert_eq!(attrs.len(), 4);
+        assert_eq!(attrs.get("a").unwrap(), "1");
+        assert_eq!(attrs.get("b").unwrap(), "two words");
+        assert_eq!(attrs.get("c").unwrap(), "3");
+        assert_eq!(attrs.get("d").unwrap(), "4");
+    }
+
+    #[test]
+    fn test_parse_code_fences() {
+        let ptr =
+            "Here's what I'll do:\n\n```xml\n<do foo=\"bar\">this!</do>\n<clear-plan/>\n```\n";
+        let invocations = try_parse(ptr).unwrap();
+
+        assert_eq!(invocations.len(), 2);
+        assert_eq!(&invocations[0].action, "do");
+        assert_eq!(invocations[0].payload, Some("this!".to_string()));
+        assert_eq!(&invocations[1].action, "clear-plan");
+
+        // fences inside of a payload are preserved
+        let payload = "\n```rust\nfn main() {}\n```\n";
+        let invocations = try_parse(&format!(
+            "```\n<write-file path=\"notes.md\">{}</write-file>\n```",
+            payload
+        ))
+        .unwrap();
+        assert_eq!(invocations.len(), 1);
+        assert_eq!(invocations[0].payload, Some(payload.to_string()));
+    }
+
+    #[test]
+    fn test_parse_strict() {
+        let ptr = "<clear-plan></clear-plan> <search site:bing.com Darmepinter</search>";
+
+        // by default the broken block is skipped
+        let invocations = try_parse_with(ptr, DuplicateAttributes::default(), false).unwrap();
+        assert_eq!(invocations.len(), 1);
+        assert_eq!(&invocations[0].action, "clear-plan");
+
+        let err = try_parse_with(ptr, DuplicateAttributes::default(), true).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("can't parse '<search site:bing.com Darmepinter</search>'"));
+
+        // things that don't look like tags are fine
+        let invocations = try_parse_with(
+            "if a < b then <do>this!</do>",
+            DuplicateAttributes::default(),
+            true,
+        )
+        .unwrap();
+        assert_eq!(invocations.len(), 1);
+    }
+
+    #[test]
+    fn test_stream_parser() {
+        let response = "let's see <clear-plan/> then <do foo=\"bar\">this!</do> and <yo/>";
+        let expected = try_parse(response).unwrap();
+
+        // split at every possible position, tags split across chunks must not be lost
+        for split in 1..response.len() {
+            let mut parser = StreamParser::new(DuplicateAttributes::default(), false);
+            let mut streamed = parser.push(&response[..split]).unwrap();
+            streamed.extend(parser.push(&response[split..]).unwrap());
+
+            // complete blocks are parsed before the flush
+            assert_eq!(streamed, expected);
+            assert_eq!(parser.finish().unwrap(), expected);
+        }
+
+        let mut parser = StreamParser::new(DuplicateAttributes::default(), false);
+        let first = parser.push("<do>th").unwrap();
+        assert!(first.is_empty());
+        let second = parser.push("is!</do> <other>").unwrap();
+        assert_eq!(second.len(), 1);
+        assert_eq!(second[0].payload, Some("this!".to_string()));
+        assert_eq!(parser.finish().unwrap().len(), 1);
+    }
+
     #[test]
     fn test_preprocess_broken_block() {
         let block = "<search site:bing.com Darmepinter</search>";
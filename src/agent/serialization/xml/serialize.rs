@@ -1,3 +1,5 @@
+use itertools::Itertools;
+
 use crate::agent::{
     namespaces::Action,
     state::storage::{Storage, StorageType, CURRENT_TAG, PREVIOUS_TAG},
@@ -7,7 +9,8 @@ use crate::agent::{
 pub(crate) fn invocation(inv: &Invocation) -> String {
     let mut xml = format!("<{}", inv.action);
     if let Some(attrs) = &inv.attributes {
-        for (key, value) in attrs {
+        // sorted for a stable output
+        for (key, value) in attrs.iter().sorted() {
             xml += &format!(" {key}=\"{value}\"");
         }
     }
@@ -29,7 +32,7 @@ pub(crate) fn action(action: &Box<dyn Action>) -> String {
     let mut xml = format!("<{}", action.name());
 
     if let Some(attrs) = action.attributes() {
-        for (name, example_value) in &attrs {
+        for (name, example_value) in attrs.iter().sorted() {
             xml += &format!(" {}=\"{}\"", name, example_value);
         }
     }
@@ -44,8 +47,28 @@ pub(crate) fn action(action: &Box<dyn Action>) -> String {
     xml
 }
 
+// same as action but without example values
+#[allow(clippy::borrowed_box)]
+pub(crate) fn action_signature(action: &Box<dyn Action>) -> String {
+    let mut xml = format!("<{}", action.name());
+
+    if let Some(attrs) = action.attributes() {
+        for name in attrs.keys().sorted() {
+            xml += &format!(" {}=\"...\"", name);
+        }
+    }
+
+    if action.example_payload().is_some() {
+        xml += &format!(">...</{}>", action.name());
+    } else {
+        xml += "/>";
+    }
+
+    xml
+}
+
 pub(crate) fn storage(storage: &Storage) -> String {
-    if storage.is_empty() {
+    if storage.entries().next().is_none() {
         return "".to_string();
     }
 
@@ -53,7 +76,7 @@ pub(crate) fn storage(storage: &Storage) -> String {
         StorageType::Tagged => {
             let mut xml: String = format!("<{}>\n", storage.get_name());
 
-            for (key, entry) in storage.iter() {
+            for (key, entry) in storage.entries() {
                 xml += &format!("  - {}={}\n", key, &entry.data);
             }
 
@@ -64,7 +87,7 @@ pub(crate) fn storage(storage: &Storage) -> String {
         StorageType::Untagged => {
             let mut xml = format!("<{}>\n", storage.get_name());
 
-            for entry in storage.values() {
+            for (_, entry) in storage.entries() {
                 xml += &format!("  - {}\n", &entry.data);
             }
 
@@ -75,7 +98,7 @@ pub(crate) fn storage(storage: &Storage) -> String {
         StorageType::Completion => {
             let mut xml = format!("<{}>\n", storage.get_name());
 
-            for entry in storage.values() {
+            for (_, entry) in storage.entries() {
                 xml += &format!(
                     "  - {} : {}\n",
                     &entry.data,
@@ -91,10 +114,38 @@ pub(crate) fn storage(storage: &Storage) -> String {
 
             xml.to_string()
         }
+        StorageType::Tasklist => {
+            let mut xml = format!("<{}>\n", storage.get_name());
+
+            for (pos, entry) in storage.entries() {
+                xml += &format!(
+                    "  - [{}] {}. {}\n",
+                    if entry.complete { "x" } else { " " },
+                    pos,
+                    &entry.data
+                );
+            }
+
+            let total = storage.entries().count();
+            let completed = storage
+                .entries()
+                .filter(|(_, entry)| entry.complete)
+                .count();
+            xml += &format!(
+                "  ({} of {} completed, {} remaining)\n",
+                completed,
+                total,
+                total - completed
+            );
+
+            xml += &format!("</{}>", storage.get_name());
+
+            xml.to_string()
+        }
         StorageType::CurrentPrevious => {
-            if let Some(current) = storage.get(CURRENT_TAG) {
+            if let Some(current) = storage.get_entry(CURRENT_TAG) {
                 let mut str = format!("* Current {}: {}", storage.get_name(), current.data.trim());
-                if let Some(prev) = storage.get(PREVIOUS_TAG) {
+                if let Some(prev) = storage.get_entry(PREVIOUS_TAG) {
                     str += &format!("\n* Previous {}: {}", storage.get_name(), prev.data.trim());
                 }
                 str
@@ -0,0 +1,62 @@
+use std::{collections::HashMap, path::Path};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    history::Execution,
+    metrics::Metrics,
+    storage::{Entry, StorageType},
+    usage::Usage,
+    FollowedFile,
+};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(super) struct StoredStorage {
+    pub name: String,
+    pub type_: StorageType,
+    pub entries: Vec<(String, Entry)>,
+}
+
+// the dynamic part of the state, the task itself is rebuilt from its definition
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Checkpoint {
+    // name and prompt of the task, used to make sure we resume the same one
+    pub(super) task: Option<String>,
+    pub(super) prompt: String,
+    pub(super) complete: bool,
+    pub(super) concluding: bool,
+    pub(super) metrics: Metrics,
+    pub(super) storages: Vec<StoredStorage>,
+    pub(super) history: Vec<Execution>,
+    pub(super) usage: Usage,
+    pub(super) followed_files: HashMap<String, FollowedFile>,
+    // names of the namespaces in use and of the disabled ones, as they might have been toggled
+    #[serde(default)]
+    pub(super) namespaces: Vec<String>,
+    #[serde(default)]
+    pub(super) disabled_namespaces: Vec<String>,
+}
+
+impl Checkpoint {
+    pub fn load(path: &Path) -> Result<Self> {
+        let data = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("can't read checkpoint {}: {}", path.display(), e))?;
+
+        serde_json::from_str(&data)
+            .map_err(|e| anyhow!("can't parse checkpoint {}: {}", path.display(), e))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        // write to a temporary file first so that a crash never leaves a truncated checkpoint
+        let tmp_path = path.with_extension("tmp");
+        std::fs::write(&tmp_path, serde_json::to_string(self)?)?;
+        std::fs::rename(&tmp_path, path)?;
+
+        Ok(())
+    }
+
+    pub fn current_step(&self) -> usize {
+        self.metrics.current_step
+    }
+}
@@ -1,54 +1,134 @@
 use anyhow::Result;
+use serde::{Deserialize, Serialize};
 
-use crate::agent::{generator::Message, Invocation};
+use crate::agent::{
+    generator::Message, serialization::InvocationFormat, tokenizer::Tokenizer, Invocation,
+};
 
-#[derive(Debug, Clone, Default)]
+// how the transcript of the run is rendered
+#[derive(Debug, Default, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum TranscriptFormat {
+    // plain text log
+    #[default]
+    Text,
+    // markdown document with a section per step
+    Markdown,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Execution {
+    // step the execution happened at
+    #[serde(default)]
+    step: usize,
     // unparsed response caused an error
     response: Option<String>,
     // parsed invocation
     invocation: Option<Invocation>,
 
     result: Option<String>,
+    // typed output of the action, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    data: Option<serde_json::Value>,
     error: Option<String>,
 }
 
 impl Execution {
-    pub fn with_unparsed_response(response: &str, error: String) -> Self {
+    pub fn with_unparsed_response(step: usize, response: &str, error: String) -> Self {
         Self {
+            step,
             invocation: None,
             response: Some(response.to_string()),
             result: None,
+            data: None,
             error: Some(error),
         }
     }
 
-    pub fn with_error(invocation: Invocation, error: String) -> Self {
+    pub fn with_error(step: usize, invocation: Invocation, error: String) -> Self {
         Self {
+            step,
             invocation: Some(invocation),
             response: None,
             result: None,
+            data: None,
             error: Some(error),
         }
     }
 
-    pub fn with_result(invocation: Invocation, result: Option<String>) -> Self {
+    pub fn with_result(step: usize, invocation: Invocation, result: Option<String>) -> Self {
         Self {
+            step,
             invocation: Some(invocation),
             response: None,
             result,
+            data: None,
             error: None,
         }
     }
 
-    pub fn to_messages(&self) -> Vec<Message> {
+    pub fn with_data(mut self, data: Option<serde_json::Value>) -> Self {
+        self.data = data;
+        self
+    }
+
+    // what the model did, as it would write it
+    fn to_request(&self) -> String {
+        if let Some(invocation) = self.invocation.as_ref() {
+            crate::agent::serialization::xml::serialize::invocation(invocation)
+        } else {
+            self.response.clone().unwrap_or_default()
+        }
+    }
+
+    fn status(&self) -> &str {
+        if self.error.is_none() {
+            "OK"
+        } else if self.invocation.is_none() {
+            "UNPARSED"
+        } else {
+            "ERROR"
+        }
+    }
+
+    fn to_transcript(&self, format: TranscriptFormat) -> String {
+        let request = self.to_request();
+        let output = self
+            .error
+            .as_ref()
+            .or(self.result.as_ref())
+            .map(|out| out.trim())
+            .unwrap_or_default();
+
+        match format {
+            TranscriptFormat::Text => {
+                let mut entry = format!("[{}] {} {}\n", self.step, self.status(), request.trim());
+                if !output.is_empty() {
+                    entry += &format!("{output}\n");
+                }
+                entry
+            }
+            TranscriptFormat::Markdown => {
+                let mut entry = format!(
+                    "**{}**\n\n```xml\n{}\n```\n\n",
+                    self.status(),
+                    request.trim()
+                );
+                if !output.is_empty() {
+                    entry += &format!("```\n{output}\n```\n\n");
+                }
+                entry
+            }
+        }
+    }
+
+    pub fn to_messages(&self, format: InvocationFormat) -> Vec<Message> {
         let mut messages = vec![];
 
         if let Some(response) = self.response.as_ref() {
             messages.push(Message::Agent(response.to_string(), None));
         } else if let Some(invocation) = self.invocation.as_ref() {
             messages.push(Message::Agent(
-                crate::agent::serialization::xml::serialize::invocation(invocation),
+                format.invocation(invocation),
                 Some(invocation.clone()),
             ));
         }
@@ -76,19 +156,55 @@ impl History {
         Self(vec![])
     }
 
-    pub fn to_chat_history(&self, max: usize) -> Result<Vec<Message>> {
-        let mut history = vec![];
+    // the latest max executions, the oldest ones are also dropped until the messages fit in
+    // budget tokens (0 for no limit) as measured by the tokenizer, the model's own actions are
+    // written in the given format
+    pub fn to_chat_history(
+        &self,
+        max: usize,
+        budget: usize,
+        tokenizer: &dyn Tokenizer,
+        format: InvocationFormat,
+    ) -> Result<Vec<Message>> {
         let latest = if self.0.len() > max {
-            self.0[self.0.len() - max..].to_vec()
+            &self.0[self.0.len() - max..]
         } else {
-            self.0.to_vec().to_vec()
+            &self.0[..]
         };
 
-        for entry in latest {
-            history.extend(entry.to_messages());
+        let mut used = 0;
+        let mut kept = vec![];
+        for entry in latest.iter().rev() {
+            let messages = entry.to_messages(format);
+            if budget > 0 {
+                used += messages
+                    .iter()
+                    .map(|m| tokenizer.count(m.content()))
+                    .sum::<usize>();
+                if used > budget {
+                    break;
+                }
+            }
+            kept.push(messages);
         }
 
-        Ok(history)
+        Ok(kept.into_iter().rev().flatten().collect())
+    }
+
+    // chronological and human readable log of the executions
+    pub fn to_transcript(&self, format: TranscriptFormat) -> String {
+        let mut transcript = String::new();
+        let mut step = None;
+
+        for entry in self.0.iter() {
+            if format == TranscriptFormat::Markdown && step != Some(entry.step) {
+                transcript += &format!("## Step {}\n\n", entry.step);
+                step = Some(entry.step);
+            }
+            transcript += &entry.to_transcript(format);
+        }
+
+        transcript
     }
 }
 
@@ -104,3 +220,102 @@ impl std::ops::DerefMut for History {
         &mut self.0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transcript() {
+        let mut history = History::new();
+        history.push(Execution::with_result(
+            1,
+            Invocation::new("think".to_string(), None, Some("first".to_string())),
+            None,
+        ));
+        history.push(Execution::with_error(
+            1,
+            Invocation::new("read-file".to_string(), None, Some("/nope".to_string())),
+            "file not found".to_string(),
+        ));
+        history.push(Execution::with_unparsed_response(
+            2,
+            "what now?",
+            "no valid actions".to_string(),
+        ));
+        history.push(Execution::with_result(
+            3,
+            Invocation::new("think".to_string(), None, Some("last".to_string())),
+            Some("done".to_string()),
+        ));
+
+        assert_eq!(
+            history.to_transcript(TranscriptFormat::Text),
+            "[1] OK <think>first</think>\n\
+             [1] ERROR <read-file>/nope</read-file>\n\
+             file not found\n\
+             [2] UNPARSED what now?\n\
+             no valid actions\n\
+             [3] OK <think>last</think>\n\
+             done\n"
+        );
+
+        let markdown = history.to_transcript(TranscriptFormat::Markdown);
+        // one header per step, in order
+        let headers: Vec<&str> = markdown
+            .lines()
+            .filter(|line| line.starts_with("## "))
+            .collect();
+        assert_eq!(headers, vec!["## Step 1", "## Step 2", "## Step 3"]);
+        assert!(markdown.find("**OK**").unwrap() < markdown.find("**ERROR**").unwrap());
+        assert!(markdown.find("**ERROR**").unwrap() < markdown.find("**UNPARSED**").unwrap());
+        assert!(markdown
+            .contains("```xml\n<read-file>/nope</read-file>\n```\n\n```\nfile not found\n```"));
+    }
+
+    #[test]
+    fn test_chat_history_within_budget() {
+        let tokenizer = crate::agent::tokenizer::Estimator::default();
+        let mut history = History::new();
+        for i in 0..10 {
+            history.push(Execution::with_result(
+                i,
+                Invocation::new("think".to_string(), None, Some(format!("thought {i}"))),
+                Some("x".repeat(100)),
+            ));
+        }
+
+        // no budget, only the count limit applies
+        assert_eq!(
+            history
+                .to_chat_history(5, 0, &tokenizer, InvocationFormat::Xml)
+                .unwrap()
+                .len(),
+            10
+        );
+
+        let budget = 100;
+        let trimmed = history
+            .to_chat_history(10, budget, &tokenizer, InvocationFormat::Xml)
+            .unwrap();
+        let used: usize = trimmed.iter().map(|m| tokenizer.count(m.content())).sum();
+        assert!(used <= budget);
+        assert_eq!(trimmed.len(), 6);
+        // the most recent executions are kept
+        assert_eq!(trimmed[4].content(), "<think>thought 9</think>");
+
+        let json = history
+            .to_chat_history(1, 0, &tokenizer, InvocationFormat::Json)
+            .unwrap();
+        assert_eq!(
+            json[0].content(),
+            "{\"action\": \"think\", \"payload\": \"thought 9\"}"
+        );
+
+        // nothing fits
+        assert!(history
+            .to_chat_history(10, 10, &tokenizer, InvocationFormat::Xml)
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -1,8 +1,10 @@
-use std::fmt::Display;
+use std::{fmt::Display, time::Duration};
 
 use memory_stats::memory_stats;
 use serde::{Deserialize, Serialize};
 
+use crate::agent::generator::TokenUsage;
+
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ErrorMetrics {
     pub empty_responses: usize,
@@ -11,6 +13,7 @@ pub struct ErrorMetrics {
     pub invalid_actions: usize,
     pub errored_actions: usize,
     pub timedout_actions: usize,
+    pub looping_actions: usize,
 }
 
 impl ErrorMetrics {
@@ -19,7 +22,10 @@ impl ErrorMetrics {
     }
 
     fn has_action_errors(&self) -> bool {
-        self.unknown_actions > 0 || self.invalid_actions > 0 || self.errored_actions > 0
+        self.unknown_actions > 0
+            || self.invalid_actions > 0
+            || self.errored_actions > 0
+            || self.looping_actions > 0
     }
 }
 
@@ -27,21 +33,67 @@ impl ErrorMetrics {
 pub struct Metrics {
     pub max_steps: usize,
     pub current_step: usize,
+    // number of reasoning only steps that don't count as steps
+    pub max_reasoning_steps: usize,
+    pub reasoning_steps: usize,
+    pub max_duration: Option<Duration>,
+    pub elapsed: Duration,
+    // maximum cost of the executed actions or 0 for no limit
+    pub max_cost: u32,
+    // total cost of the executed actions
+    pub cost: u32,
     pub valid_responses: usize,
     pub valid_actions: usize,
     pub success_actions: usize,
     pub errors: ErrorMetrics,
+    // fingerprint of the last system prompt
+    pub system_prompt_fingerprint: Option<String>,
+    // number of steps where the system prompt didn't change from the previous one
+    pub system_prompt_reuses: usize,
+    // tokens consumed by the generations, for backends reporting them
+    #[serde(default)]
+    pub tokens: TokenUsage,
+}
+
+impl Metrics {
+    // the steps counting against max_steps, reasoning only ones are taken from their own budget
+    pub fn counted_steps(&self) -> usize {
+        self.current_step - self.reasoning_steps
+    }
 }
 
 impl Display for Metrics {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "step:")?;
         if self.max_steps > 0 {
-            write!(f, "{}/{} ", self.current_step, self.max_steps)?;
+            write!(f, "{}/{} ", self.counted_steps(), self.max_steps)?;
         } else {
             write!(f, "{} ", self.current_step)?;
         }
 
+        if self.max_reasoning_steps > 0 {
+            write!(
+                f,
+                "reasoning:{}/{} ",
+                self.reasoning_steps, self.max_reasoning_steps
+            )?;
+        }
+
+        if let Some(max_duration) = self.max_duration {
+            write!(
+                f,
+                "time:{}s/{}s ",
+                self.elapsed.as_secs(),
+                max_duration.as_secs()
+            )?;
+        } else {
+            write!(f, "time:{}s ", self.elapsed.as_secs())?;
+        }
+
+        if self.max_cost > 0 {
+            write!(f, "cost:{}/{} ", self.cost, self.max_cost)?;
+        }
+
         if self.errors.has_response_errors() {
             write!(
                 f,
@@ -55,17 +107,35 @@ impl Display for Metrics {
         if self.errors.has_action_errors() {
             write!(
                 f,
-                "actions(valid:{} ok:{} errored:{} unknown:{} invalid:{}) ",
+                "actions(valid:{} ok:{} errored:{} unknown:{} invalid:{} looping:{}) ",
                 self.valid_actions,
                 self.success_actions,
                 self.errors.errored_actions,
                 self.errors.unknown_actions,
-                self.errors.invalid_actions
+                self.errors.invalid_actions,
+                self.errors.looping_actions
             )?;
         } else if self.valid_actions > 0 {
             write!(f, "actions:{} ", self.valid_actions,)?;
         }
 
+        if let Some(fingerprint) = &self.system_prompt_fingerprint {
+            write!(
+                f,
+                "prompt({} reused:{}) ",
+                &fingerprint[..8],
+                self.system_prompt_reuses
+            )?;
+        }
+
+        if self.tokens.total() > 0 {
+            write!(
+                f,
+                "tokens(prompt:{} completion:{}) ",
+                self.tokens.prompt_tokens, self.tokens.completion_tokens
+            )?;
+        }
+
         if let Some(usage) = memory_stats() {
             write!(
                 f,
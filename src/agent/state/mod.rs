@@ -1,21 +1,65 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashMap, sync::Arc, time::Instant};
 
 use anyhow::Result;
+use itertools::Itertools;
 use metrics::Metrics;
+use serde::{Deserialize, Serialize};
 
 use super::{
     events::Event,
     generator::Message,
     namespaces::{self, Namespace},
-    task::Task,
+    rag,
+    serialization::{ActionsStyle, InvocationFormat},
+    task::{Phase, Task},
+    tokenizer::{Estimator, Tokenizer},
     Invocation,
 };
-use history::{Execution, History};
+use checkpoint::{Checkpoint, StoredStorage};
+use history::{Execution, History, TranscriptFormat};
+use redaction::Redactor;
+use secrets::Secrets;
 use storage::Storage;
+use usage::Usage;
 
-mod history;
+pub(crate) mod checkpoint;
+pub(crate) mod history;
 pub(crate) mod metrics;
+pub(crate) mod redaction;
+pub(crate) mod secrets;
 pub(crate) mod storage;
+pub(crate) mod truncation;
+pub(crate) mod usage;
+
+// storage and key of the summary of the executions dropped from the history
+pub(crate) const SUMMARY_STORAGE: &str = "summary";
+const SUMMARY_KEY: &str = "previous_actions";
+
+// namespaces are referenced by their registry key (like "env") or by their name
+fn namespace_display_name(name: &str) -> String {
+    if let Some(build_fn) = namespaces::NAMESPACES.get(name) {
+        build_fn().name
+    } else {
+        name.to_string()
+    }
+}
+
+// create the storages required by the namespace if they don't exist yet
+fn create_storages(
+    storages: &mut HashMap<String, Storage>,
+    namespace: &Namespace,
+    events_tx: &super::events::Sender,
+) {
+    for storage in namespace.storages.iter().flatten() {
+        if !storages.contains_key(&storage.name) {
+            storages.insert(
+                storage.name.to_string(),
+                Storage::new(&storage.name, storage.type_, events_tx.clone())
+                    .with_limits(storage.max_entries, storage.ttl),
+            );
+        }
+    }
+}
 
 pub struct State {
     // the task
@@ -24,12 +68,46 @@ pub struct State {
     storages: HashMap<String, Storage>,
     // available actions and execution history
     namespaces: Vec<Namespace>,
+    // namespaces disabled by the task or at runtime, they can be enabled again
+    disabled_namespaces: Vec<Namespace>,
     // list of executed actions
     history: History,
+    // recent invocations and per action counters
+    usage: Usage,
     // optional rag engine
-    rag: Option<mini_rag::VectorStore>,
+    rag: Option<Box<dyn rag::VectorStore>>,
+    // maximum number of documents the model can retrieve at once
+    rag_max_results: usize,
+    // optional redaction of secrets from action results
+    redactor: Option<Redactor>,
+    // secrets that actions can reference by name
+    secrets: Secrets,
+    // measures the history against its token budget
+    tokenizer: Arc<dyn Tokenizer>,
+    // how actions are shown to the model
+    invocation_format: InvocationFormat,
+    // how much detail about the actions is shown to the model
+    actions_style: ActionsStyle,
+    // custom system prompt template, the default one if not set
+    system_prompt_template: Option<String>,
+    // files being followed by path
+    followed_files: HashMap<String, FollowedFile>,
+    // base64 encoded images to send with the next generation
+    images: Vec<String>,
     // set to true when task is complete
     complete: bool,
+    // whether the task has been set as impossible and why it's complete
+    completion: Option<(bool, Option<String>)>,
+    // behaviour when the maximum number of steps is reached
+    on_max_steps: OnMaxSteps,
+    // number of recent invocations considered for loop detection
+    loop_window: usize,
+    // maximum executions of the same invocation within the window or 0 for no limit
+    loop_threshold: usize,
+    // set to true when the model has been asked to conclude
+    concluding: bool,
+    // when the run started
+    started_at: Instant,
     // events channel
     events_tx: super::events::Sender,
     // runtime metrics
@@ -38,12 +116,41 @@ pub struct State {
 
 pub type SharedState = Arc<tokio::sync::Mutex<State>>;
 
+// what to do once the maximum number of steps is reached
+#[derive(Debug, Default, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum OnMaxSteps {
+    // abort with an error
+    #[default]
+    Error,
+    // ask the model for one last response to conclude the task
+    Conclude,
+    // set the task as complete
+    Complete,
+}
+
+// what to do when the model keeps repeating the same invocations
+#[derive(Debug, Default, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum OnLoop {
+    // don't execute the invocation and ask the model to try something else
+    #[default]
+    Warn,
+    // abort with an error
+    Abort,
+}
+
+// position of a followed file at the time of the last read
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct FollowedFile {
+    pub inode: u64,
+    pub offset: u64,
+}
+
 impl State {
     pub async fn new(
         events_tx: super::events::Sender,
         task: Box<dyn Task>,
-        embedder: Box<dyn mini_rag::Embedder>,
-        max_iterations: usize,
+        embedder: Box<dyn rag::Embedder>,
+        config: &super::Config,
     ) -> Result<Self> {
         let complete = false;
         let mut storages = HashMap::new();
@@ -85,8 +192,10 @@ impl State {
         }
 
         // add RAG namespace
-        let rag: Option<mini_rag::VectorStore> = if let Some(config) = task.get_rag_config() {
-            let mut v_store = mini_rag::VectorStore::new(embedder, config)?;
+        let mut rag_max_results = 0;
+        let rag: Option<Box<dyn rag::VectorStore>> = if let Some(config) = task.get_rag_config() {
+            rag_max_results = config.get_max_results();
+            let mut v_store = rag::create(embedder, config)?;
 
             // import new documents if needed
             v_store.import_new_documents().await?;
@@ -98,22 +207,42 @@ impl State {
             None
         };
 
+        let redactor = if let Some(config) = task.get_redaction_config() {
+            Some(Redactor::new(&config)?)
+        } else {
+            None
+        };
+
         // add task defined actions
         namespaces.append(&mut task.get_functions());
 
+        // remove the namespaces the task explicitly disabled
+        let mut disabled_namespaces = vec![];
+        for name in task.disabled_namespaces().unwrap_or_default() {
+            let display_name = namespace_display_name(&name);
+            while let Some(idx) = namespaces
+                .iter()
+                .position(|ns| ns.name.eq_ignore_ascii_case(&display_name))
+            {
+                disabled_namespaces.push(namespaces.remove(idx));
+            }
+        }
+
         // if any namespace requires a specific storage, create it
         for namespace in &namespaces {
-            if let Some(ns_storages) = &namespace.storages {
-                for storage in ns_storages {
-                    // not created yet
-                    if !storages.contains_key(&storage.name) {
-                        storages.insert(
-                            storage.name.to_string(),
-                            Storage::new(&storage.name, storage.type_, events_tx.clone()),
-                        );
-                    }
-                }
-            }
+            create_storages(&mut storages, namespace, &events_tx);
+        }
+
+        // older executions are summarized into this storage
+        if config.summarize_after > 0 {
+            storages.insert(
+                SUMMARY_STORAGE.to_string(),
+                Storage::new(
+                    SUMMARY_STORAGE,
+                    storage::StorageType::Tagged,
+                    events_tx.clone(),
+                ),
+            );
         }
 
         // if the goal namespace is enabled, set the current goal
@@ -123,7 +252,10 @@ impl State {
         }
 
         let metrics = Metrics {
-            max_steps: max_iterations,
+            max_steps: config.max_iterations,
+            max_reasoning_steps: config.reasoning_budget,
+            max_duration: config.max_duration.or(task.get_max_duration()),
+            max_cost: config.max_cost,
             ..Default::default()
         };
 
@@ -131,37 +263,225 @@ impl State {
             task,
             storages,
             history,
+            usage: Usage::new(config.loop_window.max(usage::DEFAULT_CAPACITY)),
             namespaces,
             complete,
+            completion: None,
+            on_max_steps: config.on_max_iterations,
+            loop_window: config.loop_window,
+            loop_threshold: config.loop_threshold,
+            secrets: config.secrets.clone(),
+            concluding: false,
+            started_at: Instant::now(),
             metrics,
             rag,
+            rag_max_results,
+            redactor,
+            disabled_namespaces,
+            tokenizer: config
+                .tokenizer
+                .clone()
+                .unwrap_or_else(|| Arc::new(Estimator::default())),
+            invocation_format: config.invocation_format,
+            actions_style: config.actions_style,
+            system_prompt_template: config.system_prompt_template.clone(),
+            followed_files: HashMap::new(),
+            images: vec![],
             events_tx,
         })
     }
 
-    pub fn on_step(&mut self) -> Result<()> {
+    // returns false if no more inference should be done
+    pub fn on_step(&mut self) -> Result<bool> {
+        self.metrics.elapsed = self.started_at.elapsed();
+        if let Some(max_duration) = self.metrics.max_duration {
+            if self.metrics.elapsed >= max_duration {
+                self.on_complete(
+                    false,
+                    Some(format!("time budget of {:?} exhausted", max_duration)),
+                )?;
+                return Ok(false);
+            }
+        }
+
+        if self.metrics.max_cost > 0 && self.metrics.cost >= self.metrics.max_cost {
+            self.on_complete(
+                false,
+                Some(format!(
+                    "maximum actions cost of {} reached",
+                    self.metrics.max_cost
+                )),
+            )?;
+            return Ok(false);
+        }
+
+        // all the allowed steps have been executed
+        if self.metrics.max_steps > 0 && self.metrics.counted_steps() >= self.metrics.max_steps {
+            let reason = "maximum number of steps reached";
+            match self.on_max_steps {
+                OnMaxSteps::Error => return Err(anyhow!(reason)),
+                _ => {
+                    self.on_complete(false, Some(reason.to_string()))?;
+                    return Ok(false);
+                }
+            }
+        }
+
         self.metrics.current_step += 1;
-        if self.metrics.max_steps > 0 && self.metrics.current_step >= self.metrics.max_steps {
-            Err(anyhow!("maximum number of steps reached"))
+        for phase in self.task.phases() {
+            if phase.step == self.metrics.current_step {
+                self.on_phase(&phase)?;
+            }
+        }
+
+        // the last step is used to conclude
+        if self.metrics.max_steps > 0
+            && self.metrics.counted_steps() == self.metrics.max_steps
+            && self.on_max_steps == OnMaxSteps::Conclude
+        {
+            self.concluding = true;
+        }
+
+        Ok(true)
+    }
+
+    fn on_phase(&mut self, phase: &Phase) -> Result<()> {
+        log::info!("step {}: entering a new phase", phase.step);
+        for name in &phase.not_using {
+            // fine if it's not in use already
+            if let Err(error) = self.disable_namespace(name) {
+                log::debug!("{}", error);
+            }
+        }
+        for name in &phase.using {
+            self.enable_namespace(name)?;
+        }
+        Ok(())
+    }
+
+    // a step where the model only reasoned, returns true if it's been taken from the reasoning budget
+    pub fn on_reasoning_step(&mut self) -> bool {
+        if self.metrics.reasoning_steps < self.metrics.max_reasoning_steps
+            && self.metrics.counted_steps() > 0
+        {
+            // the step keeps its number, it just doesn't count against max_steps
+            self.metrics.reasoning_steps += 1;
+            true
         } else {
-            Ok(())
+            false
         }
     }
 
+    pub fn is_concluding(&self) -> bool {
+        self.concluding
+    }
+
     pub async fn rag_query(
         &mut self,
         query: &str,
         top_k: usize,
-    ) -> Result<Vec<(mini_rag::Document, f64)>> {
-        if let Some(rag) = &self.rag {
-            rag.retrieve(query, top_k).await
+    ) -> Result<Vec<(rag::Document, f64)>> {
+        if let Some(rag) = &mut self.rag {
+            rag.retrieve(query, top_k, None).await
         } else {
             Err(anyhow!("no RAG engine has been configured"))
         }
     }
 
-    pub fn to_chat_history(&self, max: usize) -> Result<Vec<Message>> {
-        self.history.to_chat_history(max)
+    pub fn get_rag_max_results(&self) -> usize {
+        self.rag_max_results
+    }
+
+    pub fn get_secrets(&self) -> &Secrets {
+        &self.secrets
+    }
+
+    pub fn redact(&self, data: String) -> String {
+        // secret values are always masked
+        let data = self.secrets.mask(&data);
+        if let Some(redactor) = &self.redactor {
+            redactor.redact(&data)
+        } else {
+            data
+        }
+    }
+
+    // same as redact, for every string of a structured output
+    pub fn redact_data(&self, data: serde_json::Value) -> serde_json::Value {
+        match data {
+            serde_json::Value::String(s) => serde_json::Value::String(self.redact(s)),
+            serde_json::Value::Array(list) => {
+                serde_json::Value::Array(list.into_iter().map(|v| self.redact_data(v)).collect())
+            }
+            serde_json::Value::Object(map) => serde_json::Value::Object(
+                map.into_iter()
+                    .map(|(k, v)| (k, self.redact_data(v)))
+                    .collect(),
+            ),
+            other => other,
+        }
+    }
+
+    pub fn get_followed_file(&self, path: &str) -> Option<FollowedFile> {
+        self.followed_files.get(path).copied()
+    }
+
+    pub fn set_followed_file(&mut self, path: &str, inode: u64, offset: u64) {
+        self.followed_files
+            .insert(path.to_string(), FollowedFile { inode, offset });
+    }
+
+    pub fn add_image(&mut self, image: String) {
+        self.images.push(image);
+    }
+
+    // images are only sent once, with the generation that follows the action attaching them
+    pub fn take_images(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.images)
+    }
+
+    pub fn history_len(&self) -> usize {
+        self.history.len()
+    }
+
+    pub fn get_history_summary(&self) -> Option<String> {
+        self.storages
+            .get(SUMMARY_STORAGE)
+            .and_then(|storage| storage.get_tagged(SUMMARY_KEY))
+    }
+
+    // transcript of the oldest executions to be summarized
+    pub fn oldest_history_transcript(&self, count: usize) -> String {
+        let mut oldest = History::new();
+        oldest.extend(self.history.iter().take(count).cloned());
+        oldest.to_transcript(TranscriptFormat::Text)
+    }
+
+    // replace the oldest executions with their summary
+    pub fn on_history_summarized(&mut self, count: usize, summary: &str) -> Result<()> {
+        self.get_storage_mut(SUMMARY_STORAGE)?
+            .add_tagged(SUMMARY_KEY, summary.trim());
+        let count = count.min(self.history.len());
+        self.history.drain(..count);
+        Ok(())
+    }
+
+    // the task prompt is not part of the history so it's never trimmed
+    pub fn to_chat_history(&self, max: usize, budget: usize) -> Result<Vec<Message>> {
+        self.history
+            .to_chat_history(max, budget, self.tokenizer.as_ref(), self.invocation_format)
+    }
+
+    pub fn get_invocation_format(&self) -> InvocationFormat {
+        self.invocation_format
+    }
+
+    pub fn get_actions_style(&self) -> ActionsStyle {
+        self.actions_style
+    }
+
+    pub fn get_system_prompt_template(&self) -> Option<&str> {
+        self.system_prompt_template.as_deref()
     }
 
     #[allow(clippy::borrowed_box)]
@@ -197,22 +517,184 @@ impl State {
         self.complete
     }
 
+    pub fn export_transcript(&self, format: TranscriptFormat) -> String {
+        self.history.to_transcript(format)
+    }
+
     pub fn get_namespaces(&self) -> &Vec<Namespace> {
         &self.namespaces
     }
 
-    pub fn add_success_to_history(&mut self, invocation: Invocation, result: Option<String>) {
-        self.history
-            .push(Execution::with_result(invocation, result));
+    // make the actions of a namespace available, by name or registry key
+    pub fn enable_namespace(&mut self, name: &str) -> Result<()> {
+        let display_name = namespace_display_name(name);
+        if self
+            .namespaces
+            .iter()
+            .any(|ns| ns.name.eq_ignore_ascii_case(&display_name))
+        {
+            return Ok(());
+        }
+
+        let namespace = if let Some(idx) = self
+            .disabled_namespaces
+            .iter()
+            .position(|ns| ns.name.eq_ignore_ascii_case(&display_name))
+        {
+            self.disabled_namespaces.remove(idx)
+        } else if let Some(build_fn) = namespaces::NAMESPACES.get(name) {
+            build_fn()
+        } else if let Some(namespace) = namespaces::NAMESPACES
+            .values()
+            .map(|build_fn| build_fn())
+            .find(|ns| ns.name.eq_ignore_ascii_case(name))
+        {
+            namespace
+        } else {
+            return Err(anyhow!("no namespace '{}' defined", name));
+        };
+
+        create_storages(&mut self.storages, &namespace, &self.events_tx);
+        self.namespaces.push(namespace);
+
+        Ok(())
+    }
+
+    // remove the actions of a namespace, its storages are kept
+    pub fn disable_namespace(&mut self, name: &str) -> Result<()> {
+        let display_name = namespace_display_name(name);
+        if let Some(idx) = self
+            .namespaces
+            .iter()
+            .position(|ns| ns.name.eq_ignore_ascii_case(&display_name))
+        {
+            let namespace = self.namespaces.remove(idx);
+            self.disabled_namespaces.push(namespace);
+            Ok(())
+        } else {
+            Err(anyhow!("namespace '{}' is not enabled", name))
+        }
+    }
+
+    pub fn to_checkpoint(&self) -> Result<Checkpoint> {
+        Ok(Checkpoint {
+            task: self.task.get_name(),
+            prompt: self.task.to_prompt()?,
+            complete: self.complete,
+            concluding: self.concluding,
+            metrics: self.metrics.clone(),
+            // sorted so that the checkpoint is stable across runs
+            storages: self
+                .storages
+                .values()
+                .sorted_by_key(|storage| storage.get_name())
+                .map(|storage| StoredStorage {
+                    name: storage.get_name().to_string(),
+                    type_: *storage.get_type(),
+                    entries: storage
+                        .iter()
+                        .map(|(key, entry)| (key.to_string(), entry.clone()))
+                        .collect(),
+                })
+                .collect(),
+            history: self.history.to_vec(),
+            usage: self.usage.clone(),
+            followed_files: self.followed_files.clone(),
+            namespaces: self.namespaces.iter().map(|ns| ns.name.clone()).collect(),
+            disabled_namespaces: self
+                .disabled_namespaces
+                .iter()
+                .map(|ns| ns.name.clone())
+                .collect(),
+        })
+    }
+
+    // restore the dynamic state of a previous run of the same task
+    pub fn restore(&mut self, checkpoint: Checkpoint) -> Result<()> {
+        let task = self.task.get_name();
+        if checkpoint.task != task {
+            return Err(anyhow!(
+                "checkpoint is for task {:?}, not {:?}",
+                checkpoint.task.unwrap_or_default(),
+                task.unwrap_or_default()
+            ));
+        } else if checkpoint.prompt != self.task.to_prompt()? {
+            return Err(anyhow!("checkpoint is for a different prompt"));
+        }
+
+        // limits are the ones of the current run
+        self.metrics = Metrics {
+            max_steps: self.metrics.max_steps,
+            max_reasoning_steps: self.metrics.max_reasoning_steps,
+            max_duration: self.metrics.max_duration,
+            max_cost: self.metrics.max_cost,
+            ..checkpoint.metrics
+        };
+        // account for the time already spent
+        self.started_at = Instant::now()
+            .checked_sub(self.metrics.elapsed)
+            .unwrap_or_else(Instant::now);
+
+        for stored in checkpoint.storages {
+            self.storages
+                .entry(stored.name.to_string())
+                .or_insert_with(|| Storage::new(&stored.name, stored.type_, self.events_tx.clone()))
+                .restore(stored.entries);
+        }
+
+        // namespaces toggled during the run
+        for name in &checkpoint.disabled_namespaces {
+            if self.namespaces.iter().any(|ns| &ns.name == name) {
+                self.disable_namespace(name)?;
+            }
+        }
+        for name in &checkpoint.namespaces {
+            self.enable_namespace(name)?;
+        }
+
+        self.history.clear();
+        self.history.extend(checkpoint.history);
+        self.usage = checkpoint.usage;
+        self.followed_files = checkpoint.followed_files;
+        self.complete = checkpoint.complete;
+        self.concluding = checkpoint.concluding;
+
+        Ok(())
+    }
+
+    // returns true if the invocation has been executed too many times recently
+    pub fn is_looping(&self, invocation: &Invocation) -> bool {
+        self.loop_threshold > 0
+            && self.usage.repetitions(invocation, self.loop_window) >= self.loop_threshold
+    }
+
+    pub fn add_success_to_history(
+        &mut self,
+        invocation: Invocation,
+        result: Option<String>,
+        data: Option<serde_json::Value>,
+    ) {
+        self.usage.record(&invocation);
+        self.history.push(
+            Execution::with_result(self.metrics.current_step, invocation, result).with_data(data),
+        );
     }
 
     pub fn add_error_to_history(&mut self, invocation: Invocation, error: String) {
-        self.history.push(Execution::with_error(invocation, error));
+        self.usage.record(&invocation);
+        self.history.push(Execution::with_error(
+            self.metrics.current_step,
+            invocation,
+            error,
+        ));
     }
 
     pub fn add_unparsed_response_to_history(&mut self, response: &str, error: String) {
-        self.history
-            .push(Execution::with_unparsed_response(response, error));
+        self.history.push(Execution::with_unparsed_response(
+            self.metrics.current_step,
+            response,
+            error,
+        ));
     }
 
     pub fn get_action(&self, name: &str) -> Option<Box<dyn namespaces::Action>> {
@@ -227,12 +709,54 @@ impl State {
         None
     }
 
+    pub fn get_action_names(&self) -> Vec<&str> {
+        self.namespaces
+            .iter()
+            .flat_map(|group| group.actions.iter().map(|action| action.name()))
+            .collect()
+    }
+
     pub fn on_complete(&mut self, impossible: bool, reason: Option<String>) -> Result<()> {
         self.complete = true;
+        self.completion = Some((impossible, reason.clone()));
         self.on_event(Event::TaskComplete { impossible, reason })
     }
 
+    pub fn get_completion(&self) -> Option<(bool, Option<String>)> {
+        self.completion.clone()
+    }
+
     pub fn on_event(&self, event: Event) -> Result<()> {
         self.events_tx.send(event).map_err(|e| anyhow!(e))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use super::*;
+    use crate::agent::events::create_channel;
+    use crate::agent::namespaces::memory::{MAX_MEMORIES, MEMORIES_TTL};
+
+    #[test]
+    fn test_storage_limits_from_namespace() {
+        let (tx, _rx) = create_channel();
+        let mut storages = HashMap::new();
+        create_storages(&mut storages, &namespaces::NAMESPACES["memory"](), &tx);
+
+        let memories = storages.get_mut("memories").unwrap();
+        for i in 0..=MAX_MEMORIES {
+            memories.add_tagged(&format!("note-{i}"), "data");
+        }
+        assert_eq!(memories.entries().count(), MAX_MEMORIES);
+        assert!(memories.get_entry("note-0").is_none());
+
+        let old = SystemTime::now() - MEMORIES_TTL - Duration::from_secs(1);
+        memories.restore(vec![(
+            "old".to_string(),
+            storage::Entry::new("data".to_string(), old),
+        )]);
+        assert_eq!(memories.entries().count(), 0);
+    }
+}
@@ -0,0 +1,112 @@
+use anyhow::Result;
+use indexmap::IndexMap;
+use lazy_static::lazy_static;
+use regex::Regex;
+
+lazy_static! {
+    static ref SECRET_REF_PARSER: Regex = Regex::new(r"\$([A-Za-z][A-Za-z0-9_]*)").unwrap();
+    static ref SECRET_NAME_PARSER: Regex = Regex::new(r"^[A-Za-z][A-Za-z0-9_]*$").unwrap();
+}
+
+// what secret values are replaced with in action outputs
+pub(crate) const MASKED: &str = "[SECRET]";
+
+// named values that actions can reference as $NAME, never shown to the model
+#[derive(Clone, Default)]
+pub struct Secrets {
+    values: IndexMap<String, String>,
+}
+
+// never print the values
+impl std::fmt::Debug for Secrets {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.values.keys()).finish()
+    }
+}
+
+impl Secrets {
+    // parse NAME=VALUE definitions, or NAME alone to read the value from the environment
+    pub fn from_definitions(definitions: &[String]) -> Result<Self> {
+        let mut values = IndexMap::new();
+
+        for definition in definitions {
+            let (name, value) = if let Some((name, value)) = definition.split_once('=') {
+                (name.trim().to_string(), value.to_string())
+            } else {
+                let name = definition.trim().to_string();
+                let value = std::env::var(&name)
+                    .map_err(|_| anyhow!("secret '{}' is not set in the environment", &name))?;
+                (name, value)
+            };
+
+            if !SECRET_NAME_PARSER.is_match(&name) {
+                return Err(anyhow!("'{}' is not a valid secret name", name));
+            }
+
+            values.insert(name, value);
+        }
+
+        Ok(Self { values })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.values.keys().map(|s| s.as_str()).collect()
+    }
+
+    // replace $NAME references of known secrets with their values
+    pub fn resolve(&self, data: &str) -> String {
+        SECRET_REF_PARSER
+            .replace_all(data, |caps: &regex::Captures| {
+                if let Some(value) = self.values.get(&caps[1]) {
+                    value.to_string()
+                } else {
+                    caps[0].to_string()
+                }
+            })
+            .to_string()
+    }
+
+    // replace any secret value with a placeholder
+    pub fn mask(&self, data: &str) -> String {
+        let mut masked = data.to_string();
+        for value in self.values.values() {
+            if !value.is_empty() {
+                masked = masked.replace(value, MASKED);
+            }
+        }
+        masked
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_and_mask() {
+        let secrets = Secrets::from_definitions(&["API_TOKEN=s3cr3t".to_string()]).unwrap();
+
+        assert_eq!(
+            secrets.resolve("Bearer $API_TOKEN $OTHER"),
+            "Bearer s3cr3t $OTHER"
+        );
+        assert_eq!(
+            secrets.mask("token s3cr3t rejected"),
+            format!("token {} rejected", MASKED)
+        );
+        assert!(!format!("{:?}", secrets).contains("s3cr3t"));
+    }
+
+    #[test]
+    fn test_invalid_definitions() {
+        assert!(Secrets::from_definitions(&["1TOKEN=x".to_string()]).is_err());
+        assert!(Secrets::from_definitions(&["API-TOKEN=x".to_string()]).is_err());
+        assert!(Secrets::from_definitions(&["API TOKEN=x".to_string()]).is_err());
+        assert!(Secrets::from_definitions(&["API_TOKEN_2=x".to_string()]).is_ok());
+        assert!(Secrets::from_definitions(&["NERVE_SURELY_UNDEFINED_SECRET".to_string()]).is_err());
+    }
+}
@@ -1,23 +1,28 @@
-use std::{ops::Deref /* , time::SystemTime*/};
+use std::{
+    ops::Deref,
+    time::{Duration, SystemTime},
+};
 
 use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
 
 use crate::agent::events::{Event, Sender};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Entry {
-    //pub time: SystemTime,
+    // entries restored from older checkpoints are considered fresh
+    #[serde(default = "SystemTime::now")]
+    pub time: SystemTime,
     pub complete: bool, // for Completion storage
     pub data: String,
 }
 
 impl Entry {
-    pub fn new(data: String) -> Self {
-        //let time = SystemTime::now();
+    pub fn new(data: String, time: SystemTime) -> Self {
         let complete = false;
         Self {
-            /* time ,*/ data,
+            time,
+            data,
             complete,
         }
     }
@@ -34,6 +39,8 @@ pub enum StorageType {
     CurrentPrevious,
     // a list of tasks that can be set as complete
     Completion,
+    // like Completion, but rendered as a checklist with the remaining items count
+    Tasklist,
 }
 
 impl StorageType {
@@ -43,6 +50,7 @@ impl StorageType {
             StorageType::Completion => 1,
             StorageType::Untagged => 2,
             StorageType::Tagged => 3,
+            StorageType::Tasklist => 4,
         }
     }
 }
@@ -56,6 +64,9 @@ pub struct Storage {
     name: String,
     type_: StorageType,
     inner: IndexMap<String, Entry>,
+    max_entries: Option<usize>,
+    ttl: Option<Duration>,
+    clock: fn() -> SystemTime,
 }
 
 impl Deref for Storage {
@@ -71,14 +82,32 @@ impl Storage {
     pub fn new(name: &str, type_: StorageType, events_tx: Sender) -> Self {
         let name = name.to_string();
         let inner = IndexMap::new();
+        let max_entries = None;
+        let ttl = None;
+        let clock = SystemTime::now;
         Self {
             name,
             type_,
             inner,
             events_tx,
+            max_entries,
+            ttl,
+            clock,
         }
     }
 
+    pub fn with_limits(mut self, max_entries: Option<usize>, ttl: Option<Duration>) -> Self {
+        self.max_entries = max_entries;
+        self.ttl = ttl;
+        self
+    }
+
+    #[cfg(test)]
+    pub fn with_clock(mut self, clock: fn() -> SystemTime) -> Self {
+        self.clock = clock;
+        self
+    }
+
     pub fn get_name(&self) -> &str {
         &self.name
     }
@@ -91,11 +120,82 @@ impl Storage {
         self.events_tx.send(event).unwrap();
     }
 
+    fn new_entry(&self, data: &str) -> Entry {
+        Entry::new(data.to_string(), (self.clock)())
+    }
+
+    fn is_expired(&self, entry: &Entry) -> bool {
+        self.ttl.is_some_and(|ttl| {
+            (self.clock)()
+                .duration_since(entry.time)
+                .is_ok_and(|age| age >= ttl)
+        })
+    }
+
+    // entries that are not expired yet, in insertion order
+    pub fn entries(&self) -> impl Iterator<Item = (&String, &Entry)> {
+        self.inner
+            .iter()
+            .filter(|(_, entry)| !self.is_expired(entry))
+    }
+
+    pub fn get_entry(&self, key: &str) -> Option<&Entry> {
+        self.inner.get(key).filter(|entry| !self.is_expired(entry))
+    }
+
+    // next position for list storages, positions are never reused after an eviction
+    fn next_position(&self) -> String {
+        let last = self
+            .inner
+            .keys()
+            .last()
+            .and_then(|key| key.parse::<usize>().ok())
+            .unwrap_or(self.inner.len());
+
+        format!("{}", last + 1)
+    }
+
+    fn remove(&mut self, key: &str) -> Option<Entry> {
+        let old = self.inner.shift_remove(key)?;
+
+        self.on_event(Event::StorageUpdate {
+            storage_name: self.name.to_string(),
+            storage_type: self.type_,
+            key: key.to_string(),
+            prev: Some(old.data.to_string()),
+            new: None,
+        });
+
+        Some(old)
+    }
+
+    // drop the expired entries and then the oldest ones exceeding the maximum size
+    fn evict(&mut self) {
+        let expired: Vec<String> = self
+            .inner
+            .iter()
+            .filter(|(_, entry)| self.is_expired(entry))
+            .map(|(key, _)| key.to_string())
+            .collect();
+        for key in expired {
+            self.remove(&key);
+        }
+
+        if let Some(max_entries) = self.max_entries {
+            while self.inner.len() > max_entries {
+                let oldest = self.inner.keys().next().unwrap().to_string();
+                self.remove(&oldest);
+            }
+        }
+    }
+
     pub fn add_tagged(&mut self, key: &str, data: &str) {
         assert!(matches!(self.type_, StorageType::Tagged));
 
-        self.inner
-            .insert(key.to_string(), Entry::new(data.to_string()));
+        // updating an entry makes it the newest one
+        self.inner.shift_remove(key);
+        let entry = self.new_entry(data);
+        self.inner.insert(key.to_string(), entry);
 
         self.on_event(Event::StorageUpdate {
             storage_name: self.name.to_string(),
@@ -104,35 +204,28 @@ impl Storage {
             prev: None,
             new: Some(data.to_string()),
         });
+
+        self.evict();
     }
 
     pub fn del_tagged(&mut self, key: &str) -> Option<String> {
         assert!(matches!(self.type_, StorageType::Tagged));
-        if let Some(old) = self.inner.shift_remove(key) {
-            self.on_event(Event::StorageUpdate {
-                storage_name: self.name.to_string(),
-                storage_type: self.type_,
-                key: key.to_string(),
-                prev: Some(old.data.to_string()),
-                new: None,
-            });
-
-            Some(old.data)
-        } else {
-            None
-        }
+        self.remove(key).map(|old| old.data)
     }
 
     pub fn get_tagged(&self, key: &str) -> Option<String> {
         assert!(matches!(self.type_, StorageType::Tagged));
-        self.inner.get(key).map(|va| va.data.to_string())
+        self.get_entry(key).map(|va| va.data.to_string())
     }
 
     pub fn add_completion(&mut self, data: &str) {
-        assert!(matches!(self.type_, StorageType::Completion));
-        let tag = format!("{}", self.inner.len() + 1);
-        self.inner
-            .insert(tag.to_string(), Entry::new(data.to_string()));
+        assert!(matches!(
+            self.type_,
+            StorageType::Completion | StorageType::Tasklist
+        ));
+        let tag = self.next_position();
+        let entry = self.new_entry(data);
+        self.inner.insert(tag.to_string(), entry);
 
         self.on_event(Event::StorageUpdate {
             storage_name: self.name.to_string(),
@@ -141,28 +234,24 @@ impl Storage {
             prev: None,
             new: Some(data.to_string()),
         });
+
+        self.evict();
     }
 
     pub fn del_completion(&mut self, pos: usize) -> Option<String> {
-        assert!(matches!(self.type_, StorageType::Completion));
+        assert!(matches!(
+            self.type_,
+            StorageType::Completion | StorageType::Tasklist
+        ));
         let tag = format!("{}", pos);
-        if let Some(old) = self.inner.shift_remove(&tag) {
-            self.on_event(Event::StorageUpdate {
-                storage_name: self.name.to_string(),
-                storage_type: self.type_,
-                key: tag,
-                prev: Some(old.data.to_string()),
-                new: None,
-            });
-
-            Some(old.data)
-        } else {
-            None
-        }
+        self.remove(&tag).map(|old| old.data)
     }
 
     pub fn set_complete(&mut self, pos: usize) -> Option<bool> {
-        assert!(matches!(self.type_, StorageType::Completion));
+        assert!(matches!(
+            self.type_,
+            StorageType::Completion | StorageType::Tasklist
+        ));
         let tag = format!("{}", pos);
         if let Some(entry) = self.inner.get_mut(&tag) {
             let prev = entry.complete;
@@ -183,7 +272,10 @@ impl Storage {
     }
 
     pub fn set_incomplete(&mut self, pos: usize) -> Option<bool> {
-        assert!(matches!(self.type_, StorageType::Completion));
+        assert!(matches!(
+            self.type_,
+            StorageType::Completion | StorageType::Tasklist
+        ));
         let tag = format!("{}", pos);
         if let Some(entry) = self.inner.get_mut(&tag) {
             let prev = entry.complete;
@@ -205,9 +297,9 @@ impl Storage {
 
     pub fn add_untagged(&mut self, data: &str) {
         assert!(matches!(self.type_, StorageType::Untagged));
-        let tag = format!("{}", self.inner.len() + 1);
-        self.inner
-            .insert(tag.to_string(), Entry::new(data.to_string()));
+        let tag = self.next_position();
+        let entry = self.new_entry(data);
+        self.inner.insert(tag.to_string(), entry);
 
         self.on_event(Event::StorageUpdate {
             storage_name: self.name.to_string(),
@@ -216,31 +308,22 @@ impl Storage {
             prev: None,
             new: Some(data.to_string()),
         });
+
+        self.evict();
     }
 
     pub fn del_untagged(&mut self, pos: usize) -> Option<String> {
         assert!(matches!(self.type_, StorageType::Untagged));
         let tag = format!("{}", pos);
-        if let Some(old) = self.inner.shift_remove(&tag) {
-            self.on_event(Event::StorageUpdate {
-                storage_name: self.name.to_string(),
-                storage_type: self.type_,
-                key: tag,
-                prev: Some(old.data.to_string()),
-                new: None,
-            });
-            Some(old.data)
-        } else {
-            None
-        }
+        self.remove(&tag).map(|old| old.data)
     }
 
     pub fn set_current(&mut self, data: &str) {
         assert!(matches!(self.type_, StorageType::CurrentPrevious));
 
         let old_current = self.inner.shift_remove(CURRENT_TAG);
-        self.inner
-            .insert(CURRENT_TAG.to_string(), Entry::new(data.to_string()));
+        let entry = self.new_entry(data);
+        self.inner.insert(CURRENT_TAG.to_string(), entry);
         let prev = if let Some(old_curr) = old_current {
             let data = old_curr.data.to_string();
             self.inner.insert(PREVIOUS_TAG.to_string(), old_curr);
@@ -258,6 +341,11 @@ impl Storage {
         });
     }
 
+    // replace the contents with the ones from a checkpoint
+    pub fn restore(&mut self, entries: Vec<(String, Entry)>) {
+        self.inner = entries.into_iter().collect();
+    }
+
     pub fn clear(&mut self) {
         self.inner.clear();
         self.on_event(Event::StorageUpdate {
@@ -269,3 +357,81 @@ impl Storage {
         });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    use super::*;
+    use crate::agent::events::create_channel;
+
+    static NOW: AtomicU64 = AtomicU64::new(0);
+
+    fn test_clock() -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(NOW.load(Ordering::SeqCst))
+    }
+
+    #[test]
+    fn test_eviction_past_max_entries() {
+        let (tx, _rx) = create_channel();
+        let mut storage =
+            Storage::new("memories", StorageType::Tagged, tx.clone()).with_limits(Some(2), None);
+
+        storage.add_tagged("a", "1");
+        storage.add_tagged("b", "2");
+        // updating makes it the newest
+        storage.add_tagged("a", "3");
+        storage.add_tagged("c", "4");
+
+        assert_eq!(storage.keys().collect::<Vec<_>>(), vec!["a", "c"]);
+        assert_eq!(storage.get_tagged("a"), Some("3".to_string()));
+
+        let mut storage =
+            Storage::new("list", StorageType::Untagged, tx).with_limits(Some(2), None);
+        for data in ["x", "y", "z", "w"] {
+            storage.add_untagged(data);
+        }
+
+        // positions are not reused after the oldest entries are evicted
+        assert_eq!(storage.keys().collect::<Vec<_>>(), vec!["3", "4"]);
+        assert_eq!(storage.get("4").unwrap().data, "w");
+    }
+
+    #[test]
+    fn test_ttl_expiry() {
+        let (tx, _rx) = create_channel();
+        let mut storage = Storage::new("memories", StorageType::Tagged, tx)
+            .with_limits(None, Some(Duration::from_secs(60)))
+            .with_clock(test_clock);
+
+        NOW.store(1000, Ordering::SeqCst);
+        storage.add_tagged("old", "first");
+        NOW.store(1030, Ordering::SeqCst);
+        storage.add_tagged("new", "second");
+
+        NOW.store(1059, Ordering::SeqCst);
+        assert_eq!(storage.entries().count(), 2);
+
+        // expired entries are hidden right away ...
+        NOW.store(1060, Ordering::SeqCst);
+        assert_eq!(
+            storage.entries().map(|(key, _)| key).collect::<Vec<_>>(),
+            vec!["new"]
+        );
+        assert_eq!(storage.get_tagged("old"), None);
+        assert_eq!(
+            crate::agent::serialization::xml::serialize::storage(&storage),
+            "<memories>\n  - new=second\n</memories>"
+        );
+
+        // ... and dropped on the next write
+        storage.add_tagged("newer", "third");
+        assert_eq!(storage.keys().collect::<Vec<_>>(), vec!["new", "newer"]);
+
+        NOW.store(2000, Ordering::SeqCst);
+        assert_eq!(
+            crate::agent::serialization::xml::serialize::storage(&storage),
+            ""
+        );
+    }
+}
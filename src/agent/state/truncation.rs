@@ -0,0 +1,98 @@
+// marker placed where the content has been cut
+pub(crate) const ELISION: &str = "\n[... truncated ...]\n";
+
+// which part of an oversized action result to keep
+#[derive(Debug, Default, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum Truncation {
+    // keep the beginning
+    #[default]
+    Head,
+    // keep the end
+    Tail,
+    // keep the beginning and the end
+    HeadTail,
+}
+
+// returns the largest char boundary that is <= index
+fn floor_boundary(data: &str, mut index: usize) -> usize {
+    while !data.is_char_boundary(index) {
+        index -= 1;
+    }
+    index
+}
+
+// returns the smallest char boundary that is >= index
+fn ceil_boundary(data: &str, mut index: usize) -> usize {
+    while !data.is_char_boundary(index) {
+        index += 1;
+    }
+    index
+}
+
+impl Truncation {
+    // cut data to at most max_length bytes (elision marker excluded), 0 for no limit
+    pub fn apply(&self, data: String, max_length: usize) -> String {
+        if max_length == 0 || data.len() <= max_length {
+            return data;
+        }
+
+        match self {
+            Self::Head => format!("{}{}", &data[..floor_boundary(&data, max_length)], ELISION),
+            Self::Tail => format!(
+                "{}{}",
+                ELISION,
+                &data[ceil_boundary(&data, data.len() - max_length)..]
+            ),
+            Self::HeadTail => {
+                let head = floor_boundary(&data, max_length / 2);
+                let tail = ceil_boundary(&data, data.len() - (max_length - max_length / 2));
+                format!("{}{}{}", &data[..head], ELISION, &data[tail..])
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_no_truncation() {
+        assert_eq!(Truncation::Head.apply("hello".to_string(), 0), "hello");
+        assert_eq!(Truncation::Tail.apply("hello".to_string(), 5), "hello");
+    }
+
+    #[test]
+    fn test_strategies() {
+        let data = "0123456789".to_string();
+
+        assert_eq!(
+            Truncation::Head.apply(data.clone(), 4),
+            format!("0123{}", ELISION)
+        );
+        assert_eq!(
+            Truncation::Tail.apply(data.clone(), 4),
+            format!("{}6789", ELISION)
+        );
+        assert_eq!(
+            Truncation::HeadTail.apply(data, 4),
+            format!("01{}89", ELISION)
+        );
+    }
+
+    #[test]
+    fn test_char_boundaries() {
+        // each character is two bytes long
+        let data = "ààààà".to_string();
+
+        assert_eq!(
+            Truncation::Head.apply(data.clone(), 3),
+            format!("à{}", ELISION)
+        );
+        assert_eq!(
+            Truncation::Tail.apply(data.clone(), 3),
+            format!("{}à", ELISION)
+        );
+        assert_eq!(Truncation::HeadTail.apply(data, 3), format!("{}à", ELISION));
+    }
+}
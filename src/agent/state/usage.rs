@@ -0,0 +1,141 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use serde::{Deserialize, Serialize};
+
+use crate::agent::Invocation;
+
+// number of recent invocations kept for loop detection
+pub(crate) const DEFAULT_CAPACITY: usize = 32;
+
+// stable signature of an invocation, attributes are sorted by name
+pub(crate) fn signature(invocation: &Invocation) -> String {
+    let mut sig = invocation.action.to_string();
+    if let Some(attrs) = &invocation.attributes {
+        let mut names: Vec<&String> = attrs.keys().collect();
+        names.sort();
+        for name in names {
+            sig += &format!(" {}={:?}", name, attrs.get(name).unwrap());
+        }
+    }
+    if let Some(payload) = &invocation.payload {
+        sig += &format!(" {:?}", payload.trim());
+    }
+    sig
+}
+
+// recently executed invocations and per action usage counters, this is part of the
+// serializable state so that a resumed run doesn't repeat what it already tried
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Usage {
+    capacity: usize,
+    // ring buffer of the most recent invocation signatures
+    recent: VecDeque<String>,
+    // number of executions per action name
+    counters: BTreeMap<String, usize>,
+}
+
+impl Default for Usage {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+impl Usage {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            recent: VecDeque::with_capacity(capacity),
+            counters: BTreeMap::new(),
+        }
+    }
+
+    pub fn record(&mut self, invocation: &Invocation) {
+        if self.capacity > 0 {
+            if self.recent.len() == self.capacity {
+                self.recent.pop_front();
+            }
+            self.recent.push_back(signature(invocation));
+        }
+
+        *self
+            .counters
+            .entry(invocation.action.to_string())
+            .or_insert(0) += 1;
+    }
+
+    // number of times the invocation was executed among the last `window` ones
+    pub fn repetitions(&self, invocation: &Invocation, window: usize) -> usize {
+        let sig = signature(invocation);
+        self.recent
+            .iter()
+            .rev()
+            .take(window)
+            .filter(|recent| **recent == sig)
+            .count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[test]
+    fn test_usage_roundtrip() {
+        let mut usage = Usage::new(2);
+        let mut attributes = HashMap::new();
+        attributes.insert("b".to_string(), "2".to_string());
+        attributes.insert("a".to_string(), "1".to_string());
+
+        usage.record(&Invocation::new("first".to_string(), None, None));
+        usage.record(&Invocation::new(
+            "second".to_string(),
+            Some(attributes),
+            Some("payload".to_string()),
+        ));
+        usage.record(&Invocation::new("first".to_string(), None, None));
+
+        // oldest entry dropped from the ring buffer, counters are kept
+        assert_eq!(
+            usage.recent,
+            VecDeque::from(vec![
+                "second a=\"1\" b=\"2\" \"payload\"".to_string(),
+                "first".to_string()
+            ])
+        );
+        assert_eq!(usage.counters.get("first"), Some(&2));
+        assert_eq!(usage.counters.get("second"), Some(&1));
+        assert_eq!(usage.counters.get("none"), None);
+
+        let restored: Usage =
+            serde_json::from_str(&serde_json::to_string(&usage).unwrap()).unwrap();
+        assert_eq!(restored.recent, usage.recent);
+        assert_eq!(restored.counters, usage.counters);
+    }
+
+    #[test]
+    fn test_usage_repetitions() {
+        let a = Invocation::new("read-file".to_string(), None, Some("a.txt".to_string()));
+        let b = Invocation::new("read-file".to_string(), None, Some("b.txt".to_string()));
+        let mut usage = Usage::default();
+
+        // A, B, A, B
+        for _ in 0..2 {
+            usage.record(&a);
+            usage.record(&b);
+        }
+
+        assert_eq!(usage.repetitions(&a, 4), 2);
+        assert_eq!(usage.repetitions(&b, 4), 2);
+        // only the most recent entries are considered
+        assert_eq!(usage.repetitions(&a, 2), 1);
+        assert_eq!(
+            usage.repetitions(
+                &Invocation::new("read-file".to_string(), None, Some("c.txt".to_string())),
+                4
+            ),
+            0
+        );
+    }
+}
@@ -1,24 +1,114 @@
 use std::time::Duration;
 
 use anyhow::Result;
+use serde::Deserialize;
 
-use super::namespaces::Namespace;
+use super::{
+    namespaces::{env, search, shell, Namespace},
+    rag,
+    state::redaction,
+};
 
 pub(crate) mod tasklet;
 pub(crate) mod variables;
 
 // TODO: comment the shit out of everything.
 
+// which action examples to include in the system prompt
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum ActionExamples {
+    // all or none
+    Enabled(bool),
+    // only for the actions with these names
+    Only(Vec<String>),
+}
+
+impl Default for ActionExamples {
+    fn default() -> Self {
+        Self::Enabled(true)
+    }
+}
+
+// namespaces to enable and disable once the run reaches a step, for tasks whose capabilities
+// change over time
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Phase {
+    pub step: usize,
+    #[serde(default)]
+    pub using: Vec<String>,
+    #[serde(default)]
+    pub not_using: Vec<String>,
+}
+
+// merge multiple sources of guidance in order, skipping empty and duplicate lines
+pub fn merge_guidance(sources: Vec<Vec<String>>) -> Vec<String> {
+    let mut seen = vec![];
+    let mut merged = vec![];
+
+    for line in sources.into_iter().flatten() {
+        let line = line.trim().to_string();
+        let normalized = line
+            .to_lowercase()
+            .split_whitespace()
+            .collect::<Vec<&str>>()
+            .join(" ")
+            .trim_end_matches('.')
+            .to_string();
+
+        if !normalized.is_empty() && !seen.contains(&normalized) {
+            seen.push(normalized);
+            merged.push(line);
+        }
+    }
+
+    merged
+}
+
 pub trait Task: std::fmt::Debug + Send + Sync {
     fn to_system_prompt(&self) -> Result<String>;
     fn to_prompt(&self) -> Result<String>;
     fn get_functions(&self) -> Vec<Namespace>;
 
+    // used to identify the task in checkpoints
+    fn get_name(&self) -> Option<String> {
+        None
+    }
+
     fn get_timeout(&self) -> Option<Duration> {
         None
     }
 
-    fn get_rag_config(&self) -> Option<mini_rag::Configuration> {
+    // maximum duration of the whole run
+    fn get_max_duration(&self) -> Option<Duration> {
+        None
+    }
+
+    fn get_rag_config(&self) -> Option<rag::Configuration> {
+        None
+    }
+
+    fn get_redaction_config(&self) -> Option<redaction::Configuration> {
+        None
+    }
+
+    // filesystem actions can only access paths inside this folder
+    fn get_jail(&self) -> Option<String> {
+        None
+    }
+
+    // programs the shell actions can execute
+    fn get_shell_config(&self) -> Option<shell::Configuration> {
+        None
+    }
+
+    // environment variables the env actions can read
+    fn get_env_config(&self) -> Option<env::Configuration> {
+        None
+    }
+
+    // provider used by the search actions
+    fn get_search_config(&self) -> Option<search::Configuration> {
         None
     }
 
@@ -34,6 +124,19 @@ pub trait Task: std::fmt::Debug + Send + Sync {
         None
     }
 
+    // namespaces removed from the ones in use
+    fn disabled_namespaces(&self) -> Option<Vec<String>> {
+        None
+    }
+
+    fn phases(&self) -> Vec<Phase> {
+        vec![]
+    }
+
+    fn show_action_example(&self, _action_name: &str) -> bool {
+        true
+    }
+
     fn base_guidance(&self) -> Result<Vec<String>> {
         // basic rules to extend
         Ok(include_str!("basic_guidance.prompt")
@@ -43,3 +146,22 @@ pub trait Task: std::fmt::Debug + Send + Sync {
             .collect())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_guidance() {
+        let merged = merge_guidance(vec![
+            vec!["Be concise.".to_string(), "".to_string()],
+            vec![
+                "be   concise".to_string(),
+                "Use full paths.".to_string(),
+                "  Be concise.  ".to_string(),
+            ],
+        ]);
+
+        assert_eq!(merged, vec!["Be concise.", "Use full paths."]);
+    }
+}
@@ -11,12 +11,15 @@ use serde::Deserialize;
 use serde_trim::*;
 use simple_home_dir::home_dir;
 
-use super::{variables::interpolate_variables, Task};
+use super::{merge_guidance, variables::interpolate_variables, ActionExamples, Phase, Task};
 use crate::{
     agent::{
-        namespaces::{Action, Namespace},
-        state::SharedState,
-        task::variables::{parse_pre_defined_values, parse_variable_expr},
+        namespaces::{self, env, search, shell, Action, Namespace},
+        rag,
+        state::{redaction, SharedState},
+        task::variables::{
+            parse_defines, parse_pre_defined_values, parse_variable_expr, substitute,
+        },
     },
     cli,
 };
@@ -40,6 +43,7 @@ pub struct TaskletAction {
     args: Option<HashMap<String, String>>,
     example_payload: Option<String>,
     timeout: Option<String>,
+    cost: Option<u32>,
     #[serde(deserialize_with = "string_trim")]
     tool: String,
 }
@@ -62,6 +66,10 @@ impl Action for TaskletAction {
         self.args.clone()
     }
 
+    fn cost(&self) -> u32 {
+        self.cost.unwrap_or(1)
+    }
+
     fn timeout(&self) -> Option<Duration> {
         if let Some(timeout) = &self.timeout {
             if let Ok(tm) = timeout.parse::<DurationString>() {
@@ -117,22 +125,27 @@ impl Action for TaskletAction {
             cmd.arg(payload);
         }
 
+        // secrets have already been resolved, don't leak them to the logs
+        let secrets = state.lock().await.get_secrets().clone();
+
         log::info!(
             "{}{}{}",
             self.name.bold(),
-            if payload.is_some() {
-                format!(" {}", payload.as_ref().unwrap().red())
+            if let Some(payload) = &payload {
+                format!(" {}", secrets.mask(payload).red())
             } else {
                 "".to_string()
             },
-            if attributes.is_some() {
+            if let Some(attributes) = &attributes {
                 format!(
                     " {}",
                     attributes
-                        .as_ref()
-                        .unwrap()
                         .iter()
-                        .map(|(key, value)| format!("{key}{}{}", "=".dimmed(), value.bright_blue()))
+                        .map(|(key, value)| format!(
+                            "{key}{}{}",
+                            "=".dimmed(),
+                            secrets.mask(value).bright_blue()
+                        ))
                         .collect::<Vec<String>>()
                         .join(" ")
                 )
@@ -141,7 +154,7 @@ impl Action for TaskletAction {
             },
         );
 
-        log::debug!("! {:?}", &cmd);
+        log::debug!("! {}", secrets.mask(&format!("{:?}", &cmd)));
 
         let output = cmd.output();
         if let Ok(output) = output {
@@ -149,6 +162,7 @@ impl Action for TaskletAction {
             let out = String::from_utf8_lossy(&output.stdout).trim().to_string();
 
             if !err.is_empty() {
+                let err = secrets.mask(&err);
                 log::error!(
                     "{}",
                     if err.len() > self.max_shown_output {
@@ -164,6 +178,7 @@ impl Action for TaskletAction {
             }
 
             if !out.is_empty() {
+                let out = secrets.mask(&out);
                 let lines = if out.len() > self.max_shown_output {
                     let end = out
                         .char_indices()
@@ -243,16 +258,40 @@ pub struct Tasklet {
     #[serde(deserialize_with = "string_trim")]
     system_prompt: String,
     pub prompt: Option<String>,
-    pub rag: Option<mini_rag::Configuration>,
+    pub rag: Option<rag::Configuration>,
+    redact: Option<redaction::Configuration>,
+    // folder the filesystem actions are confined to
+    jail: Option<String>,
+    shell: Option<shell::Configuration>,
+    env: Option<env::Configuration>,
+    search: Option<search::Configuration>,
     timeout: Option<String>,
+    // time budget of the run, overridden by --max-duration
+    max_duration: Option<String>,
     using: Option<Vec<String>>,
+    // namespaces to remove from the ones in use
+    not_using: Option<Vec<String>>,
+    // namespaces enabled or disabled as the run goes on
+    phases: Option<Vec<Phase>>,
     guidance: Option<Vec<String>>,
+    // put task specific guidance before the basic one
+    #[serde(default)]
+    prioritize_guidance: bool,
+    // additional guidance provided by the operator
+    #[serde(skip_deserializing, skip_serializing)]
+    extra_guidance: Vec<String>,
+    // values of the {{name}} placeholders of the prompts
+    #[serde(skip_deserializing, skip_serializing)]
+    variables: HashMap<String, String>,
+    #[serde(default)]
+    action_examples: ActionExamples,
     functions: Option<Vec<FunctionGroup>>,
 }
 
 impl Tasklet {
     pub fn from_path(path: &str, defines: &Vec<String>) -> Result<Self> {
         parse_pre_defined_values(defines)?;
+        let variables = parse_defines(defines)?;
 
         let mut ppath = PathBuf::from_str(path)?;
 
@@ -266,11 +305,15 @@ impl Tasklet {
             }
         }
 
-        if ppath.is_dir() {
+        let mut tasklet = if ppath.is_dir() {
             Self::from_folder(ppath.to_str().unwrap())
         } else {
             Self::from_yaml_file(ppath.to_str().unwrap())
-        }
+        }?;
+
+        tasklet.variables = variables;
+
+        Ok(tasklet)
     }
 
     fn from_folder(path: &str) -> Result<Self> {
@@ -295,7 +338,8 @@ impl Tasklet {
             };
 
             let yaml = std::fs::read_to_string(&canon)?;
-            let mut tasklet: Self = serde_yaml::from_str(&yaml)?;
+            let mut tasklet = Self::from_yaml(&yaml)
+                .map_err(|e| anyhow!("invalid task {}: {}", canon.display(), e))?;
 
             tasklet.folder = if let Some(folder) = tasklet_parent_folder.to_str() {
                 folder.to_string()
@@ -320,6 +364,117 @@ impl Tasklet {
         }
     }
 
+    fn from_yaml(yaml: &str) -> Result<Self> {
+        // serde_yaml errors already point to the offending key
+        let tasklet: Self = serde_yaml::from_str(yaml)?;
+        tasklet.validate()?;
+        Ok(tasklet)
+    }
+
+    // catch the mistakes the yaml schema alone can't, reporting the offending key
+    fn validate(&self) -> Result<()> {
+        if self.system_prompt.is_empty() {
+            return Err(anyhow!("system_prompt: can't be empty"));
+        }
+
+        for (key, value) in [
+            ("timeout", &self.timeout),
+            ("max_duration", &self.max_duration),
+        ] {
+            if let Some(value) = value {
+                if value.parse::<DurationString>().is_err() {
+                    return Err(anyhow!(
+                        "{}: can't parse '{}' as duration string",
+                        key,
+                        value
+                    ));
+                }
+            }
+        }
+
+        if let Some(using) = &self.using {
+            for (i, name) in using.iter().enumerate() {
+                if name != "*" && !namespaces::NAMESPACES.contains_key(name) {
+                    return Err(anyhow!("using[{}]: no namespace '{}' defined", i, name));
+                }
+            }
+        }
+
+        for (i, name) in self.not_using.iter().flatten().enumerate() {
+            if !namespaces::NAMESPACES.contains_key(name) {
+                return Err(anyhow!("not_using[{}]: no namespace '{}' defined", i, name));
+            }
+        }
+
+        for (i, phase) in self.phases.iter().flatten().enumerate() {
+            if phase.step == 0 {
+                return Err(anyhow!("phases[{}].step: steps start from 1", i));
+            }
+            for (key, names) in [("using", &phase.using), ("not_using", &phase.not_using)] {
+                for (j, name) in names.iter().enumerate() {
+                    if !namespaces::NAMESPACES.contains_key(name) {
+                        return Err(anyhow!(
+                            "phases[{}].{}[{}]: no namespace '{}' defined",
+                            i,
+                            key,
+                            j,
+                            name
+                        ));
+                    }
+                }
+            }
+        }
+
+        let mut names = HashMap::new();
+        for (i, group) in self.functions.iter().flatten().enumerate() {
+            if group.name.is_empty() {
+                return Err(anyhow!("functions[{}].name: can't be empty", i));
+            }
+
+            for (j, action) in group.actions.iter().enumerate() {
+                let key = format!("functions[{}].actions[{}]", i, j);
+                if action.name.is_empty() {
+                    return Err(anyhow!("{}.name: can't be empty", key));
+                }
+                if action.tool.is_empty() {
+                    return Err(anyhow!("{}.tool: can't be empty", key));
+                }
+                if let Some(timeout) = &action.timeout {
+                    if timeout.parse::<DurationString>().is_err() {
+                        return Err(anyhow!(
+                            "{}.timeout: can't parse '{}' as duration string",
+                            key,
+                            timeout
+                        ));
+                    }
+                }
+                if let Some(other) = names.insert(action.name.to_string(), key.to_string()) {
+                    return Err(anyhow!(
+                        "{}.name: action '{}' already defined by {}",
+                        key,
+                        action.name,
+                        other
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn add_guidance_from_file(&mut self, path: &str) -> Result<()> {
+        let data = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("could not read guidance file {path}: {e}"))?;
+
+        self.extra_guidance.extend(
+            data.split('\n')
+                .map(|l| l.trim().trim_start_matches("- ").to_string())
+                .filter(|l| !l.is_empty()),
+        );
+
+        Ok(())
+    }
+
     pub fn prepare(&mut self, user_prompt: &Option<String>) -> Result<()> {
         if self.prompt.is_none() {
             self.prompt = Some(if let Some(prompt) = &user_prompt {
@@ -352,11 +507,19 @@ impl Tasklet {
             }
         }
 
+        // fail now rather than at the first step if a placeholder has no value
+        self.to_system_prompt()?;
+        self.to_prompt()?;
+
         Ok(())
     }
 }
 
 impl Task for Tasklet {
+    fn get_name(&self) -> Option<String> {
+        Some(self.name.to_string())
+    }
+
     fn get_timeout(&self) -> Option<std::time::Duration> {
         if let Some(timeout) = &self.timeout {
             if let Ok(tm) = timeout.parse::<DurationString>() {
@@ -368,17 +531,48 @@ impl Task for Tasklet {
         None
     }
 
-    fn get_rag_config(&self) -> Option<mini_rag::Configuration> {
+    fn get_max_duration(&self) -> Option<std::time::Duration> {
+        if let Some(max_duration) = &self.max_duration {
+            if let Ok(tm) = max_duration.parse::<DurationString>() {
+                return Some(*tm);
+            } else {
+                log::error!("can't parse '{}' as duration string", max_duration);
+            }
+        }
+        None
+    }
+
+    fn get_rag_config(&self) -> Option<rag::Configuration> {
         self.rag.clone()
     }
 
+    fn get_redaction_config(&self) -> Option<redaction::Configuration> {
+        self.redact.clone()
+    }
+
+    fn get_jail(&self) -> Option<String> {
+        self.jail.clone()
+    }
+
+    fn get_shell_config(&self) -> Option<shell::Configuration> {
+        self.shell.clone()
+    }
+
+    fn get_env_config(&self) -> Option<env::Configuration> {
+        self.env.clone()
+    }
+
+    fn get_search_config(&self) -> Option<search::Configuration> {
+        self.search.clone()
+    }
+
     fn to_system_prompt(&self) -> Result<String> {
-        Ok(self.system_prompt.to_string())
+        substitute(&self.system_prompt, &self.variables)
     }
 
     fn to_prompt(&self) -> Result<String> {
         if let Some(prompt) = &self.prompt {
-            Ok(prompt.to_string())
+            substitute(prompt, &self.variables)
         } else {
             Err(anyhow!("prompt not specified"))
         }
@@ -388,10 +582,32 @@ impl Task for Tasklet {
         self.using.clone()
     }
 
+    fn disabled_namespaces(&self) -> Option<Vec<String>> {
+        self.not_using.clone()
+    }
+
+    fn phases(&self) -> Vec<Phase> {
+        self.phases.clone().unwrap_or_default()
+    }
+
+    fn show_action_example(&self, action_name: &str) -> bool {
+        match &self.action_examples {
+            ActionExamples::Enabled(enabled) => *enabled,
+            ActionExamples::Only(names) => names.iter().any(|name| name == action_name),
+        }
+    }
+
     fn guidance(&self) -> Result<Vec<String>> {
         let base = self.base_guidance()?;
+        let custom = self.guidance.as_ref().unwrap_or(&vec![]).clone();
+        let extra = self.extra_guidance.clone();
+
         // extend the set of basic rules
-        Ok([base, self.guidance.as_ref().unwrap_or(&vec![]).clone()].concat())
+        Ok(merge_guidance(if self.prioritize_guidance {
+            vec![custom, extra, base]
+        } else {
+            vec![base, custom, extra]
+        }))
     }
 
     fn get_functions(&self) -> Vec<Namespace> {
@@ -406,3 +622,125 @@ impl Task for Tasklet {
         groups
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TASK: &str = r#"
+system_prompt: You are a helpful assistant.
+prompt: list the files
+using:
+  - '*'
+  - data
+guidance:
+  - Always use absolute paths.
+functions:
+  - name: Commands
+    description: Run commands.
+    actions:
+      - name: list
+        description: To list the files of a folder.
+        example_payload: /tmp
+        tool: ls -la
+"#;
+
+    #[test]
+    fn test_from_yaml() {
+        let tasklet = Tasklet::from_yaml(TASK).unwrap();
+
+        assert_eq!(
+            tasklet.to_system_prompt().unwrap(),
+            "You are a helpful assistant."
+        );
+        assert_eq!(tasklet.to_prompt().unwrap(), "list the files");
+        assert_eq!(
+            tasklet.namespaces().unwrap(),
+            vec!["*".to_string(), "data".to_string()]
+        );
+        assert!(tasklet
+            .guidance()
+            .unwrap()
+            .contains(&"Always use absolute paths.".to_string()));
+
+        let functions = tasklet.get_functions();
+        assert_eq!(functions.len(), 1);
+        assert_eq!(functions[0].name, "Commands");
+        assert_eq!(functions[0].actions[0].name(), "list");
+    }
+
+    #[test]
+    fn test_prompt_variables() {
+        let mut tasklet = Tasklet::from_yaml(
+            "system_prompt: You are testing {{target}}.\nprompt: scan {{target}}{{ports?}}",
+        )
+        .unwrap();
+        assert!(tasklet.to_prompt().is_err());
+
+        tasklet.variables = parse_defines(&vec!["target=10.0.0.1".to_string()]).unwrap();
+        assert_eq!(
+            tasklet.to_system_prompt().unwrap(),
+            "You are testing 10.0.0.1."
+        );
+        assert_eq!(tasklet.to_prompt().unwrap(), "scan 10.0.0.1");
+    }
+
+    #[test]
+    fn test_examples_are_valid() {
+        let examples = concat!(env!("CARGO_MANIFEST_DIR"), "/examples/*/task.yml");
+        for path in glob::glob(examples).unwrap().flatten() {
+            let yaml = std::fs::read_to_string(&path).unwrap();
+            if let Err(e) = Tasklet::from_yaml(&yaml) {
+                panic!("{}: {}", path.display(), e);
+            }
+        }
+    }
+
+    #[test]
+    fn test_action_examples() {
+        let tasklet = Tasklet::from_yaml(TASK).unwrap();
+        assert!(tasklet.show_action_example("list"));
+
+        let tasklet = Tasklet::from_yaml(&format!("{}action_examples: false\n", TASK)).unwrap();
+        assert!(!tasklet.show_action_example("list"));
+
+        let tasklet =
+            Tasklet::from_yaml(&format!("{}action_examples: [save-memory]\n", TASK)).unwrap();
+        assert!(tasklet.show_action_example("save-memory"));
+        assert!(!tasklet.show_action_example("list"));
+    }
+
+    #[test]
+    fn test_from_yaml_errors() {
+        let error = |yaml: &str| Tasklet::from_yaml(yaml).unwrap_err().to_string();
+
+        assert!(error("prompt: hi").contains("system_prompt"));
+        assert!(error("system_prompt: hi\nusing: ['*', 'nope']").contains("using[1]"));
+        assert!(error("system_prompt: hi\nnot_using: ['nope']").contains("not_using[0]"));
+        assert!(
+            error("system_prompt: hi\nphases:\n  - step: 3\n    using: [shell, nope]")
+                .contains("phases[0].using[1]")
+        );
+        assert!(error("system_prompt: hi\nphases:\n  - step: 0").contains("phases[0].step"));
+        assert!(error("system_prompt: hi\nmax_duration: forever")
+            .contains("max_duration: can't parse 'forever' as duration string"));
+        assert!(error("system_prompt: hi\ntimeout: later").starts_with("timeout:"));
+        assert_eq!(
+            Tasklet::from_yaml("system_prompt: hi\nmax_duration: 2m")
+                .unwrap()
+                .get_max_duration(),
+            Some(std::time::Duration::from_secs(120))
+        );
+        assert!(error(&TASK.replace("tool: ls -la", "tool: ''"))
+            .contains("functions[0].actions[0].tool"));
+        assert!(
+            error(&TASK.replace("example_payload: /tmp", "timeout: soon"))
+                .contains("functions[0].actions[0].timeout")
+        );
+        // missing required fields are reported by serde_yaml with their path
+        assert!(
+            error(&TASK.replace("description: To list the files of a folder.", ""))
+                .contains("functions[0].actions[0]")
+        );
+    }
+}
@@ -11,24 +11,61 @@ lazy_static! {
     static ref VAR_CACHE: Mutex<HashMap<String, String>> = Mutex::new(HashMap::new());
     static ref VAR_PARSER: Regex =
         Regex::new(r"(?m)(\$[A-Za-z][A-Za-z0-9_]+)(\|\|[^\s]+)?").unwrap();
+    // {{name}} or {{name?}} for optional ones
+    static ref PLACEHOLDER_PARSER: Regex =
+        Regex::new(r"\{\{\s*([A-Za-z_][A-Za-z0-9_]*)(\?)?\s*\}\}").unwrap();
 }
 
-pub(crate) fn parse_pre_defined_values(defines: &Vec<String>) -> Result<()> {
+pub(crate) fn parse_defines(defines: &Vec<String>) -> Result<HashMap<String, String>> {
+    let mut variables = HashMap::new();
     for keyvalue in defines {
         let parts: Vec<&str> = keyvalue.splitn(2, '=').collect();
         if parts.len() != 2 {
             return Err(anyhow!("can't parse {keyvalue}, syntax is: key=value"));
         }
 
-        VAR_CACHE
-            .lock()
-            .unwrap()
-            .insert(parts[0].to_owned(), parts[1].to_owned());
+        variables.insert(parts[0].to_owned(), parts[1].to_owned());
     }
 
+    Ok(variables)
+}
+
+pub(crate) fn parse_pre_defined_values(defines: &Vec<String>) -> Result<()> {
+    VAR_CACHE.lock().unwrap().extend(parse_defines(defines)?);
+
     Ok(())
 }
 
+// replace the {{name}} placeholders with their values, unresolved ones are an error unless
+// marked as optional with {{name?}}, in which case they're removed
+pub(crate) fn substitute(template: &str, variables: &HashMap<String, String>) -> Result<String> {
+    let mut missing = vec![];
+    let substituted = PLACEHOLDER_PARSER.replace_all(template, |caps: &regex::Captures| {
+        let name = caps.get(1).unwrap().as_str();
+        if let Some(value) = variables.get(name) {
+            value.to_string()
+        } else {
+            if caps.get(2).is_none() {
+                missing.push(name.to_string());
+            }
+            "".to_string()
+        }
+    });
+
+    if missing.is_empty() {
+        Ok(substituted.to_string())
+    } else {
+        Err(anyhow!(
+            "no value for {}, set with --define name=value",
+            missing
+                .iter()
+                .map(|name| format!("{{{{{}}}}}", name))
+                .collect::<Vec<String>>()
+                .join(", ")
+        ))
+    }
+}
+
 pub(crate) fn interpolate_variables(expr: &str) -> Result<String> {
     let matches = VAR_PARSER.captures_iter(expr);
     let mut interpolated = expr.to_string();
@@ -79,3 +116,25 @@ pub(crate) fn parse_variable_expr(expr: &str) -> Result<(String, String)> {
 
     Ok((var_name.to_string(), var_value))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_substitute() {
+        let variables = parse_defines(&vec!["target=10.0.0.1".to_string()]).unwrap();
+
+        assert_eq!(
+            substitute("scan {{target}} and {{ target }}{{port?}}", &variables).unwrap(),
+            "scan 10.0.0.1 and 10.0.0.1"
+        );
+        assert_eq!(
+            substitute("no placeholders", &variables).unwrap(),
+            "no placeholders"
+        );
+
+        let error = substitute("scan {{target}}:{{port}}", &variables).unwrap_err();
+        assert!(error.to_string().contains("{{port}}"));
+    }
+}
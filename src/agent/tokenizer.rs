@@ -0,0 +1,186 @@
+use std::sync::Arc;
+
+use lazy_static::lazy_static;
+use tiktoken_rs::{
+    tokenizer::{get_tokenizer, Tokenizer as TiktokenType},
+    CoreBPE,
+};
+
+lazy_static! {
+    // used to chunk documents by tokens
+    pub(crate) static ref CL100K: Arc<dyn Tokenizer> = Arc::new(Tiktoken::cl100k_base());
+}
+
+// measures text in tokens, used to size chunks and context budgets
+pub trait Tokenizer: Send + Sync {
+    fn name(&self) -> &str;
+
+    fn count(&self, text: &str) -> usize;
+
+    // split text in chunks of chunk_size tokens where consecutive chunks share chunk_overlap
+    // tokens, chunk_overlap must be smaller than chunk_size
+    fn chunks(&self, text: &str, chunk_size: usize, chunk_overlap: usize) -> Vec<String>;
+}
+
+impl std::fmt::Debug for dyn Tokenizer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Tokenizer({})", self.name())
+    }
+}
+
+// characters per token of the estimator
+const CHARS_PER_TOKEN: usize = 4;
+
+// rough estimate for models without a known tokenizer, about four characters per token
+#[derive(Debug, Default)]
+pub struct Estimator {}
+
+impl Tokenizer for Estimator {
+    fn name(&self) -> &str {
+        "estimate"
+    }
+
+    fn count(&self, text: &str) -> usize {
+        text.chars().count().div_ceil(CHARS_PER_TOKEN)
+    }
+
+    fn chunks(&self, text: &str, chunk_size: usize, chunk_overlap: usize) -> Vec<String> {
+        let chunk_size = chunk_size * CHARS_PER_TOKEN;
+        let chunk_overlap = chunk_overlap * CHARS_PER_TOKEN;
+        let chars = text.chars().collect::<Vec<char>>();
+
+        let mut chunks = vec![];
+        let mut start = 0;
+        while start < chars.len() {
+            let end = (start + chunk_size).min(chars.len());
+            chunks.push(chars[start..end].iter().collect::<String>());
+            if end == chars.len() {
+                break;
+            }
+            start = end - chunk_overlap;
+        }
+
+        chunks
+    }
+}
+
+// byte pair encoding of the OpenAI models
+pub struct Tiktoken {
+    name: String,
+    bpe: CoreBPE,
+}
+
+impl Tiktoken {
+    pub fn cl100k_base() -> Self {
+        Self {
+            name: "cl100k_base".to_string(),
+            bpe: tiktoken_rs::cl100k_base().unwrap(),
+        }
+    }
+
+    // the tokenizer of the model, if it's a known one
+    pub fn for_model(model: &str) -> Option<Self> {
+        let tokenizer = get_tokenizer(model)?;
+        let name = match tokenizer {
+            TiktokenType::O200kBase => "o200k_base",
+            TiktokenType::Cl100kBase => "cl100k_base",
+            TiktokenType::P50kBase => "p50k_base",
+            TiktokenType::R50kBase => "r50k_base",
+            TiktokenType::P50kEdit => "p50k_edit",
+            TiktokenType::Gpt2 => "gpt2",
+        };
+
+        Some(Self {
+            name: name.to_string(),
+            bpe: tiktoken_rs::get_bpe_from_tokenizer(tokenizer).ok()?,
+        })
+    }
+}
+
+impl Tokenizer for Tiktoken {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn count(&self, text: &str) -> usize {
+        self.bpe.encode_ordinary(text).len()
+    }
+
+    // a chunk can be slightly longer or the overlap slightly shorter if a multibyte character is
+    // split across tokens
+    fn chunks(&self, text: &str, chunk_size: usize, chunk_overlap: usize) -> Vec<String> {
+        let tokens = self.bpe.encode_ordinary(text);
+        // true if the token doesn't start in the middle of a character
+        let is_boundary = |idx: usize| {
+            self.bpe
+                ._decode_native(&tokens[idx..idx + 1])
+                .first()
+                .is_none_or(|byte| byte & 0b1100_0000 != 0b1000_0000)
+        };
+
+        let mut chunks = vec![];
+        let mut start = 0;
+        while start < tokens.len() {
+            let mut end = (start + chunk_size).min(tokens.len());
+            // only cut on character boundaries
+            while end < tokens.len() && !is_boundary(end) {
+                end += 1;
+            }
+
+            chunks.push(
+                String::from_utf8_lossy(&self.bpe._decode_native(&tokens[start..end])).to_string(),
+            );
+            if end == tokens.len() {
+                break;
+            }
+
+            start = end - chunk_overlap;
+            while start < end && !is_boundary(start) {
+                start += 1;
+            }
+        }
+
+        chunks
+    }
+}
+
+// the tokenizer of the model family if known, or the estimator
+pub fn for_model(model: &str) -> Arc<dyn Tokenizer> {
+    match Tiktoken::for_model(model) {
+        Some(tokenizer) => Arc::new(tokenizer),
+        None => Arc::new(Estimator::default()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_counts() {
+        let text = "The quick brown fox jumps over the lazy dog.";
+
+        assert_eq!(Estimator::default().count(text), 11);
+        assert_eq!(Tiktoken::cl100k_base().count(text), 10);
+        assert_eq!(Estimator::default().count(""), 0);
+        assert_eq!(Tiktoken::cl100k_base().count(""), 0);
+
+        // multibyte characters count as one for the estimator
+        assert_eq!(Estimator::default().count("テキスト"), 1);
+    }
+
+    #[test]
+    fn test_for_model() {
+        assert_eq!(for_model("gpt-4o-mini").name(), "o200k_base");
+        assert_eq!(for_model("gpt-4-turbo").name(), "cl100k_base");
+        assert_eq!(for_model("llama3").name(), "estimate");
+    }
+
+    #[test]
+    fn test_estimator_chunks() {
+        let chunks = Estimator::default().chunks("abcdefghij", 1, 0);
+        assert_eq!(chunks, vec!["abcd", "efgh", "ij"]);
+        let chunks = Estimator::default().chunks("abcdefghijkl", 2, 1);
+        assert_eq!(chunks, vec!["abcdefgh", "efghijkl"]);
+    }
+}
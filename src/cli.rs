@@ -1,16 +1,35 @@
 use std::io::{self, Write};
+use std::time::Duration;
 
 use anyhow::Result;
 use clap::Parser;
+use duration_string::DurationString;
 use lazy_static::lazy_static;
 use regex::Regex;
 
+use crate::{
+    agent::{
+        ensemble,
+        serialization::{
+            xml::parsing::{DuplicateAttributes, OnParseError},
+            ActionsStyle, InvocationFormat,
+        },
+        state::{history::TranscriptFormat, truncation::Truncation, OnLoop, OnMaxSteps},
+    },
+    ui::OutputMode,
+};
+
 lazy_static! {
     pub static ref PUBLIC_GENERATOR_PARSER: Regex = Regex::new(r"(?m)^(.+)://(.+)$").unwrap();
     pub static ref LOCAL_GENERATOR_PARSER: Regex =
         Regex::new(r"(?m)^(.+)://(.+)@(.+):(\d+)$").unwrap();
+    pub static ref URL_GENERATOR_PARSER: Regex =
+        Regex::new(r"(?m)^([^:/]+)://([^@]+)@([a-zA-Z][a-zA-Z0-9+.\-]*://.+)$").unwrap();
 }
 
+// used when the context window is neither specified nor detected
+const DEFAULT_CONTEXT_WINDOW: u32 = 8000;
+
 #[derive(Default)]
 pub(crate) struct GeneratorOptions {
     pub type_name: String,
@@ -24,10 +43,10 @@ pub(crate) struct GeneratorOptions {
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 pub(crate) struct Args {
-    /// Generator string as <type>://<model name>@<host>:<port>
+    /// Generator string as <type>://<model name>@<host>:<port> or <type>://<model name>@<base url>
     #[arg(short = 'G', long, default_value = "ollama://llama3@localhost:11434")]
     pub generator: String,
-    /// Embedder string as <type>://<model name>@<host>:<port>
+    /// Embedder string as <type>://<model name>@<host>:<port> or <type>://<model name>@<base url>
     #[arg(
         short = 'E',
         long,
@@ -43,12 +62,129 @@ pub(crate) struct Args {
     /// Pre define variables.
     #[arg(short = 'D', long, value_parser, num_args = 1.., value_delimiter = ' ')]
     pub define: Vec<String>,
-    /// Context window size.
-    #[arg(long, default_value_t = 8000)]
-    pub context_window: u32,
+    /// Secrets that actions can reference as $NAME without the model seeing them, as NAME=VALUE or NAME to read it from the environment.
+    #[arg(long, value_parser, num_args = 1.., value_delimiter = ' ')]
+    pub secret: Vec<String>,
+    /// RAG configuration file (yaml or json) to use instead of the rag section of the tasklet.
+    #[arg(long)]
+    pub rag_config: Option<String>,
+    /// Additional guidance file to merge with the tasklet one, one rule per line.
+    #[arg(long)]
+    pub guidance_file: Option<String>,
+    /// Custom system prompt template file, it must contain the {iterations}, {system_prompt}, {storages}, {available_actions} and {guidance} placeholders.
+    #[arg(long)]
+    pub system_prompt_template: Option<String>,
+    /// Context window size, detected from the model if not set and supported by the generator.
+    #[arg(long)]
+    pub context_window: Option<u32>,
+    /// Sampling temperature, use 0 together with --seed for reproducible runs (model default if not set).
+    #[arg(long)]
+    pub temperature: Option<f32>,
+    /// Only sample from the K most likely tokens (model default if not set).
+    #[arg(long)]
+    pub top_k: Option<u32>,
+    /// Only sample from the smallest set of tokens whose cumulative probability exceeds P (model default if not set).
+    #[arg(long)]
+    pub top_p: Option<f32>,
+    /// Penalty for repeated tokens (model default if not set, not supported by every generator).
+    #[arg(long)]
+    pub repeat_penalty: Option<f32>,
+    /// Random seed used for sampling (not supported by every generator).
+    #[arg(long)]
+    pub seed: Option<i32>,
     /// Maximum number of steps to complete the task or 0 for no limit.
     #[arg(long, default_value_t = 0)]
     pub max_iterations: usize,
+    /// Number of steps where the model only reasons (with the think action) that don't count against --max-iterations.
+    #[arg(long, default_value_t = 0)]
+    pub reasoning_budget: usize,
+    /// What to do when the maximum number of steps is reached.
+    #[arg(long, value_enum, default_value_t = OnMaxSteps::Error)]
+    pub on_max_iterations: OnMaxSteps,
+    /// Maximum duration of the run (for instance 30s, 10m or 2h), then the task is set as complete.
+    #[arg(long)]
+    pub max_duration: Option<String>,
+    /// Maximum total cost of the executed actions or 0 for no limit, expensive actions consume more of it.
+    #[arg(long, default_value_t = 0)]
+    pub max_cost: u32,
+    /// Abort after this number of consecutive responses without valid actions or 0 for no limit.
+    #[arg(long, default_value_t = 0)]
+    pub max_unparsed_responses: usize,
+    /// Number of recent actions considered when looking for the model repeating itself.
+    #[arg(long, default_value_t = 10)]
+    pub loop_window: usize,
+    /// Maximum number of times the same action with the same arguments can run within --loop-window or 0 for no limit.
+    #[arg(long, default_value_t = 0)]
+    pub loop_threshold: usize,
+    /// What to do when the model repeats the same action more than --loop-threshold times.
+    #[arg(long, value_enum, default_value_t = OnLoop::Warn)]
+    pub on_loop: OnLoop,
+    /// Maximum length in bytes of an action result added to the history or 0 for no limit.
+    #[arg(long, default_value_t = 0)]
+    pub max_result_length: usize,
+    /// Approximate maximum number of tokens of the chat history sent to the model or 0 for no limit, the oldest actions are dropped first.
+    #[arg(long, default_value_t = 0)]
+    pub max_history_tokens: usize,
+    /// Summarize the oldest actions into the system prompt when the history has more than this number of actions or 0 to never.
+    #[arg(long, default_value_t = 0)]
+    pub summarize_after: usize,
+    /// What part of an action result to keep when it exceeds --max-result-length.
+    #[arg(long, value_enum, default_value_t = Truncation::Head)]
+    pub truncation: Truncation,
+    /// Advertise the actions as structured tools to backends supporting them (openai, fireworks and anthropic), falling back to parsing the response text otherwise.
+    #[arg(long)]
+    pub native_tools: bool,
+    /// How the available actions are described to the model, compact uses a single line per action to save context.
+    #[arg(long, value_enum, default_value_t = ActionsStyle::Verbose)]
+    pub actions_style: ActionsStyle,
+    /// Format of the actions, both as described to the model and as parsed from its responses.
+    #[arg(long, value_enum, default_value_t = InvocationFormat::Xml)]
+    pub invocation_format: InvocationFormat,
+    /// How to handle the same attribute specified more than once in an action.
+    #[arg(long, value_enum, default_value_t = DuplicateAttributes::Error)]
+    pub duplicate_attributes: DuplicateAttributes,
+    /// What to do when the response contains tags that can't be parsed as actions.
+    #[arg(long, value_enum, default_value_t = OnParseError::Ignore)]
+    pub on_parse_error: OnParseError,
+    /// Print the response as it's generated and parse actions as soon as they are complete (ignored with --ensemble-size > 1).
+    #[arg(long)]
+    pub stream: bool,
+    /// Maximum number of attempts for a generation failing with a transient error (timeouts, rate limits, server errors).
+    #[arg(long, default_value_t = 3)]
+    pub generation_attempts: usize,
+    /// Run consecutive actions without side effects (like reading files) concurrently.
+    #[arg(long)]
+    pub parallel_actions: bool,
+    /// Reproducible runs: zero temperature, fixed seed (42 unless --seed is set), no retry jitter and no concurrent actions. Backends ignoring the seed can still be nondeterministic.
+    #[arg(long)]
+    pub deterministic: bool,
+    /// Maximum duration of a single generation (for instance 30s or 5m) or 0 for no limit, then it fails and can be retried.
+    #[arg(long, default_value = "120s")]
+    pub generation_timeout: String,
+    /// Price per 1000 prompt tokens, used to estimate the cost of the run.
+    #[arg(long)]
+    pub prompt_price: Option<f64>,
+    /// Price per 1000 completion tokens, used to estimate the cost of the run.
+    #[arg(long)]
+    pub completion_price: Option<f64>,
+    /// Number of responses to generate in parallel at each step and vote on.
+    #[arg(long, default_value_t = 1)]
+    pub ensemble_size: usize,
+    /// How to select the actions to execute among the responses of the ensemble.
+    #[arg(long, value_enum, default_value_t = ensemble::Strategy::Majority)]
+    pub ensemble_strategy: ensemble::Strategy,
+    /// At every step, save the state of the run to this file so that it can be continued with --resume.
+    #[arg(long)]
+    pub checkpoint: Option<String>,
+    /// Continue the run saved to this checkpoint file by a previous execution of the same task.
+    #[arg(long)]
+    pub resume: Option<String>,
+    /// At the end of the run, write a human readable transcript of the executed actions to this file.
+    #[arg(long)]
+    pub transcript: Option<String>,
+    /// Format of the --transcript file.
+    #[arg(long, value_enum, default_value_t = TranscriptFormat::Text)]
+    pub transcript_format: TranscriptFormat,
     /// At every step, save the dynamic system prompt contents to this file.
     #[arg(long)]
     pub save_to: Option<String>,
@@ -61,6 +197,12 @@ pub(crate) struct Args {
     /// Report runtime statistics.
     #[arg(long)]
     pub stats: bool,
+    /// How the agent activity is reported on the standard output.
+    #[arg(long, value_enum, default_value_t = OutputMode::Text)]
+    pub output: OutputMode,
+    /// Also write the agent activity to this file as json lines, regardless of --output.
+    #[arg(long)]
+    pub events_file: Option<String>,
 }
 
 impl Args {
@@ -71,11 +213,25 @@ impl Args {
         }
 
         let mut generator = GeneratorOptions {
-            context_window: self.context_window,
+            context_window: self.context_window.unwrap_or(DEFAULT_CONTEXT_WINDOW),
             ..Default::default()
         };
 
-        if raw.contains('@') {
+        if let Some(caps) = URL_GENERATOR_PARSER.captures_iter(raw).next() {
+            // full base url, the port is part of it
+            caps.get(1)
+                .unwrap()
+                .as_str()
+                .clone_into(&mut generator.type_name);
+            caps.get(2)
+                .unwrap()
+                .as_str()
+                .clone_into(&mut generator.model_name);
+            caps.get(3)
+                .unwrap()
+                .as_str()
+                .clone_into(&mut generator.host);
+        } else if raw.contains('@') {
             let caps = if let Some(caps) = LOCAL_GENERATOR_PARSER.captures_iter(raw).next() {
                 caps
             } else {
@@ -132,12 +288,36 @@ impl Args {
         Ok(generator)
     }
 
+    pub fn get_max_duration(&self) -> Result<Option<Duration>> {
+        if let Some(raw) = &self.max_duration {
+            let duration = raw
+                .parse::<DurationString>()
+                .map_err(|e| anyhow!("can't parse '{raw}' as duration string: {e}"))?;
+            Ok(Some(*duration))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn get_generation_timeout(&self) -> Result<Option<Duration>> {
+        let raw = self.generation_timeout.trim();
+        if raw == "0" {
+            return Ok(None);
+        }
+
+        let duration = raw
+            .parse::<DurationString>()
+            .map_err(|e| anyhow!("can't parse '{raw}' as duration string: {e}"))?;
+        Ok(Some(*duration).filter(|d| !d.is_zero()))
+    }
+
     pub fn to_generator_options(&self) -> Result<GeneratorOptions> {
         self.parse_connection_string(&self.generator, "generator")
     }
 
-    pub fn to_embedder_options(&self) -> Result<GeneratorOptions> {
-        self.parse_connection_string(&self.embedder, "embedder")
+    // the embedder of the rag configuration, if any, takes precedence over --embedder
+    pub fn to_embedder_options(&self, embedder: Option<&str>) -> Result<GeneratorOptions> {
+        self.parse_connection_string(embedder.unwrap_or(&self.embedder), "embedder")
     }
 }
 
@@ -15,7 +15,6 @@ const APP_VERSION: &str = env!("CARGO_PKG_VERSION");
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    // TODO: save/restore session
     let args = cli::Args::parse();
     let with_stats = args.stats;
 
@@ -38,15 +37,24 @@ async fn main() -> Result<()> {
     let (mut agent, events_rx) = setup::setup_agent(&args).await?;
 
     // spawn the events consumer
-    tokio::spawn(ui::text::consume_events(args, events_rx));
+    tokio::spawn(ui::consume_events(args, events_rx));
 
-    // keep going until the task is complete or a fatal error is reached
-    while !agent.is_done().await {
-        // next step
-        if let Err(error) = agent.step().await {
-            log::error!("{}", error.to_string());
-            return Err(error);
+    // stop cleanly on the first ctrl-c, exit right away on the second
+    let cancellation = agent.cancellation_token();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            log::warn!("stopping after the current action, press ctrl-c again to exit now");
+            cancellation.cancel();
+            if tokio::signal::ctrl_c().await.is_ok() {
+                std::process::exit(130);
+            }
         }
+    });
+
+    // keep going until the task is complete or a fatal error is reached
+    if let Err(error) = agent.run().await {
+        log::error!("{}", error.to_string());
+        return Err(error);
     }
 
     // report final metrics on exit
@@ -54,5 +62,10 @@ async fn main() -> Result<()> {
         agent.on_event(Event::MetricsUpdate(agent.get_metrics().await))?;
     }
 
+    let usage = agent.usage().await;
+    if usage.tokens.total() > 0 {
+        log::info!("{}", usage);
+    }
+
     Ok(())
 }
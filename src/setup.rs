@@ -1,25 +1,24 @@
+use std::path::{Path, PathBuf};
+
 use anyhow::Result;
 use colored::Colorize;
 
 use crate::{
     agent::{
+        self,
         events::{self, create_channel},
-        generator,
+        generator, rag,
         task::tasklet::Tasklet,
         Agent,
     },
-    cli, APP_NAME, APP_VERSION,
+    cli,
+    ui::OutputMode,
+    APP_NAME, APP_VERSION,
 };
 
-#[allow(clippy::type_complexity)]
-fn setup_models(
+fn setup_generator(
     args: &cli::Args,
-) -> Result<(
-    cli::GeneratorOptions,
-    Box<dyn generator::Client>,
-    Box<dyn mini_rag::Embedder>,
-)> {
-    // create generator
+) -> Result<(cli::GeneratorOptions, Box<dyn generator::Client>)> {
     let gen_options = args.to_generator_options()?;
     let generator = generator::factory(
         &gen_options.type_name,
@@ -29,22 +28,29 @@ fn setup_models(
         gen_options.context_window,
     )?;
 
-    // create embedder
-    let emb_options = args.to_embedder_options()?;
-    let embedder = generator::factory_embedder(
+    Ok((gen_options, generator))
+}
+
+// the embedder is created separately from the generator, so that a small embedding model can be
+// used with a large chat model
+fn setup_embedder(
+    args: &cli::Args,
+    rag: Option<&rag::Configuration>,
+) -> Result<Box<dyn rag::Embedder>> {
+    let emb_options = args.to_embedder_options(rag.and_then(|r| r.embedder.as_deref()))?;
+
+    generator::factory_embedder(
         &emb_options.type_name,
         &emb_options.host,
         emb_options.port,
         &emb_options.model_name,
         emb_options.context_window,
-    )?;
-
-    Ok((gen_options, generator, embedder))
+    )
 }
 
 pub(crate) async fn setup_agent(args: &cli::Args) -> Result<(Agent, events::Receiver)> {
-    // create generator and embedder
-    let (gen_options, generator, embedder) = setup_models(args)?;
+    // create generator
+    let (gen_options, generator) = setup_generator(args)?;
 
     // read and create the tasklet
     let tasklet = if let Some(t) = &args.tasklet {
@@ -56,30 +62,150 @@ pub(crate) async fn setup_agent(args: &cli::Args) -> Result<(Agent, events::Rece
     let mut tasklet = Tasklet::from_path(tasklet, &args.define)?;
     let tasklet_name = tasklet.name.clone();
 
-    println!(
-        "{} v{} 🧠 {}{} > {}\n",
-        APP_NAME,
-        APP_VERSION,
-        gen_options.model_name.bold(),
-        if gen_options.port == 0 {
-            format!("@{}", gen_options.type_name.dimmed())
-        } else {
-            format!(
-                "@{}:{}",
-                gen_options.host.dimmed(),
-                gen_options.port.to_string().dimmed()
-            )
-        },
-        tasklet_name.green().bold(),
-    );
+    // keep the standard output parseable in json mode
+    if args.output == OutputMode::Text {
+        println!(
+            "{} v{} 🧠 {}{} > {}\n",
+            APP_NAME,
+            APP_VERSION,
+            gen_options.model_name.bold(),
+            if gen_options.port == 0 {
+                format!("@{}", gen_options.type_name.dimmed())
+            } else {
+                format!(
+                    "@{}:{}",
+                    gen_options.host.dimmed(),
+                    gen_options.port.to_string().dimmed()
+                )
+            },
+            tasklet_name.green().bold(),
+        );
+    }
+
+    if let Some(guidance_file) = &args.guidance_file {
+        tasklet.add_guidance_from_file(guidance_file)?;
+    }
+
+    if let Some(rag_config) = &args.rag_config {
+        tasklet.rag = Some(agent::rag::Configuration::from_file(Path::new(rag_config))?);
+    }
 
     tasklet.prepare(&args.prompt)?;
 
+    let embedder = setup_embedder(args, tasklet.rag.as_ref())?;
+
     let task = Box::new(tasklet);
     let (tx, rx) = create_channel();
 
+    let tokenizer = agent::tokenizer::for_model(&gen_options.model_name);
+    log::debug!("using the {} tokenizer", tokenizer.name());
+
     // create the agent
-    let agent = Agent::new(tx, generator, embedder, task, args.max_iterations).await?;
+    let config = agent::Config {
+        max_iterations: args.max_iterations,
+        on_max_iterations: args.on_max_iterations,
+        max_duration: args.get_max_duration()?,
+        max_cost: args.max_cost,
+        reasoning_budget: args.reasoning_budget,
+        max_unparsed: args.max_unparsed_responses,
+        loop_window: args.loop_window,
+        loop_threshold: args.loop_threshold,
+        on_loop: args.on_loop,
+        max_result_length: args.max_result_length,
+        max_history_tokens: args.max_history_tokens,
+        detect_context_window: args.context_window.is_none(),
+        tokenizer: Some(tokenizer),
+        summarize_after: args.summarize_after,
+        truncation: args.truncation,
+        secrets: agent::state::secrets::Secrets::from_definitions(&args.secret)?,
+        native_tools: args.native_tools,
+        actions_style: args.actions_style,
+        system_prompt_template: args
+            .system_prompt_template
+            .as_ref()
+            .map(|path| agent::serialization::load_system_prompt_template(Path::new(path)))
+            .transpose()?,
+        invocation_format: args.invocation_format,
+        duplicate_attributes: args.duplicate_attributes,
+        on_parse_error: args.on_parse_error,
+        stream: args.stream,
+        retry_policy: agent::generator::retry::RetryPolicy {
+            max_attempts: args.generation_attempts.max(1),
+            ..Default::default()
+        },
+        generation_timeout: args.get_generation_timeout()?,
+        parallel_actions: args.parallel_actions,
+        deterministic: args.deterministic,
+        token_prices: if args.prompt_price.is_some() || args.completion_price.is_some() {
+            Some(agent::generator::TokenPrices {
+                prompt: args.prompt_price.unwrap_or_default(),
+                completion: args.completion_price.unwrap_or_default(),
+            })
+        } else {
+            None
+        },
+        generation: agent::generator::GenerationConfig {
+            temperature: args.temperature,
+            top_k: args.top_k,
+            top_p: args.top_p,
+            repeat_penalty: args.repeat_penalty,
+            seed: args.seed,
+        },
+        ensemble_size: args.ensemble_size,
+        ensemble_strategy: args.ensemble_strategy,
+        // a resumed run keeps saving its progress to the same file
+        checkpoint: args
+            .checkpoint
+            .clone()
+            .or(args.resume.clone())
+            .map(PathBuf::from),
+        transcript: args.transcript.as_ref().map(PathBuf::from),
+        transcript_format: args.transcript_format,
+    };
+    let mut agent = Agent::new(tx, generator, embedder, task, config, None).await?;
+
+    if let Some(path) = &args.resume {
+        agent.resume(&PathBuf::from(path)).await?;
+    }
 
     Ok((agent, rx))
 }
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    #[test]
+    fn test_rag_embedder() {
+        let args = cli::Args::parse_from(["nerve", "-G", "ollama://llama3@localhost:11434"]);
+        let config = rag::Configuration {
+            source_path: "docs".to_string(),
+            data_path: std::env::temp_dir()
+                .join(format!("nerve-rag-embedder-test-{}", std::process::id()))
+                .display()
+                .to_string(),
+            embedder: Some("ollama://nomic-embed-text@localhost:11435".to_string()),
+            ..Default::default()
+        };
+
+        let options = args
+            .to_embedder_options(config.embedder.as_deref())
+            .unwrap();
+        assert_eq!(options.model_name, "nomic-embed-text");
+        assert_eq!(options.port, 11435);
+        assert_ne!(
+            options.model_name,
+            args.to_generator_options().unwrap().model_name
+        );
+        // --embedder is used when the configuration doesn't specify one
+        assert_eq!(
+            args.to_embedder_options(None).unwrap().model_name,
+            "all-minilm"
+        );
+
+        let embedder = setup_embedder(&args, Some(&config)).unwrap();
+        assert!(rag::create(embedder, config).is_ok());
+    }
+}
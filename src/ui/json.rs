@@ -0,0 +1,69 @@
+use std::{
+    io::{self, Write},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+
+use crate::agent::events::Event;
+
+#[derive(Serialize)]
+struct Line<'a> {
+    // seconds since the epoch
+    timestamp: f64,
+    #[serde(flatten)]
+    event: &'a Event,
+}
+
+// write the event as a single line of json so that it can be tailed and parsed
+pub(crate) fn write_event(writer: &mut impl Write, event: &Event) -> io::Result<()> {
+    let line = Line {
+        timestamp: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs_f64())
+            .unwrap_or(0.0),
+        event,
+    };
+
+    serde_json::to_writer(&mut *writer, &line)?;
+    writer.write_all(b"\n")?;
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_event() {
+        let mut buffer = vec![];
+        write_event(
+            &mut buffer,
+            &Event::StepStarted {
+                step: 1,
+                max_steps: 10,
+            },
+        )
+        .unwrap();
+        write_event(&mut buffer, &Event::ResponseChunk("hello".to_string())).unwrap();
+        write_event(&mut buffer, &Event::EmptyResponse).unwrap();
+
+        let lines: Vec<serde_json::Value> = String::from_utf8(buffer)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+
+        assert_eq!(lines.len(), 3);
+        assert!(lines.iter().all(|line| line["timestamp"].is_f64()));
+        assert_eq!(lines[0]["event"], "step_started");
+        assert_eq!(
+            lines[0]["data"],
+            serde_json::json!({"step": 1, "max_steps": 10})
+        );
+        assert_eq!(lines[1]["event"], "response_chunk");
+        assert_eq!(lines[1]["data"], "hello");
+        assert_eq!(lines[2]["event"], "empty_response");
+        assert!(lines[2].get("data").is_none());
+    }
+}
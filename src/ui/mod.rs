@@ -1 +1,46 @@
+use std::io::Write;
+
+use crate::{agent::events::Receiver, cli};
+
+pub(crate) mod json;
 pub(crate) mod text;
+
+// how the events are reported on the standard output
+#[derive(Debug, Default, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum OutputMode {
+    // colored human readable output
+    #[default]
+    Text,
+    // one json object per line
+    Json,
+}
+
+// render every event with the selected output mode and, if requested, to the events file
+pub(crate) async fn consume_events(args: cli::Args, mut events_rx: Receiver) {
+    let mut events_file: Option<Box<dyn Write + Send>> = match &args.events_file {
+        Some(path) => match std::fs::File::create(path) {
+            Ok(file) => Some(Box::new(file)),
+            Err(e) => {
+                log::error!("can't create {}: {}", path, e);
+                None
+            }
+        },
+        None => None,
+    };
+    let mut stdout = std::io::stdout();
+    let mut text = (args.output == OutputMode::Text).then(|| text::TextRenderer::new(args));
+
+    while let Some(event) = events_rx.recv().await {
+        if let Some(file) = events_file.as_mut() {
+            if let Err(e) = json::write_event(file, &event) {
+                log::error!("can't write event: {}", e);
+            }
+        }
+
+        if let Some(text) = text.as_mut() {
+            text.render(event);
+        } else if let Err(e) = json::write_event(&mut stdout, &event) {
+            log::error!("can't write event: {}", e);
+        }
+    }
+}
@@ -1,13 +1,45 @@
+use std::io::{self, Write};
+
 use colored::Colorize;
 
-use crate::{
-    agent::events::{Event, Receiver},
-    cli,
-};
+use crate::{agent::events::Event, cli};
+
+// renders the events as colored human readable output
+pub(crate) struct TextRenderer {
+    args: cli::Args,
+    streaming: bool,
+}
+
+impl TextRenderer {
+    pub fn new(args: cli::Args) -> Self {
+        Self {
+            args,
+            streaming: false,
+        }
+    }
+
+    pub fn render(&mut self, event: Event) {
+        let args = &self.args;
+        if let Event::ResponseChunk(chunk) = &event {
+            print!("{}", chunk.dimmed());
+            let _ = io::stdout().flush();
+            self.streaming = true;
+            return;
+        } else if self.streaming {
+            // end of the streamed response
+            println!();
+            self.streaming = false;
+        }
 
-pub(crate) async fn consume_events(args: cli::Args, mut events_rx: Receiver) {
-    while let Some(event) = events_rx.recv().await {
         match event {
+            Event::ResponseChunk(_) => {}
+            Event::StepStarted { step, max_steps } => {
+                if max_steps > 0 {
+                    log::debug!("step {}/{}", step, max_steps);
+                } else {
+                    log::debug!("step {}", step);
+                }
+            }
             Event::MetricsUpdate(metrics) => {
                 log::info!("{}", metrics);
             }
@@ -45,6 +77,9 @@ pub(crate) async fn consume_events(args: cli::Args, mut events_rx: Receiver) {
             Event::InvalidAction { invocation, error } => {
                 log::warn!("invalid action {} : {:?}", invocation.action, error,);
             }
+            Event::ActionStarted { invocation } => {
+                log::debug!("running {} ...", invocation);
+            }
             Event::ActionTimeout {
                 invocation,
                 elapsed,
@@ -60,16 +95,12 @@ pub(crate) async fn consume_events(args: cli::Args, mut events_rx: Receiver) {
                 error,
                 result,
                 elapsed,
+                ..
             } => {
                 if let Some(err) = error {
                     log::error!("{}: {}", invocation, err);
                 } else if let Some(res) = result {
-                    log::debug!(
-                        "{} -> {} bytes in {:?}",
-                        invocation,
-                        res.as_bytes().len(),
-                        elapsed
-                    );
+                    log::debug!("{} -> {} bytes in {:?}", invocation, res.len(), elapsed);
                 } else {
                     log::debug!("{} {} in {:?}", invocation, "no output".dimmed(), elapsed);
                 }
@@ -97,6 +128,9 @@ pub(crate) async fn consume_events(args: cli::Args, mut events_rx: Receiver) {
                     );
                 }
             }
+            Event::Error(_) => {
+                // reported by the main loop
+            }
             Event::StorageUpdate {
                 storage_name,
                 storage_type: _,